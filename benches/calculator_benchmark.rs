@@ -1,5 +1,5 @@
 use criterion::{Criterion, black_box, criterion_group, criterion_main};
-use rust_calculator::{evaluate_expression, set_variable};
+use rust_calculator::{CompiledExpression, evaluate_cached, evaluate_expression, set_variable};
 
 fn calculator_benchmark(c: &mut Criterion) {
   c.bench_function("simple addition", |b| {
@@ -59,6 +59,17 @@ fn calculator_benchmark(c: &mut Criterion) {
       evaluate_expression(black_box("sqrt(x^2 + y^2)")).unwrap();
     })
   });
+
+  // Benchmark for a formula compiled once and evaluated repeatedly
+  c.bench_function("compiled expression", |b| {
+    let formula = CompiledExpression::new("sqrt(x^2 + y^2)").unwrap();
+    b.iter(|| formula.evaluate(black_box(&[10.0, 5.0])).unwrap())
+  });
+
+  // The same expression as "complex expression", compiled on first use
+  c.bench_function("cached expression", |b| {
+    b.iter(|| evaluate_cached(black_box("(2 + 3) * 4 ^ 2 / (1 + 1)")))
+  });
 }
 
 criterion_group!(benches, calculator_benchmark);
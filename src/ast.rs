@@ -0,0 +1,569 @@
+//! # Expression Trees
+//!
+//! The parsed form of an expression as a tree, built from the same postfix
+//! sequence the evaluator uses. Trees can be serialized to JSON and back, so
+//! external tools can analyze, transform, or visualize expressions.
+//!
+//! ```
+//! use rust_calculator::{Expr, parse};
+//!
+//! let tree = parse("2 * x + 1").unwrap();
+//! let Expr::Binary { operator, .. } = &tree else { panic!() };
+//! assert_eq!(operator, "+");
+//! assert_eq!(tree.to_string(), "2 * x + 1");
+//! assert_eq!(tree.evaluate_with(&|_| Some(3.0)).unwrap(), 7.0);
+//! ```
+//!
+//! Each node is a JSON object tagged by `type`:
+//!
+//! - `{"type": "number", "value": 2.0}`
+//! - `{"type": "variable", "name": "x"}`
+//! - `{"type": "binary", "operator": "+", "left": {...}, "right": {...}}`
+//! - `{"type": "function", "name": "sqrt", "argument": {...}}`
+//!
+//! Trees are walked recursively, so an expression whose tree would be more
+//! than [`MAX_TREE_DEPTH`] levels deep, such as a sum of thousands of terms,
+//! fails to parse with [`CalculatorError::LimitExceeded`].
+
+use crate::prelude::*;
+use crate::{CalculatorError, Resolver, Token};
+use core::fmt;
+#[cfg(feature = "std")]
+use serde_json::{Value, json};
+
+/// Deepest tree [`parse`] builds
+pub const MAX_TREE_DEPTH: usize = 1000;
+
+/// A node of a parsed expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  /// A literal number; constants such as `pi` are resolved to their values
+  Number(f64),
+  /// A variable, looked up when the tree is evaluated
+  Variable(String),
+  /// A binary operation such as `left + right`
+  Binary {
+    operator: String,
+    left: Box<Expr>,
+    right: Box<Expr>,
+  },
+  /// A function applied to a single argument, such as `sqrt(argument)`
+  Function { name: String, argument: Box<Expr> },
+}
+
+/// Parses an expression into a tree without evaluating it, keeping variables
+/// as [`Expr::Variable`] nodes
+pub fn parse(expression: &str) -> Result<Expr, CalculatorError> {
+  let expanded = crate::roman::expand_literals(expression)?;
+  parse_with_resolver(&expanded, &|_| None)
+}
+
+/// Parses an expression and writes it back in canonical form, as
+/// [`Expr::to_canonical`] does
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::canonicalize;
+///
+/// assert_eq!(canonicalize("2+3*x").unwrap(), "2 + (3 * x)");
+/// assert_eq!(canonicalize("-3^2").unwrap(), "(-3) ^ 2");
+/// ```
+pub fn canonicalize(expression: &str) -> Result<String, CalculatorError> {
+  Ok(parse(expression)?.to_canonical())
+}
+
+/// Parses an expression, replacing the identifiers `resolve` knows with their
+/// values
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    name = "parse_tree",
+    level = "trace",
+    skip(resolve),
+    err(level = "debug")
+  )
+)]
+pub(crate) fn parse_with_resolver(
+  expression: &str,
+  resolve: Resolver,
+) -> Result<Expr, CalculatorError> {
+  parse_tokens(crate::tokenize_spanned(expression, resolve)?, expression)
+}
+
+/// Builds the tree of the tokens of `expression`
+pub(crate) fn parse_tokens(
+  tokens: Vec<crate::SpannedToken>,
+  expression: &str,
+) -> Result<Expr, CalculatorError> {
+  if tokens.is_empty() {
+    return Err(
+      CalculatorError::SyntaxError("Empty expression".to_string()).at(0..expression.len()),
+    );
+  }
+
+  // Each node is kept with the depth of its tree
+  let mut stack: Vec<(Expr, usize)> = Vec::new();
+  for (token, span) in crate::parser::to_postfix(tokens)? {
+    let node = match token {
+      Token::Number(value) => (Expr::Number(value), 1),
+      Token::Identifier(name) => (Expr::Variable(name), 1),
+      Token::Operator(operator) => {
+        let (Some((right, right_depth)), Some((left, left_depth))) = (stack.pop(), stack.pop())
+        else {
+          return Err(
+            CalculatorError::SyntaxError("Invalid expression: not enough operands".to_string())
+              .at(span),
+          );
+        };
+        let node = Expr::Binary {
+          operator,
+          left: Box::new(left),
+          right: Box::new(right),
+        };
+        (node, left_depth.max(right_depth) + 1)
+      }
+      Token::Function(name) => {
+        let Some((argument, depth)) = stack.pop() else {
+          return Err(
+            CalculatorError::SyntaxError(
+              "Invalid expression: function without argument".to_string(),
+            )
+            .at(span),
+          );
+        };
+        let node = Expr::Function {
+          name,
+          argument: Box::new(argument),
+        };
+        (node, depth + 1)
+      }
+      Token::LeftParen | Token::RightParen => {
+        return Err(CalculatorError::SyntaxError("Mismatched parentheses".to_string()).at(span));
+      }
+    };
+    if node.1 > MAX_TREE_DEPTH {
+      return Err(CalculatorError::LimitExceeded(format!(
+        "expression tree is more than {} levels deep",
+        MAX_TREE_DEPTH
+      )));
+    }
+    stack.push(node);
+  }
+
+  match (stack.pop(), stack.is_empty()) {
+    (Some((expr, _)), true) => Ok(expr),
+    _ => Err(
+      CalculatorError::SyntaxError("Invalid expression: too many operands".to_string())
+        .at(0..expression.len()),
+    ),
+  }
+}
+
+/// Evaluates the arguments of a call that takes lists
+fn evaluate_values(
+  arguments: &[&Expr],
+  resolve: Resolver,
+) -> Result<Vec<crate::Value>, CalculatorError> {
+  arguments
+    .iter()
+    .map(|argument| argument.evaluate_value_with(resolve))
+    .collect()
+}
+
+impl Expr {
+  /// Evaluates the tree, looking variables up among the global variables
+  pub fn evaluate(&self) -> Result<f64, CalculatorError> {
+    self.evaluate_with(&crate::get_variable)
+  }
+
+  /// Evaluates the tree, looking variables up through `resolve`
+  pub fn evaluate_with(&self, resolve: Resolver) -> Result<f64, CalculatorError> {
+    match self {
+      Expr::Number(value) => Ok(*value),
+      Expr::Variable(name) => match resolve(name) {
+        Some(value) => crate::check_input(value),
+        None => Err(crate::unknown_token(name)),
+      },
+      Expr::Function { .. } | Expr::Binary { .. } if crate::lists::is_list(self) => {
+        self.evaluate_value_with(resolve)?.into_number()
+      }
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } => crate::evaluate_binary_operation(
+        left.evaluate_with(resolve)?,
+        operator,
+        right.evaluate_with(resolve)?,
+      ),
+      Expr::Function { name, argument } => match argument.arguments().as_slice() {
+        [matrix] if name == "det" => crate::matrices::det(matrix.evaluate_value_with(resolve)?),
+        [list] if crate::lists::is_aggregate(name) => {
+          crate::lists::aggregate(name, list.evaluate_value_with(resolve)?)
+        }
+        arguments if crate::lists::takes_function(name) => {
+          crate::lists::apply_function(name, arguments, resolve)?.into_number()
+        }
+        arguments if crate::finance::takes_list(name) => {
+          crate::finance::evaluate_with_list(name, evaluate_values(arguments, resolve)?)
+        }
+        arguments if crate::series::is_series(name) => {
+          crate::series::evaluate_call(name, arguments, resolve)
+        }
+        // Only the branch taken is evaluated, so a recursive user function
+        // can stop
+        [condition, then, otherwise] if name == "if" => {
+          let condition = condition.evaluate_with(resolve)?;
+          if condition.is_nan() {
+            Ok(f64::NAN)
+          } else if condition != 0.0 {
+            then.evaluate_with(resolve)
+          } else {
+            otherwise.evaluate_with(resolve)
+          }
+        }
+        arguments if name == "solve" => crate::solver::evaluate_call(arguments, resolve),
+        arguments if name == "limit" => crate::calculus::evaluate_call(arguments, resolve),
+        arguments if name == "minimize" || name == "maximize" => {
+          crate::optimize::evaluate_call(name, arguments, resolve)
+        }
+        [argument] => crate::evaluate_function(name, argument.evaluate_with(resolve)?, resolve),
+        arguments => {
+          let values = arguments
+            .iter()
+            .map(|argument| argument.evaluate_with(resolve))
+            .collect::<Result<Vec<f64>, CalculatorError>>()?;
+          crate::evaluate_call(name, &values, resolve)
+        }
+      },
+    }
+  }
+
+  /// Evaluates the tree to a [`Value`](crate::Value), which is a list for
+  /// a list literal such as `[1, 2, 3]`, a matrix for one such as
+  /// `[[1, 2], [3, 4]]` and a number otherwise
+  pub fn evaluate_value_with(&self, resolve: Resolver) -> Result<crate::Value, CalculatorError> {
+    match self {
+      Expr::Function { name, argument } if name == "list" => argument
+        .arguments()
+        .into_iter()
+        .map(|item| item.evaluate_value_with(resolve))
+        .collect::<Result<Vec<_>, CalculatorError>>()
+        .map(crate::matrices::from_items),
+      Expr::Function { name, argument } if crate::lists::takes_function(name) => {
+        crate::lists::apply_function(name, &argument.arguments(), resolve)
+      }
+      Expr::Function { name, argument } if crate::lists::returns_list(name) => {
+        crate::lists::apply(name, evaluate_values(&argument.arguments(), resolve)?)
+      }
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } if operator == "at" => crate::lists::item(
+        left.evaluate_value_with(resolve)?,
+        right.evaluate_with(resolve)?,
+      ),
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } if crate::lists::is_list(self) => {
+        let left = left.evaluate_value_with(resolve)?;
+        let right = right.evaluate_value_with(resolve)?;
+        if matches!(left, crate::Value::Matrix(_)) || matches!(right, crate::Value::Matrix(_)) {
+          crate::matrices::binary(left, operator, right)
+        } else {
+          crate::evaluate_binary_operation(left.into_number()?, operator, right.into_number()?)
+            .map(crate::Value::Number)
+        }
+      }
+      _ => self.evaluate_with(resolve).map(crate::Value::Number),
+    }
+  }
+
+  /// The arguments of a call with this tree as its argument: the operands of
+  /// a chain of commas, as in `clamp(x, 0, 1)`, or the tree itself
+  pub fn arguments(&self) -> Vec<&Expr> {
+    match self {
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } if operator == "," => {
+        let mut arguments = left.arguments();
+        arguments.push(right);
+        arguments
+      }
+      _ => vec![self],
+    }
+  }
+
+  /// Lists the variables the tree refers to, sorted and without duplicates
+  pub fn variables(&self) -> Vec<&str> {
+    let mut names = Vec::new();
+    self.collect_variables(&mut names);
+    names.sort_unstable();
+    names.dedup();
+    names
+  }
+
+  fn collect_variables<'a>(&'a self, names: &mut Vec<&'a str>) {
+    match self {
+      Expr::Number(_) => {}
+      Expr::Variable(name) => names.push(name),
+      Expr::Binary { left, right, .. } => {
+        left.collect_variables(names);
+        right.collect_variables(names);
+      }
+      Expr::Function { argument, .. } => argument.collect_variables(names),
+    }
+  }
+
+  /// Writes the tree in a normalized form that shows how it's grouped.
+  ///
+  /// Operators have one space on each side and functions are written as
+  /// calls. Unlike [`Display`](fmt::Display), which only adds the parentheses
+  /// the structure needs, this also adds them wherever the grouping rests on
+  /// precedence or associativity: between operators of different precedence,
+  /// `*` after `/`, nested `^`, and a negative base of `^`. Chains of `+` and
+  /// `-`, or of one repeated operator, read left to right without them.
+  ///
+  /// Constants appear as their values, as the parser resolves them.
+  pub fn to_canonical(&self) -> String {
+    let mut output = String::new();
+    self.write_canonical(&mut output);
+    output
+  }
+
+  fn write_canonical(&self, output: &mut String) {
+    match self {
+      Expr::Number(value) => output.push_str(&value.to_string()),
+      Expr::Variable(name) => output.push_str(name),
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } if operator == "at" => {
+        left.write_grouped(output, left.needs_grouping(operator, true));
+        output.push('[');
+        right.write_canonical(output);
+        output.push(']');
+      }
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } => {
+        left.write_grouped(output, left.needs_grouping(operator, true));
+        if operator != "," {
+          output.push(' ');
+        }
+        output.push_str(operator);
+        output.push(' ');
+        right.write_grouped(output, right.needs_grouping(operator, false));
+      }
+      Expr::Function { name, argument } if name == "neg" => {
+        output.push('-');
+        let simple = match argument.as_ref() {
+          Expr::Number(value) => *value >= 0.0,
+          Expr::Variable(_) => true,
+          Expr::Function { name, .. } => name != "neg",
+          Expr::Binary { .. } => false,
+        };
+        argument.write_grouped(output, !simple);
+      }
+      Expr::Function { name, argument } if name == "percent" => {
+        let simple = match argument.as_ref() {
+          Expr::Function { name, .. } => name != "neg",
+          other => !matches!(other, Expr::Binary { .. }),
+        };
+        argument.write_grouped(output, !simple);
+        output.push('%');
+      }
+      Expr::Function { name, argument } if name == "list" => {
+        output.push('[');
+        argument.write_canonical(output);
+        output.push(']');
+      }
+      Expr::Function { name, .. } if crate::parser::is_nullary_function(name) => {
+        output.push_str(name)
+      }
+      Expr::Function { name, argument } => {
+        output.push_str(name);
+        argument.write_grouped(output, true);
+      }
+    }
+  }
+
+  fn write_grouped(&self, output: &mut String, grouped: bool) {
+    if grouped {
+      output.push('(');
+      self.write_canonical(output);
+      output.push(')');
+    } else {
+      self.write_canonical(output);
+    }
+  }
+
+  /// Whether this operand of `parent` is put in parentheses by [`Expr::to_canonical`]
+  fn needs_grouping(&self, parent: &str, left: bool) -> bool {
+    match self {
+      // Function arguments need no grouping, only a list within a list
+      Expr::Binary { operator, .. } if parent == "," => operator == "," && !left,
+      Expr::Binary { operator, .. } => {
+        let (precedence, parent_precedence) = (
+          crate::get_precedence(operator),
+          crate::get_precedence(parent),
+        );
+        precedence != parent_precedence
+          || !left
+          || parent == "^"
+          || (operator != parent && parent_precedence != crate::get_precedence("+"))
+      }
+      // A negative base of ^ would otherwise read as the negation of a power
+      Expr::Number(value) => left && parent == "^" && *value < 0.0,
+      Expr::Function { name, .. } => left && parent == "^" && name == "neg",
+      Expr::Variable(_) => false,
+    }
+  }
+
+  /// Writes the tree in postfix order, the input format of [`crate::rpn::from_rpn`]
+  fn write_postfix(&self, output: &mut Vec<String>) {
+    match self {
+      Expr::Number(value) => output.push(value.to_string()),
+      Expr::Variable(name) => output.push(name.clone()),
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } => {
+        left.write_postfix(output);
+        right.write_postfix(output);
+        output.push(operator.clone());
+      }
+      Expr::Function { name, argument } => {
+        argument.write_postfix(output);
+        output.push(name.clone());
+      }
+    }
+  }
+
+  /// Serializes the tree to JSON
+  #[cfg(feature = "std")]
+  pub fn to_json(&self) -> Value {
+    match self {
+      Expr::Number(value) => json!({ "type": "number", "value": value }),
+      Expr::Variable(name) => json!({ "type": "variable", "name": name }),
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } => json!({
+        "type": "binary",
+        "operator": operator,
+        "left": left.to_json(),
+        "right": right.to_json(),
+      }),
+      Expr::Function { name, argument } => json!({
+        "type": "function",
+        "name": name,
+        "argument": argument.to_json(),
+      }),
+    }
+  }
+
+  /// Deserializes a tree produced by [`Expr::to_json`], validating operators and functions
+  #[cfg(feature = "std")]
+  pub fn from_json(json: &Value) -> Result<Expr, CalculatorError> {
+    let field = |name: &str| {
+      json.get(name).ok_or_else(|| {
+        CalculatorError::ParseError(format!("Expression node is missing '{}'", name))
+      })
+    };
+    let string_field = |name: &str| {
+      field(name)?.as_str().ok_or_else(|| {
+        CalculatorError::ParseError(format!("Expression field '{}' must be a string", name))
+      })
+    };
+
+    match string_field("type")? {
+      "number" => field("value")?.as_f64().map(Expr::Number).ok_or_else(|| {
+        CalculatorError::ParseError("Expression field 'value' must be a number".to_string())
+      }),
+      "variable" => {
+        let name = string_field("name")?;
+        check_variable(name)?;
+        Ok(Expr::Variable(name.to_string()))
+      }
+      "binary" => {
+        let operator = string_field("operator")?;
+        check_operator(operator)?;
+        Ok(Expr::Binary {
+          operator: operator.to_string(),
+          left: Box::new(Expr::from_json(field("left")?)?),
+          right: Box::new(Expr::from_json(field("right")?)?),
+        })
+      }
+      "function" => {
+        let name = string_field("name")?;
+        check_function(name)?;
+        Ok(Expr::Function {
+          name: name.to_string(),
+          argument: Box::new(Expr::from_json(field("argument")?)?),
+        })
+      }
+      other => Err(CalculatorError::ParseError(format!(
+        "Unknown expression node type: {}",
+        other
+      ))),
+    }
+  }
+}
+
+/// Checks the name of a deserialized variable node
+#[cfg(any(feature = "std", feature = "serde"))]
+pub(crate) fn check_variable(name: &str) -> Result<(), CalculatorError> {
+  if !crate::is_identifier(name) {
+    return Err(CalculatorError::ParseError(format!(
+      "Invalid variable name: {}",
+      name
+    )));
+  }
+  Ok(())
+}
+
+/// Checks the operator of a deserialized binary node
+#[cfg(any(feature = "std", feature = "serde"))]
+pub(crate) fn check_operator(operator: &str) -> Result<(), CalculatorError> {
+  if !crate::is_binary_operator(operator) {
+    return Err(CalculatorError::ParseError(format!(
+      "Unknown operator: {}",
+      operator
+    )));
+  }
+  Ok(())
+}
+
+/// Checks the name of a deserialized function node
+#[cfg(any(feature = "std", feature = "serde"))]
+pub(crate) fn check_function(name: &str) -> Result<(), CalculatorError> {
+  if !crate::is_math_function(name) && !crate::is_valid_conversion(name) {
+    return Err(CalculatorError::ParseError(format!(
+      "Unknown function: {}",
+      name
+    )));
+  }
+  Ok(())
+}
+
+impl fmt::Display for Expr {
+  /// Writes the tree as an infix expression with only the parentheses it needs
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut postfix = Vec::new();
+    self.write_postfix(&mut postfix);
+    let infix = crate::rpn::from_rpn(&postfix.join(" ")).map_err(|_| fmt::Error)?;
+    f.write_str(&infix)
+  }
+}
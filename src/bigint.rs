@@ -0,0 +1,467 @@
+//! # Big Integers
+//!
+//! Exact integer arithmetic for results beyond the 2^53 up to which an `f64`
+//! holds every integer, so `fact 25` can be shown as
+//! `15511210043330985984000000` rather than with its last digits lost.
+//!
+//! [`evaluate_integer`](crate::evaluate_integer) evaluates an expression
+//! with [`BigInt`]s when it only adds, subtracts, multiplies and raises
+//! integers to non-negative integer powers, and uses `fact`, `abs`, `nCr`,
+//! `nPr` and the [sequence](crate::sequences) functions. [`format_exact`](crate::format_exact) uses it to show large
+//! whole results with all their digits.
+
+use crate::ast::Expr;
+use crate::prelude::*;
+use crate::{CalculatorError, limits};
+use core::cmp::Ordering;
+use core::f64::consts::{E, LOG10_2, TAU};
+use core::fmt;
+
+/// Each limb holds nine decimal digits, which keeps formatting simple
+const BASE: u64 = 1_000_000_000;
+
+/// The largest integer below which every integer is an exact `f64`
+pub(crate) const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+
+/// Most digits an evaluated result may have, so that something like
+/// `fact 1e9` fails at once instead of running for hours
+const MAX_DIGITS: f64 = 10_000.0;
+
+/// log10 of the golden ratio
+const LOG10_PHI: f64 = 0.208_987_640_249_978_73;
+
+/// An integer of any size
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::BigInt;
+///
+/// let big = BigInt::factorial(25);
+/// assert_eq!(big.to_string(), "15511210043330985984000000");
+/// assert_eq!(BigInt::from(2).pow(100).to_string(), "1267650600228229401496703205376");
+/// assert_eq!(BigInt::binomial(60, 30).to_string(), "118264581564861424");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BigInt {
+  negative: bool,
+  /// Base 10^9 digits, least significant first, without leading zeros
+  limbs: Vec<u32>,
+}
+
+impl BigInt {
+  /// Converts a whole `f64` that is represented exactly
+  pub fn from_f64(value: f64) -> Option<BigInt> {
+    if value.fract() != 0.0 || value.abs() > MAX_EXACT {
+      return None;
+    }
+    let magnitude = BigInt::from(value.abs() as u64);
+    Some(if value < 0.0 { -magnitude } else { magnitude })
+  }
+
+  /// The nearest `f64`
+  pub fn to_f64(&self) -> f64 {
+    self.to_string().parse().unwrap_or(f64::NAN)
+  }
+
+  pub fn is_zero(&self) -> bool {
+    self.limbs.is_empty()
+  }
+
+  pub fn is_negative(&self) -> bool {
+    self.negative
+  }
+
+  pub fn abs(mut self) -> BigInt {
+    self.negative = false;
+    self
+  }
+
+  /// Raises to a power by repeated squaring
+  pub fn pow(&self, mut exponent: u32) -> BigInt {
+    let mut base = self.clone();
+    let mut result = BigInt::from(1);
+    while exponent > 0 {
+      if exponent & 1 == 1 {
+        result = &result * &base;
+      }
+      exponent >>= 1;
+      if exponent > 0 {
+        base = &base * &base;
+      }
+    }
+    result
+  }
+
+  /// n!
+  pub fn factorial(n: u64) -> BigInt {
+    let mut result = BigInt::from(1);
+    for i in 2..=n {
+      result.mul_small(i);
+    }
+    result
+  }
+
+  /// The number of ways to choose `k` of `n` items, nCr
+  pub fn binomial(n: u64, k: u64) -> BigInt {
+    if k > n {
+      return BigInt::default();
+    }
+    let k = k.min(n - k);
+    let mut result = BigInt::from(1);
+    for i in 1..=k {
+      // Each partial product is itself a binomial coefficient, so this divides exactly
+      result.mul_small(n - k + i);
+      result.div_small(i);
+    }
+    result
+  }
+
+  /// The number of ordered arrangements of `k` of `n` items, nPr
+  pub fn permutations(n: u64, k: u64) -> BigInt {
+    if k > n {
+      return BigInt::default();
+    }
+    let mut result = BigInt::from(1);
+    for i in n - k + 1..=n {
+      result.mul_small(i);
+    }
+    result
+  }
+
+  /// The `n`th Fibonacci number
+  pub fn fibonacci(n: u64) -> BigInt {
+    BigInt::linear(BigInt::default(), BigInt::from(1), n)
+  }
+
+  /// The `n`th Lucas number
+  pub fn lucas(n: u64) -> BigInt {
+    BigInt::linear(BigInt::from(2), BigInt::from(1), n)
+  }
+
+  /// The `n`th triangular number, `n (n + 1) / 2`
+  pub fn triangular(n: u64) -> BigInt {
+    let n = BigInt::from(n);
+    let mut result = &(&n * &n) + &n;
+    result.div_small(2);
+    result
+  }
+
+  /// The `n`th Catalan number, `2n nCr n / (n + 1)`
+  pub fn catalan(n: u64) -> BigInt {
+    let mut result = BigInt::binomial(2 * n, n);
+    result.div_small(n + 1);
+    result
+  }
+
+  /// The `n`th term of the sequence starting `first, second` in which each
+  /// term is the sum of the two before it
+  fn linear(first: BigInt, second: BigInt, n: u64) -> BigInt {
+    let (mut current, mut next) = (first, second);
+    for _ in 0..n {
+      let sum = &current + &next;
+      current = core::mem::replace(&mut next, sum);
+    }
+    current
+  }
+
+  /// About the base-10 logarithm of the magnitude, for sizing results
+  fn log10(&self) -> f64 {
+    match self.limbs.last() {
+      Some(top) => (self.limbs.len() - 1) as f64 * 9.0 + (*top as f64).log10(),
+      None => f64::NEG_INFINITY,
+    }
+  }
+
+  fn mul_small(&mut self, factor: u64) {
+    // A limb times any u64 fits in a u128
+    let mut carry: u128 = 0;
+    for limb in self.limbs.iter_mut() {
+      let product = *limb as u128 * factor as u128 + carry;
+      *limb = (product % BASE as u128) as u32;
+      carry = product / BASE as u128;
+    }
+    while carry > 0 {
+      self.limbs.push((carry % BASE as u128) as u32);
+      carry /= BASE as u128;
+    }
+    self.trim();
+  }
+
+  fn div_small(&mut self, divisor: u64) {
+    let mut remainder: u128 = 0;
+    for limb in self.limbs.iter_mut().rev() {
+      let current = remainder * BASE as u128 + *limb as u128;
+      *limb = (current / divisor as u128) as u32;
+      remainder = current % divisor as u128;
+    }
+    self.trim();
+  }
+
+  /// Drops leading zero limbs, and the sign of zero
+  fn trim(&mut self) {
+    while self.limbs.last() == Some(&0) {
+      self.limbs.pop();
+    }
+    if self.limbs.is_empty() {
+      self.negative = false;
+    }
+  }
+
+  fn compare_magnitude(&self, other: &BigInt) -> Ordering {
+    self
+      .limbs
+      .len()
+      .cmp(&other.limbs.len())
+      .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+  }
+
+  fn add_magnitude(&self, other: &BigInt) -> Vec<u32> {
+    let mut limbs = Vec::with_capacity(self.limbs.len().max(other.limbs.len()) + 1);
+    let mut carry = 0;
+    for i in 0..self.limbs.len().max(other.limbs.len()) {
+      let sum =
+        *self.limbs.get(i).unwrap_or(&0) as u64 + *other.limbs.get(i).unwrap_or(&0) as u64 + carry;
+      limbs.push((sum % BASE) as u32);
+      carry = sum / BASE;
+    }
+    if carry > 0 {
+      limbs.push(carry as u32);
+    }
+    limbs
+  }
+
+  /// The magnitude of `self` less that of `other`, which mustn't be larger
+  fn sub_magnitude(&self, other: &BigInt) -> Vec<u32> {
+    let mut limbs = Vec::with_capacity(self.limbs.len());
+    let mut borrow = 0;
+    for (i, limb) in self.limbs.iter().enumerate() {
+      let subtrahend = *other.limbs.get(i).unwrap_or(&0) as i64 + borrow;
+      let mut difference = *limb as i64 - subtrahend;
+      borrow = 0;
+      if difference < 0 {
+        difference += BASE as i64;
+        borrow = 1;
+      }
+      limbs.push(difference as u32);
+    }
+    limbs
+  }
+
+  fn signed(negative: bool, limbs: Vec<u32>) -> BigInt {
+    let mut result = BigInt { negative, limbs };
+    result.trim();
+    result
+  }
+}
+
+impl From<u64> for BigInt {
+  fn from(mut value: u64) -> Self {
+    let mut limbs = Vec::new();
+    while value > 0 {
+      limbs.push((value % BASE) as u32);
+      value /= BASE;
+    }
+    BigInt {
+      negative: false,
+      limbs,
+    }
+  }
+}
+
+impl From<i64> for BigInt {
+  fn from(value: i64) -> Self {
+    let magnitude = BigInt::from(value.unsigned_abs());
+    if value < 0 { -magnitude } else { magnitude }
+  }
+}
+
+impl From<i32> for BigInt {
+  fn from(value: i32) -> Self {
+    BigInt::from(value as i64)
+  }
+}
+
+impl core::ops::Neg for BigInt {
+  type Output = BigInt;
+
+  fn neg(mut self) -> BigInt {
+    self.negative = !self.negative;
+    self.trim();
+    self
+  }
+}
+
+impl core::ops::Add for &BigInt {
+  type Output = BigInt;
+
+  fn add(self, other: &BigInt) -> BigInt {
+    if self.negative == other.negative {
+      return BigInt::signed(self.negative, self.add_magnitude(other));
+    }
+    // Opposite signs: the larger magnitude decides the sign
+    match self.compare_magnitude(other) {
+      Ordering::Less => BigInt::signed(other.negative, other.sub_magnitude(self)),
+      _ => BigInt::signed(self.negative, self.sub_magnitude(other)),
+    }
+  }
+}
+
+impl core::ops::Sub for &BigInt {
+  type Output = BigInt;
+
+  fn sub(self, other: &BigInt) -> BigInt {
+    self + &-other.clone()
+  }
+}
+
+impl core::ops::Mul for &BigInt {
+  type Output = BigInt;
+
+  fn mul(self, other: &BigInt) -> BigInt {
+    let mut limbs = vec![0u64; self.limbs.len() + other.limbs.len()];
+    for (i, a) in self.limbs.iter().enumerate() {
+      let mut carry = 0;
+      for (j, b) in other.limbs.iter().enumerate() {
+        let current = limbs[i + j] + *a as u64 * *b as u64 + carry;
+        limbs[i + j] = current % BASE;
+        carry = current / BASE;
+      }
+      limbs[i + other.limbs.len()] += carry;
+    }
+    BigInt::signed(
+      self.negative != other.negative,
+      limbs.into_iter().map(|limb| limb as u32).collect(),
+    )
+  }
+}
+
+impl fmt::Display for BigInt {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let Some((most, rest)) = self.limbs.split_last() else {
+      return f.write_str("0");
+    };
+    if self.negative {
+      f.write_str("-")?;
+    }
+    write!(f, "{}", most)?;
+    for limb in rest.iter().rev() {
+      write!(f, "{:09}", limb)?;
+    }
+    Ok(())
+  }
+}
+
+/// Evaluates a tree exactly, failing for anything that may not give an integer
+pub(crate) fn evaluate(tree: &Expr) -> Result<BigInt, CalculatorError> {
+  match tree {
+    Expr::Number(value) => BigInt::from_f64(*value).ok_or_else(|| not_integer(value)),
+    Expr::Variable(name) => Err(crate::unknown_token(name)),
+    Expr::Binary {
+      operator,
+      left,
+      right,
+    } => {
+      let (left, right) = (evaluate(left)?, evaluate(right)?);
+      match operator.as_str() {
+        "+" => Ok(&left + &right),
+        "-" => Ok(&left - &right),
+        "*" => Ok(&left * &right),
+        "^" => {
+          let exponent = small(&right)?;
+          limits::check_exponent(exponent as f64)?;
+          check_digits(exponent as f64 * left.log10())?;
+          Ok(left.pow(u32::try_from(exponent).map_err(|_| too_large(&right))?))
+        }
+        "ncr" => {
+          let (n, k) = (small(&left)?, small(&right)?);
+          // nCr is at most both 2^n and n^k
+          check_digits((n as f64 * LOG10_2).min(k as f64 * (n as f64).log10()))?;
+          Ok(BigInt::binomial(n, k))
+        }
+        "npr" => {
+          let (n, k) = (small(&left)?, small(&right)?);
+          check_digits(k as f64 * (n as f64).log10())?;
+          Ok(BigInt::permutations(n, k))
+        }
+        _ => Err(not_exact(operator)),
+      }
+    }
+    Expr::Function { name, argument } => {
+      let argument = evaluate(argument)?;
+      match name.as_str() {
+        "neg" => Ok(-argument),
+        "abs" => Ok(argument.abs()),
+        "fact" => {
+          let n = small(&argument)?;
+          limits::check_factorial(n as f64)?;
+          // Stirling's approximation of log10(n!)
+          let x = n as f64;
+          check_digits(x * (x / E).log10() + 0.5 * (TAU * x).log10())?;
+          Ok(BigInt::factorial(n))
+        }
+        "fib" | "lucas" => {
+          let n = small(&argument)?;
+          // Both grow by a factor of the golden ratio each term
+          check_digits(n as f64 * LOG10_PHI)?;
+          Ok(match name.as_str() {
+            "fib" => BigInt::fibonacci(n),
+            _ => BigInt::lucas(n),
+          })
+        }
+        "triangular" => Ok(BigInt::triangular(small(&argument)?)),
+        "catalan" => {
+          let n = small(&argument)?;
+          // The terms grow by a factor of nearly 4 each
+          check_digits(n as f64 * 2.0 * LOG10_2)?;
+          Ok(BigInt::catalan(n))
+        }
+        _ => Err(not_exact(name)),
+      }
+    }
+  }
+}
+
+/// Converts a non-negative integer small enough to count with
+fn small(value: &BigInt) -> Result<u64, CalculatorError> {
+  if value.negative {
+    return Err(CalculatorError::ArgumentError(format!(
+      "Expected a non-negative integer, got {}",
+      value
+    )));
+  }
+  value
+    .limbs
+    .iter()
+    .rev()
+    .try_fold(0u64, |total, limb| {
+      total.checked_mul(BASE)?.checked_add(*limb as u64)
+    })
+    .ok_or_else(|| too_large(value))
+}
+
+/// Fails if a result of about `digits` digits would be too long to work out
+fn check_digits(digits: f64) -> Result<(), CalculatorError> {
+  if digits > MAX_DIGITS {
+    return Err(CalculatorError::Overflow(format!(
+      "The exact result would have more than {} digits",
+      MAX_DIGITS
+    )));
+  }
+  Ok(())
+}
+
+fn not_integer(value: &f64) -> CalculatorError {
+  CalculatorError::ArgumentError(format!("Not an exact integer: {}", value))
+}
+
+fn not_exact(name: &str) -> CalculatorError {
+  CalculatorError::ArgumentError(format!(
+    "{} isn't supported in exact integer arithmetic",
+    name
+  ))
+}
+
+fn too_large(value: &BigInt) -> CalculatorError {
+  CalculatorError::ArgumentError(format!("{} is too large", value))
+}
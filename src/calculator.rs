@@ -0,0 +1,292 @@
+//! # Calculator Sessions
+//!
+//! A [`Calculator`] owns its memory, variables, history, last result and
+//! settings, so several independent sessions can run in one process. The
+//! free functions such as [`evaluate_expression`](crate::evaluate_expression)
+//! keep using the global state shared by the CLI and GUI.
+//!
+//! A session's evaluation state can be saved as a [`Snapshot`] in constant
+//! time, to roll back to later or to branch new sessions from, e.g. for
+//! "what-if" calculations on other threads.
+
+use crate::CalculatorError;
+use crate::memory::{Memory, MemoryChange};
+use crate::settings::{self, Settings};
+use std::collections::HashMap;
+#[cfg(feature = "persistence")]
+use std::fs::OpenOptions;
+#[cfg(feature = "persistence")]
+use std::io::Write;
+#[cfg(feature = "persistence")]
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// An independent calculator session
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::Calculator;
+///
+/// let mut calc = Calculator::new();
+/// calc.set_variable("x", 4.0).unwrap();
+/// assert_eq!(calc.evaluate("x * 2").unwrap(), 8.0);
+/// calc.evaluate("m+(x)").unwrap();
+/// assert_eq!(calc.memory(), 4.0);
+/// assert_eq!(calc.evaluate("ans + 1").unwrap(), 5.0);
+///
+/// // Other sessions don't see the variable, the memory or the last result
+/// let mut other = Calculator::new();
+/// assert!(other.evaluate("x").is_err());
+/// assert_eq!(other.memory(), 0.0);
+/// assert!(other.evaluate("ans").is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Calculator {
+  memory: Memory,
+  answer: Option<f64>,
+  // Shared with snapshots until either side changes them
+  variables: Arc<HashMap<String, f64>>,
+  history: Vec<(String, f64)>,
+  #[cfg(feature = "persistence")]
+  history_file: Option<PathBuf>,
+  settings: Settings,
+}
+
+impl Calculator {
+  /// Creates a session with empty memory, no variables and default settings
+  pub fn new() -> Self {
+    Calculator::default()
+  }
+
+  /// Creates a session that uses `settings` instead of the defaults
+  pub fn with_settings(settings: Settings) -> Self {
+    Calculator {
+      settings,
+      ..Calculator::default()
+    }
+  }
+
+  /// Also appends each successful evaluation to the file at `path`, in the
+  /// same `expression = result` format as the global history
+  #[cfg(feature = "persistence")]
+  pub fn with_history_file(mut self, path: impl AsRef<Path>) -> Self {
+    self.history_file = Some(path.as_ref().to_path_buf());
+    self
+  }
+
+  /// Evaluates an expression with this session's memory, variables and
+  /// settings, recording successful results in its history and as its `ans`.
+  /// An assignment such as `x = 3 * pi` sets this session's variable.
+  ///
+  /// Fails if the result can't be appended to the history file.
+  pub fn evaluate(&mut self, expression: &str) -> Result<f64, CalculatorError> {
+    let value = self.value_of(expression)?;
+    self.answer = Some(value);
+    self.record(expression, value)?;
+    Ok(value)
+  }
+
+  /// Evaluates an expression, storing the value of an assignment such as
+  /// `x = 3 * pi` in this session's variable
+  fn value_of(&mut self, expression: &str) -> Result<f64, CalculatorError> {
+    if let Some((name, start)) = crate::assignment(expression)? {
+      let value = self
+        .value_of(&expression[start..])
+        .map_err(|err| err.offset(start))?;
+      self.set_variable(name, value)?;
+      return Ok(value);
+    }
+    let variables = &self.variables;
+    let resolve = |name: &str| variables.get(&name.to_lowercase()).copied();
+    let memory = std::mem::take(&mut self.memory);
+    let (result, memory) = crate::with_session(memory, self.answer, || {
+      settings::scoped(self.settings, || match crate::continue_answer(expression) {
+        Some(continued) => {
+          crate::evaluate_with_resolver(&continued, &resolve).map_err(CalculatorError::without_span)
+        }
+        None => crate::evaluate_with_resolver(expression, &resolve),
+      })
+    });
+    self.memory = memory;
+    result
+  }
+
+  /// Adds an entry to the history and the history file, if any
+  fn record(&mut self, expression: &str, value: f64) -> Result<(), CalculatorError> {
+    self.history.push((expression.to_string(), value));
+    #[cfg(feature = "persistence")]
+    if let Some(path) = &self.history_file {
+      OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{} = {}", expression, value))
+        .map_err(|err| CalculatorError::Other(format!("Error saving to history: {}", err)))?;
+    }
+    Ok(())
+  }
+
+  /// Sets a variable in this session.
+  ///
+  /// Fails if the name isn't an identifier, or with
+  /// [`CalculatorError::ReservedName`] if it's that of a constant or a
+  /// function, as [`set_variable`](crate::set_variable) does.
+  pub fn set_variable(&mut self, name: &str, value: f64) -> Result<(), CalculatorError> {
+    crate::variable_name(name)?;
+    Arc::make_mut(&mut self.variables).insert(name.to_lowercase(), value);
+    Ok(())
+  }
+
+  /// Gets a variable's value
+  pub fn get_variable(&self, name: &str) -> Option<f64> {
+    self.variables.get(&name.to_lowercase()).copied()
+  }
+
+  /// Removes a variable, returning its value if it was defined
+  pub fn remove_variable(&mut self, name: &str) -> Option<f64> {
+    Arc::make_mut(&mut self.variables).remove(&name.to_lowercase())
+  }
+
+  /// Lists all variables, sorted by name
+  pub fn variables(&self) -> Vec<(String, f64)> {
+    sorted(&self.variables)
+  }
+
+  /// Returns the value in memory
+  pub fn memory(&self) -> f64 {
+    self.memory.value()
+  }
+
+  /// Returns the changes made to the memory, oldest first, which `mundo`
+  /// reverts from the newest
+  pub fn memory_changes(&self) -> &[MemoryChange] {
+    self.memory.changes()
+  }
+
+  /// Resets the memory to zero, as `mc` does
+  pub fn clear_memory(&mut self) {
+    self.memory.clear();
+  }
+
+  /// Returns the last result, which `ans` stands for, if there is one
+  pub fn answer(&self) -> Option<f64> {
+    self.answer
+  }
+
+  /// Forgets the last result, so `ans` is an error until there's another
+  pub fn reset_answer(&mut self) {
+    self.answer = None;
+  }
+
+  /// Returns the evaluated expressions and their results, oldest first
+  pub fn history(&self) -> &[(String, f64)] {
+    &self.history
+  }
+
+  /// Forgets the history kept in this session; the history file is unchanged
+  pub fn clear_history(&mut self) {
+    self.history.clear();
+  }
+
+  /// Returns this session's settings
+  pub fn settings(&self) -> &Settings {
+    &self.settings
+  }
+
+  /// Returns this session's settings for changing
+  pub fn settings_mut(&mut self) -> &mut Settings {
+    &mut self.settings
+  }
+
+  /// Saves the memory, variables and settings, without copying the variables
+  pub fn snapshot(&self) -> Snapshot {
+    Snapshot {
+      memory: self.memory.clone(),
+      variables: Arc::clone(&self.variables),
+      settings: self.settings,
+    }
+  }
+
+  /// Returns the memory, variables and settings to those of `snapshot`; the
+  /// history is kept
+  pub fn restore(&mut self, snapshot: &Snapshot) {
+    self.memory = snapshot.memory.clone();
+    self.variables = Arc::clone(&snapshot.variables);
+    self.settings = snapshot.settings;
+  }
+
+  /// Creates a new session with the state of `snapshot` and an empty history
+  pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+    let mut calculator = Calculator::new();
+    calculator.restore(snapshot);
+    calculator
+  }
+}
+
+/// The memory, variables and settings of a [`Calculator`] at one point.
+/// Snapshots are cheap to take and to clone, and can be sent to other threads.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::Calculator;
+///
+/// let mut calc = Calculator::new();
+/// calc.set_variable("rate", 0.05).unwrap();
+/// let saved = calc.snapshot();
+///
+/// // Try a different rate, then roll back
+/// calc.set_variable("rate", 0.07).unwrap();
+/// assert_eq!(calc.evaluate("1000 * rate").unwrap(), 70.0);
+/// calc.restore(&saved);
+/// assert_eq!(calc.evaluate("1000 * rate").unwrap(), 50.0);
+///
+/// // Or branch a session on another thread
+/// let branch = saved.clone();
+/// let result = std::thread::spawn(move || {
+///   let mut what_if = Calculator::from_snapshot(&branch);
+///   what_if.set_variable("rate", 0.1).unwrap();
+///   what_if.evaluate("1000 * rate")
+/// });
+/// assert_eq!(result.join().unwrap().unwrap(), 100.0);
+/// assert_eq!(saved.get_variable("rate"), Some(0.05));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+  memory: Memory,
+  variables: Arc<HashMap<String, f64>>,
+  settings: Settings,
+}
+
+impl Snapshot {
+  /// Returns the value that was in memory
+  pub fn memory(&self) -> f64 {
+    self.memory.value()
+  }
+
+  /// Gets a variable's value
+  pub fn get_variable(&self, name: &str) -> Option<f64> {
+    self.variables.get(&name.to_lowercase()).copied()
+  }
+
+  /// Lists all variables, sorted by name
+  pub fn variables(&self) -> Vec<(String, f64)> {
+    sorted(&self.variables)
+  }
+
+  /// Returns the settings
+  pub fn settings(&self) -> &Settings {
+    &self.settings
+  }
+}
+
+/// Lists variables sorted by name
+fn sorted(variables: &HashMap<String, f64>) -> Vec<(String, f64)> {
+  let mut variables: Vec<(String, f64)> = variables
+    .iter()
+    .map(|(name, value)| (name.clone(), *value))
+    .collect();
+  variables.sort_by(|a, b| a.0.cmp(&b.0));
+  variables
+}
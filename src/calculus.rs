@@ -0,0 +1,234 @@
+//! # Limits
+//!
+//! `limit(expr, x, a)` estimates the limit of `expr` as `x` approaches `a`,
+//! by evaluating it ever closer to `a` from both sides. `limit(expr, x, a,
+//! 1)` approaches from above only and `limit(expr, x, a, -1)` from below,
+//! and `a` may be `inf` or `-inf`. Like the variable of
+//! [`sum`](crate::series), `x` hides any stored variable of that name.
+//!
+//! The limit is where the samples are heading once they settle, found by
+//! Aitken's extrapolation and rounded to [`SIGNIFICANT_DIGITS`] significant
+//! digits, as they rarely agree on more.
+//! When the samples grow without bound, or the two sides settle on
+//! different values, or they never settle, the error says so. A side where
+//! the expression can't be evaluated, as below 0 for `sqrt(x)`, is left
+//! out.
+//!
+//! ```
+//! use rust_calculator::evaluate_expression;
+//!
+//! assert_eq!(evaluate_expression("limit((exp(x) - 1) / x, x, 0)").unwrap(), 1.0);
+//! assert_eq!(evaluate_expression("limit((x^2 - 1) / (x - 1), x, 1)").unwrap(), 2.0);
+//! assert_eq!(evaluate_expression("limit((1 + 1/n)^n, n, inf)").unwrap(), 2.718281828);
+//! assert!(evaluate_expression("limit(1 / x, x, 0)").is_err());
+//! ```
+
+use crate::ast::Expr;
+use crate::prelude::*;
+use crate::{CalculatorError, Resolver, limits};
+
+/// How many significant digits a limit is given to
+pub const SIGNIFICANT_DIGITS: i32 = 10;
+
+/// How many samples are taken on each side, each twice as close as the last
+const SAMPLES: i32 = 50;
+
+/// How one side of a limit behaves
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Approach {
+  /// The samples settle on a value
+  Converges(f64),
+  /// The samples grow without bound, with this sign
+  Diverges(f64),
+  /// The samples never settle
+  Oscillates,
+  /// The expression can't be evaluated on this side
+  Undefined,
+}
+
+/// Evaluates a call of `limit` with `arguments` already split at their
+/// commas
+pub(crate) fn evaluate_call(
+  arguments: &[&Expr],
+  resolve: Resolver,
+) -> Result<f64, CalculatorError> {
+  crate::check_arity("limit", arguments.len())?;
+  let (expr, variable, point) = (arguments[0], arguments[1], arguments[2]);
+  let Expr::Variable(variable) = variable else {
+    return Err(CalculatorError::ArgumentError(
+      "limit needs a variable name as its second argument".to_string(),
+    ));
+  };
+  let point = point.evaluate_with(resolve)?;
+  let side = match arguments.get(3) {
+    Some(side) => side.evaluate_with(resolve)?,
+    None => 0.0,
+  };
+  if point.is_nan() || ![-1.0, 0.0, 1.0].contains(&side) {
+    return Err(CalculatorError::ArgumentError(
+      "limit needs a point and a side of 1 for above, -1 for below or none for both".to_string(),
+    ));
+  }
+
+  let f = |x: f64| {
+    let binding = |name: &str| {
+      if name == variable {
+        Some(x)
+      } else {
+        resolve(name)
+      }
+    };
+    expr.evaluate_with(&binding)
+  };
+  // Infinity is only approached from one side
+  let sides: &[f64] = if point.is_infinite() {
+    &[-point.signum()]
+  } else if side == 0.0 {
+    &[-1.0, 1.0]
+  } else {
+    &[side]
+  };
+  let approaches = sides
+    .iter()
+    .map(|&side| approach(&f, point, side))
+    .collect::<Result<Vec<_>, CalculatorError>>()?;
+  let defined: Vec<Approach> = approaches
+    .into_iter()
+    .filter(|approach| *approach != Approach::Undefined)
+    .collect();
+
+  let arrow = format!("{} → {}", variable, crate::format_value(point));
+  match defined.as_slice() {
+    [] => f(point),
+    [Approach::Converges(value)] => Ok(*value),
+    [Approach::Converges(below), Approach::Converges(above)] => {
+      if below == above {
+        Ok(*below)
+      } else {
+        Err(CalculatorError::MathError(format!(
+          "No limit as {}: it's {} from below and {} from above",
+          arrow,
+          crate::format_value(*below),
+          crate::format_value(*above)
+        )))
+      }
+    }
+    [Approach::Diverges(sign)] => Err(diverges(*sign, &arrow)),
+    [Approach::Diverges(below), Approach::Diverges(above)] if below == above => {
+      Err(diverges(*below, &arrow))
+    }
+    [Approach::Diverges(below), Approach::Diverges(_)] => {
+      let (below, above) = if *below < 0.0 {
+        ("-∞", "∞")
+      } else {
+        ("∞", "-∞")
+      };
+      Err(CalculatorError::MathError(format!(
+        "No limit as {}: the values grow towards {} from below and {} from above",
+        arrow, below, above
+      )))
+    }
+    [_] => Err(CalculatorError::MathError(format!(
+      "No limit as {}: the values never settle",
+      arrow
+    ))),
+    _ => Err(CalculatorError::MathError(format!(
+      "No limit as {}: the two sides differ",
+      arrow
+    ))),
+  }
+}
+
+/// Samples `f` ever closer to `point` from one side, `direction` being 1
+/// for above and -1 for below, and sees how it behaves
+fn approach(
+  f: &dyn Fn(f64) -> Result<f64, CalculatorError>,
+  point: f64,
+  direction: f64,
+) -> Result<Approach, CalculatorError> {
+  let scale = if point.is_finite() {
+    point.abs().max(1.0) / 8.0
+  } else {
+    1.0
+  };
+  let mut values = Vec::new();
+  for step in 1..=SAMPLES {
+    limits::check_time()?;
+    let distance = scale * 2f64.powi(-step);
+    let x = if point.is_finite() {
+      point + direction * distance
+    } else {
+      -direction / distance
+    };
+    if x == point {
+      break;
+    }
+    // Points where the expression can't be evaluated are passed over
+    if let Ok(value) = f(x) {
+      if value.is_finite() {
+        values.push(value);
+      }
+    }
+  }
+  if values.len() < SAMPLES as usize / 2 {
+    return Ok(Approach::Undefined);
+  }
+
+  // Where three samples in a row change least is where they've settled,
+  // before rounding takes over
+  let change = |triple: &[f64]| {
+    (triple[1] - triple[0])
+      .abs()
+      .max((triple[2] - triple[1]).abs())
+  };
+  let settled = values
+    .windows(3)
+    .min_by(|a, b| change(a).total_cmp(&change(b)))
+    .expect("there are at least three samples");
+  if change(settled) <= 1e-7 * settled[2].abs().max(1.0) {
+    let limit = aitken(settled);
+    // What's left of the limit within the changes of the samples is rounding
+    let limit = if limit.abs() <= change(settled) {
+      0.0
+    } else {
+      round_significant(limit)
+    };
+    return Ok(Approach::Converges(limit));
+  }
+
+  let tail = &values[values.len() - 3..];
+  let growing = tail
+    .windows(2)
+    .all(|pair| pair[1].abs() > pair[0].abs() && pair[1].signum() == pair[0].signum());
+  if growing && tail[2].abs() > 1e12 {
+    return Ok(Approach::Diverges(tail[2].signum()));
+  }
+  Ok(Approach::Oscillates)
+}
+
+fn diverges(sign: f64, arrow: &str) -> CalculatorError {
+  let infinity = if sign < 0.0 { "-∞" } else { "∞" };
+  CalculatorError::MathError(format!(
+    "No limit as {}: the values grow towards {}",
+    arrow, infinity
+  ))
+}
+
+/// Extrapolates three samples whose differences shrink by a constant
+/// factor to where they're heading, by Aitken's delta-squared process
+fn aitken(samples: &[f64]) -> f64 {
+  let (a, b, c) = (samples[0], samples[1], samples[2]);
+  let denominator = c - 2.0 * b + a;
+  let limit = c - (c - b) * (c - b) / denominator;
+  if limit.is_finite() { limit } else { c }
+}
+
+/// Rounds a value to [`SIGNIFICANT_DIGITS`] significant digits
+fn round_significant(value: f64) -> f64 {
+  if value == 0.0 {
+    return 0.0;
+  }
+  let exponent = SIGNIFICANT_DIGITS - 1 - value.abs().log10().floor() as i32;
+  let factor = 10f64.powi(exponent);
+  (value * factor).round() / factor
+}
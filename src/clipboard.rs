@@ -0,0 +1,45 @@
+//! # Clipboard Integration
+//!
+//! A single clipboard implementation shared by the CLI and the GUI, so results
+//! are copied with the same formatting everywhere. Enabled with the `clipboard`
+//! cargo feature.
+
+use crate::CalculatorError;
+
+/// Copies a result to the system clipboard and returns the copied text
+pub fn copy_result(value: f64) -> Result<String, CalculatorError> {
+  let text = crate::format_value(value);
+  arboard::Clipboard::new()
+    .and_then(|mut clipboard| clipboard.set_text(text.clone()))
+    .map_err(clipboard_error)?;
+  Ok(text)
+}
+
+/// Reads an expression from the system clipboard, ready to be evaluated
+pub fn paste_expression() -> Result<String, CalculatorError> {
+  let text = arboard::Clipboard::new()
+    .and_then(|mut clipboard| clipboard.get_text())
+    .map_err(clipboard_error)?;
+
+  let expression = normalize_expression(&text);
+  if expression.is_empty() {
+    return Err(CalculatorError::Other("Clipboard is empty".to_string()));
+  }
+  Ok(expression)
+}
+
+/// Cleans up pasted text: joins lines and replaces typographic operators
+/// (`×`, `÷`, `−`) with the ones the calculator understands
+pub fn normalize_expression(text: &str) -> String {
+  text
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+    .replace(['×', '·'], "*")
+    .replace('÷', "/")
+    .replace(['−', '–'], "-")
+}
+
+fn clipboard_error(err: arboard::Error) -> CalculatorError {
+  CalculatorError::Other(format!("Clipboard error: {}", err))
+}
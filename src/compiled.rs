@@ -0,0 +1,318 @@
+//! # Compiled Expressions
+//!
+//! A [`CompiledExpression`] is parsed once into a flat postfix program, so the
+//! same formula can be evaluated over many variable bindings without running
+//! the tokenizer and shunting-yard again each time.
+//!
+//! With the `std` feature, [`CompiledExpression::cached`] keeps the programs
+//! of recently seen expressions, keyed by their text, which is how
+//! [`evaluate_cached`](crate::evaluate_cached) skips the tokenizer for an
+//! expression it has evaluated before.
+
+use crate::ast::{self, Expr};
+use crate::prelude::*;
+use crate::{CalculatorError, Resolver};
+use core::fmt;
+
+/// One step of a compiled program, run against a stack of values
+#[derive(Debug, Clone, PartialEq)]
+enum Instruction {
+  Push(f64),
+  /// Pushes the value bound to the variable at this index
+  Load(usize),
+  Binary(Operator),
+  Function(String),
+  /// Calls a function with this many arguments from the top of the stack
+  Call(String, usize),
+}
+
+/// The binary operators, so running a program doesn't compare strings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+  Add,
+  Subtract,
+  Multiply,
+  Divide,
+  Remainder,
+  Power,
+  Choose,
+  Permute,
+  BitAnd,
+  BitOr,
+  BitXor,
+  ShiftLeft,
+  ShiftRight,
+  Equal,
+  NotEqual,
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+  And,
+  Or,
+}
+
+impl Operator {
+  fn from_symbol(symbol: &str) -> Option<Operator> {
+    match symbol {
+      "+" => Some(Operator::Add),
+      "-" => Some(Operator::Subtract),
+      "*" => Some(Operator::Multiply),
+      "/" => Some(Operator::Divide),
+      "%" => Some(Operator::Remainder),
+      "^" => Some(Operator::Power),
+      "ncr" => Some(Operator::Choose),
+      "npr" => Some(Operator::Permute),
+      "&" => Some(Operator::BitAnd),
+      "|" => Some(Operator::BitOr),
+      "xor" => Some(Operator::BitXor),
+      "<<" => Some(Operator::ShiftLeft),
+      ">>" => Some(Operator::ShiftRight),
+      "==" => Some(Operator::Equal),
+      "!=" => Some(Operator::NotEqual),
+      "<" => Some(Operator::Less),
+      "<=" => Some(Operator::LessOrEqual),
+      ">" => Some(Operator::Greater),
+      ">=" => Some(Operator::GreaterOrEqual),
+      "and" => Some(Operator::And),
+      "or" => Some(Operator::Or),
+      _ => None,
+    }
+  }
+
+  fn symbol(self) -> &'static str {
+    match self {
+      Operator::Add => "+",
+      Operator::Subtract => "-",
+      Operator::Multiply => "*",
+      Operator::Divide => "/",
+      Operator::Remainder => "%",
+      Operator::Power => "^",
+      Operator::Choose => "ncr",
+      Operator::Permute => "npr",
+      Operator::BitAnd => "&",
+      Operator::BitOr => "|",
+      Operator::BitXor => "xor",
+      Operator::ShiftLeft => "<<",
+      Operator::ShiftRight => ">>",
+      Operator::Equal => "==",
+      Operator::NotEqual => "!=",
+      Operator::Less => "<",
+      Operator::LessOrEqual => "<=",
+      Operator::Greater => ">",
+      Operator::GreaterOrEqual => ">=",
+      Operator::And => "and",
+      Operator::Or => "or",
+    }
+  }
+}
+
+/// Most programs [`CompiledExpression::cached`] keeps; the cache is emptied
+/// when it fills up
+#[cfg(feature = "std")]
+const CACHE_CAPACITY: usize = 256;
+
+#[cfg(feature = "std")]
+lazy_static::lazy_static! {
+    static ref CACHE: std::sync::Mutex<std::collections::HashMap<String, std::sync::Arc<CompiledExpression>>> =
+      std::sync::Mutex::new(std::collections::HashMap::new());
+}
+
+/// An expression parsed once for repeated evaluation
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::CompiledExpression;
+///
+/// let formula = CompiledExpression::new("x^2 + y").unwrap();
+/// assert_eq!(formula.variables(), ["x", "y"]);
+/// assert_eq!(formula.evaluate(&[3.0, 1.0]).unwrap(), 10.0);
+/// assert_eq!(formula.evaluate(&[4.0, 0.5]).unwrap(), 16.5);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledExpression {
+  source: String,
+  program: Vec<Instruction>,
+  variables: Vec<String>,
+  stack_size: usize,
+}
+
+impl CompiledExpression {
+  /// Parses and compiles an expression; its variables are bound when it's evaluated
+  pub fn new(expression: &str) -> Result<Self, CalculatorError> {
+    let tree = ast::parse(expression)?;
+    let variables: Vec<String> = tree.variables().into_iter().map(String::from).collect();
+    // Simplifying may drop a variable, as from x - x, which still needs a value
+    let mut program = Vec::new();
+    let stack_size = compile(&tree.simplify(), &variables, &mut program)?;
+    Ok(CompiledExpression {
+      source: expression.to_string(),
+      program,
+      variables,
+      stack_size,
+    })
+  }
+
+  #[cfg(feature = "std")]
+  /// Compiles an expression, or returns the program compiled by an earlier
+  /// call with the same text
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rust_calculator::CompiledExpression;
+  /// use std::sync::Arc;
+  ///
+  /// let first = CompiledExpression::cached("2 * r * pi").unwrap();
+  /// let second = CompiledExpression::cached("2 * r * pi").unwrap();
+  /// assert!(Arc::ptr_eq(&first, &second));
+  /// ```
+  pub fn cached(expression: &str) -> Result<std::sync::Arc<Self>, CalculatorError> {
+    if let Some(compiled) = CACHE.lock().unwrap().get(expression) {
+      return Ok(compiled.clone());
+    }
+
+    // Compile without holding the lock, so other threads aren't kept waiting
+    let compiled = std::sync::Arc::new(CompiledExpression::new(expression)?);
+    let mut cache = CACHE.lock().unwrap();
+    if cache.len() >= CACHE_CAPACITY {
+      cache.clear();
+    }
+    cache.insert(expression.to_string(), compiled.clone());
+    Ok(compiled)
+  }
+
+  /// The variables the expression uses, sorted; values are bound in this order
+  pub fn variables(&self) -> &[String] {
+    &self.variables
+  }
+
+  /// Evaluates with `values[i]` bound to the i-th name of [`variables`](Self::variables).
+  /// User-defined functions called by the expression see these bindings, not
+  /// the stored variables.
+  pub fn evaluate(&self, values: &[f64]) -> Result<f64, CalculatorError> {
+    if values.len() != self.variables.len() {
+      return Err(CalculatorError::ArgumentError(format!(
+        "Expected {} variable values, got {}",
+        self.variables.len(),
+        values.len()
+      )));
+    }
+    let values = values
+      .iter()
+      .map(|value| crate::check_input(*value))
+      .collect::<Result<Vec<f64>, CalculatorError>>()?;
+    let resolve = |name: &str| {
+      self
+        .variables
+        .iter()
+        .position(|variable| variable.eq_ignore_ascii_case(name))
+        .map(|index| values[index])
+    };
+    self.run(&values, &resolve)
+  }
+
+  /// Evaluates, looking each variable up through `resolve`
+  pub fn evaluate_with(&self, resolve: Resolver) -> Result<f64, CalculatorError> {
+    let values = self
+      .variables
+      .iter()
+      .map(|name| match resolve(name) {
+        Some(value) => crate::check_input(value),
+        None => Err(crate::unknown_token(name)),
+      })
+      .collect::<Result<Vec<f64>, CalculatorError>>()?;
+    self.run(&values, resolve)
+  }
+
+  /// Runs the program with `values` loaded by index, and `resolve` for the
+  /// names in the bodies of user-defined functions it calls
+  fn run(&self, values: &[f64], resolve: Resolver) -> Result<f64, CalculatorError> {
+    let mut stack = Vec::with_capacity(self.stack_size);
+    for instruction in &self.program {
+      let value = match instruction {
+        Instruction::Push(value) => *value,
+        Instruction::Load(index) => values[*index],
+        Instruction::Binary(operator) => {
+          // The compiler only emits operators after both of their operands
+          let right = stack.pop().unwrap();
+          let left = stack.pop().unwrap();
+          crate::evaluate_binary_operation(left, operator.symbol(), right)?
+        }
+        Instruction::Function(name) => {
+          let argument = stack.pop().unwrap();
+          crate::evaluate_function(name, argument, resolve)?
+        }
+        Instruction::Call(name, count) => {
+          let arguments = stack.split_off(stack.len() - count);
+          crate::evaluate_call(name, &arguments, resolve)?
+        }
+      };
+      stack.push(value);
+    }
+    Ok(crate::round_to_precision(stack.pop().unwrap()))
+  }
+}
+
+#[cfg(feature = "std")]
+/// Forgets every cached program
+pub(crate) fn clear_cache() {
+  CACHE.lock().unwrap().clear();
+}
+
+/// Appends the postfix program for `tree`, returning the stack depth it needs
+fn compile(
+  tree: &Expr,
+  variables: &[String],
+  program: &mut Vec<Instruction>,
+) -> Result<usize, CalculatorError> {
+  Ok(match tree {
+    Expr::Number(value) => {
+      program.push(Instruction::Push(*value));
+      1
+    }
+    Expr::Variable(name) => {
+      // variables lists every name in the tree
+      let index = variables.iter().position(|known| known == name).unwrap();
+      program.push(Instruction::Load(index));
+      1
+    }
+    Expr::Binary { operator, .. } if operator == "," => return Err(crate::misplaced_comma()),
+    Expr::Binary {
+      operator,
+      left,
+      right,
+    } => {
+      let left_depth = compile(left, variables, program)?;
+      let right_depth = compile(right, variables, program)?;
+      let operator = Operator::from_symbol(operator)
+        .ok_or_else(|| CalculatorError::SyntaxError(format!("Unknown operator: {}", operator)))?;
+      program.push(Instruction::Binary(operator));
+      left_depth.max(right_depth + 1)
+    }
+    Expr::Function { name, argument } => match argument.arguments().as_slice() {
+      [argument] => {
+        let depth = compile(argument, variables, program)?;
+        program.push(Instruction::Function(name.clone()));
+        depth
+      }
+      arguments => {
+        // Each argument is computed above those before it
+        let mut depth = 0;
+        for (index, argument) in arguments.iter().enumerate() {
+          depth = depth.max(index + compile(argument, variables, program)?);
+        }
+        program.push(Instruction::Call(name.clone(), arguments.len()));
+        depth
+      }
+    },
+  })
+}
+
+impl fmt::Display for CompiledExpression {
+  /// Shows the expression as it was written
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.source)
+  }
+}
@@ -0,0 +1,286 @@
+//! # Complex Numbers
+//!
+//! Complex arithmetic used when the [`DomainPolicy::Complex`] setting is active,
+//! so that `sqrt(-4)` is `2i` and `sqrt(-4) * sqrt(-4)` is `-4` instead of an
+//! error. Expressions are evaluated over their [`Expr`] tree; trigonometric
+//! functions follow the angle mode of the current settings.
+//!
+//! [`DomainPolicy::Complex`]: crate::settings::DomainPolicy::Complex
+
+use crate::ast::Expr;
+use crate::prelude::*;
+use crate::{CalculatorError, Resolver};
+use core::f64::consts::LN_10;
+use core::fmt;
+
+/// A complex number `re + im·i`
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Complex {
+  pub re: f64,
+  pub im: f64,
+}
+
+const I: Complex = Complex { re: 0.0, im: 1.0 };
+const ONE: Complex = Complex { re: 1.0, im: 0.0 };
+
+impl Complex {
+  /// Creates a complex number from its real and imaginary parts
+  pub fn new(re: f64, im: f64) -> Self {
+    Complex { re, im }
+  }
+
+  /// Creates a complex number with no imaginary part
+  pub fn real(re: f64) -> Self {
+    Complex { re, im: 0.0 }
+  }
+
+  /// Checks whether the imaginary part is negligible compared to the real part
+  pub fn is_real(&self) -> bool {
+    self.im.abs() <= 1e-12 * self.re.abs().max(1.0)
+  }
+
+  /// Returns the modulus |z|
+  pub fn abs(&self) -> f64 {
+    self.re.hypot(self.im)
+  }
+
+  /// Returns the argument (angle) in radians
+  pub fn arg(&self) -> f64 {
+    self.im.atan2(self.re)
+  }
+
+  fn add(self, other: Complex) -> Complex {
+    Complex::new(self.re + other.re, self.im + other.im)
+  }
+
+  fn sub(self, other: Complex) -> Complex {
+    Complex::new(self.re - other.re, self.im - other.im)
+  }
+
+  fn mul(self, other: Complex) -> Complex {
+    Complex::new(
+      self.re * other.re - self.im * other.im,
+      self.re * other.im + self.im * other.re,
+    )
+  }
+
+  fn div(self, other: Complex) -> Result<Complex, CalculatorError> {
+    let denominator = other.re * other.re + other.im * other.im;
+    if denominator == 0.0 {
+      return Err(CalculatorError::MathError("Division by zero".to_string()));
+    }
+    Ok(Complex::new(
+      (self.re * other.re + self.im * other.im) / denominator,
+      (self.im * other.re - self.re * other.im) / denominator,
+    ))
+  }
+
+  fn scale(self, factor: f64) -> Complex {
+    Complex::new(self.re * factor, self.im * factor)
+  }
+
+  fn exp(self) -> Complex {
+    let magnitude = self.re.exp();
+    Complex::new(magnitude * self.im.cos(), magnitude * self.im.sin())
+  }
+
+  fn ln(self) -> Result<Complex, CalculatorError> {
+    if self.re == 0.0 && self.im == 0.0 {
+      return Err(CalculatorError::ArgumentError(
+        "Cannot calculate logarithm of zero".to_string(),
+      ));
+    }
+    Ok(Complex::new(self.abs().ln(), self.arg()))
+  }
+
+  // Algebraic form, so that sqrt(-4) is exactly 2i rather than going through angles
+  fn sqrt(self) -> Complex {
+    if self.re == 0.0 && self.im == 0.0 {
+      return Complex::real(0.0);
+    }
+    let t = ((self.abs() + self.re.abs()) / 2.0).sqrt();
+    if self.re >= 0.0 {
+      Complex::new(t, self.im / (2.0 * t))
+    } else {
+      Complex::new(self.im.abs() / (2.0 * t), t.copysign(self.im))
+    }
+  }
+
+  fn pow(self, exponent: Complex) -> Result<Complex, CalculatorError> {
+    if self.re == 0.0 && self.im == 0.0 {
+      return if exponent.re > 0.0 {
+        Ok(Complex::real(0.0))
+      } else if exponent.re == 0.0 && exponent.im == 0.0 {
+        Ok(ONE)
+      } else {
+        Err(CalculatorError::MathError(
+          "Zero raised to a non-positive power".to_string(),
+        ))
+      };
+    }
+    Ok(exponent.mul(self.ln()?).exp())
+  }
+
+  fn sin(self) -> Complex {
+    Complex::new(
+      self.re.sin() * self.im.cosh(),
+      self.re.cos() * self.im.sinh(),
+    )
+  }
+
+  fn cos(self) -> Complex {
+    Complex::new(
+      self.re.cos() * self.im.cosh(),
+      -self.re.sin() * self.im.sinh(),
+    )
+  }
+
+  // asin(z) = -i·ln(iz + sqrt(1 - z²))
+  fn asin(self) -> Result<Complex, CalculatorError> {
+    let root = ONE.sub(self.mul(self)).sqrt();
+    Ok(I.mul(self).add(root).ln()?.mul(Complex::new(0.0, -1.0)))
+  }
+
+  // acos(z) = -i·ln(z + i·sqrt(1 - z²))
+  fn acos(self) -> Result<Complex, CalculatorError> {
+    let root = ONE.sub(self.mul(self)).sqrt();
+    Ok(self.add(I.mul(root)).ln()?.mul(Complex::new(0.0, -1.0)))
+  }
+
+  // atan(z) = i/2·(ln(1 - iz) - ln(1 + iz))
+  fn atan(self) -> Result<Complex, CalculatorError> {
+    let iz = I.mul(self);
+    Ok(
+      ONE
+        .sub(iz)
+        .ln()?
+        .sub(ONE.add(iz).ln()?)
+        .mul(Complex::new(0.0, 0.5)),
+    )
+  }
+}
+
+impl fmt::Display for Complex {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let re = crate::format_value(self.re);
+    let im = crate::format_value(self.im.abs());
+    if self.is_real() {
+      write!(f, "{}", re)
+    } else if self.re == 0.0 {
+      let sign = if self.im < 0.0 { "-" } else { "" };
+      write!(f, "{}{}i", sign, im)
+    } else {
+      let sign = if self.im < 0.0 { '-' } else { '+' };
+      write!(f, "{} {} {}i", re, sign, im)
+    }
+  }
+}
+
+/// Evaluates an expression tree in complex arithmetic
+pub fn evaluate(tree: &Expr) -> Result<Complex, CalculatorError> {
+  evaluate_with(tree, &crate::get_variable)
+}
+
+/// Evaluates an expression tree in complex arithmetic, looking variables up
+/// through `resolve`
+fn evaluate_with(tree: &Expr, resolve: Resolver) -> Result<Complex, CalculatorError> {
+  match tree {
+    Expr::Number(value) => Ok(Complex::real(*value)),
+    Expr::Variable(name) => match resolve(name) {
+      Some(value) => Ok(Complex::real(value)),
+      None => Err(crate::unknown_token(name)),
+    },
+    Expr::Binary {
+      operator,
+      left,
+      right,
+    } => {
+      let (left, right) = (
+        evaluate_with(left, resolve)?,
+        evaluate_with(right, resolve)?,
+      );
+      match operator.as_str() {
+        "+" => Ok(left.add(right)),
+        "-" => Ok(left.sub(right)),
+        "*" => Ok(left.mul(right)),
+        "/" => left.div(right),
+        "^" => left.pow(right),
+        _ => crate::evaluate_binary_operation(real_part(left)?, operator, real_part(right)?)
+          .map(Complex::real),
+      }
+    }
+    Expr::Function { name, argument } => {
+      evaluate_function(name, evaluate_with(argument, resolve)?, resolve)
+    }
+  }
+}
+
+/// Evaluates a complex function; functions without a complex extension
+/// require a real argument
+fn evaluate_function(
+  name: &str,
+  value: Complex,
+  resolve: Resolver,
+) -> Result<Complex, CalculatorError> {
+  // Angle conversions are linear, so they scale both parts alike
+  let angle_mode = crate::settings::current().angle_mode;
+  let radians = value.scale(angle_mode.to_radians(1.0));
+  let to_angle = angle_mode.from_radians(1.0);
+  match name {
+    "sqrt" => Ok(value.sqrt()),
+    "sin" => Ok(radians.sin()),
+    "cos" => Ok(radians.cos()),
+    "tan" => radians.sin().div(radians.cos()),
+    "asin" => Ok(value.asin()?.scale(to_angle)),
+    "acos" => Ok(value.acos()?.scale(to_angle)),
+    "atan" => Ok(value.atan()?.scale(to_angle)),
+    "log" => Ok(value.ln()?.scale(1.0 / LN_10)),
+    "ln" => value.ln(),
+    "exp" => Ok(value.exp()),
+    "neg" => Ok(value.scale(-1.0)),
+    "percent" => Ok(value.scale(0.01)),
+    "abs" => Ok(Complex::real(value.abs())),
+    // Memory has side effects that must not run twice when a real evaluation is retried
+    "m+" | "m-" | "mr" | "mc" | "mundo" => Err(CalculatorError::ArgumentError(
+      "Memory functions are not available in complex arithmetic".to_string(),
+    )),
+    _ => crate::evaluate_function(name, real_part(value)?, resolve).map(Complex::real),
+  }
+}
+
+/// Returns the real value of `value`, failing if it has an imaginary part
+fn real_part(value: Complex) -> Result<f64, CalculatorError> {
+  if value.is_real() {
+    Ok(value.re)
+  } else {
+    Err(CalculatorError::ArgumentError(format!(
+      "{} is not a real number",
+      value
+    )))
+  }
+}
+
+/// Retries a failed or NaN real evaluation in complex arithmetic, returning
+/// the real result if there is one. The original outcome is kept when the
+/// complex evaluation fails too.
+pub(crate) fn promote(
+  expression: &str,
+  resolve: Resolver,
+  original: Result<f64, CalculatorError>,
+) -> Result<f64, CalculatorError> {
+  let value = match crate::ast::parse_with_resolver(expression, resolve)
+    .and_then(|tree| evaluate_with(&tree, resolve))
+  {
+    Ok(value) => value,
+    Err(_) => return original,
+  };
+  if value.is_real() {
+    Ok(value.re)
+  } else {
+    Err(CalculatorError::MathError(format!(
+      "Result is complex: {}",
+      value
+    )))
+  }
+}
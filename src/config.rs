@@ -0,0 +1,256 @@
+//! # Configuration
+//!
+//! Preferences shared by the CLI and GUI, kept in `config.toml` in the user's
+//! config directory with the `config` feature. Every entry is optional:
+//!
+//! ```toml
+//! precision = 10        # significant figures results are shown with
+//! angle = "radians"     # degrees, radians or gradians
+//! locale = "de"         # separators of results: plain, en, de, fr, ch or system
+//! theme = "light"       # colours of the GUI: dark or light
+//!
+//! [history]
+//! limit = 1000          # entries kept in the history
+//!
+//! [paths]
+//! data_dir = "/home/me/calculator"  # where history and variables are saved
+//! ```
+//!
+//! Both front-ends [`load`](Config::load) the file at startup and
+//! [`apply`](Config::apply) it, which makes the precision, angle and locale
+//! the default [`settings`](crate::settings). `locale = "system"` takes the
+//! locale from the `LC_ALL`, `LC_NUMERIC` or `LANG` environment variable.
+
+use crate::settings::{self, AngleMode, Locale};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Colours of the GUI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Theme {
+  #[default]
+  Dark,
+  Light,
+}
+
+impl FromStr for Theme {
+  type Err = io::Error;
+
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    match name.trim().to_lowercase().as_str() {
+      "dark" => Ok(Theme::Dark),
+      "light" => Ok(Theme::Light),
+      other => Err(invalid(format!(
+        "Unknown theme: {} (expected dark, light)",
+        other
+      ))),
+    }
+  }
+}
+
+impl fmt::Display for Theme {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Theme::Dark => "dark",
+      Theme::Light => "light",
+    })
+  }
+}
+
+/// Preferences read from the configuration file; entries missing from the
+/// file are `None` and leave the built-in behaviour alone
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::config::{Config, Theme};
+/// use rust_calculator::settings::AngleMode;
+///
+/// let config = Config::from_toml("angle = \"rad\"\ntheme = \"light\"\n[history]\nlimit = 50").unwrap();
+/// assert_eq!(config.angle_mode, Some(AngleMode::Radians));
+/// assert_eq!(config.theme, Theme::Light);
+/// assert_eq!(config.history_limit, Some(50));
+/// assert!(Config::from_toml("precision = 40").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config {
+  /// Significant figures results are shown with
+  pub precision: Option<u32>,
+  /// Angle unit of the trigonometric functions
+  pub angle_mode: Option<AngleMode>,
+  /// Digit grouping and decimal separator of results
+  pub locale: Option<Locale>,
+  /// Colours of the GUI
+  pub theme: Theme,
+  /// Most entries kept in the history; older ones are dropped
+  pub history_limit: Option<usize>,
+  /// Directory for the history and variable files, in place of the user's
+  /// data directory
+  pub data_dir: Option<PathBuf>,
+}
+
+/// The layout of the configuration file
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct File {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  precision: Option<u32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  angle: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  locale: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  theme: Option<String>,
+  #[serde(default, skip_serializing_if = "HistorySection::is_empty")]
+  history: HistorySection,
+  #[serde(default, skip_serializing_if = "PathsSection::is_empty")]
+  paths: PathsSection,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct HistorySection {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  limit: Option<usize>,
+}
+
+impl HistorySection {
+  fn is_empty(&self) -> bool {
+    self.limit.is_none()
+  }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct PathsSection {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  data_dir: Option<PathBuf>,
+}
+
+impl PathsSection {
+  fn is_empty(&self) -> bool {
+    self.data_dir.is_none()
+  }
+}
+
+impl Config {
+  /// Location of the configuration file, if the platform has a config directory
+  pub fn path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("com", "thickkoezz", "rust-calculator")
+      .map(|dirs| dirs.config_dir().join("config.toml"))
+  }
+
+  /// Reads the configuration file, or returns the defaults if there isn't one
+  pub fn load() -> io::Result<Config> {
+    match Config::path() {
+      Some(path) if path.exists() => Config::from_toml(&std::fs::read_to_string(path)?),
+      _ => Ok(Config::default()),
+    }
+  }
+
+  /// Parses the contents of a configuration file
+  pub fn from_toml(text: &str) -> io::Result<Config> {
+    let file: File = toml::from_str(text).map_err(|err| invalid(err.to_string()))?;
+
+    if let Some(precision) = file.precision {
+      if !(1..=17).contains(&precision) {
+        return Err(invalid(format!(
+          "Precision must be between 1 and 17: {}",
+          precision
+        )));
+      }
+    }
+    let angle_mode = match file.angle {
+      Some(angle) => Some(
+        angle
+          .parse()
+          .map_err(|err: crate::CalculatorError| invalid(err.to_string()))?,
+      ),
+      None => None,
+    };
+    let locale = match file.locale.as_deref().map(str::trim) {
+      Some(system) if system.eq_ignore_ascii_case("system") => system_locale(),
+      Some(locale) => Some(
+        locale
+          .parse()
+          .map_err(|err: crate::CalculatorError| invalid(err.to_string()))?,
+      ),
+      None => None,
+    };
+    let theme = match file.theme {
+      Some(theme) => theme.parse()?,
+      None => Theme::default(),
+    };
+
+    Ok(Config {
+      precision: file.precision,
+      angle_mode,
+      locale,
+      theme,
+      history_limit: file.history.limit,
+      data_dir: file.paths.data_dir,
+    })
+  }
+
+  /// Writes the configuration in the file format, leaving out unset entries
+  pub fn to_toml(&self) -> String {
+    let file = File {
+      precision: self.precision,
+      angle: self.angle_mode.map(|angle| angle.to_string()),
+      locale: self.locale.map(|locale| locale.to_string()),
+      theme: (self.theme != Theme::default()).then(|| self.theme.to_string()),
+      history: HistorySection {
+        limit: self.history_limit,
+      },
+      paths: PathsSection {
+        data_dir: self.data_dir.clone(),
+      },
+    };
+    toml::to_string(&file).unwrap_or_default()
+  }
+
+  /// Saves the configuration to the configuration file
+  pub fn save(&self) -> io::Result<()> {
+    let path = Config::path().ok_or_else(|| invalid("No config directory".to_string()))?;
+    if let Some(dir) = path.parent() {
+      std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, self.to_toml())
+  }
+
+  /// Puts the configuration into effect for this process: the precision,
+  /// angle mode and locale become the default settings, and the history limit and data
+  /// directory apply to the saved history and variables
+  pub fn apply(&self) {
+    let mut defaults = settings::get_default();
+    if let Some(precision) = self.precision {
+      defaults.sigfigs = Some(precision);
+    }
+    if let Some(angle_mode) = self.angle_mode {
+      defaults.angle_mode = angle_mode;
+    }
+    if let Some(locale) = self.locale {
+      defaults.locale = locale;
+    }
+    settings::set_default(defaults);
+    crate::storage::set_history_limit(self.history_limit);
+    crate::storage::set_data_dir(self.data_dir.clone());
+  }
+}
+
+/// The locale the environment asks numbers to be written in, if it's one
+/// [`Locale::from_tag`] knows
+fn system_locale() -> Option<Locale> {
+  ["LC_ALL", "LC_NUMERIC", "LANG"]
+    .into_iter()
+    .filter_map(|name| std::env::var(name).ok())
+    .find(|tag| !tag.is_empty())
+    .and_then(|tag| Locale::from_tag(&tag))
+}
+
+fn invalid(message: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,181 @@
+//! # Constants
+//!
+//! Named values that expressions can use in place of numbers. The
+//! mathematical constants `pi`, `e`, `tau` and `phi` are always known and
+//! matched case-insensitively. The `physics` feature adds the [`PHYSICS`]
+//! constants, in SI units, whose names are case-sensitive so `G` and `h`
+//! keep their usual spelling. With the `std` feature more can be
+//! [`register`]ed at runtime.
+//!
+//! Constants are looked up before variables, so a variable can't hide one.
+//!
+//! ```
+//! use rust_calculator::{constants, evaluate_expression};
+//!
+//! constants::register("g0", 9.80665).unwrap();
+//! assert_eq!(evaluate_expression("g0").unwrap(), 9.80665);
+//! assert!(constants::register("pi", 3.0).is_err());
+//! ```
+
+#[cfg(feature = "std")]
+use crate::CalculatorError;
+use crate::prelude::*;
+
+/// A named value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Constant {
+  pub name: &'static str,
+  pub value: f64,
+  pub description: &'static str,
+}
+
+/// The mathematical constants
+pub const MATH: &[Constant] = &[
+  Constant {
+    name: "pi",
+    value: core::f64::consts::PI,
+    description: "Ratio of a circle's circumference to its diameter",
+  },
+  Constant {
+    name: "e",
+    value: core::f64::consts::E,
+    description: "Base of the natural logarithm",
+  },
+  Constant {
+    name: "tau",
+    value: core::f64::consts::TAU,
+    description: "2π",
+  },
+  Constant {
+    name: "phi",
+    value: 1.618033988749895,
+    description: "Golden ratio",
+  },
+];
+
+#[cfg(feature = "physics")]
+/// Physical constants in SI units, with their CODATA 2018 values
+pub const PHYSICS: &[Constant] = &[
+  Constant {
+    name: "c",
+    value: 299_792_458.0,
+    description: "Speed of light in vacuum (m/s)",
+  },
+  Constant {
+    name: "G",
+    value: 6.674_30e-11,
+    description: "Gravitational constant (m³/(kg·s²))",
+  },
+  Constant {
+    name: "h",
+    value: 6.626_070_15e-34,
+    description: "Planck constant (J·s)",
+  },
+  Constant {
+    name: "k_B",
+    value: 1.380_649e-23,
+    description: "Boltzmann constant (J/K)",
+  },
+  Constant {
+    name: "N_A",
+    value: 6.022_140_76e23,
+    description: "Avogadro constant (1/mol)",
+  },
+  Constant {
+    name: "q_e",
+    value: 1.602_176_634e-19,
+    description: "Elementary charge (C)",
+  },
+];
+
+#[cfg(not(feature = "physics"))]
+const PHYSICS: &[Constant] = &[];
+
+#[cfg(feature = "std")]
+/// Constants registered at runtime, by name
+static REGISTERED: std::sync::RwLock<alloc::collections::BTreeMap<String, f64>> =
+  std::sync::RwLock::new(alloc::collections::BTreeMap::new());
+
+/// Looks up the value of a constant
+pub fn lookup(name: &str) -> Option<f64> {
+  if let Some(constant) = MATH
+    .iter()
+    .find(|constant| constant.name.eq_ignore_ascii_case(name))
+  {
+    return Some(constant.value);
+  }
+  if let Some(constant) = PHYSICS.iter().find(|constant| constant.name == name) {
+    return Some(constant.value);
+  }
+  registered(name)
+}
+
+#[cfg(feature = "std")]
+fn registered(name: &str) -> Option<f64> {
+  REGISTERED.read().unwrap().get(name).copied()
+}
+
+#[cfg(not(feature = "std"))]
+fn registered(_name: &str) -> Option<f64> {
+  None
+}
+
+#[cfg(feature = "std")]
+/// Adds a constant, or changes the value of one added before. Names are
+/// case-sensitive and can't be those of built-in constants or functions.
+pub fn register(name: &str, value: f64) -> Result<(), CalculatorError> {
+  if !crate::is_identifier(name) {
+    return Err(CalculatorError::ArgumentError(format!(
+      "Invalid constant name: {}",
+      name
+    )));
+  }
+  let built_in = MATH
+    .iter()
+    .chain(PHYSICS)
+    .any(|constant| constant.name.eq_ignore_ascii_case(name));
+  if built_in
+    || crate::is_math_function(name)
+    || crate::parser::is_postfix_function(name)
+    || crate::parser::is_nullary_function(name)
+  {
+    return Err(CalculatorError::ReservedName(name.to_string()));
+  }
+  if !value.is_finite() {
+    return Err(CalculatorError::ArgumentError(format!(
+      "Constant {} must be a finite number",
+      name
+    )));
+  }
+
+  REGISTERED.write().unwrap().insert(name.to_string(), value);
+  // Compiled programs hold the values of the constants they use
+  crate::compiled::clear_cache();
+  Ok(())
+}
+
+#[cfg(feature = "std")]
+/// Removes a constant added with [`register`], returning whether there was one
+pub fn unregister(name: &str) -> bool {
+  let removed = REGISTERED.write().unwrap().remove(name).is_some();
+  crate::compiled::clear_cache();
+  removed
+}
+
+/// Every constant known, built-in ones first, as name/value pairs
+pub fn list() -> Vec<(String, f64)> {
+  let built_in = MATH
+    .iter()
+    .chain(PHYSICS)
+    .map(|constant| (constant.name.to_string(), constant.value));
+  #[cfg(feature = "std")]
+  let built_in = built_in.chain(
+    REGISTERED
+      .read()
+      .unwrap()
+      .iter()
+      .map(|(name, value)| (name.clone(), *value))
+      .collect::<Vec<_>>(),
+  );
+  built_in.collect()
+}
@@ -0,0 +1,251 @@
+//! # Evaluation Contexts
+//!
+//! An [`EvalContext`] extends the built-in function table with functions
+//! written in Rust, and holds its own variables. Expressions evaluated through
+//! the context can call its functions like any built-in one, e.g. `tax(100)`.
+//! A context can also have its own angle mode for the trigonometric functions.
+//!
+//! Each context has a memory register of its own, so `m+`, `mr` and the other
+//! memory functions in one context don't touch the global memory or another
+//! context's, and two contexts can be used side by side, on one thread or
+//! several. The memory isn't saved; only the global one is. A context shared
+//! between threads, e.g. in an `Arc`, evaluates on one thread at a time, so
+//! each evaluation sees the memory the one before left.
+
+use crate::memory::{Memory, MemoryChange};
+use crate::settings::{self, AngleMode, Settings};
+use crate::{CalculatorError, Token};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// A function registered by the embedding application
+pub type CustomFunction = Arc<dyn Fn(f64) -> f64 + Send + Sync>;
+
+/// Custom functions, variables and a memory register to evaluate expressions
+/// against
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::EvalContext;
+///
+/// let mut ctx = EvalContext::new();
+/// ctx.register_fn("tax", |x| x * 0.21).unwrap();
+/// ctx.set_variable("price", 200.0);
+/// assert_eq!(ctx.evaluate("price + tax(price)").unwrap(), 242.0);
+/// ```
+#[derive(Default)]
+pub struct EvalContext {
+  functions: HashMap<String, CustomFunction>,
+  variables: HashMap<String, f64>,
+  angle_mode: Option<AngleMode>,
+  // Behind a lock so evaluating only needs a shared reference
+  memory: Mutex<Memory>,
+}
+
+impl EvalContext {
+  /// Creates a context with no custom functions or variables, and nothing in
+  /// memory
+  pub fn new() -> Self {
+    EvalContext::default()
+  }
+
+  /// Registers `function` under `name`, replacing any function registered
+  /// with that name before.
+  ///
+  /// Fails if the name isn't an identifier or already means something else,
+  /// such as a built-in function, a constant or a unit conversion.
+  pub fn register_fn(
+    &mut self,
+    name: &str,
+    function: impl Fn(f64) -> f64 + Send + Sync + 'static,
+  ) -> Result<(), CalculatorError> {
+    // A free name is one the tokenizer leaves as an unresolved identifier
+    let free = matches!(
+      crate::tokenize_spanned(name, &|_| None).as_deref(),
+      Ok([(Token::Identifier(_), _)])
+    );
+    if !free {
+      return Err(CalculatorError::ArgumentError(format!(
+        "Cannot register a function named {}",
+        name
+      )));
+    }
+    self.functions.insert(name.to_string(), Arc::new(function));
+    Ok(())
+  }
+
+  /// Removes a custom function, returning whether it was registered
+  pub fn unregister_fn(&mut self, name: &str) -> bool {
+    self.functions.remove(name).is_some()
+  }
+
+  /// Lists the names of the custom functions, sorted
+  pub fn functions(&self) -> Vec<&str> {
+    let mut names: Vec<&str> = self.functions.keys().map(String::as_str).collect();
+    names.sort_unstable();
+    names
+  }
+
+  /// Sets a variable in this context
+  pub fn set_variable(&mut self, name: &str, value: f64) {
+    self.variables.insert(name.to_lowercase(), value);
+  }
+
+  /// Gets a variable's value
+  pub fn get_variable(&self, name: &str) -> Option<f64> {
+    self.variables.get(&name.to_lowercase()).copied()
+  }
+
+  /// Sets the angle unit of sin, cos, tan and their inverses in this
+  /// context, in place of the `angle` setting
+  pub fn set_angle_mode(&mut self, angle_mode: AngleMode) {
+    self.angle_mode = Some(angle_mode);
+  }
+
+  /// The angle unit expressions in this context are evaluated with
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rust_calculator::EvalContext;
+  /// use rust_calculator::settings::AngleMode;
+  ///
+  /// let mut ctx = EvalContext::new();
+  /// assert_eq!(ctx.angle_mode(), AngleMode::Degrees);
+  /// ctx.set_angle_mode(AngleMode::Gradians);
+  /// assert_eq!(ctx.angle_mode(), AngleMode::Gradians);
+  /// assert_eq!(ctx.evaluate("sin(100)").unwrap(), 1.0);
+  /// ```
+  pub fn angle_mode(&self) -> AngleMode {
+    self
+      .angle_mode
+      .unwrap_or_else(|| settings::current().angle_mode)
+  }
+
+  /// Returns the value in this context's memory
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rust_calculator::EvalContext;
+  ///
+  /// let first = EvalContext::new();
+  /// let second = EvalContext::new();
+  /// first.evaluate("5 m+").unwrap();
+  /// second.evaluate("2 m-").unwrap();
+  /// assert_eq!(first.memory(), 5.0);
+  /// assert_eq!(second.evaluate("mr").unwrap(), -2.0);
+  /// ```
+  pub fn memory(&self) -> f64 {
+    self.lock_memory().value()
+  }
+
+  /// Returns the changes made to this context's memory, oldest first
+  pub fn memory_changes(&self) -> Vec<MemoryChange> {
+    self.lock_memory().changes().to_vec()
+  }
+
+  /// Resets this context's memory to zero, as `mc` does
+  pub fn clear_memory(&mut self) {
+    self
+      .memory
+      .get_mut()
+      .unwrap_or_else(PoisonError::into_inner)
+      .clear();
+  }
+
+  /// Evaluates an expression with this context's functions, variables and
+  /// memory. Global variables and the global memory aren't visible, so
+  /// results don't depend on other code.
+  pub fn evaluate(&self, expression: &str) -> Result<f64, CalculatorError> {
+    // Held throughout, so evaluations on other threads wait their turn
+    let mut memory = self.lock_memory();
+    let resolve = |name: &str| self.get_variable(name);
+    let (result, changed) = crate::with_session(memory.clone(), crate::last_answer(), || {
+      scoped(&self.functions, || {
+        self.with_angle_mode(|| crate::evaluate_with_resolver(expression, &resolve))
+      })
+    });
+    *memory = changed;
+    result
+  }
+
+  /// Locks this context's memory. An evaluation that panicked left the
+  /// memory as it was before it started, so the lock is still usable.
+  fn lock_memory(&self) -> MutexGuard<'_, Memory> {
+    self.memory.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  /// Runs `f` with this context's angle mode in effect, if it has one
+  fn with_angle_mode<T>(&self, f: impl FnOnce() -> T) -> T {
+    match self.angle_mode {
+      Some(angle_mode) => {
+        let settings = Settings {
+          angle_mode,
+          ..settings::current()
+        };
+        settings::scoped(settings, f)
+      }
+      None => f(),
+    }
+  }
+
+  /// Checks an expression for errors without evaluating it, as
+  /// [`validate_expression`](crate::validate_expression) does, accepting this
+  /// context's functions and variables
+  pub fn validate(&self, expression: &str) -> Result<(), CalculatorError> {
+    let resolve = |name: &str| self.get_variable(name);
+    scoped(&self.functions, || {
+      crate::validate_with_resolver(expression, &resolve)
+    })
+  }
+}
+
+impl Clone for EvalContext {
+  fn clone(&self) -> Self {
+    EvalContext {
+      functions: self.functions.clone(),
+      variables: self.variables.clone(),
+      angle_mode: self.angle_mode,
+      memory: Mutex::new(self.lock_memory().clone()),
+    }
+  }
+}
+
+impl std::fmt::Debug for EvalContext {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("EvalContext")
+      .field("functions", &self.functions())
+      .field("variables", &self.variables)
+      .field("angle_mode", &self.angle_mode)
+      .field("memory", &*self.lock_memory())
+      .finish()
+  }
+}
+
+thread_local! {
+  // Functions of the context evaluating on this thread, if any
+  static ACTIVE: RefCell<HashMap<String, CustomFunction>> = RefCell::new(HashMap::new());
+}
+
+/// Runs `f` with `functions` callable on this thread, restoring the previous
+/// functions afterwards, even if `f` panics
+fn scoped<T>(functions: &HashMap<String, CustomFunction>, f: impl FnOnce() -> T) -> T {
+  struct Restore(HashMap<String, CustomFunction>);
+
+  impl Drop for Restore {
+    fn drop(&mut self) {
+      ACTIVE.with(|active| active.replace(std::mem::take(&mut self.0)));
+    }
+  }
+
+  let _restore = Restore(ACTIVE.with(|active| active.replace(functions.clone())));
+  f()
+}
+
+/// Looks up a custom function of the context evaluating on this thread
+pub(crate) fn custom_function(name: &str) -> Option<CustomFunction> {
+  ACTIVE.with(|active| active.borrow().get(name).cloned())
+}
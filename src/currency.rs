@@ -0,0 +1,60 @@
+//! # Currency Formatting
+//!
+//! Formats results as money: currency symbol, thousands grouping and the
+//! currency's usual number of decimals, e.g. `currency(1234.5, "USD")` gives
+//! `$1,234.50`.
+
+use crate::CalculatorError;
+use crate::prelude::*;
+
+/// Supported currencies: ISO code, symbol, whether the symbol follows the
+/// amount, and the number of decimals
+const CURRENCIES: [(&str, &str, bool, usize); 10] = [
+  ("USD", "$", false, 2),
+  ("EUR", "€", false, 2),
+  ("GBP", "£", false, 2),
+  ("JPY", "¥", false, 0),
+  ("CNY", "¥", false, 2),
+  ("INR", "₹", false, 2),
+  ("IDR", "Rp", false, 2),
+  ("CHF", "CHF ", false, 2),
+  ("SEK", " kr", true, 2),
+  ("BTC", "₿", false, 8),
+];
+
+/// Formats `value` as an amount of the currency with ISO code `code`
+pub fn format_currency(value: f64, code: &str) -> Result<String, CalculatorError> {
+  let upper = code.trim().to_uppercase();
+  let (_, symbol, symbol_after, decimals) = CURRENCIES
+    .iter()
+    .find(|(iso, ..)| *iso == upper)
+    .ok_or_else(|| CalculatorError::ArgumentError(format!("Unknown currency: {}", code)))?;
+
+  if !value.is_finite() {
+    return Err(CalculatorError::ArgumentError(
+      "Cannot format a non-finite amount as currency".to_string(),
+    ));
+  }
+
+  // Round with the configured mode first; formatting alone rounds half to even
+  let rounded = crate::settings::current()
+    .rounding
+    .round_to(value, *decimals as u32);
+  let amount = crate::format::localize(
+    &format!("{:.*}", decimals, rounded.abs()),
+    crate::settings::Locale::En,
+  );
+  // Rounding may turn tiny negative amounts into -0, which shouldn't keep the sign
+  let sign = if rounded < 0.0 { "-" } else { "" };
+
+  Ok(if *symbol_after {
+    format!("{}{}{}", sign, amount, symbol)
+  } else {
+    format!("{}{}{}", sign, symbol, amount)
+  })
+}
+
+/// Lists the supported currency codes
+pub fn supported_currencies() -> impl Iterator<Item = &'static str> {
+  CURRENCIES.iter().map(|(code, ..)| *code)
+}
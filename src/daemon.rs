@@ -0,0 +1,75 @@
+//! # Local Socket Daemon
+//!
+//! A lightweight daemon listening on a Unix domain socket, so shells and
+//! scripts can get answers without starting a new process for every query:
+//!
+//! ```text
+//! $ echo "2 + 3 * 4" | nc -U "$XDG_RUNTIME_DIR/rust-calculator/calculator.sock"
+//! 14
+//! ```
+//!
+//! The protocol is one expression per line. Each line gets exactly one reply:
+//! the result, or `error: <message>` if evaluation failed. Only available on
+//! Unix platforms.
+
+use std::io::{self, BufReader};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Default location of the daemon socket, next to the history file
+pub fn default_socket_path() -> PathBuf {
+  match directories::ProjectDirs::from("com", "thickkoezz", "rust-calculator") {
+    Some(proj_dirs) => {
+      let dir = proj_dirs.runtime_dir().unwrap_or(proj_dirs.data_dir());
+      // Create directory if it doesn't exist
+      std::fs::create_dir_all(dir).unwrap_or_default();
+      dir.join("calculator.sock")
+    }
+    // Fallback to current directory
+    None => PathBuf::from("calculator.sock"),
+  }
+}
+
+/// Binds the socket at `path`, replacing a stale socket left by a previous run
+pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixListener> {
+  let path = path.as_ref();
+  match UnixListener::bind(path) {
+    Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+      // A live daemon still accepts connections; a stale socket file does not
+      if UnixStream::connect(path).is_ok() {
+        return Err(err);
+      }
+      std::fs::remove_file(path)?;
+      UnixListener::bind(path)
+    }
+    result => result,
+  }
+}
+
+/// Accepts clients on `listener` forever, serving each on its own thread
+pub fn serve(listener: UnixListener) -> io::Result<()> {
+  for stream in listener.incoming() {
+    let stream = stream?;
+    thread::spawn(move || {
+      if let Err(err) = handle_client(stream) {
+        eprintln!("Daemon connection error: {}", err);
+      }
+    });
+  }
+  Ok(())
+}
+
+/// Answers queries from a single client until it disconnects
+fn handle_client(stream: UnixStream) -> io::Result<()> {
+  let reader = BufReader::new(stream.try_clone()?);
+  crate::jsonrpc::serve_lines(reader, stream, |line| vec![respond(line)])
+}
+
+/// Evaluates one line of the protocol and returns the reply line
+pub fn respond(line: &str) -> String {
+  match crate::metrics::evaluate(line.trim()) {
+    Ok(value) => value.to_string(),
+    Err(err) => format!("error: {}", err),
+  }
+}
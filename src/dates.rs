@@ -0,0 +1,346 @@
+//! # Dates
+//!
+//! Calendar dates in the proleptic Gregorian calendar, from year 1 to 9999.
+//! A date is written `"2024-07-15"` or `date(2024, 7, 15)`, and `today` is the
+//! current date in UTC. Durations are whole days: `90 days`, `2 weeks`, or
+//! `90d` in an expression with a date, where it can't mean an angle in
+//! degrees.
+//!
+//! Without the quotes, `2024-07-15` is a subtraction for
+//! [`evaluate_expression`](crate::evaluate_expression) and the other
+//! functions that give a number, so their results don't change. It's a date
+//! for [`evaluate`](crate::evaluate) and
+//! [`evaluate_to_string`](crate::evaluate_to_string), whose results can be
+//! dates.
+//!
+//! Underneath, a date is the number of days since 1970-01-01, so adding
+//! days to a date gives a later date and subtracting two dates gives the
+//! days between them, as does `days_between(from, to)`. `weekday(date)`
+//! names the day of the week; like `roman(x)` it produces text, so it's only
+//! available through [`evaluate_to_string`](crate::evaluate_to_string).
+//!
+//! ```
+//! use rust_calculator::dates::Date;
+//! use rust_calculator::{Value, evaluate, evaluate_expression, evaluate_to_string};
+//!
+//! assert_eq!(evaluate_expression("days_between(\"2024-01-01\", \"2024-07-15\")").unwrap(), 196.0);
+//! assert_eq!(evaluate_expression("2024-01-01 + 90").unwrap(), 2112.0);
+//! assert_eq!(evaluate("2024-01-01 + 90d").unwrap(), Value::Date(Date::new(2024, 3, 31).unwrap()));
+//! assert_eq!(evaluate_to_string("weekday(2024-07-15)").unwrap(), "Monday");
+//! ```
+
+use crate::ast::Expr;
+use crate::prelude::*;
+use crate::{CalculatorError, SpannedToken, Token};
+use alloc::borrow::Cow;
+use core::fmt;
+use core::ops::Range;
+
+/// Names of the days of the week, from Monday
+const WEEKDAYS: [&str; 7] = [
+  "Monday",
+  "Tuesday",
+  "Wednesday",
+  "Thursday",
+  "Friday",
+  "Saturday",
+  "Sunday",
+];
+
+/// Functions whose result is a date
+const DATE_FUNCTIONS: [&str; 2] = ["date", "today"];
+
+/// A day of the Gregorian calendar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Date {
+  pub year: i32,
+  pub month: u32,
+  pub day: u32,
+}
+
+impl Date {
+  /// The date with these parts, or `None` if there's no such day from year
+  /// 1 to 9999
+  pub fn new(year: i32, month: u32, day: u32) -> Option<Date> {
+    let valid = (1..=9999).contains(&year)
+      && (1..=12).contains(&month)
+      && (1..=days_in_month(year, month)).contains(&day);
+    valid.then_some(Date { year, month, day })
+  }
+
+  /// The date `days` days after 1970-01-01, or `None` outside years 1 to 9999
+  pub fn from_days(days: i64) -> Option<Date> {
+    // Counting from 0000-03-01 puts each leap day at the end of its year
+    let days = days.checked_add(719_468)?;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+      (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+      month_from_march + 3
+    } else {
+      month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + (month <= 2) as i64;
+    Date::new(i32::try_from(year).ok()?, month as u32, day as u32)
+  }
+
+  /// The number of days since 1970-01-01, negative for earlier dates
+  pub fn to_days(self) -> i64 {
+    let year = self.year as i64 - (self.month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (self.month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + self.day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+  }
+
+  /// The name of the day of the week, such as `Monday`
+  pub fn weekday(self) -> &'static str {
+    // 1970-01-01 was a Thursday
+    WEEKDAYS[(self.to_days() + 3).rem_euclid(7) as usize]
+  }
+
+  /// The current date in UTC
+  #[cfg(feature = "std")]
+  pub fn today() -> Date {
+    let seconds = std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .map_or(0, |elapsed| elapsed.as_secs());
+    Date::from_days((seconds / 86_400) as i64).expect("the current date is before year 10000")
+  }
+}
+
+impl fmt::Display for Date {
+  /// Writes the date as in ISO 8601, e.g. `2024-07-15`
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+  }
+}
+
+/// Checks whether a tree's value is a date rather than a number: a date
+/// function, a date plus or minus days, or days plus a date
+pub(crate) fn is_date(tree: &Expr) -> bool {
+  match tree {
+    Expr::Function { name, .. } => DATE_FUNCTIONS.contains(&name.as_str()),
+    Expr::Binary {
+      operator,
+      left,
+      right,
+    } => match operator.as_str() {
+      "+" => is_date(left) != is_date(right),
+      "-" => is_date(left) && !is_date(right),
+      _ => false,
+    },
+    _ => false,
+  }
+}
+
+/// Reads the value of a date expression, a number of days since 1970-01-01,
+/// as a date
+pub(crate) fn to_date(days: f64) -> Result<Date, CalculatorError> {
+  if days.fract() != 0.0 || days.abs() > 1e7 {
+    return Err(out_of_range());
+  }
+  Date::from_days(days as i64).ok_or_else(out_of_range)
+}
+
+/// Evaluates `date(year, month, day)` as days since 1970-01-01
+pub(crate) fn date(year: f64, month: f64, day: f64) -> Result<f64, CalculatorError> {
+  let whole = [year, month, day].iter().all(|part| part.fract() == 0.0);
+  // Out of range parts saturate, so they're never valid
+  let date = if whole {
+    Date::new(year as i32, month as u32, day as u32)
+  } else {
+    None
+  };
+  match date {
+    Some(date) => Ok(date.to_days() as f64),
+    None => Err(CalculatorError::ArgumentError(format!(
+      "No such date: {}-{}-{}",
+      year, month, day
+    ))),
+  }
+}
+
+/// The current date as days since 1970-01-01
+#[cfg(feature = "std")]
+pub(crate) fn today() -> Result<f64, CalculatorError> {
+  Ok(Date::today().to_days() as f64)
+}
+
+/// There is no clock without the `std` feature
+#[cfg(not(feature = "std"))]
+pub(crate) fn today() -> Result<f64, CalculatorError> {
+  Err(CalculatorError::ArgumentError(
+    "today needs the std feature".to_string(),
+  ))
+}
+
+/// Names the day of the week of a date given as days since 1970-01-01
+pub fn weekday(days: f64) -> Result<String, CalculatorError> {
+  to_date(days).map(|date| date.weekday().to_string())
+}
+
+/// Puts bare date literals such as `2024-07-15` in quotes, for the
+/// functions whose results can be dates. Text already in quotes is left as
+/// it is.
+pub(crate) fn quote_literals(expression: &str) -> Cow<'_, str> {
+  let bytes = expression.as_bytes();
+  // Parts of numbers and names can't be next to a literal
+  let joined = |index: usize| {
+    bytes
+      .get(index)
+      .is_some_and(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'_' | b'"'))
+  };
+  let literal_at = |start: usize| {
+    let Some(text) = bytes.get(start..start + 10) else {
+      return false;
+    };
+    text.iter().enumerate().all(|(index, byte)| match index {
+      4 | 7 => *byte == b'-',
+      _ => byte.is_ascii_digit(),
+    }) && (start == 0 || !joined(start - 1))
+      && !joined(start + 10)
+  };
+
+  let mut quoted = String::new();
+  let mut copied = 0;
+  let mut in_quotes = false;
+  let mut index = 0;
+  while index < bytes.len() {
+    if bytes[index] == b'"' {
+      in_quotes = !in_quotes;
+    } else if !in_quotes && literal_at(index) {
+      quoted.push_str(&expression[copied..index]);
+      quoted.push('"');
+      quoted.push_str(&expression[index..index + 10]);
+      quoted.push('"');
+      index += 10;
+      copied = index;
+      continue;
+    }
+    index += 1;
+  }
+  if copied == 0 {
+    return Cow::Borrowed(expression);
+  }
+  quoted.push_str(&expression[copied..]);
+  Cow::Owned(quoted)
+}
+
+/// Rewrites date literals in quotes such as `"2024-07-15"`, which the
+/// tokenizer reads as a subtraction in parentheses, as calls of `date`. In an
+/// expression with a date, a number of degrees such as `90d` then becomes a
+/// number of days.
+pub(crate) fn read_literals(
+  expression: &str,
+  tokens: Vec<SpannedToken>,
+) -> Result<Vec<SpannedToken>, CalculatorError> {
+  let digits = |span: &Range<usize>, count: usize| {
+    let text = &expression[span.clone()];
+    text.len() == count && text.bytes().all(|byte| byte.is_ascii_digit())
+  };
+  let quote = |span: &Range<usize>| &expression[span.clone()] == "\"";
+  let mut rewritten = Vec::with_capacity(tokens.len());
+  let mut index = 0;
+  while index < tokens.len() {
+    // A quote reads as two parentheses
+    if let [
+      (Token::LeftParen, open_span),
+      (Token::LeftParen, _),
+      (Token::Number(year), year_span),
+      (Token::Operator(first), first_span),
+      (Token::Number(month), month_span),
+      (Token::Operator(second), second_span),
+      (Token::Number(day), day_span),
+      (Token::RightParen, _),
+      (Token::RightParen, close_span),
+      ..,
+    ] = &tokens[index..]
+    {
+      let adjacent = open_span.end == year_span.start
+        && year_span.end == first_span.start
+        && first_span.end == month_span.start
+        && month_span.end == second_span.start
+        && second_span.end == day_span.start
+        && day_span.end == close_span.start;
+      if first == "-"
+        && second == "-"
+        && adjacent
+        && quote(open_span)
+        && quote(close_span)
+        && digits(year_span, 4)
+        && digits(month_span, 2)
+        && digits(day_span, 2)
+      {
+        let span = open_span.start..close_span.end;
+        date(*year, *month, *day).map_err(|err| err.at(span.clone()))?;
+        let comma = || Token::Operator(",".to_string());
+        rewritten.extend(
+          [
+            Token::Function("date".to_string()),
+            Token::LeftParen,
+            Token::Number(*year),
+            comma(),
+            Token::Number(*month),
+            comma(),
+            Token::Number(*day),
+            Token::RightParen,
+          ]
+          .into_iter()
+          .map(|token| (token, span.clone())),
+        );
+        index += 9;
+        continue;
+      }
+    }
+    rewritten.push(tokens[index].clone());
+    index += 1;
+  }
+
+  let has_date = rewritten.iter().any(
+    |(token, _)| matches!(token, Token::Function(name) if DATE_FUNCTIONS.contains(&name.as_str())),
+  );
+  if !has_date {
+    return Ok(rewritten);
+  }
+  Ok(
+    rewritten
+      .into_iter()
+      .flat_map(|(token, span)| {
+        let days = match &token {
+          Token::Number(_) => expression[span.clone()]
+            .strip_suffix('d')
+            .and_then(|number| number.parse::<f64>().ok()),
+          _ => None,
+        };
+        match days {
+          Some(days) => vec![
+            (Token::Number(days), span.clone()),
+            (Token::Function("days".to_string()), span),
+          ],
+          None => vec![(token, span)],
+        }
+      })
+      .collect(),
+  )
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+  match month {
+    2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+    2 => 28,
+    4 | 6 | 9 | 11 => 30,
+    _ => 31,
+  }
+}
+
+fn out_of_range() -> CalculatorError {
+  CalculatorError::ArgumentError("Dates must be whole days from year 1 to 9999".to_string())
+}
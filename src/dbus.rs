@@ -0,0 +1,54 @@
+//! # D-Bus Service
+//!
+//! Registers the calculator on the session bus so desktop launchers such as
+//! KRunner or GNOME search providers can query it. Enabled with the `dbus`
+//! cargo feature.
+//!
+//! The object at [`OBJECT_PATH`] implements the [`INTERFACE`] interface:
+//!
+//! - `Evaluate(s expression) -> d` - evaluate an expression
+//! - `GetHistory(u count) -> as` - the last `count` history entries
+
+use zbus::blocking::{Connection, connection};
+use zbus::{fdo, interface};
+
+/// Well-known bus name claimed by the service
+pub const BUS_NAME: &str = "io.github.thickkoezz.Calculator";
+
+/// Object path the calculator is served at
+pub const OBJECT_PATH: &str = "/io/github/thickkoezz/Calculator";
+
+/// Name of the D-Bus interface
+pub const INTERFACE: &str = "io.github.thickkoezz.Calculator1";
+
+/// The object exported on the bus
+#[derive(Debug, Default)]
+pub struct CalculatorService;
+
+#[interface(name = "io.github.thickkoezz.Calculator1")]
+impl CalculatorService {
+  /// Evaluates an expression; calculator errors become D-Bus errors
+  pub fn evaluate(&self, expression: &str) -> fdo::Result<f64> {
+    let result = crate::metrics::evaluate(expression)
+      .map_err(|err| fdo::Error::InvalidArgs(err.to_string()))?;
+    // History is best effort so a read-only data directory doesn't fail the call
+    let _ = crate::add_to_history(expression, result);
+    Ok(result)
+  }
+
+  /// Returns the last `count` history entries
+  pub fn get_history(&self, count: u32) -> fdo::Result<Vec<String>> {
+    crate::get_history(count as usize).map_err(|err| fdo::Error::IOError(err.to_string()))
+  }
+}
+
+/// Connects to the session bus and serves the calculator.
+///
+/// Requests are handled in the background for as long as the returned
+/// connection is kept alive.
+pub fn serve() -> zbus::Result<Connection> {
+  connection::Builder::session()?
+    .name(BUS_NAME)?
+    .serve_at(OBJECT_PATH, CalculatorService)?
+    .build()
+}
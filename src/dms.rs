@@ -0,0 +1,99 @@
+//! # Degrees, Minutes and Seconds
+//!
+//! Sexagesimal angles, as surveying and navigation write them. A literal such
+//! as `45°30'15"` or `45d30m15s` can appear anywhere in an expression; it's
+//! read as an angle in the current angle unit, so `sin(30°)` is 0.5 whatever
+//! the angle mode. Minutes and seconds are optional, and the parts can't be
+//! separated by spaces.
+//!
+//! `to_dms(x)` writes an angle back in degrees, minutes and seconds; like
+//! `roman(x)` it produces text, so it's only available through
+//! [`evaluate_to_string`](crate::evaluate_to_string).
+//!
+//! ```
+//! use rust_calculator::dms;
+//!
+//! assert_eq!(dms::parse("45°30'15\""), Some(45.50416666666667));
+//! assert_eq!(dms::to_dms(45.50416666666667).unwrap(), "45°30'15\"");
+//! ```
+
+use crate::CalculatorError;
+use crate::prelude::*;
+
+/// The marks that may follow degrees, minutes and seconds, and how many of
+/// each make a degree
+const PARTS: [(&[char], f64); 3] = [
+  (&['°', 'd'], 1.0),
+  (&['\'', '′', 'm'], 60.0),
+  (&['"', '″', 's'], 3600.0),
+];
+
+/// Reads a literal such as `45°30'15"`, `-12d30m` or `7.5°` as decimal
+/// degrees, or `None` if it isn't one. It must start with degrees, and
+/// minutes and seconds must be less than 60.
+pub fn parse(literal: &str) -> Option<f64> {
+  let (sign, mut rest) = match literal.strip_prefix('-') {
+    Some(rest) => (-1.0, rest),
+    None => (1.0, literal),
+  };
+  let mut degrees = 0.0;
+  for (index, (marks, per_degree)) in PARTS.iter().enumerate() {
+    let number_end = rest.find(|ch: char| !ch.is_ascii_digit() && ch != '.')?;
+    let mark = rest[number_end..].chars().next()?;
+    if !marks.contains(&mark) {
+      // Minutes may be left out, but degrees may not
+      if index == 0 {
+        return None;
+      }
+      continue;
+    }
+    let value: f64 = rest[..number_end].parse().ok()?;
+    if index > 0 && value >= 60.0 {
+      return None;
+    }
+    degrees += value / per_degree;
+    rest = &rest[number_end + mark.len_utf8()..];
+    if rest.is_empty() {
+      return Some(sign * degrees);
+    }
+  }
+  None
+}
+
+/// Writes decimal degrees as degrees, minutes and seconds, with seconds
+/// rounded to two decimals
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::dms::to_dms;
+///
+/// assert_eq!(to_dms(-12.5).unwrap(), "-12°30'0\"");
+/// assert_eq!(to_dms(0.123).unwrap(), "0°7'22.8\"");
+/// ```
+pub fn to_dms(degrees: f64) -> Result<String, CalculatorError> {
+  // Beyond this, hundredths of a second no longer fit in a u64
+  if !degrees.is_finite() || degrees.abs() >= 1e13 {
+    return Err(CalculatorError::ArgumentError(format!(
+      "Cannot write {} in degrees, minutes and seconds",
+      degrees
+    )));
+  }
+  // Rounding in hundredths of a second carries into minutes and degrees,
+  // so 59.999" becomes a whole minute
+  let hundredths = (degrees.abs() * 360_000.0).round() as u64;
+  let (whole, rest) = (hundredths / 360_000, hundredths % 360_000);
+  let (minutes, seconds) = (rest / 6000, rest % 6000);
+  let seconds = match seconds % 100 {
+    0 => format!("{}", seconds / 100),
+    fraction => format!("{}.{:02}", seconds / 100, fraction)
+      .trim_end_matches('0')
+      .to_string(),
+  };
+  let sign = if degrees < 0.0 && hundredths > 0 {
+    "-"
+  } else {
+    ""
+  };
+  Ok(format!("{}{}°{}'{}\"", sign, whole, minutes, seconds))
+}
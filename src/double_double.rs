@@ -0,0 +1,513 @@
+//! # Double-Double Arithmetic
+//!
+//! An extended-precision number held as the unevaluated sum of two `f64`s,
+//! which gives 106 bits, about 31 significant digits, with the range of an
+//! `f64`. It's much faster than arbitrary precision and enough to see the
+//! digits `f64` rounds away.
+//!
+//! [`evaluate_extended`](crate::evaluate_extended) evaluates expressions in
+//! it through the generic evaluator of [`crate::real`].
+//!
+//! ```
+//! use rust_calculator::DoubleDouble;
+//!
+//! let third = DoubleDouble::from(1.0) / DoubleDouble::from(3.0);
+//! assert_eq!(third.to_string(), "0.3333333333333333333333333333333");
+//! let tenth: DoubleDouble = "0.1".parse().unwrap();
+//! assert_eq!((tenth + "0.2".parse().unwrap()).to_string(), "0.3");
+//! ```
+
+use crate::prelude::*;
+use core::cmp::Ordering;
+use core::fmt;
+use core::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use core::str::FromStr;
+
+/// The significant digits shown by [`Display`](fmt::Display)
+const DIGITS: usize = 31;
+
+/// A number `hi + lo`, where `lo` is at most half a unit in the last place of `hi`
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct DoubleDouble {
+  hi: f64,
+  lo: f64,
+}
+
+const PI: DoubleDouble = DoubleDouble {
+  hi: core::f64::consts::PI,
+  lo: 1.2246467991473532e-16,
+};
+const FRAC_PI_2: DoubleDouble = DoubleDouble {
+  hi: core::f64::consts::FRAC_PI_2,
+  lo: 6.123233995736766e-17,
+};
+const E: DoubleDouble = DoubleDouble {
+  hi: core::f64::consts::E,
+  lo: 1.4456468917292502e-16,
+};
+const LN_2: DoubleDouble = DoubleDouble {
+  hi: core::f64::consts::LN_2,
+  lo: 2.3190468138462996e-17,
+};
+
+/// `a + b` and its rounding error
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+  let sum = a + b;
+  let b_part = sum - a;
+  (sum, (a - (sum - b_part)) + (b - b_part))
+}
+
+/// `a + b` and its rounding error, when `|a| >= |b|`
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+  let sum = a + b;
+  (sum, b - (sum - a))
+}
+
+/// `a * b` and its rounding error
+fn two_prod(a: f64, b: f64) -> (f64, f64) {
+  let product = a * b;
+  (product, a.mul_add(b, -product))
+}
+
+impl DoubleDouble {
+  /// The leading `f64`, which is the nearest `f64` to the number
+  pub fn hi(self) -> f64 {
+    self.hi
+  }
+
+  /// The rounding error left by `hi`
+  pub fn lo(self) -> f64 {
+    self.lo
+  }
+
+  /// Normalizes a sum, dropping the error term of non-finite numbers
+  fn from_sum((hi, lo): (f64, f64)) -> DoubleDouble {
+    if hi.is_finite() {
+      DoubleDouble { hi, lo }
+    } else {
+      DoubleDouble::from(hi)
+    }
+  }
+
+  fn mul_f64(self, factor: f64) -> DoubleDouble {
+    let (product, error) = two_prod(self.hi, factor);
+    if !product.is_finite() {
+      return DoubleDouble::from(product);
+    }
+    DoubleDouble::from_sum(quick_two_sum(product, error + self.lo * factor))
+  }
+
+  /// Multiplies by 2^`exponent`, which is exact while in range
+  fn scale2(self, exponent: i32) -> DoubleDouble {
+    // In two steps, since 2^exponent alone may not be an f64
+    let half = exponent / 2;
+    let (first, second) = (2f64.powi(half), 2f64.powi(exponent - half));
+    DoubleDouble::from_sum((self.hi * first * second, self.lo * first * second))
+  }
+
+  /// Multiplies by 10^`exponent`
+  fn scale10(mut self, mut exponent: i32) -> DoubleDouble {
+    // In steps, so powers of ten beyond the range of f64 aren't needed
+    while exponent != 0 {
+      let step = exponent.clamp(-300, 300);
+      let power = DoubleDouble::from(10.0).powi(step.unsigned_abs());
+      self = if step > 0 { self * power } else { self / power };
+      exponent -= step;
+    }
+    self
+  }
+
+  /// Raises to a whole power by repeated squaring
+  fn powi(self, mut exponent: u32) -> DoubleDouble {
+    let (mut square, mut result) = (self, DoubleDouble::from(1.0));
+    while exponent > 0 {
+      if exponent & 1 == 1 {
+        result = result * square;
+      }
+      exponent >>= 1;
+      if exponent > 0 {
+        square = square * square;
+      }
+    }
+    result
+  }
+
+  pub fn abs(self) -> DoubleDouble {
+    if self.hi < 0.0 { -self } else { self }
+  }
+
+  pub fn floor(self) -> DoubleDouble {
+    let hi = self.hi.floor();
+    if hi != self.hi {
+      return DoubleDouble::from(hi);
+    }
+    DoubleDouble::from_sum(quick_two_sum(hi, self.lo.floor()))
+  }
+
+  pub fn ceil(self) -> DoubleDouble {
+    -(-self).floor()
+  }
+
+  pub fn sqrt(self) -> DoubleDouble {
+    if self.hi <= 0.0 || !self.hi.is_finite() {
+      return DoubleDouble::from(self.hi.sqrt());
+    }
+    // One Newton step doubles the 53 bits of the f64 square root
+    let root = DoubleDouble::from(self.hi.sqrt());
+    root + (self - root * root) / root.mul_f64(2.0)
+  }
+
+  pub fn exp(self) -> DoubleDouble {
+    if self.hi > 709.8 {
+      return DoubleDouble::from(f64::INFINITY);
+    }
+    if self.hi < -745.2 {
+      return DoubleDouble::default();
+    }
+    // e^x = 2^k e^r with |r| <= ln 2 / 2, and e^r is (e^(r/1024))^1024
+    let k = (self.hi / LN_2.hi).round();
+    let reduced = (self - LN_2.mul_f64(k)).scale2(-10);
+    let mut term = reduced;
+    let mut sum = reduced;
+    for i in 2..30 {
+      term = term * reduced / DoubleDouble::from(i as f64);
+      sum = sum + term;
+      if term.hi.abs() <= sum.hi.abs() * 1e-33 {
+        break;
+      }
+    }
+    // Squaring e^r - 1 as (s + 1)^2 - 1 = s(s + 2) keeps its small digits
+    for _ in 0..10 {
+      sum = sum * (sum + DoubleDouble::from(2.0));
+    }
+    (sum + DoubleDouble::from(1.0)).scale2(k as i32)
+  }
+
+  pub fn ln(self) -> DoubleDouble {
+    if self.hi <= 0.0 || !self.hi.is_finite() {
+      return DoubleDouble::from(self.hi.ln());
+    }
+    // Newton's method on e^y = x, from the f64 logarithm
+    let guess = DoubleDouble::from(self.hi.ln());
+    guess + (self * (-guess).exp() - DoubleDouble::from(1.0))
+  }
+
+  /// The sine and cosine of the reduced argument, and which quarter turn
+  /// the angle was in
+  fn sin_cos(self) -> (DoubleDouble, DoubleDouble, u8) {
+    let quarters = (self / FRAC_PI_2).hi.round();
+    let reduced = self - FRAC_PI_2.mul_f64(quarters);
+    let square = reduced * reduced;
+    let (mut sin, mut cos) = (reduced, DoubleDouble::from(1.0));
+    let (mut sin_term, mut cos_term) = (reduced, DoubleDouble::from(1.0));
+    for i in 1..30 {
+      let n = 2.0 * i as f64;
+      sin_term = -sin_term * square / DoubleDouble::from(n * (n + 1.0));
+      cos_term = -cos_term * square / DoubleDouble::from((n - 1.0) * n);
+      sin = sin + sin_term;
+      cos = cos + cos_term;
+      if cos_term.hi.abs() <= 1e-33 {
+        break;
+      }
+    }
+    (sin, cos, ((quarters % 4.0 + 4.0) % 4.0) as u8)
+  }
+
+  pub fn sin(self) -> DoubleDouble {
+    if !self.hi.is_finite() {
+      return DoubleDouble::from(f64::NAN);
+    }
+    match self.sin_cos() {
+      (sin, _, 0) => sin,
+      (_, cos, 1) => cos,
+      (sin, _, 2) => -sin,
+      (_, cos, _) => -cos,
+    }
+  }
+
+  pub fn cos(self) -> DoubleDouble {
+    if !self.hi.is_finite() {
+      return DoubleDouble::from(f64::NAN);
+    }
+    match self.sin_cos() {
+      (_, cos, 0) => cos,
+      (sin, _, 1) => -sin,
+      (_, cos, 2) => -cos,
+      (sin, _, _) => sin,
+    }
+  }
+
+  pub fn tan(self) -> DoubleDouble {
+    self.sin() / self.cos()
+  }
+
+  pub fn atan(self) -> DoubleDouble {
+    if self.hi.is_nan() {
+      return self;
+    }
+    // atan x = ±π/2 - atan(1/x) keeps the Newton step below 1, where it converges well
+    if self.hi.abs() > 1.0 {
+      let right_angle = if self.hi > 0.0 { FRAC_PI_2 } else { -FRAC_PI_2 };
+      return right_angle - (DoubleDouble::from(1.0) / self).atan();
+    }
+    // Newton's method on tan y = x, from the f64 arctangent
+    let guess = DoubleDouble::from(self.hi.atan());
+    let (sin, cos) = (guess.sin(), guess.cos());
+    guess + (self * cos - sin) * cos
+  }
+
+  pub fn asin(self) -> DoubleDouble {
+    let one = DoubleDouble::from(1.0);
+    match self.abs().partial_cmp(&one) {
+      Some(Ordering::Less) => (self / ((one - self) * (one + self)).sqrt()).atan(),
+      Some(Ordering::Equal) if self.hi > 0.0 => FRAC_PI_2,
+      Some(Ordering::Equal) => -FRAC_PI_2,
+      _ => DoubleDouble::from(f64::NAN),
+    }
+  }
+
+  pub fn acos(self) -> DoubleDouble {
+    let one = DoubleDouble::from(1.0);
+    if self == -one {
+      return PI;
+    }
+    if self.abs() > one || self.hi.is_nan() {
+      return DoubleDouble::from(f64::NAN);
+    }
+    // Unlike π/2 - asin x, this keeps its digits near x = 1
+    ((one - self) / (one + self)).sqrt().atan().mul_f64(2.0)
+  }
+}
+
+impl From<f64> for DoubleDouble {
+  fn from(value: f64) -> Self {
+    DoubleDouble { hi: value, lo: 0.0 }
+  }
+}
+
+impl From<DoubleDouble> for f64 {
+  fn from(value: DoubleDouble) -> Self {
+    value.hi + value.lo
+  }
+}
+
+impl Neg for DoubleDouble {
+  type Output = DoubleDouble;
+
+  fn neg(self) -> DoubleDouble {
+    DoubleDouble {
+      hi: -self.hi,
+      lo: -self.lo,
+    }
+  }
+}
+
+impl Add for DoubleDouble {
+  type Output = DoubleDouble;
+
+  fn add(self, other: DoubleDouble) -> DoubleDouble {
+    let (sum, error) = two_sum(self.hi, other.hi);
+    if !sum.is_finite() {
+      return DoubleDouble::from(sum);
+    }
+    let (low_sum, low_error) = two_sum(self.lo, other.lo);
+    let (sum, error) = quick_two_sum(sum, error + low_sum);
+    DoubleDouble::from_sum(quick_two_sum(sum, error + low_error))
+  }
+}
+
+impl Sub for DoubleDouble {
+  type Output = DoubleDouble;
+
+  fn sub(self, other: DoubleDouble) -> DoubleDouble {
+    self + -other
+  }
+}
+
+impl Mul for DoubleDouble {
+  type Output = DoubleDouble;
+
+  fn mul(self, other: DoubleDouble) -> DoubleDouble {
+    let (product, error) = two_prod(self.hi, other.hi);
+    if !product.is_finite() {
+      return DoubleDouble::from(product);
+    }
+    let error = error + (self.hi * other.lo + self.lo * other.hi);
+    DoubleDouble::from_sum(quick_two_sum(product, error))
+  }
+}
+
+impl Div for DoubleDouble {
+  type Output = DoubleDouble;
+
+  fn div(self, other: DoubleDouble) -> DoubleDouble {
+    let quotient = self.hi / other.hi;
+    if !quotient.is_finite() || !other.hi.is_finite() {
+      return DoubleDouble::from(quotient);
+    }
+    // Long division, one f64 of the quotient at a time
+    let remainder = self - other.mul_f64(quotient);
+    let second = remainder.hi / other.hi;
+    let remainder = remainder - other.mul_f64(second);
+    let third = remainder.hi / other.hi;
+    DoubleDouble::from_sum(quick_two_sum(quotient, second)) + DoubleDouble::from(third)
+  }
+}
+
+impl Rem for DoubleDouble {
+  type Output = DoubleDouble;
+
+  /// The remainder of division truncated toward zero, as `%` on `f64`
+  fn rem(self, other: DoubleDouble) -> DoubleDouble {
+    let quotient = self / other;
+    let truncated = if quotient.hi < 0.0 {
+      quotient.ceil()
+    } else {
+      quotient.floor()
+    };
+    self - other * truncated
+  }
+}
+
+impl FromStr for DoubleDouble {
+  type Err = ();
+
+  /// Reads a decimal such as `-12.5` or `1.5e-7` to full precision
+  fn from_str(text: &str) -> Result<Self, ()> {
+    let (negative, text) = match text.strip_prefix('-') {
+      Some(rest) => (true, rest),
+      None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+      Some((mantissa, exponent)) => (mantissa, exponent.parse::<i32>().map_err(|_| ())?),
+      None => (text, 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if whole.is_empty() && fraction.is_empty() {
+      return Err(());
+    }
+    let mut value = DoubleDouble::default();
+    for digit in whole.chars().chain(fraction.chars()) {
+      let digit = digit.to_digit(10).ok_or(())?;
+      value = value.mul_f64(10.0) + DoubleDouble::from(digit as f64);
+    }
+    let value = value.scale10(exponent.saturating_sub(fraction.len() as i32));
+    Ok(if negative { -value } else { value })
+  }
+}
+
+impl fmt::Display for DoubleDouble {
+  /// Writes the number with up to 31 significant digits, in scientific
+  /// notation when it's very large or small
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if !self.hi.is_finite() || self.hi == 0.0 {
+      return write!(f, "{}", self.hi);
+    }
+    if self.hi < 0.0 {
+      f.write_str("-")?;
+    }
+    let mut exponent = self.hi.abs().log10().floor() as i32;
+    let mut scaled = self.abs().scale10(-exponent);
+    if scaled.hi >= 10.0 {
+      scaled = scaled / DoubleDouble::from(10.0);
+      exponent += 1;
+    } else if scaled.hi < 1.0 {
+      scaled = scaled.mul_f64(10.0);
+      exponent -= 1;
+    }
+
+    // One digit more than shown, to round with
+    let mut digits = Vec::with_capacity(DIGITS + 1);
+    for _ in 0..=DIGITS {
+      let digit = scaled.floor();
+      digits.push(digit.hi.clamp(0.0, 9.0) as u8);
+      scaled = (scaled - digit).mul_f64(10.0);
+    }
+    if digits.pop().is_some_and(|digit| digit >= 5) {
+      let carried = digits.iter_mut().rev().all(|digit| {
+        *digit = (*digit + 1) % 10;
+        *digit == 0
+      });
+      if carried {
+        digits.insert(0, 1);
+        digits.pop();
+        exponent += 1;
+      }
+    }
+    while digits.len() > 1 && digits.last() == Some(&0) {
+      digits.pop();
+    }
+    let digits: String = digits.iter().map(|digit| (b'0' + digit) as char).collect();
+
+    if !(-7..DIGITS as i32).contains(&exponent) {
+      let (first, rest) = digits.split_at(1);
+      return match rest {
+        "" => write!(f, "{}e{}", first, exponent),
+        _ => write!(f, "{}.{}e{}", first, rest, exponent),
+      };
+    }
+    if exponent < 0 {
+      return write!(f, "0.{}{}", "0".repeat((-exponent - 1) as usize), digits);
+    }
+    let whole_digits = exponent as usize + 1;
+    if digits.len() <= whole_digits {
+      write!(f, "{}{}", digits, "0".repeat(whole_digits - digits.len()))
+    } else {
+      let (whole, fraction) = digits.split_at(whole_digits);
+      write!(f, "{}.{}", whole, fraction)
+    }
+  }
+}
+
+impl crate::real::Real for DoubleDouble {
+  const NAME: &'static str = "double-double arithmetic";
+
+  fn from_f64(value: f64) -> Self {
+    DoubleDouble::from(value)
+  }
+  fn to_f64(self) -> f64 {
+    f64::from(self)
+  }
+  fn parse(text: &str) -> Option<Self> {
+    text.parse().ok()
+  }
+  fn pi() -> Self {
+    PI
+  }
+  fn e() -> Self {
+    E
+  }
+  fn floor(self) -> Self {
+    DoubleDouble::floor(self)
+  }
+  fn ceil(self) -> Self {
+    DoubleDouble::ceil(self)
+  }
+  fn sqrt(self) -> Self {
+    DoubleDouble::sqrt(self)
+  }
+  fn exp(self) -> Self {
+    DoubleDouble::exp(self)
+  }
+  fn ln(self) -> Self {
+    DoubleDouble::ln(self)
+  }
+  fn sin(self) -> Self {
+    DoubleDouble::sin(self)
+  }
+  fn cos(self) -> Self {
+    DoubleDouble::cos(self)
+  }
+  fn tan(self) -> Self {
+    DoubleDouble::tan(self)
+  }
+  fn asin(self) -> Self {
+    DoubleDouble::asin(self)
+  }
+  fn acos(self) -> Self {
+    DoubleDouble::acos(self)
+  }
+  fn atan(self) -> Self {
+    DoubleDouble::atan(self)
+  }
+}
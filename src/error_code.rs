@@ -0,0 +1,159 @@
+//! # Error Codes
+//!
+//! A stable, machine-readable classification of [`CalculatorError`]s, for
+//! front-ends that translate messages or handle some errors specially. The
+//! messages may be reworded between releases; the codes and their
+//! [`name`](ErrorCode::name)s won't be.
+//!
+//! ```
+//! use rust_calculator::{ErrorCode, evaluate_expression};
+//!
+//! let err = evaluate_expression("4 / (2 - 2)").unwrap_err();
+//! assert_eq!(err.code(), ErrorCode::DivisionByZero);
+//! assert_eq!(err.code().name(), "division_by_zero");
+//!
+//! let err = evaluate_expression("foo(3)").unwrap_err();
+//! assert_eq!(err.code(), ErrorCode::UnknownFunction { name: "foo".to_string() });
+//! ```
+
+use crate::CalculatorError;
+use crate::prelude::*;
+use core::fmt;
+
+/// What went wrong, independent of how the message words it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+  /// The expression has no tokens
+  EmptyExpression,
+  /// A name or symbol that isn't a number, operator, function, constant or variable
+  UnknownToken { token: String },
+  /// A call to a function that doesn't exist
+  UnknownFunction { name: String },
+  /// An operator that doesn't exist
+  UnknownOperator { operator: String },
+  /// A parenthesis without its partner
+  MismatchedParens,
+  /// An operator or function without the operands it needs
+  MissingOperand,
+  /// Operands with no operator between them
+  TooManyOperands,
+  /// Any other parse or syntax error
+  InvalidSyntax,
+  /// Division by zero
+  DivisionByZero,
+  /// Modulo by zero
+  ModuloByZero,
+  /// Any other math error
+  MathError,
+  /// A function argument outside the function's real domain, such as `sqrt(-4)`
+  DomainError,
+  /// Any other unacceptable function argument
+  InvalidArgument,
+  /// A result too large to represent, or not a number
+  Overflow,
+  /// The evaluation went past one of its [`limits`](crate::limits)
+  LimitExceeded,
+  /// A definition used the name of a constant or function
+  ReservedName { name: String },
+  /// Errors of the surroundings rather than the expression, such as I/O errors
+  Other,
+}
+
+/// Messages of the argument errors that [`ErrorCode::DomainError`] covers
+const DOMAIN_ERRORS: [&str; 8] = [
+  "Cannot calculate square root of negative number",
+  "Inverse sine argument must be between -1 and 1",
+  "Inverse cosine argument must be between -1 and 1",
+  "Cannot calculate logarithm of non-positive number",
+  "Cannot calculate natural logarithm of non-positive number",
+  "Cannot raise a negative number to a fractional power",
+  "Cannot take an even or fractional root of a negative number",
+  "Cannot calculate logarithm of zero",
+];
+
+impl ErrorCode {
+  /// The code's stable identifier in snake case, e.g. `division_by_zero`,
+  /// suitable as a translation key
+  pub fn name(&self) -> &'static str {
+    match self {
+      ErrorCode::EmptyExpression => "empty_expression",
+      ErrorCode::UnknownToken { .. } => "unknown_token",
+      ErrorCode::UnknownFunction { .. } => "unknown_function",
+      ErrorCode::UnknownOperator { .. } => "unknown_operator",
+      ErrorCode::MismatchedParens => "mismatched_parens",
+      ErrorCode::MissingOperand => "missing_operand",
+      ErrorCode::TooManyOperands => "too_many_operands",
+      ErrorCode::InvalidSyntax => "invalid_syntax",
+      ErrorCode::DivisionByZero => "division_by_zero",
+      ErrorCode::ModuloByZero => "modulo_by_zero",
+      ErrorCode::MathError => "math_error",
+      ErrorCode::DomainError => "domain_error",
+      ErrorCode::InvalidArgument => "invalid_argument",
+      ErrorCode::Overflow => "overflow",
+      ErrorCode::LimitExceeded => "limit_exceeded",
+      ErrorCode::ReservedName { .. } => "reserved_name",
+      ErrorCode::Other => "other",
+    }
+  }
+}
+
+impl fmt::Display for ErrorCode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl CalculatorError {
+  /// The stable code of the error, whether or not it's located
+  pub fn code(&self) -> ErrorCode {
+    match self.unspanned() {
+      CalculatorError::ParseError(message) | CalculatorError::SyntaxError(message) => {
+        syntax_code(message)
+      }
+      CalculatorError::MathError(message) => match message.as_str() {
+        "Division by zero" => ErrorCode::DivisionByZero,
+        "Modulo by zero" => ErrorCode::ModuloByZero,
+        _ => ErrorCode::MathError,
+      },
+      CalculatorError::ArgumentError(message) if DOMAIN_ERRORS.contains(&message.as_str()) => {
+        ErrorCode::DomainError
+      }
+      CalculatorError::ArgumentError(_) => ErrorCode::InvalidArgument,
+      CalculatorError::Overflow(_) => ErrorCode::Overflow,
+      CalculatorError::LimitExceeded(_) => ErrorCode::LimitExceeded,
+      CalculatorError::ReservedName(name) => ErrorCode::ReservedName { name: name.clone() },
+      CalculatorError::Other(_) => ErrorCode::Other,
+      CalculatorError::Spanned { .. } => unreachable!("unspanned errors aren't located"),
+    }
+  }
+}
+
+/// Classifies the message of a parse or syntax error
+fn syntax_code(message: &str) -> ErrorCode {
+  if let Some(token) = message.strip_prefix("Unknown token: ") {
+    ErrorCode::UnknownToken {
+      token: token.to_string(),
+    }
+  } else if let Some(name) = message.strip_prefix("Unknown function: ") {
+    ErrorCode::UnknownFunction {
+      name: name.to_string(),
+    }
+  } else if let Some(operator) = message.strip_prefix("Unknown operator: ") {
+    ErrorCode::UnknownOperator {
+      operator: operator.to_string(),
+    }
+  } else if message.starts_with("Mismatched parentheses") {
+    ErrorCode::MismatchedParens
+  } else if message.starts_with("Invalid expression: not enough operands")
+    || message == "Invalid expression: function without argument"
+  {
+    ErrorCode::MissingOperand
+  } else if message == "Invalid expression: too many operands" {
+    ErrorCode::TooManyOperands
+  } else if message == "Empty expression" {
+    ErrorCode::EmptyExpression
+  } else {
+    ErrorCode::InvalidSyntax
+  }
+}
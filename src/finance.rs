@@ -0,0 +1,158 @@
+//! # Financial Functions
+//!
+//! Time value of money with a rate per period, such as `0.05` for 5%:
+//!
+//! - `fv(rate, nper, pv)` is what `pv` grows to with compound interest over
+//!   `nper` periods
+//! - `pmt(rate, nper, pv)` is the payment per period that pays off a loan of
+//!   `pv` over `nper` periods
+//! - `npv(rate, cashflows)` is the net present value of a list of cash
+//!   flows, one per period, the first of them now and so not discounted
+//! - `irr(cashflows)` is the internal rate of return, the rate at which the
+//!   net present value of the cash flows is 0
+//!
+//! Unlike in spreadsheets, amounts keep the sign they're given, so a loan
+//! and its payments are both positive. Cash flows are negative for money
+//! paid out and positive for money coming in.
+//!
+//! ```
+//! use rust_calculator::{evaluate_expression, finance};
+//!
+//! assert_eq!(evaluate_expression("fv(0.5, 2, 100)").unwrap(), 225.0);
+//! assert_eq!(evaluate_expression("pmt(0, 10, 1000)").unwrap(), 100.0);
+//! assert_eq!(evaluate_expression("npv(0.5, [-100, 150])").unwrap(), 0.0);
+//! assert!((finance::irr(&[-100.0, 60.0, 60.0]).unwrap() - 0.1306623).abs() < 1e-7);
+//! ```
+
+use crate::prelude::*;
+use crate::{CalculatorError, Value};
+
+/// Rates tried in turn for a change of sign of the net present value, which
+/// [`irr`] then narrows down
+const IRR_BRACKETS: [f64; 15] = [
+  -0.99, -0.9, -0.5, -0.2, 0.0, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 100.0, 1000.0,
+];
+
+/// Checks whether `name` is a financial function taking a list of cash flows
+pub(crate) fn takes_list(name: &str) -> bool {
+  matches!(name, "npv" | "irr")
+}
+
+/// The value of `pv` after `nper` periods of compound interest at `rate`
+pub fn future_value(rate: f64, nper: f64, pv: f64) -> f64 {
+  pv * (1.0 + rate).powf(nper)
+}
+
+/// The payment per period that pays off a loan of `pv` at `rate` over `nper`
+/// periods
+pub fn payment(rate: f64, nper: f64, pv: f64) -> f64 {
+  if rate == 0.0 {
+    return pv / nper;
+  }
+  pv * rate / (1.0 - (1.0 + rate).powf(-nper))
+}
+
+/// The net present value at `rate` of cash flows one period apart, the
+/// first of them now
+pub fn npv(rate: f64, cashflows: &[f64]) -> f64 {
+  let factor = 1.0 + rate;
+  cashflows
+    .iter()
+    .rev()
+    .fold(0.0, |later, cashflow| cashflow + later / factor)
+}
+
+/// The internal rate of return of cash flows one period apart, or `None` if
+/// no rate above -100% makes their net present value 0. Of several such
+/// rates, the lowest found is given.
+pub fn irr(cashflows: &[f64]) -> Option<f64> {
+  let mut bracket = None;
+  for pair in IRR_BRACKETS.windows(2) {
+    let (low, high) = (npv(pair[0], cashflows), npv(pair[1], cashflows));
+    if low == 0.0 {
+      return Some(pair[0]);
+    }
+    if (low < 0.0) != (high < 0.0) {
+      bracket = Some((pair[0], pair[1], low < 0.0));
+      break;
+    }
+  }
+  // Halve the bracket until it can't get any narrower
+  let (mut low, mut high, low_negative) = bracket?;
+  for _ in 0..200 {
+    let middle = (low + high) / 2.0;
+    let value = npv(middle, cashflows);
+    if value == 0.0 {
+      return Some(middle);
+    }
+    if (value < 0.0) == low_negative {
+      low = middle;
+    } else {
+      high = middle;
+    }
+  }
+  Some((low + high) / 2.0)
+}
+
+/// Evaluates `fv` or `pmt`
+pub(crate) fn evaluate_call(function: &str, arguments: &[f64]) -> Result<f64, CalculatorError> {
+  let &[rate, nper, pv] = arguments else {
+    unreachable!("check_arity allows only 3 arguments");
+  };
+  check_rate(function, rate)?;
+  match function {
+    "fv" => Ok(future_value(rate, nper, pv)),
+    _ if nper <= 0.0 => Err(CalculatorError::ArgumentError(format!(
+      "{} needs a positive number of periods, got {}",
+      function, nper
+    ))),
+    _ => Ok(payment(rate, nper, pv)),
+  }
+}
+
+/// Evaluates `npv` or `irr`, whose cash flows are a list
+pub(crate) fn evaluate_with_list(
+  function: &str,
+  arguments: Vec<Value>,
+) -> Result<f64, CalculatorError> {
+  crate::check_arity(function, arguments.len())?;
+  match arguments.as_slice() {
+    [rate, cashflows] => {
+      let rate = rate.clone().into_number()?;
+      check_rate(function, rate)?;
+      Ok(npv(rate, &numbers(function, cashflows)?))
+    }
+    [cashflows] => irr(&numbers(function, cashflows)?).ok_or_else(|| {
+      CalculatorError::ArgumentError(
+        "irr found no rate of return; the cash flows need both signs".to_string(),
+      )
+    }),
+    _ => unreachable!("check_arity allows only 1 or 2 arguments"),
+  }
+}
+
+/// The cash flows passed to `function`, which must be a list of numbers
+fn numbers(function: &str, cashflows: &Value) -> Result<Vec<f64>, CalculatorError> {
+  match cashflows {
+    Value::List(items) => items
+      .iter()
+      .map(|item| item.clone().into_number())
+      .collect(),
+    other => Err(CalculatorError::ArgumentError(format!(
+      "{} needs a list of cash flows, got a {}",
+      function,
+      other.type_name()
+    ))),
+  }
+}
+
+/// Checks that a rate is above -100%, below which compounding has no meaning
+fn check_rate(function: &str, rate: f64) -> Result<(), CalculatorError> {
+  if rate <= -1.0 {
+    return Err(CalculatorError::ArgumentError(format!(
+      "{} needs a rate above -1, got {}",
+      function, rate
+    )));
+  }
+  Ok(())
+}
@@ -0,0 +1,211 @@
+//! # Result Formatting
+//!
+//! Turns results into text in a chosen [`Notation`] with a chosen precision.
+//! The CLI and GUI show results through [`format_value`], which is
+//! [`format_result`] with the default options and the `sigfigs` setting,
+//! written with the separators of the `locale` setting by [`localize`].
+//!
+//! Without a precision, results are rounded to 15 significant digits, the
+//! most an `f64` always holds exactly, so `0.1 + 0.2` shows as `0.3` rather
+//! than `0.30000000000000004`. Rounding follows the `rounding` setting.
+//!
+//! ```
+//! use rust_calculator::format::{FormatOptions, Notation, format_result};
+//!
+//! let fixed = FormatOptions { notation: Notation::Fixed, precision: Some(3) };
+//! assert_eq!(format_result(2.0 / 3.0, &fixed), "0.667");
+//!
+//! let engineering = FormatOptions { notation: Notation::Engineering, precision: Some(3) };
+//! assert_eq!(format_result(12345.0, &engineering), "12.3e3");
+//! ```
+//!
+//! [`format_value`]: crate::format_value
+
+use crate::prelude::*;
+use crate::settings::{self, Locale};
+
+/// Significant digits shown when no precision is given
+const MAX_DIGITS: u32 = 15;
+
+/// Decimal places shown in fixed notation when no precision is given
+const FIXED_DECIMALS: u32 = 2;
+
+/// How a number is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Notation {
+  /// Plain decimals, switching to scientific notation for very large or
+  /// small numbers when the precision is limited
+  #[default]
+  Auto,
+  /// A fixed number of decimal places, e.g. `3.14`
+  Fixed,
+  /// One digit before the point and a power of ten, e.g. `1.23e4`
+  Scientific,
+  /// Like scientific, with the power of ten a multiple of 3, e.g. `12.3e3`
+  Engineering,
+}
+
+/// Options for [`format_result`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FormatOptions {
+  pub notation: Notation,
+  /// Decimal places in fixed notation and significant digits otherwise;
+  /// `None` shows as many as are needed, up to 15 significant digits, or 2
+  /// decimal places in fixed notation
+  pub precision: Option<u32>,
+}
+
+/// Formats a number as `options` describe. Infinity and NaN are shown as
+/// `inf`, `-inf` and `NaN` in every notation.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::format::{FormatOptions, Notation, format_result};
+///
+/// assert_eq!(format_result(0.1 + 0.2, &FormatOptions::default()), "0.3");
+///
+/// let scientific = FormatOptions { notation: Notation::Scientific, precision: None };
+/// assert_eq!(format_result(0.00042, &scientific), "4.2e-4");
+/// ```
+pub fn format_result(value: f64, options: &FormatOptions) -> String {
+  if !value.is_finite() {
+    return format!("{}", value);
+  }
+
+  match options.notation {
+    Notation::Auto => match options.precision {
+      Some(digits) => significant(value, digits),
+      None => shortest(value),
+    },
+    Notation::Fixed => {
+      let decimals = options.precision.unwrap_or(FIXED_DECIMALS);
+      let rounded = settings::current().rounding.round_to(value, decimals);
+      // Adding zero turns a negative zero into zero, so -0.001 isn't "-0.00"
+      format!("{:.*}", decimals as usize, rounded + 0.0)
+    }
+    Notation::Scientific => exponential(value, options.precision, 1),
+    Notation::Engineering => exponential(value, options.precision, 3),
+  }
+}
+
+/// Writes a formatted number with the separators of `locale`, grouping the
+/// digits of its whole part in threes. Text that isn't a number, such as
+/// `inf`, is left alone.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::format::localize;
+/// use rust_calculator::settings::Locale;
+///
+/// assert_eq!(localize("1234567.89", Locale::En), "1,234,567.89");
+/// assert_eq!(localize("-1234567.89", Locale::De), "-1.234.567,89");
+/// assert_eq!(localize("1.5e-7", Locale::Fr), "1,5e-7");
+/// ```
+pub fn localize(number: &str, locale: Locale) -> String {
+  let (sign, unsigned) = match number.strip_prefix('-') {
+    Some(rest) => ("-", rest),
+    None => ("", number),
+  };
+  let whole_length = unsigned
+    .find(|ch: char| !ch.is_ascii_digit())
+    .unwrap_or(unsigned.len());
+  let (whole, rest) = unsigned.split_at(whole_length);
+
+  let mut text = String::from(sign);
+  for (index, digit) in whole.chars().enumerate() {
+    if let Some(separator) = locale.group_separator() {
+      if index > 0 && (whole.len() - index) % 3 == 0 {
+        text.push(separator);
+      }
+    }
+    text.push(digit);
+  }
+  match rest.strip_prefix('.') {
+    Some(fraction) if !whole.is_empty() => {
+      text.push(locale.decimal_separator());
+      text.push_str(fraction);
+    }
+    _ => text.push_str(rest),
+  }
+  text
+}
+
+/// Shows a number without a fractional part when it's whole, and with up to
+/// 15 significant digits otherwise
+fn shortest(value: f64) -> String {
+  // Rounding through the decimal form drops the noise of binary fractions
+  let value: f64 = format!("{:.*e}", MAX_DIGITS as usize - 1, value)
+    .parse()
+    .unwrap_or(value);
+  if value.fract() == 0.0 && value.abs() < 1e12 {
+    format!("{:.0}", value)
+  } else {
+    format!("{}", value)
+  }
+}
+
+/// Shows a number with `digits` significant figures, in scientific notation
+/// when trailing zeros would otherwise look significant or it's very small
+fn significant(value: f64, digits: u32) -> String {
+  if value == 0.0 {
+    return format!("{}", value);
+  }
+
+  let digits = digits.max(1) as i32;
+  let (mantissa, exponent) = round_significant(value, digits);
+  if exponent >= digits || exponent < -4 {
+    let decimals = (digits - 1) as usize;
+    format!(
+      "{:.*}e{}",
+      decimals,
+      mantissa / 10f64.powi(digits - 1),
+      exponent
+    )
+  } else {
+    let decimals = (digits - 1 - exponent).max(0) as usize;
+    format!(
+      "{:.*}",
+      decimals,
+      mantissa * 10f64.powi(exponent - digits + 1)
+    )
+  }
+}
+
+/// Shows a number with a power of ten that's a multiple of `step`, keeping
+/// trailing zeros only when the precision asks for them
+fn exponential(value: f64, precision: Option<u32>, step: i32) -> String {
+  if value == 0.0 {
+    return "0e0".to_string();
+  }
+
+  let digits = precision.unwrap_or(MAX_DIGITS).max(1) as i32;
+  let (mantissa, exponent) = round_significant(value, digits);
+  let shown = exponent.div_euclid(step) * step;
+  let decimals = (digits - 1 - (exponent - shown)).max(0) as usize;
+  let mut text = format!(
+    "{:.*}",
+    decimals,
+    mantissa * 10f64.powi(exponent - shown - digits + 1)
+  );
+  if precision.is_none() && text.contains('.') {
+    text.truncate(text.trim_end_matches('0').trim_end_matches('.').len());
+  }
+  format!("{}e{}", text, shown)
+}
+
+/// Rounds a nonzero number to `digits` significant figures, returning them
+/// as a whole number along with the power of ten of the leading digit
+fn round_significant(value: f64, digits: i32) -> (f64, i32) {
+  let mut exponent = value.abs().log10().floor() as i32;
+  let mut mantissa = settings::current()
+    .rounding
+    .round(value / 10f64.powi(exponent - digits + 1));
+  // Rounding up can carry into a new digit, as 9.99 becomes 10.0
+  if mantissa.abs() >= 10f64.powi(digits) {
+    mantissa /= 10.0;
+    exponent += 1;
+  }
+  (mantissa, exponent)
+}
@@ -0,0 +1,346 @@
+//! # Formula Variables
+//!
+//! `let area := pi * r^2` keeps the expression rather than its value, so
+//! `area` follows the current value of `r`. A formula can use variables that
+//! don't exist yet, and other formulas, though not itself, directly or
+//! through them.
+//!
+//! As with the cells of a [`Sheet`](crate::sheet::Sheet), the dependencies
+//! between formulas are tracked, including those through the bodies of
+//! user-defined [`functions`](crate::functions). Changing a variable
+//! recalculates every formula that depends on it, each after the formulas it
+//! uses, so reading a formula only looks up its last value. A formula that
+//! would depend on itself is rejected, and one that comes to through a
+//! redefined function fails with a circular reference error.
+//!
+//! Formulas share their names with the variables: defining one replaces a
+//! variable of that name, and [`set_variable`](crate::set_variable) replaces
+//! a formula. Like variables, they're saved with the `persistence` feature
+//! and brought back by [`load_variables`](crate::load_variables).
+//!
+//! ```
+//! use rust_calculator::{evaluate_expression, formulas, set_variable};
+//!
+//! formulas::define("area", "pi * radius^2").unwrap();
+//! set_variable("radius", 2.0).unwrap();
+//! assert_eq!(evaluate_expression("area").unwrap(), 4.0 * std::f64::consts::PI);
+//! set_variable("radius", 1.0).unwrap();
+//! assert_eq!(formulas::get("area").unwrap().value, Ok(std::f64::consts::PI));
+//! ```
+
+use crate::{CalculatorError, Token, storage};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+use std::sync::RwLock;
+
+/// A variable defined with `:=`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formula {
+  /// The name, in lowercase as variable names are
+  pub name: String,
+  /// The expression worked out whenever what it uses changes
+  pub expression: String,
+  /// The value from the last recalculation, or the error message if it failed
+  pub value: Result<f64, String>,
+  /// Variables and formulas the expression uses, directly or through
+  /// user-defined functions
+  pub dependencies: BTreeSet<String>,
+}
+
+impl fmt::Display for Formula {
+  /// Shows the formula as it's defined, as in `area := pi * r^2`
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} := {}", self.name, self.expression)
+  }
+}
+
+/// The formulas, by name
+static FORMULAS: RwLock<BTreeMap<String, Formula>> = RwLock::new(BTreeMap::new());
+
+/// Defines the formula `name` as `expression`, replacing any formula or
+/// variable of that name, recalculates it and the formulas that use it, and
+/// saves the formulas.
+///
+/// Fails if the name is taken by something built in, the expression doesn't
+/// parse, or the formula would depend on itself.
+pub fn define(name: &str, expression: &str) -> Result<Formula, CalculatorError> {
+  let name = name.trim();
+  if !crate::is_identifier(name) {
+    return Err(CalculatorError::ArgumentError(format!(
+      "Invalid formula name: {}",
+      name
+    )));
+  }
+  if !crate::functions::is_free(name) {
+    return Err(CalculatorError::ReservedName(name.to_string()));
+  }
+  let name = name.to_lowercase();
+  let expression = expression.trim().to_string();
+  // Variables used by the formula may not be defined yet
+  crate::validate_with_resolver(&expression, &|_| Some(1.0))?;
+  let dependencies = dependencies(&expression);
+  if reaches(&FORMULAS.read().unwrap(), &dependencies, &name) {
+    return Err(circular(&name));
+  }
+
+  crate::delete_variable(&name).map_err(saving_error)?;
+  {
+    let mut formulas = FORMULAS.write().unwrap();
+    let formula = Formula {
+      name: name.clone(),
+      expression,
+      value: Ok(0.0),
+      dependencies,
+    };
+    formulas.insert(name.clone(), formula);
+    storage::save_formulas(formulas.values()).map_err(saving_error)?;
+  }
+  recalculate_from(&name);
+  Ok(get(&name).expect("the formula was just defined"))
+}
+
+/// Looks up a formula
+pub fn get(name: &str) -> Option<Formula> {
+  FORMULAS.read().unwrap().get(&name.to_lowercase()).cloned()
+}
+
+/// Every formula, sorted by name
+pub fn list() -> Vec<Formula> {
+  FORMULAS.read().unwrap().values().cloned().collect()
+}
+
+/// The formulas that depend on `name`, directly or through other formulas,
+/// in the order they're recalculated
+pub fn dependents(name: &str) -> Vec<Formula> {
+  let name = name.to_lowercase();
+  let formulas = FORMULAS.read().unwrap();
+  let (order, _) = evaluation_order(&formulas, affected(&formulas, &name));
+  order
+    .into_iter()
+    .filter(|dependent| *dependent != name)
+    .filter_map(|dependent| formulas.get(&dependent).cloned())
+    .collect()
+}
+
+/// Removes a formula, also from the saved formulas, and returns it, or
+/// `None` if there was no such formula. The formulas that used it are
+/// recalculated, which fails them unless a variable of that name remains.
+pub fn remove(name: &str) -> io::Result<Option<Formula>> {
+  let removed = {
+    let mut formulas = FORMULAS.write().unwrap();
+    let removed = formulas.remove(&name.to_lowercase());
+    if removed.is_some() {
+      storage::save_formulas(formulas.values())?;
+    }
+    removed
+  };
+  if removed.is_some() {
+    recalculate_from(name);
+  }
+  Ok(removed)
+}
+
+/// Recalculates every formula, finding their dependencies again, as is
+/// needed after a user-defined function changed
+pub fn recalculate_all() {
+  let (order, circular) = {
+    let mut formulas = FORMULAS.write().unwrap();
+    for formula in formulas.values_mut() {
+      formula.dependencies = dependencies(&formula.expression);
+    }
+    let names = formulas.keys().cloned().collect();
+    evaluation_order(&formulas, names)
+  };
+  evaluate(&order, &circular);
+}
+
+/// Recalculates the formulas that depend on the variable or formula `name`,
+/// along with `name` itself if it's a formula
+pub(crate) fn recalculate_from(name: &str) {
+  let (order, circular) = {
+    let formulas = FORMULAS.read().unwrap();
+    evaluation_order(&formulas, affected(&formulas, &name.to_lowercase()))
+  };
+  evaluate(&order, &circular);
+}
+
+/// Loads the formulas saved by earlier sessions and works them out
+pub(crate) fn load() -> io::Result<()> {
+  let saved = storage::read_formulas()?;
+  {
+    let mut formulas = FORMULAS.write().unwrap();
+    for definition in saved {
+      if let Some((name, expression)) = definition.split_once(":=") {
+        let name = name.trim().to_lowercase();
+        let formula = Formula {
+          name: name.clone(),
+          expression: expression.trim().to_string(),
+          value: Ok(0.0),
+          dependencies: BTreeSet::new(),
+        };
+        formulas.insert(name, formula);
+      }
+    }
+  }
+  recalculate_all();
+  Ok(())
+}
+
+/// The last value of the formula `name`, or `None` if there's no such
+/// formula
+pub(crate) fn value(name: &str) -> Option<Result<f64, CalculatorError>> {
+  let formulas = FORMULAS.read().unwrap();
+  let formula = formulas.get(&name.to_lowercase())?;
+  Some(formula.value.clone().map_err(CalculatorError::Other))
+}
+
+/// `name` and the formulas that depend on it, directly or transitively
+fn affected(formulas: &BTreeMap<String, Formula>, name: &str) -> BTreeSet<String> {
+  let mut affected = BTreeSet::new();
+  let mut pending = vec![name.to_string()];
+  while let Some(name) = pending.pop() {
+    if affected.insert(name.clone()) {
+      pending.extend(
+        formulas
+          .values()
+          .filter(|formula| formula.dependencies.contains(&name))
+          .map(|formula| formula.name.clone()),
+      );
+    }
+  }
+  affected
+}
+
+/// Orders the formulas among `names` so that each comes after the formulas it
+/// uses, along with those that depend on themselves
+fn evaluation_order(
+  formulas: &BTreeMap<String, Formula>,
+  names: BTreeSet<String>,
+) -> (Vec<String>, BTreeSet<String>) {
+  struct Visit<'a> {
+    formulas: &'a BTreeMap<String, Formula>,
+    names: BTreeSet<String>,
+    visiting: Vec<String>,
+    order: Vec<String>,
+    circular: BTreeSet<String>,
+  }
+
+  fn visit(state: &mut Visit, name: &str) {
+    if state.order.iter().any(|done| done == name) {
+      return;
+    }
+    if let Some(start) = state.visiting.iter().position(|open| open == name) {
+      state
+        .circular
+        .extend(state.visiting[start..].iter().cloned());
+      return;
+    }
+    let Some(formula) = state.formulas.get(name) else {
+      return;
+    };
+    state.visiting.push(name.to_string());
+    for dependency in &formula.dependencies {
+      if state.names.contains(dependency) {
+        visit(state, dependency);
+      }
+    }
+    state.visiting.pop();
+    state.order.push(name.to_string());
+  }
+
+  let mut state = Visit {
+    formulas,
+    names,
+    visiting: Vec::new(),
+    order: Vec::new(),
+    circular: BTreeSet::new(),
+  };
+  for name in state.names.clone() {
+    visit(&mut state, &name);
+  }
+  (state.order, state.circular)
+}
+
+/// Works out the formulas in `order`, each with the values of the ones
+/// before it
+fn evaluate(order: &[String], circular: &BTreeSet<String>) {
+  for name in order {
+    let value = if circular.contains(name) {
+      Err(circular_message(name))
+    } else {
+      let Some(formula) = get(name) else {
+        continue;
+      };
+      // The lock is let go of here, as the expression reads other formulas
+      crate::evaluate_with_resolver(&formula.expression, &crate::get_variable)
+        .map_err(|err| err.without_span().to_string())
+    };
+    if let Some(formula) = FORMULAS.write().unwrap().get_mut(name) {
+      formula.value = value;
+    }
+  }
+}
+
+/// The identifiers `expression` uses, including those in the bodies of the
+/// user-defined functions it calls other than their parameters
+fn dependencies(expression: &str) -> BTreeSet<String> {
+  let mut dependencies = BTreeSet::new();
+  let mut called = BTreeSet::new();
+  let mut pending = vec![(expression.to_string(), Vec::new())];
+  while let Some((expression, parameters)) = pending.pop() {
+    let tokens = crate::tokenize_spanned(&expression, &|_| None).unwrap_or_default();
+    for (token, _) in tokens {
+      match token {
+        Token::Identifier(name) => {
+          let name = name.to_lowercase();
+          if !parameters.contains(&name) {
+            dependencies.insert(name);
+          }
+        }
+        Token::Function(name) => {
+          if let Some(function) = crate::functions::get(&name) {
+            if called.insert(name) {
+              pending.push((function.body, function.parameters));
+            }
+          }
+        }
+        _ => {}
+      }
+    }
+  }
+  dependencies
+}
+
+/// Whether any of `dependencies` is `target` or depends on it
+fn reaches(
+  formulas: &BTreeMap<String, Formula>,
+  dependencies: &BTreeSet<String>,
+  target: &str,
+) -> bool {
+  let mut visited = BTreeSet::new();
+  let mut pending: Vec<&String> = dependencies.iter().collect();
+  while let Some(name) = pending.pop() {
+    if name == target {
+      return true;
+    }
+    if visited.insert(name) {
+      if let Some(formula) = formulas.get(name) {
+        pending.extend(&formula.dependencies);
+      }
+    }
+  }
+  false
+}
+
+fn circular(name: &str) -> CalculatorError {
+  CalculatorError::ArgumentError(circular_message(name))
+}
+
+fn circular_message(name: &str) -> String {
+  format!("Circular reference: {} depends on itself", name)
+}
+
+fn saving_error(err: io::Error) -> CalculatorError {
+  CalculatorError::Other(format!("Cannot save formulas: {}", err))
+}
@@ -0,0 +1,290 @@
+//! # User-Defined Functions
+//!
+//! `def f(x) = x^2 + 3*x` names a formula with parameters so it can be
+//! called like a built-in function, as in `f(2) + 1`. A function takes any
+//! number of parameters, `def area(w, h) = w * h`, and its body can use
+//! stored variables, constants and other user-defined functions. Variables in
+//! the body are read when the function is called, so a function always sees
+//! their current values, while its parameters hide any variable with the same
+//! name. They come from wherever the call is evaluated, so a function called
+//! by an [`EvalContext`](crate::EvalContext) or
+//! [`evaluate_with_variables`](crate::evaluate_with_variables) reads that
+//! evaluation's variables rather than the stored ones.
+//!
+//! A function can call itself, with `if` deciding when to stop, as in
+//! `def fact(n) = if(n <= 1, 1, n * fact(n - 1))`. The `recursion`
+//! [setting](crate::settings::Settings::max_recursion) bounds how many calls
+//! can be under way at once, and a call past it fails with
+//! [`CalculatorError::LimitExceeded`] instead of overflowing the stack.
+//!
+//! Defining a function again replaces it. Like variables, functions are
+//! saved with the `persistence` feature, and
+//! [`load_variables`](crate::load_variables) brings them back.
+//!
+//! ```
+//! use rust_calculator::{evaluate_expression, functions};
+//!
+//! functions::define("f(x) = x^2 + 3*x").unwrap();
+//! assert_eq!(evaluate_expression("f(2) + 1").unwrap(), 11.0);
+//! functions::define("hyp(a, b) = sqrt(a^2 + b^2)").unwrap();
+//! assert_eq!(evaluate_expression("hyp(3, 4)").unwrap(), 5.0);
+//! functions::define("gcd2(a, b) = if(b == 0, a, gcd2(b, a % b))").unwrap();
+//! assert_eq!(evaluate_expression("gcd2(48, 18)").unwrap(), 6.0);
+//! assert!(functions::define("sin(x) = x").is_err());
+//! ```
+
+use crate::{CalculatorError, Resolver, Token, settings, storage};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::RwLock;
+
+/// A function defined with `def`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserFunction {
+  pub name: String,
+  /// Names of the parameters, in lowercase as variable names are
+  pub parameters: Vec<String>,
+  /// The expression the function evaluates
+  pub body: String,
+}
+
+impl fmt::Display for UserFunction {
+  /// Shows the function as it's defined, as in `f(x, y) = x * y`
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}({}) = {}",
+      self.name,
+      self.parameters.join(", "),
+      self.body
+    )
+  }
+}
+
+/// The user-defined functions, by name
+static FUNCTIONS: RwLock<BTreeMap<String, UserFunction>> = RwLock::new(BTreeMap::new());
+
+thread_local! {
+  // User-defined function calls under way on this thread
+  static DEPTH: Cell<u32> = const { Cell::new(0) };
+  // The function being defined on this thread, while its body is checked
+  static DEFINING: RefCell<Option<UserFunction>> = const { RefCell::new(None) };
+}
+
+/// Defines a function from a definition such as `f(x) = x^2 + 3*x`,
+/// replacing any function of the same name, and saves the functions.
+///
+/// Fails if the name is taken by something built in or a constant, a
+/// parameter isn't a free name, or the body doesn't parse.
+pub fn define(definition: &str) -> Result<UserFunction, CalculatorError> {
+  let function = parse_definition(definition)?;
+  let name = &function.name;
+  if !is_free(name) && get(name).is_none() {
+    return Err(CalculatorError::ReservedName(name.clone()));
+  }
+  if let Some(parameter) = function
+    .parameters
+    .iter()
+    .find(|parameter| !is_free(parameter))
+  {
+    return Err(CalculatorError::ArgumentError(format!(
+      "Cannot name a parameter {}",
+      parameter
+    )));
+  }
+  if let Some(parameter) = function
+    .parameters
+    .iter()
+    .enumerate()
+    .find_map(|(index, parameter)| {
+      function.parameters[..index]
+        .contains(parameter)
+        .then_some(parameter)
+    })
+  {
+    return Err(CalculatorError::ArgumentError(format!(
+      "Parameter {} appears twice",
+      parameter
+    )));
+  }
+
+  // Calls to the function itself in the body have to parse, so it's known
+  // to this thread alone while the body is checked
+  defining(&function, || {
+    crate::validate_with_resolver(&function.body, &|variable| {
+      bound(&function.parameters, variable).or_else(|| crate::get_variable(variable))
+    })
+  })?;
+  let mut functions = FUNCTIONS.write().unwrap();
+  let previous = functions.insert(name.clone(), function.clone());
+  if let Err(err) = storage::save_functions(functions.values()) {
+    // What's defined stays the same as what's saved
+    match previous {
+      Some(previous) => functions.insert(name.clone(), previous),
+      None => functions.remove(name),
+    };
+    return Err(CalculatorError::ArgumentError(format!(
+      "Cannot save functions: {}",
+      err
+    )));
+  }
+  drop(functions);
+  // Compiled programs may have read the name as a variable
+  crate::compiled::clear_cache();
+  // Formulas calling the function may now depend on other variables
+  crate::formulas::recalculate_all();
+  Ok(function)
+}
+
+/// Looks up a user-defined function
+pub fn get(name: &str) -> Option<UserFunction> {
+  FUNCTIONS.read().unwrap().get(name).cloned()
+}
+
+/// Looks up a user-defined function, including the one being defined on
+/// this thread
+pub(crate) fn find(name: &str) -> Option<UserFunction> {
+  let defining = DEFINING.with(|defining| {
+    defining
+      .borrow()
+      .as_ref()
+      .filter(|function| function.name == name)
+      .cloned()
+  });
+  defining.or_else(|| get(name))
+}
+
+/// Runs `f` with `function` known to this thread as if it were defined,
+/// until `f` returns or panics
+fn defining<T>(function: &UserFunction, f: impl FnOnce() -> T) -> T {
+  struct Restore(Option<UserFunction>);
+
+  impl Drop for Restore {
+    fn drop(&mut self) {
+      DEFINING.with(|defining| *defining.borrow_mut() = self.0.take());
+    }
+  }
+
+  let _restore = Restore(DEFINING.with(|defining| defining.replace(Some(function.clone()))));
+  f()
+}
+
+/// Every user-defined function, sorted by name
+pub fn list() -> Vec<UserFunction> {
+  FUNCTIONS.read().unwrap().values().cloned().collect()
+}
+
+/// Loads the functions saved by earlier sessions
+pub(crate) fn load() -> std::io::Result<()> {
+  let saved = storage::read_functions()?;
+  let mut functions = FUNCTIONS.write().unwrap();
+  for definition in saved {
+    // A definition that no longer parses is left out rather than failing
+    // the whole load
+    if let Ok(function) = parse_definition(&definition) {
+      functions.insert(function.name.clone(), function);
+    }
+  }
+  Ok(())
+}
+
+/// Calls the user-defined function `name` with `arguments`, or gives `None`
+/// if there's no such function. Names in the body other than its parameters
+/// are looked up through the caller's `resolve`.
+pub(crate) fn call(
+  name: &str,
+  arguments: &[f64],
+  resolve: Resolver,
+) -> Option<Result<f64, CalculatorError>> {
+  let function = get(name)?;
+  if arguments.len() != function.parameters.len() {
+    return Some(Err(CalculatorError::ArgumentError(format!(
+      "{} takes {} argument{}, got {}",
+      name,
+      function.parameters.len(),
+      if function.parameters.len() == 1 {
+        ""
+      } else {
+        "s"
+      },
+      arguments.len()
+    ))));
+  }
+  let bind = |variable: &str| match function
+    .parameters
+    .iter()
+    .position(|parameter| *parameter == variable.to_lowercase())
+  {
+    Some(index) => Some(arguments[index]),
+    None => resolve(variable),
+  };
+  let depth = DEPTH.with(Cell::get);
+  let max_recursion = settings::current().max_recursion;
+  if depth >= max_recursion {
+    return Some(Err(CalculatorError::LimitExceeded(format!(
+      "user functions are nested more than {} calls deep",
+      max_recursion
+    ))));
+  }
+  // Restored even if the body panics, which would otherwise leave later
+  // calls on this thread capped early
+  struct Restore(u32);
+
+  impl Drop for Restore {
+    fn drop(&mut self) {
+      DEPTH.with(|cell| cell.set(self.0));
+    }
+  }
+
+  let _restore = Restore(DEPTH.with(|cell| cell.replace(depth + 1)));
+  Some(crate::evaluate_with_resolver(&function.body, &bind).map_err(CalculatorError::without_span))
+}
+
+/// Splits a definition such as `f(x, y) = x * y` into its parts
+fn parse_definition(definition: &str) -> Result<UserFunction, CalculatorError> {
+  let invalid = || {
+    CalculatorError::SyntaxError(format!(
+      "Invalid function definition: {}. Format: f(x, y) = expression",
+      definition.trim()
+    ))
+  };
+  let (head, body) = definition.split_once('=').ok_or_else(invalid)?;
+  let (name, parameters) = head.trim().split_once('(').ok_or_else(invalid)?;
+  let parameters = parameters
+    .trim_end()
+    .strip_suffix(')')
+    .ok_or_else(invalid)?;
+  let name = name.trim();
+  let parameters: Vec<String> = if parameters.trim().is_empty() {
+    Vec::new()
+  } else {
+    parameters
+      .split(',')
+      .map(|parameter| parameter.trim().to_lowercase())
+      .collect()
+  };
+  let body = body.trim();
+  if !crate::is_identifier(name) || parameters.is_empty() || body.is_empty() {
+    return Err(invalid());
+  }
+  Ok(UserFunction {
+    name: name.to_string(),
+    parameters,
+    body: body.to_string(),
+  })
+}
+
+/// Checks whether a name is left as an unresolved identifier, rather than
+/// being a built-in function, constant or conversion
+pub(crate) fn is_free(name: &str) -> bool {
+  matches!(
+    crate::tokenize_spanned(name, &|_| None).as_deref(),
+    Ok([(Token::Identifier(_), _)])
+  )
+}
+
+/// The value standing in for a parameter while the body is checked
+fn bound(parameters: &[String], variable: &str) -> Option<f64> {
+  parameters.contains(&variable.to_lowercase()).then_some(1.0)
+}
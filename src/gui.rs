@@ -6,17 +6,47 @@
 use iced::{
   Application, Color, Command, Element, Length, Settings, Subscription, Theme, alignment, executor,
   keyboard, subscription,
-  widget::{Button, Column, Container, Row, Text, button, container},
+  widget::{Button, Checkbox, Column, Container, Row, Text, button, container},
   window,
 };
 use rust_calculator::evaluate_expression;
 use std::vec;
 
-// Custom theme colors
-const DARK_BG: Color = Color::from_rgb(0.15, 0.15, 0.20);
-const DISPLAY_BG: Color = Color::from_rgb(0.2, 0.2, 0.25);
-const TEXT_COLOR: Color = Color::from_rgb(0.9, 0.9, 0.9);
-const BUTTON_BG: Color = Color::from_rgb(0.25, 0.25, 0.30);
+// Remote displays mirroring this window, when started with --websocket ADDR
+#[cfg(feature = "websocket")]
+static REMOTE: std::sync::OnceLock<rust_calculator::websocket::Broadcaster> =
+  std::sync::OnceLock::new();
+
+// Colors that differ between the dark and light themes
+struct Palette {
+  background: Color,
+  display: Color,
+  text: Color,
+  button: Color,
+}
+
+const DARK: Palette = Palette {
+  background: Color::from_rgb(0.15, 0.15, 0.20),
+  display: Color::from_rgb(0.2, 0.2, 0.25),
+  text: Color::from_rgb(0.9, 0.9, 0.9),
+  button: Color::from_rgb(0.25, 0.25, 0.30),
+};
+
+const LIGHT: Palette = Palette {
+  background: Color::from_rgb(0.92, 0.92, 0.94),
+  display: Color::from_rgb(1.0, 1.0, 1.0),
+  text: Color::from_rgb(0.1, 0.1, 0.15),
+  button: Color::from_rgb(0.5, 0.5, 0.56),
+};
+
+fn palette(theme: &Theme) -> &'static Palette {
+  match theme {
+    Theme::Light => &LIGHT,
+    _ => &DARK,
+  }
+}
+
+// Colors shared by both themes
 const OPERATOR_BG: Color = Color::from_rgb(0.3, 0.5, 0.8);
 const FUNCTION_BG: Color = Color::from_rgb(0.4, 0.4, 0.7);
 const CLEAR_BG: Color = Color::from_rgb(0.8, 0.3, 0.3);
@@ -46,7 +76,6 @@ impl button::StyleSheet for CalculatorButtonStyle {
       shadow_offset: iced::Vector::new(1.0, 2.0),
       border_width: 0.0,
       border_color: Color::TRANSPARENT,
-      ..button::Appearance::default()
     }
   }
 
@@ -82,15 +111,13 @@ impl From<DisplayStyle> for iced::theme::Container {
 impl container::StyleSheet for DisplayStyle {
   type Style = iced::Theme;
 
-  fn appearance(&self, _style: &Self::Style) -> container::Appearance {
+  fn appearance(&self, style: &Self::Style) -> container::Appearance {
+    let display = palette(style).display;
     container::Appearance {
-      background: Some(DISPLAY_BG.into()),
+      background: Some(display.into()),
       border_radius: 6.0,
       border_width: 1.0,
-      border_color: Color {
-        a: 0.3,
-        ..DISPLAY_BG
-      },
+      border_color: Color { a: 0.3, ..display },
       ..container::Appearance::default()
     }
   }
@@ -108,9 +135,9 @@ impl From<CalculatorContainerStyle> for iced::theme::Container {
 impl container::StyleSheet for CalculatorContainerStyle {
   type Style = iced::Theme;
 
-  fn appearance(&self, _style: &Self::Style) -> container::Appearance {
+  fn appearance(&self, style: &Self::Style) -> container::Appearance {
     container::Appearance {
-      background: Some(DARK_BG.into()),
+      background: Some(palette(style).background.into()),
       border_radius: 0.0,
       border_width: 0.0,
       border_color: Color::TRANSPARENT,
@@ -120,11 +147,11 @@ impl container::StyleSheet for CalculatorContainerStyle {
 }
 
 // Text style for display
-struct DisplayTextStyle;
+struct DisplayTextStyle(Color);
 
 impl From<DisplayTextStyle> for iced::theme::Text {
-  fn from(_: DisplayTextStyle) -> Self {
-    iced::theme::Text::Color(TEXT_COLOR)
+  fn from(style: DisplayTextStyle) -> Self {
+    iced::theme::Text::Color(style.0)
   }
 }
 
@@ -134,6 +161,15 @@ pub struct Calculator {
   result: String,
   last_key: Option<Key>,
   buttons: Vec<CalcButton>,
+  // Dark or light colors, from the configuration file
+  theme: Theme,
+  // Whether the input is Reverse Polish Notation, such as 3 4 + 2 *
+  rpn: bool,
+  // Whether results are shown as exact fractions, such as 1/2
+  fractions: bool,
+  // Last successful result, for copying to the clipboard
+  #[cfg(feature = "clipboard")]
+  value: Option<f64>,
 }
 
 // Different types of calculator buttons
@@ -161,6 +197,12 @@ pub enum Message {
   ClearEntry,
   Backspace,
   KeyboardInput(keyboard::Event),
+  ToggleRpn(bool),
+  ToggleFractions(bool),
+  #[cfg(feature = "clipboard")]
+  Copy,
+  #[cfg(feature = "clipboard")]
+  Paste,
 }
 
 // Key identification for buttons
@@ -327,18 +369,43 @@ impl CalcButton {
   }
 }
 
+impl Calculator {
+  // Operators stand alone between spaces in RPN, as in 3 4 +
+  fn push_operator(&mut self, operator: char) {
+    if self.rpn {
+      self.push_word(&operator.to_string());
+    } else {
+      self.input.push(operator);
+    }
+  }
+
+  // Adds a word to RPN input, separated from the one before it
+  fn push_word(&mut self, word: &str) {
+    if !self.input.is_empty() && !self.input.ends_with(' ') {
+      self.input.push(' ');
+    }
+    self.input.push_str(word);
+    self.input.push(' ');
+  }
+}
+
 impl Application for Calculator {
   type Executor = executor::Default;
   type Message = Message;
-  type Flags = ();
+  type Flags = Theme;
   type Theme = Theme;
 
-  fn new(_flags: ()) -> (Self, Command<Message>) {
+  fn new(theme: Theme) -> (Self, Command<Message>) {
     let mut calculator = Calculator {
       input: String::new(),
       result: String::new(),
       last_key: None,
       buttons: vec![],
+      rpn: false,
+      fractions: false,
+      theme,
+      #[cfg(feature = "clipboard")]
+      value: None,
     };
 
     // Create calculator buttons in the desired layout
@@ -351,6 +418,10 @@ impl Application for Calculator {
     String::from("Rust Calculator")
   }
 
+  fn theme(&self) -> Theme {
+    self.theme.clone()
+  }
+
   fn update(&mut self, message: Message) -> Command<Message> {
     match message {
       Message::InputChanged(input) => {
@@ -358,21 +429,90 @@ impl Application for Calculator {
         Command::none()
       }
       Message::Calculate => {
-        match evaluate_expression(&self.input) {
+        // Text results such as roman(14) are shown as they are
+        if !self.rpn && rust_calculator::returns_text(&self.input) {
+          self.result = match rust_calculator::evaluate_to_string(&self.input) {
+            Ok(text) => text,
+            Err(err) => format!("Error: {}", err),
+          };
+          return Command::none();
+        }
+
+        let previous = rust_calculator::last_answer();
+        let result = if self.rpn {
+          rust_calculator::evaluate_rpn_expression(&self.input)
+        } else {
+          evaluate_expression(&self.input)
+        };
+        #[cfg(feature = "websocket")]
+        if let Some(remote) = REMOTE.get() {
+          remote.publish(&self.input, &result);
+        }
+
+        #[cfg(feature = "clipboard")]
+        {
+          self.value = result.as_ref().ok().copied();
+        }
+
+        match result {
           Ok(result) => {
-            // Ensure we format the number properly
-            if result.fract() == 0.0 && result.abs() < 1e12 {
-              self.result = format!("{:.0}", result);
-            } else {
-              self.result = format!("{}", result);
-            }
+            // Showing the input may evaluate it again, with ans as it was
+            self.result = rust_calculator::with_answer(previous, || {
+              let fraction = (self.fractions && !self.rpn)
+                .then(|| rust_calculator::evaluate_fraction(&self.input).ok())
+                .flatten();
+              match fraction {
+                Some(fraction) => fraction.to_string(),
+                None if self.rpn => rust_calculator::format_value(result),
+                None => rust_calculator::format_exact(&self.input, result),
+              }
+            });
           }
           Err(err) => {
+            // Integers too large for floating point, such as fact 200, are shown exactly
+            let exact = if self.rpn {
+              Err(err)
+            } else {
+              rust_calculator::format_overflow(&self.input, err)
+            };
+            self.result = match exact {
+              Ok(text) => text,
+              Err(err) => match err.char_span(&self.input) {
+                Some(span) => format!("Error: {} (column {})", err, span.start + 1),
+                None => format!("Error: {}", err),
+              },
+            };
+          }
+        }
+        Command::none()
+      }
+      #[cfg(feature = "clipboard")]
+      Message::Copy => {
+        if let Some(value) = self.value {
+          if let Err(err) = rust_calculator::clipboard::copy_result(value) {
             self.result = format!("Error: {}", err);
           }
         }
         Command::none()
       }
+      #[cfg(feature = "clipboard")]
+      Message::Paste => {
+        match rust_calculator::clipboard::paste_expression() {
+          Ok(expression) => self.input.push_str(&expression),
+          Err(err) => self.result = format!("Error: {}", err),
+        }
+        Command::none()
+      }
+      Message::ToggleRpn(rpn) => {
+        self.rpn = rpn;
+        self.input.clear();
+        self.result.clear();
+        Command::none()
+      }
+      Message::ToggleFractions(fractions) => {
+        self.fractions = fractions;
+        Command::none()
+      }
       Message::Clear => {
         self.input.clear();
         self.result.clear();
@@ -395,6 +535,16 @@ impl Application for Calculator {
           modifiers,
         } = event
         {
+          // Ctrl+C copies the result, Ctrl+V pastes an expression
+          #[cfg(feature = "clipboard")]
+          if modifiers.control() {
+            match key_code {
+              keyboard::KeyCode::C => return self.update(Message::Copy),
+              keyboard::KeyCode::V => return self.update(Message::Paste),
+              _ => {}
+            }
+          }
+
           match key_code {
             keyboard::KeyCode::Key1 | keyboard::KeyCode::Numpad1 => {
               return self.update(Message::KeyPressed(Key::Number(1)));
@@ -459,6 +609,8 @@ impl Application for Calculator {
             keyboard::KeyCode::Backspace => {
               return self.update(Message::KeyPressed(Key::Backspace));
             }
+            // Space separates operands in RPN
+            keyboard::KeyCode::Space if self.rpn => self.input.push(' '),
             keyboard::KeyCode::Escape => return self.update(Message::KeyPressed(Key::Clear)),
             keyboard::KeyCode::Delete => return self.update(Message::KeyPressed(Key::ClearEntry)),
             keyboard::KeyCode::Enter | keyboard::KeyCode::NumpadEnter => {
@@ -482,15 +634,15 @@ impl Application for Calculator {
           Key::Number(num) => {
             self.input.push_str(&num.to_string());
           }
-          Key::Add => self.input.push('+'),
-          Key::Subtract => self.input.push('-'),
-          Key::Multiply => self.input.push('*'),
-          Key::Divide => self.input.push('/'),
-          Key::Modulo => self.input.push('%'),
-          Key::Power => self.input.push('^'),
+          Key::Add => self.push_operator('+'),
+          Key::Subtract => self.push_operator('-'),
+          Key::Multiply => self.push_operator('*'),
+          Key::Divide => self.push_operator('/'),
+          Key::Modulo => self.push_operator('%'),
+          Key::Power => self.push_operator('^'),
           Key::Decimal => self.input.push('.'),
           Key::Equals => {
-            return Command::perform(async { () }, |_| Message::Calculate);
+            return Command::perform(async {}, |_| Message::Calculate);
           }
           Key::LeftParen => self.input.push('('),
           Key::RightParen => self.input.push(')'),
@@ -511,19 +663,28 @@ impl Application for Calculator {
               Function::Ceil => "ceil",
               Function::Factorial => "fact",
             };
-            self.input.push_str(func_str);
-            self.input.push('(');
+            if self.rpn {
+              // Functions follow their argument in RPN
+              self.push_word(func_str);
+            } else {
+              self.input.push_str(func_str);
+              self.input.push('(');
+            }
           }
           Key::Constant(c) => {
             let const_str = match c {
               Constant::Pi => "pi",
               Constant::E => "e",
             };
-            self.input.push_str(const_str);
+            if self.rpn {
+              self.push_word(const_str);
+            } else {
+              self.input.push_str(const_str);
+            }
           }
-          Key::Clear => return Command::perform(async { () }, |_| Message::Clear),
-          Key::ClearEntry => return Command::perform(async { () }, |_| Message::ClearEntry),
-          Key::Backspace => return Command::perform(async { () }, |_| Message::Backspace),
+          Key::Clear => return Command::perform(async {}, |_| Message::Clear),
+          Key::ClearEntry => return Command::perform(async {}, |_| Message::ClearEntry),
+          Key::Backspace => return Command::perform(async {}, |_| Message::Backspace),
         }
 
         Command::none()
@@ -531,7 +692,7 @@ impl Application for Calculator {
     }
   }
 
-  fn view(&self) -> Element<Message> {
+  fn view(&self) -> Element<'_, Message> {
     // Create the calculator display - using a non-interactive container with styled text
     // instead of TextInput to prevent mouse interactions
     let input_display = Container::new(
@@ -543,7 +704,7 @@ impl Application for Calculator {
       .size(30)
       .width(Length::Fill)
       .horizontal_alignment(alignment::Horizontal::Left)
-      .style(DisplayTextStyle),
+      .style(DisplayTextStyle(palette(&self.theme).text)),
     )
     .padding(10)
     .width(Length::Fill)
@@ -558,7 +719,7 @@ impl Application for Calculator {
     .size(24)
     .width(Length::Fill)
     .horizontal_alignment(alignment::Horizontal::Right)
-    .style(DisplayTextStyle);
+    .style(DisplayTextStyle(palette(&self.theme).text));
 
     // Create button grid layout
     let mut button_rows: Vec<Row<'_, Message>> = vec![];
@@ -598,7 +759,7 @@ impl Application for Calculator {
             CalcButton::Operator(_) => OPERATOR_BG,
             CalcButton::Function(_) => FUNCTION_BG,
             CalcButton::Constant(_) => CONSTANT_BG,
-            _ => BUTTON_BG,
+            _ => palette(&self.theme).button,
           },
         },
       )));
@@ -607,10 +768,23 @@ impl Application for Calculator {
     }
 
     // Add the last row if it has any buttons
-    if self.buttons.len() % buttons_per_row != 0 {
+    if !self.buttons.len().is_multiple_of(buttons_per_row) {
       button_rows.push(current_row);
     }
 
+    let notation = Row::new()
+      .spacing(20)
+      .push(
+        Checkbox::new("RPN", self.rpn, Message::ToggleRpn)
+          .text_size(18)
+          .style(iced::theme::Checkbox::Primary),
+      )
+      .push(
+        Checkbox::new("Fractions", self.fractions, Message::ToggleFractions)
+          .text_size(18)
+          .style(iced::theme::Checkbox::Primary),
+      );
+
     // Combine everything into a column
     let mut content = Column::new()
       .padding(10)
@@ -621,7 +795,8 @@ impl Application for Calculator {
     // Add input and result displays, with smaller vertical proportions
     content = content
       .push(input_display.height(Length::FillPortion(2)))
-      .push(result.height(Length::FillPortion(1)));
+      .push(result.height(Length::FillPortion(1)))
+      .push(notation);
 
     // Add all button rows to the column with equal proportions
     for row in button_rows {
@@ -650,60 +825,87 @@ impl Application for Calculator {
 
 // Create the calculator buttons layout
 fn create_buttons() -> Vec<CalcButton> {
-  let mut buttons = Vec::new();
-
-  // First row - Clear, functions, etc.
-  buttons.push(CalcButton::new(Key::Clear));
-  buttons.push(CalcButton::new(Key::ClearEntry));
-  buttons.push(CalcButton::new(Key::Backspace));
-  buttons.push(CalcButton::new(Key::LeftParen));
-  buttons.push(CalcButton::new(Key::RightParen));
-
-  // Second row - Functions and constants
-  buttons.push(CalcButton::new(Key::Function(Function::Sqrt)));
-  buttons.push(CalcButton::new(Key::Function(Function::Sin)));
-  buttons.push(CalcButton::new(Key::Function(Function::Cos)));
-  buttons.push(CalcButton::new(Key::Function(Function::Tan)));
-  buttons.push(CalcButton::new(Key::Constant(Constant::Pi)));
-
-  // Third row - More functions
-  buttons.push(CalcButton::new(Key::Function(Function::Log)));
-  buttons.push(CalcButton::new(Key::Function(Function::Ln)));
-  buttons.push(CalcButton::new(Key::Function(Function::Exp)));
-  buttons.push(CalcButton::new(Key::Power));
-  buttons.push(CalcButton::new(Key::Constant(Constant::E)));
-
-  // Fourth row - Numbers 7, 8, 9 and operators
-  buttons.push(CalcButton::new(Key::Number(7)));
-  buttons.push(CalcButton::new(Key::Number(8)));
-  buttons.push(CalcButton::new(Key::Number(9)));
-  buttons.push(CalcButton::new(Key::Divide));
-  buttons.push(CalcButton::new(Key::Modulo));
-
-  // Fifth row - Numbers 4, 5, 6 and operators
-  buttons.push(CalcButton::new(Key::Number(4)));
-  buttons.push(CalcButton::new(Key::Number(5)));
-  buttons.push(CalcButton::new(Key::Number(6)));
-  buttons.push(CalcButton::new(Key::Multiply));
-  buttons.push(CalcButton::new(Key::Function(Function::Abs)));
-
-  // Sixth row - Numbers 1, 2, 3 and operators
-  buttons.push(CalcButton::new(Key::Number(1)));
-  buttons.push(CalcButton::new(Key::Number(2)));
-  buttons.push(CalcButton::new(Key::Number(3)));
-  buttons.push(CalcButton::new(Key::Subtract));
-  buttons.push(CalcButton::new(Key::Function(Function::Factorial)));
-
-  // Seventh row - 0, decimal, equals and operators
-  buttons.push(CalcButton::new(Key::Number(0)));
-  buttons.push(CalcButton::new(Key::Decimal));
-  buttons.push(CalcButton::new(Key::Equals));
-  buttons.push(CalcButton::new(Key::Add));
-
-  buttons
+  vec![
+    // First row - Clear, functions, etc.
+    CalcButton::new(Key::Clear),
+    CalcButton::new(Key::ClearEntry),
+    CalcButton::new(Key::Backspace),
+    CalcButton::new(Key::LeftParen),
+    CalcButton::new(Key::RightParen),
+    // Second row - Functions and constants
+    CalcButton::new(Key::Function(Function::Sqrt)),
+    CalcButton::new(Key::Function(Function::Sin)),
+    CalcButton::new(Key::Function(Function::Cos)),
+    CalcButton::new(Key::Function(Function::Tan)),
+    CalcButton::new(Key::Constant(Constant::Pi)),
+    // Third row - More functions
+    CalcButton::new(Key::Function(Function::Log)),
+    CalcButton::new(Key::Function(Function::Ln)),
+    CalcButton::new(Key::Function(Function::Exp)),
+    CalcButton::new(Key::Power),
+    CalcButton::new(Key::Constant(Constant::E)),
+    // Fourth row - Numbers 7, 8, 9 and operators
+    CalcButton::new(Key::Number(7)),
+    CalcButton::new(Key::Number(8)),
+    CalcButton::new(Key::Number(9)),
+    CalcButton::new(Key::Divide),
+    CalcButton::new(Key::Modulo),
+    // Fifth row - Numbers 4, 5, 6 and operators
+    CalcButton::new(Key::Number(4)),
+    CalcButton::new(Key::Number(5)),
+    CalcButton::new(Key::Number(6)),
+    CalcButton::new(Key::Multiply),
+    CalcButton::new(Key::Function(Function::Abs)),
+    // Sixth row - Numbers 1, 2, 3 and operators
+    CalcButton::new(Key::Number(1)),
+    CalcButton::new(Key::Number(2)),
+    CalcButton::new(Key::Number(3)),
+    CalcButton::new(Key::Subtract),
+    CalcButton::new(Key::Function(Function::Factorial)),
+    // Seventh row - 0, decimal, equals and operators
+    CalcButton::new(Key::Number(0)),
+    CalcButton::new(Key::Decimal),
+    CalcButton::new(Key::Equals),
+    CalcButton::new(Key::Add),
+  ]
 }
 
 fn main() -> iced::Result {
+  #[cfg(feature = "websocket")]
+  {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(addr) = args
+      .iter()
+      .position(|arg| arg == "--websocket")
+      .and_then(|index| args.get(index + 1))
+    {
+      match rust_calculator::websocket::serve(addr.as_str()) {
+        Ok(broadcaster) => {
+          let _ = REMOTE.set(broadcaster);
+        }
+        Err(err) => eprintln!("Error starting WebSocket server: {}", err),
+      }
+    }
+  }
+
+  // Preferences from the configuration file, including the theme
+  #[cfg(feature = "config")]
+  let theme = match rust_calculator::config::Config::load() {
+    Ok(config) => {
+      config.apply();
+      match config.theme {
+        rust_calculator::config::Theme::Light => Theme::Light,
+        rust_calculator::config::Theme::Dark => Theme::Dark,
+      }
+    }
+    Err(err) => {
+      eprintln!("Error loading configuration: {}", err);
+      Theme::Dark
+    }
+  };
+  #[cfg(not(feature = "config"))]
+  let theme = Theme::Dark;
+
   // Set up application with sensible defaults
   let settings = Settings {
     window: window::Settings {
@@ -713,6 +915,7 @@ fn main() -> iced::Result {
       resizable: true,
       ..window::Settings::default()
     },
+    flags: theme,
     ..Settings::default()
   };
 
@@ -0,0 +1,273 @@
+//! # JSON-RPC Interface
+//!
+//! Line-delimited JSON-RPC 2.0 interface to the calculator library.
+//! Each request is a single JSON object on its own line, and each response
+//! is written back as a single line. This lets editors and other processes
+//! keep the calculator running as a long-lived child process.
+//!
+//! ## Methods
+//!
+//! - `evaluate` - `{"expression": "2 + 3"}` returns `{"expression": ..., "value": 5.0}`
+//! - `setVariable` - `{"name": "x", "value": 10}` returns `{"name": "x", "value": 10.0}`
+//! - `getHistory` - `{"count": 10}` returns an array of history entries
+//! - `parse` - `{"expression": "2 + 3"}` returns the expression tree as JSON
+//! - `evaluateTree` - `{"tree": {...}}` evaluates a tree in the format returned by `parse`
+//! - `subscribe` / `unsubscribe` - toggle `evaluated` notifications that are
+//!   sent after every successful evaluation
+
+use serde_json::{Value, json};
+use std::io::{self, BufRead, Write};
+
+/// Invalid JSON was received
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters
+pub const INVALID_PARAMS: i64 = -32602;
+/// The calculator rejected the request (evaluation error, I/O error, etc.)
+pub const CALCULATOR_ERROR: i64 = -32000;
+
+/// Error returned by a method handler, converted to a JSON-RPC error object
+#[derive(Debug, Clone)]
+pub struct RpcError {
+  /// JSON-RPC error code
+  pub code: i64,
+  /// Human-readable error message
+  pub message: String,
+}
+
+impl RpcError {
+  pub(crate) fn new(code: i64, message: impl Into<String>) -> Self {
+    RpcError {
+      code,
+      message: message.into(),
+    }
+  }
+}
+
+/// A JSON-RPC session holding per-connection state such as subscriptions
+#[derive(Debug, Default)]
+pub struct Session {
+  subscribed: bool,
+}
+
+impl Session {
+  /// Creates a new session with no active subscriptions
+  pub fn new() -> Self {
+    Session::default()
+  }
+
+  /// Handles a single line of input and returns the lines to write back.
+  ///
+  /// Notifications (requests without an `id`) produce no response, but a
+  /// successful evaluation may still produce an `evaluated` notification
+  /// when the session is subscribed.
+  pub fn handle_line(&mut self, line: &str) -> Vec<String> {
+    let request = match parse_request(line) {
+      Ok(request) => request,
+      Err(response) => return vec![response],
+    };
+
+    let mut output = Vec::new();
+    let result = self.dispatch(&request.method, &request.params);
+
+    if let Some(id) = request.id {
+      output.push(response(id, &result));
+    }
+
+    // Send the notification after the response so clients always see the reply first
+    if request.method == "evaluate" && self.subscribed {
+      if let Ok(value) = result {
+        output.push(notification("evaluated", value));
+      }
+    }
+
+    output
+  }
+
+  /// Routes a method call to its handler
+  fn dispatch(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+      "evaluate" => evaluate(params),
+      "setVariable" => set_variable(params),
+      "getHistory" => get_history(params),
+      "parse" => parse(params),
+      "evaluateTree" => evaluate_tree(params),
+      "subscribe" => {
+        self.subscribed = true;
+        Ok(json!(true))
+      }
+      "unsubscribe" => {
+        self.subscribed = false;
+        Ok(json!(true))
+      }
+      _ => Err(RpcError::new(
+        METHOD_NOT_FOUND,
+        format!("Method not found: {}", method),
+      )),
+    }
+  }
+}
+
+/// Runs a JSON-RPC session, reading requests from `reader` until EOF
+pub fn serve<R: BufRead, W: Write>(reader: R, writer: W) -> io::Result<()> {
+  let mut session = Session::new();
+  serve_lines(reader, writer, |line| session.handle_line(line))
+}
+
+/// A request decoded from a single line of input
+pub(crate) struct Request {
+  pub id: Option<Value>,
+  pub method: String,
+  pub params: Value,
+}
+
+/// Decodes a request line, or returns the error response to send back
+pub(crate) fn parse_request(line: &str) -> Result<Request, String> {
+  let request: Value = serde_json::from_str(line).map_err(|err| {
+    error_response(
+      Value::Null,
+      RpcError::new(PARSE_ERROR, format!("Parse error: {}", err)),
+    )
+  })?;
+
+  let id = request.get("id").cloned();
+  match request.get("method").and_then(Value::as_str) {
+    Some(method) if request.get("jsonrpc") == Some(&json!("2.0")) => Ok(Request {
+      id,
+      method: method.to_string(),
+      params: request.get("params").cloned().unwrap_or(Value::Null),
+    }),
+    _ => Err(error_response(
+      id.unwrap_or(Value::Null),
+      RpcError::new(INVALID_REQUEST, "Invalid request"),
+    )),
+  }
+}
+
+/// Feeds each non-empty line from `reader` to `handle` and writes back whatever it returns
+pub(crate) fn serve_lines<R: BufRead, W: Write>(
+  reader: R,
+  mut writer: W,
+  mut handle: impl FnMut(&str) -> Vec<String>,
+) -> io::Result<()> {
+  for line in reader.lines() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    for response in handle(&line) {
+      writeln!(writer, "{}", response)?;
+    }
+    writer.flush()?;
+  }
+
+  Ok(())
+}
+
+/// Looks up a parameter by name, or by position when params is an array
+pub(crate) fn param<'a>(params: &'a Value, name: &str, position: usize) -> Option<&'a Value> {
+  match params {
+    Value::Object(map) => map.get(name),
+    Value::Array(items) => items.get(position),
+    _ => None,
+  }
+}
+
+fn evaluate(params: &Value) -> Result<Value, RpcError> {
+  let expression = param(params, "expression", 0)
+    .and_then(Value::as_str)
+    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing string parameter 'expression'"))?;
+
+  let value = crate::metrics::evaluate(expression)
+    .map_err(|err| RpcError::new(CALCULATOR_ERROR, err.to_string()))?;
+
+  // History is best effort; a read-only data directory shouldn't fail the request
+  let _ = crate::add_to_history(expression, value);
+
+  Ok(json!({ "expression": expression, "value": value }))
+}
+
+fn parse(params: &Value) -> Result<Value, RpcError> {
+  let expression = param(params, "expression", 0)
+    .and_then(Value::as_str)
+    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing string parameter 'expression'"))?;
+
+  crate::ast::parse(expression)
+    .map(|tree| tree.to_json())
+    .map_err(|err| RpcError::new(CALCULATOR_ERROR, err.to_string()))
+}
+
+fn evaluate_tree(params: &Value) -> Result<Value, RpcError> {
+  let tree = param(params, "tree", 0)
+    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing parameter 'tree'"))?;
+  let tree = crate::ast::Expr::from_json(tree)
+    .map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))?;
+
+  let value = tree
+    .evaluate()
+    .map_err(|err| RpcError::new(CALCULATOR_ERROR, err.to_string()))?;
+  Ok(json!({ "value": value }))
+}
+
+fn set_variable(params: &Value) -> Result<Value, RpcError> {
+  let name = param(params, "name", 0)
+    .and_then(Value::as_str)
+    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing string parameter 'name'"))?;
+  let value = param(params, "value", 1)
+    .and_then(Value::as_f64)
+    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing numeric parameter 'value'"))?;
+  // Saving would leave out a variable whose name isn't an identifier
+  if !crate::is_identifier(name) {
+    return Err(RpcError::new(
+      INVALID_PARAMS,
+      format!("Invalid variable name: {}", name),
+    ));
+  }
+
+  crate::set_variable(name, value)
+    .map_err(|err| RpcError::new(CALCULATOR_ERROR, err.to_string()))?;
+
+  Ok(json!({ "name": name.to_lowercase(), "value": value }))
+}
+
+fn get_history(params: &Value) -> Result<Value, RpcError> {
+  let count = match param(params, "count", 0) {
+    None | Some(Value::Null) => 10,
+    Some(value) => value.as_u64().ok_or_else(|| {
+      RpcError::new(
+        INVALID_PARAMS,
+        "Parameter 'count' must be a non-negative integer",
+      )
+    })? as usize,
+  };
+
+  let entries =
+    crate::get_history(count).map_err(|err| RpcError::new(CALCULATOR_ERROR, err.to_string()))?;
+
+  Ok(json!(entries))
+}
+
+/// Encodes the outcome of a method call as a response line
+pub(crate) fn response(id: Value, result: &Result<Value, RpcError>) -> String {
+  match result {
+    Ok(value) => json!({ "jsonrpc": "2.0", "id": id, "result": value }).to_string(),
+    Err(err) => error_response(id, err.clone()),
+  }
+}
+
+fn error_response(id: Value, error: RpcError) -> String {
+  json!({
+    "jsonrpc": "2.0",
+    "id": id,
+    "error": { "code": error.code, "message": error.message },
+  })
+  .to_string()
+}
+
+fn notification(method: &str, params: Value) -> String {
+  json!({ "jsonrpc": "2.0", "method": method, "params": params }).to_string()
+}
@@ -5,155 +5,774 @@
 //!
 //! ## Features
 //!
+//! - Results as typed [`Value`]s, with [`evaluate_expression`] for plain numbers
 //! - Basic operations: addition, subtraction, multiplication, division, modulo, and exponentiation
-//! - Mathematical functions: sqrt, sin, cos, tan, log, ln, exp, abs, floor, ceil
-//! - Mathematical constants: pi, e, tau, phi
+//! - Integer mode with fixed-width types and wrapping or checked overflow ([`programmer`])
+//! - Exact fraction arithmetic, so `1/3 + 1/6` is `1/2` ([`evaluate_fraction`])
+//! - Combinations and permutations (`nCr`, `nPr`), with exact [`BigInt`] results for large integers
+//! - Mathematical functions: sqrt, sin, cos, tan, log, ln, exp, abs, floor, ceil, round
+//! - Mathematical constants: pi, e, tau, phi, physical constants (`physics` feature) and constants [registered](constants::register) at runtime
+//! - Roman numeral conversions: roman, from_roman
+//! - Numbers written out in words with `spell` ([`words`])
+//! - Currency formatting with symbols and thousands grouping
+//! - Values with uncertainties, such as `5.0 ± 0.1`, propagated through calculations
 //! - Memory functions: M+, M-, MR, MC
-//! - History persistence between sessions
-//! - User-defined variables
-//! - Error handling for invalid inputs
+//! - History, variable and memory persistence between sessions (`persistence` feature, on by default)
+//! - Preferences shared by the CLI and GUI in a TOML file (`config` feature, on by default)
+//! - User-defined variables, assigned with `x = 3 * pi` and grouped into namespaces as in `physics.c`, or variables passed to a single evaluation
+//! - [Formula variables](formulas) such as `area := pi * r^2`, recalculated when the variables they use change
+//! - User-defined [`functions`] with parameters, such as `f(x) = x^2 + 3*x`
+//! - `ans` for the last result
+//! - Independent [`Calculator`] sessions with their own memory, variables, history and `ans`
+//! - Error handling for invalid inputs, with stable [`ErrorCode`]s, and checking expressions without evaluating them
+//! - Limits on expression size and evaluation work for untrusted input
+//! - Configurable handling of domain violations: error, NaN, or complex results
+//! - Conversion between infix expressions and Reverse Polish Notation
+//! - A public tokenizer that locates every token, for highlighting and tooling ([`tokenize`])
+//! - Public expression trees ([`Expr`], [`parse`]) that serialize to and from JSON
+//! - Result formatting with significant figures, fixed decimals, or scientific and engineering notation ([`format_result`])
+//! - Canonical forms of expressions that show how they're grouped ([`canonicalize`])
+//! - Algebraic simplification by constant folding and combining like terms ([`simplify()`])
+//! - [`CompiledExpression`] for evaluating one formula over many variable bindings
+//! - Custom functions written in Rust, registered on an [`EvalContext`]
+//! - `serde` support for expression trees, values and errors (`serde` feature)
+//! - Line-delimited JSON-RPC interface for embedding in other processes
+//! - Model Context Protocol tool server for AI assistants
+//! - WebSocket streaming of results to remote front-ends (`websocket` feature)
+//! - D-Bus service for desktop launchers (`dbus` feature)
+//! - Local socket daemon answering one expression per line (Unix)
+//! - Prometheus-style metrics for the server modes
+//! - `tracing` spans around tokenizing, parsing and evaluating, with timings and errors (`tracing` feature)
+//! - Shared clipboard copy/paste for the CLI and GUI (`clipboard` feature)
+//! - Spreadsheet-style grid with cell references and automatic recalculation
+//! - Parameter sweep tables over a range of values, as aligned text or CSV
+//! - Plot rendering to SVG or PNG files (`plot` feature)
+//!
+//! ## `no_std`
+//!
+//! Without the default `std` feature the crate is `no_std` and only needs
+//! `alloc`. The tokenizer, parser and evaluator remain, along with
+//! [`Expr`], [`CompiledExpression`] and [`Value`]; settings keep their
+//! defaults, and there are no global variables, memory or history.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
+use crate::prelude::*;
+use alloc::borrow::Cow;
+use core::ops::Range;
+#[cfg(feature = "std")]
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Write};
-use std::path::Path;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
 use std::sync::{Arc, Mutex, RwLock};
 
-// Global memory state for calculator
-static MEMORY: RwLock<f64> = RwLock::new(0.0);
+pub use ast::{Expr, canonicalize, parse};
+pub use bigint::BigInt;
+#[cfg(feature = "std")]
+pub use calculator::Calculator;
+pub use compiled::CompiledExpression;
+#[cfg(feature = "std")]
+pub use context::EvalContext;
+#[cfg(feature = "double-double")]
+pub use double_double::DoubleDouble;
+pub use error_code::ErrorCode;
+use format::Notation;
+pub use format::{FormatOptions, format_result};
+pub use rational::Rational;
+#[cfg(feature = "std")]
+use settings::Settings;
+use settings::{AngleMode, DomainPolicy, NonFinitePolicy, OverflowPolicy};
+pub use simplify::simplify;
+pub use value::Value;
+
+pub mod ast;
+pub mod bigint;
+#[cfg(feature = "std")]
+pub mod calculator;
+pub mod calculus;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod compiled;
+pub mod complex;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod constants;
+#[cfg(feature = "std")]
+pub mod context;
+pub mod currency;
+#[cfg(all(unix, feature = "std"))]
+pub mod daemon;
+pub mod dates;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+pub mod dms;
+#[cfg(feature = "double-double")]
+pub mod double_double;
+pub mod error_code;
+pub mod finance;
+pub mod format;
+#[cfg(feature = "std")]
+pub mod formulas;
+#[cfg(feature = "std")]
+pub mod functions;
+#[cfg(feature = "std")]
+pub mod jsonrpc;
+pub mod limits;
+pub mod lists;
+pub mod matrices;
+#[cfg(feature = "std")]
+pub mod mcp;
+#[cfg(feature = "std")]
+pub mod memory;
+#[cfg(feature = "std")]
+pub mod metrics;
+pub mod optimize;
+mod parser;
+#[cfg(feature = "plot")]
+pub mod plot;
+pub mod polynomials;
+pub mod primes;
+pub mod programmer;
+pub mod rational;
+pub mod real;
+pub mod roman;
+pub mod rpn;
+pub mod sequences;
+#[cfg(feature = "serde")]
+mod serialization;
+pub mod series;
+pub mod settings;
+#[cfg(feature = "std")]
+pub mod sheet;
+pub mod simplify;
+pub mod solver;
+#[cfg(feature = "std")]
+mod storage;
+#[cfg(feature = "std")]
+pub mod table;
+pub mod uncertainty;
+pub mod value;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+pub mod words;
+
+/// Items of the standard prelude, which `no_std` builds take from `alloc`,
+/// and the float functions `core` lacks
+mod prelude {
+  pub(crate) use alloc::boxed::Box;
+  pub(crate) use alloc::string::{String, ToString};
+  pub(crate) use alloc::vec::Vec;
+  pub(crate) use alloc::{format, vec};
+  // Unused when another crate of the build links std, as the
+  // dev-dependencies do, which brings in the methods of f64
+  #[cfg(not(feature = "std"))]
+  #[allow(unused_imports)]
+  pub(crate) use num_traits::Float;
+}
 
-// Global variables storage
-lazy_static::lazy_static! {
-    static ref VARIABLES: Arc<Mutex<HashMap<String, f64>>> = Arc::new(Mutex::new(HashMap::new()));
+// The last result, which `ans` stands for
+#[cfg(feature = "std")]
+static ANSWER: RwLock<Option<f64>> = RwLock::new(None);
+
+/// The memory and last result of a [`Calculator`] session or an
+/// [`EvalContext`]
+#[cfg(feature = "std")]
+struct Session {
+  memory: memory::Memory,
+  answer: Option<f64>,
 }
 
-/// Adds an expression and its result to the history file
-pub fn add_to_history(expression: &str, result: f64) -> io::Result<()> {
-  let history_path = get_history_path();
-  let mut file = OpenOptions::new()
-    .create(true)
-    .append(true)
-    .open(history_path)?;
+#[cfg(feature = "std")]
+thread_local! {
+  // Session of the Calculator or EvalContext evaluating on this thread, if any
+  static SESSION: std::cell::RefCell<Option<Session>> = const { std::cell::RefCell::new(None) };
+}
 
-  writeln!(file, "{} = {}", expression, result)?;
-  Ok(())
+/// Applies the memory function `function` with `value` to the memory in
+/// use, which is the evaluating [`Calculator`]'s or [`EvalContext`]'s or else
+/// the global one, and returns the memory's value. The global memory is saved
+/// whenever it changes.
+#[cfg(feature = "std")]
+fn update_memory(function: &str, value: f64) -> Result<f64, CalculatorError> {
+  let change = |memory: &mut memory::Memory| match function {
+    "m+" => Ok(memory.add(value)),
+    "m-" => Ok(memory.subtract(value)),
+    "mc" => Ok(memory.clear()),
+    "mundo" => memory
+      .undo()
+      .map(|_| memory.value())
+      .ok_or_else(memory::nothing_to_undo),
+    _ => Ok(memory.value()),
+  };
+  let in_session = SESSION.with(|cell| {
+    let mut session = cell.borrow_mut();
+    session.as_mut().map(|session| change(&mut session.memory))
+  });
+  match in_session {
+    Some(result) => result,
+    None => memory::update(change),
+  }
 }
 
-/// Gets the last n entries from the history file
-pub fn get_history(n: usize) -> io::Result<Vec<String>> {
-  let history_path = get_history_path();
-  if !Path::new(&history_path).exists() {
-    return Ok(Vec::new());
+/// There is no memory to update without the `std` feature
+#[cfg(not(feature = "std"))]
+fn update_memory(_function: &str, _value: f64) -> Result<f64, CalculatorError> {
+  Err(CalculatorError::ArgumentError(
+    "Memory functions need the std feature".to_string(),
+  ))
+}
+
+/// Runs `f` with `memory` and `answer` in place of the global memory and
+/// last result on this thread, returning its result and the memory as `f`
+/// left it. The previous session is restored even if `f` panics.
+#[cfg(feature = "std")]
+pub(crate) fn with_session<T>(
+  memory: memory::Memory,
+  answer: Option<f64>,
+  f: impl FnOnce() -> T,
+) -> (T, memory::Memory) {
+  struct Restore(Option<Session>);
+
+  impl Drop for Restore {
+    fn drop(&mut self) {
+      SESSION.with(|cell| cell.replace(self.0.take()));
+    }
   }
 
-  let file = File::open(history_path)?;
-  let reader = BufReader::new(file);
+  let _restore = Restore(SESSION.with(|cell| cell.replace(Some(Session { memory, answer }))));
+  let result = f();
+  let memory = SESSION.with(|cell| {
+    let mut session = cell.borrow_mut();
+    session
+      .as_mut()
+      .map(|session| std::mem::take(&mut session.memory))
+  });
+  (result, memory.unwrap_or_default())
+}
 
-  // Read all lines, but keep only the last n
-  let lines: Vec<String> = reader.lines().filter_map(Result::ok).collect();
+/// The value of `ans`: the evaluating [`Calculator`]'s last result, or else
+/// the global one
+#[cfg(feature = "std")]
+pub(crate) fn answer() -> Result<f64, CalculatorError> {
+  let answer = match SESSION.with(|cell| cell.borrow().as_ref().map(|session| session.answer)) {
+    Some(answer) => answer,
+    None => *ANSWER.read().unwrap(),
+  };
+  answer.ok_or_else(|| {
+    CalculatorError::ArgumentError("There is no previous result for ans".to_string())
+  })
+}
 
-  let start = if lines.len() > n { lines.len() - n } else { 0 };
+/// Makes `value` the value of `ans` for whatever is evaluating on this
+/// thread: the session in [`with_answer`], if any, or else the global one
+#[cfg(feature = "std")]
+fn record_answer(value: f64) {
+  let in_session = SESSION.with(|cell| {
+    let mut session = cell.borrow_mut();
+    session.as_mut().map(|session| session.answer = Some(value))
+  });
+  if in_session.is_none() {
+    set_answer(value);
+  }
+}
 
-  Ok(lines[start..].to_vec())
+/// There is no last result without the `std` feature
+#[cfg(not(feature = "std"))]
+pub(crate) fn answer() -> Result<f64, CalculatorError> {
+  Err(CalculatorError::ArgumentError(
+    "ans needs the std feature".to_string(),
+  ))
 }
 
-/// Clear history file
-pub fn clear_history() -> io::Result<()> {
-  let history_path = get_history_path();
-  if Path::new(&history_path).exists() {
-    // Truncate file to zero length
-    let _file = OpenOptions::new()
-      .write(true)
-      .truncate(true)
-      .open(history_path)?;
+#[cfg(feature = "std")]
+/// Returns the last result, which `ans` stands for in expressions, if there
+/// is one.
+///
+/// Each successful evaluation changes it, as do [`add_to_history`] and
+/// [`set_answer`]. A front end that evaluates the same input again, e.g. to
+/// show it as a fraction, can do so with [`with_answer`] so `ans` still
+/// means what it did the first time.
+///
+/// # Examples
+///
+/// ```
+/// rust_calculator::set_answer(21.0);
+/// assert_eq!(rust_calculator::last_answer(), Some(21.0));
+/// assert_eq!(rust_calculator::evaluate_expression("ans * 2").unwrap(), 42.0);
+///
+/// rust_calculator::reset_answer();
+/// assert!(rust_calculator::evaluate_expression("ans").is_err());
+/// ```
+pub fn last_answer() -> Option<f64> {
+  *ANSWER.read().unwrap()
+}
+
+#[cfg(feature = "std")]
+/// Makes `value` the last result, which `ans` stands for
+pub fn set_answer(value: f64) {
+  *ANSWER.write().unwrap() = Some(value);
+}
+
+#[cfg(feature = "std")]
+/// Forgets the last result, so `ans` is an error until there's another
+pub fn reset_answer() {
+  *ANSWER.write().unwrap() = None;
+}
+
+#[cfg(feature = "std")]
+/// Runs `f` with `answer` as the value of `ans` on this thread, leaving the
+/// last result as it is. Memory functions change a copy of the memory, which
+/// is discarded afterwards.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::{evaluate_expression, evaluate_to_string, last_answer, set_answer, with_answer};
+///
+/// set_answer(1.0);
+/// assert_eq!(evaluate_expression("ans + 0.5").unwrap(), 1.5);
+///
+/// // The same input shown as text, with ans as it was before
+/// let text = with_answer(Some(1.0), || evaluate_to_string("ans + 0.5")).unwrap();
+/// assert_eq!(text, "1.5");
+/// assert_eq!(last_answer(), Some(1.5));
+/// ```
+pub fn with_answer<T>(answer: Option<f64>, f: impl FnOnce() -> T) -> T {
+  with_session(memory::current(), answer, f).0
+}
+
+/// A stored variable, with what's known about it besides its value
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+  pub value: f64,
+  /// What the variable is for, if it's been described
+  pub description: Option<String>,
+  /// When the variable was first set, in seconds since the Unix epoch
+  pub created: u64,
+}
+
+#[cfg(feature = "std")]
+impl Variable {
+  /// A variable created now
+  pub(crate) fn new(value: f64) -> Variable {
+    let created = std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .map_or(0, |elapsed| elapsed.as_secs());
+    Variable {
+      value,
+      description: None,
+      created,
+    }
   }
-  Ok(())
+}
+
+// Global variables storage
+#[cfg(feature = "std")]
+lazy_static::lazy_static! {
+    static ref VARIABLES: Arc<Mutex<HashMap<String, Variable>>> = Arc::new(Mutex::new(HashMap::new()));
+}
+
+/// Sets a variable's value, keeping its description and creation time
+#[cfg(feature = "std")]
+fn store_variable(vars: &mut HashMap<String, Variable>, name: String, value: f64) {
+  vars
+    .entry(name)
+    .and_modify(|variable| variable.value = value)
+    .or_insert_with(|| Variable::new(value));
+}
+
+#[cfg(feature = "std")]
+/// Adds an expression and its result to the history, which is a file with
+/// the `persistence` feature, and makes the result the value of `ans`
+pub fn add_to_history(expression: &str, result: f64) -> io::Result<()> {
+  set_answer(result);
+  storage::append_history(&format!("{} = {}", expression, result))
+}
+
+#[cfg(feature = "std")]
+/// Gets the last n entries from the history, oldest first. Only the end of
+/// the history file is read, however long it is.
+pub fn get_history(n: usize) -> io::Result<Vec<String>> {
+  storage::read_last_history(n)
+}
+
+#[cfg(feature = "std")]
+/// Iterates over the whole history from the oldest entry, reading the
+/// history file a line at a time instead of loading it into memory.
+///
+/// # Examples
+///
+/// ```
+/// let longest = rust_calculator::history_entries()
+///   .unwrap()
+///   .map_while(Result::ok)
+///   .max_by_key(|entry| entry.len());
+/// ```
+pub fn history_entries() -> io::Result<impl Iterator<Item = io::Result<String>>> {
+  storage::history_entries()
+}
+
+#[cfg(feature = "std")]
+/// Clear history
+pub fn clear_history() -> io::Result<()> {
+  storage::clear_history()
 }
 
 /// Get the path to the history file
+#[cfg(feature = "persistence")]
 pub fn get_history_path() -> String {
-  if let Some(proj_dirs) = directories::ProjectDirs::from("com", "thickkoezz", "rust-calculator") {
-    let data_dir = proj_dirs.data_dir();
-    // Create directory if it doesn't exist
-    std::fs::create_dir_all(data_dir).unwrap_or_default();
-    format!("{}/calculator_history.txt", data_dir.display())
-  } else {
-    // Fallback to current directory
-    "calculator_history.txt".to_string()
-  }
+  storage::history_path()
 }
 
-/// Sets a user-defined variable to a specific value
+#[cfg(feature = "std")]
+/// Sets a user-defined variable to a specific value.
+///
+/// Fails with an [`io::ErrorKind::InvalidInput`] error holding a
+/// [`CalculatorError::ReservedName`] if the name is that of a constant or a
+/// function, which the variable could never take the place of.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::CalculatorError;
+///
+/// let err = rust_calculator::set_variable("e", 5.0).unwrap_err();
+/// assert!(matches!(
+///   err.get_ref().and_then(|err| err.downcast_ref()),
+///   Some(CalculatorError::ReservedName(name)) if name == "e"
+/// ));
+/// ```
 pub fn set_variable(name: &str, value: f64) -> io::Result<()> {
+  reserved_name(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+  // The value takes the place of a formula of the same name
+  formulas::remove(name)?;
   let mut vars = VARIABLES.lock().unwrap();
-  vars.insert(name.to_lowercase(), value);
+  store_variable(&mut vars, name.to_lowercase(), value);
 
-  // Optionally persist variables to a file
-  let var_path = get_variables_path();
-  let mut file = OpenOptions::new()
-    .write(true)
-    .create(true)
-    .truncate(true)
-    .open(var_path)?;
+  // Persist variables to a file with the persistence feature
+  storage::save_variables(&vars)?;
+  drop(vars);
+  formulas::recalculate_from(name);
+  Ok(())
+}
 
-  for (key, val) in vars.iter() {
-    writeln!(file, "{}={}", key, val)?;
+/// Fails unless `name` can be that of a variable: an identifier that isn't
+/// taken by a constant or a function
+#[cfg(feature = "std")]
+pub(crate) fn variable_name(name: &str) -> Result<(), CalculatorError> {
+  if !is_identifier(name) {
+    return Err(CalculatorError::ArgumentError(format!(
+      "Invalid variable name: {}",
+      name
+    )));
   }
+  reserved_name(name)
+}
 
+/// Fails with [`CalculatorError::ReservedName`] if `name` is a built-in or
+/// user-defined constant or function rather than a free name
+#[cfg(feature = "std")]
+fn reserved_name(name: &str) -> Result<(), CalculatorError> {
+  if is_identifier(name) && !functions::is_free(name) {
+    return Err(CalculatorError::ReservedName(name.to_string()));
+  }
   Ok(())
 }
 
-/// Gets a user-defined variable's value
+#[cfg(feature = "std")]
+/// Removes a user-defined variable, also from the saved variables, and
+/// returns the value it had, or `None` if there was no such variable.
+///
+/// # Examples
+///
+/// ```
+/// rust_calculator::set_variable("stale", 1.5).unwrap();
+/// assert_eq!(rust_calculator::delete_variable("stale").unwrap(), Some(1.5));
+/// assert!(rust_calculator::evaluate_expression("stale").is_err());
+/// assert_eq!(rust_calculator::delete_variable("stale").unwrap(), None);
+/// ```
+pub fn delete_variable(name: &str) -> io::Result<Option<f64>> {
+  let mut vars = VARIABLES.lock().unwrap();
+  let removed = vars.remove(&name.to_lowercase());
+  if removed.is_some() {
+    storage::save_variables(&vars)?;
+    drop(vars);
+    formulas::recalculate_from(name);
+  }
+  Ok(removed.map(|variable| variable.value))
+}
+
+#[cfg(feature = "std")]
+/// Imports variables from a JSON or dotenv-style file.
+///
+/// JSON files must contain an object whose numeric (or numeric string) values
+/// become variables. Any other file is read as `KEY=VALUE` lines, allowing
+/// `#` comments, an `export` prefix and quoted values. Entries whose values
+/// aren't numbers are skipped. Returns the imported name/value pairs.
+pub fn import_variables(path: &str) -> io::Result<Vec<(String, f64)>> {
+  let content = std::fs::read_to_string(path)?;
+  let is_json = path.to_lowercase().ends_with(".json") || content.trim_start().starts_with('{');
+
+  let mut imported = if is_json {
+    parse_json_variables(&content)?
+  } else {
+    parse_dotenv_variables(&content)
+  };
+  imported.sort_by(|a, b| a.0.cmp(&b.0));
+  for (name, _) in &imported {
+    reserved_name(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+  }
+  for (name, _) in &imported {
+    formulas::remove(name)?;
+  }
+
+  let mut vars = VARIABLES.lock().unwrap();
+  for (name, value) in &imported {
+    store_variable(&mut vars, name.clone(), *value);
+  }
+  storage::save_variables(&vars)?;
+  drop(vars);
+  formulas::recalculate_all();
+
+  Ok(imported)
+}
+
+#[cfg(feature = "std")]
+/// Extracts numeric entries from a JSON object
+fn parse_json_variables(content: &str) -> io::Result<Vec<(String, f64)>> {
+  let json: serde_json::Value =
+    serde_json::from_str(content).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+  let object = json.as_object().ok_or_else(|| {
+    io::Error::new(
+      io::ErrorKind::InvalidData,
+      "Expected a JSON object of name/number pairs",
+    )
+  })?;
+
+  Ok(
+    object
+      .iter()
+      .filter_map(|(name, value)| {
+        let number = match value {
+          serde_json::Value::Number(number) => number.as_f64(),
+          serde_json::Value::String(text) => text.trim().parse::<f64>().ok(),
+          _ => None,
+        };
+        number.map(|number| (name.to_lowercase(), number))
+      })
+      .collect(),
+  )
+}
+
+#[cfg(feature = "std")]
+/// Extracts numeric `KEY=VALUE` entries from dotenv-style content
+fn parse_dotenv_variables(content: &str) -> Vec<(String, f64)> {
+  content
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    .filter_map(|line| {
+      let line = line.strip_prefix("export ").unwrap_or(line);
+      let (name, value) = line.split_once('=')?;
+      let value = value.trim().trim_matches(|ch| ch == '"' || ch == '\'');
+      let value = value.parse::<f64>().ok()?;
+      Some((name.trim().to_lowercase(), value))
+    })
+    .collect()
+}
+
+/// Gets a user-defined variable's value, working out a [formula](formulas)
+/// of that name from the current variables
+#[cfg(feature = "std")]
 pub fn get_variable(name: &str) -> Option<f64> {
-  let vars = VARIABLES.lock().unwrap();
-  vars.get(&name.to_lowercase()).cloned()
+  let value = VARIABLES
+    .lock()
+    .unwrap()
+    .get(&name.to_lowercase())
+    .map(|variable| variable.value);
+  value.or_else(|| formulas::value(name)?.ok())
 }
 
+/// There are no global variables without the `std` feature
+#[cfg(not(feature = "std"))]
+pub fn get_variable(_name: &str) -> Option<f64> {
+  None
+}
+
+#[cfg(feature = "std")]
 /// Gets all user-defined variables
 pub fn get_all_variables() -> io::Result<Vec<(String, f64)>> {
   let vars = VARIABLES.lock().unwrap();
-  let result: Vec<(String, f64)> = vars.iter().map(|(k, v)| (k.clone(), *v)).collect();
+  let result: Vec<(String, f64)> = vars.iter().map(|(k, v)| (k.clone(), v.value)).collect();
   Ok(result)
 }
 
-/// Loads user-defined variables from persistence
-pub fn load_variables() -> io::Result<()> {
-  let var_path = get_variables_path();
-  if !Path::new(&var_path).exists() {
-    return Ok(());
-  }
+#[cfg(feature = "std")]
+/// Gets a user-defined variable along with its description and creation
+/// time, or `None` if there's no such variable
+pub fn get_variable_info(name: &str) -> Option<Variable> {
+  VARIABLES.lock().unwrap().get(&name.to_lowercase()).cloned()
+}
 
-  let file = File::open(var_path)?;
-  let reader = BufReader::new(file);
+#[cfg(feature = "std")]
+/// Describes what a variable is for, or removes its description if
+/// `description` is empty, and saves the variables.
+///
+/// Fails with an [`io::ErrorKind::NotFound`] error if there's no such
+/// variable.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::{describe_variable, get_variable_info, set_variable};
+///
+/// set_variable("rate", 0.05).unwrap();
+/// describe_variable("rate", "Annual interest rate").unwrap();
+/// let rate = get_variable_info("rate").unwrap();
+/// assert_eq!(rate.description.as_deref(), Some("Annual interest rate"));
+/// assert!(describe_variable("missing", "Nothing").is_err());
+/// ```
+pub fn describe_variable(name: &str, description: &str) -> io::Result<()> {
   let mut vars = VARIABLES.lock().unwrap();
+  let variable = vars.get_mut(&name.to_lowercase()).ok_or_else(|| {
+    io::Error::new(
+      io::ErrorKind::NotFound,
+      format!("No variable named {}", name),
+    )
+  })?;
+  let description = description.trim();
+  variable.description = (!description.is_empty()).then(|| description.to_string());
+  storage::save_variables(&vars)
+}
 
-  for line in reader.lines() {
-    let line = line?;
-    if let Some((name, value_str)) = line.split_once('=') {
-      if let Ok(value) = value_str.parse::<f64>() {
-        vars.insert(name.to_lowercase(), value);
-      }
-    }
+#[cfg(feature = "std")]
+/// Gets the variables in `namespace`, sorted by name. Namespaces nest, so
+/// `project1` holds both `project1.rate` and `project1.loan.years`.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::{get_namespace, set_variable};
+///
+/// set_variable("project1.rate", 0.05).unwrap();
+/// set_variable("project1.loan.years", 10.0).unwrap();
+/// assert_eq!(
+///   get_namespace("project1").unwrap(),
+///   [("project1.loan.years".to_string(), 10.0), ("project1.rate".to_string(), 0.05)]
+/// );
+/// ```
+pub fn get_namespace(namespace: &str) -> io::Result<Vec<(String, f64)>> {
+  let vars = VARIABLES.lock().unwrap();
+  let mut result: Vec<(String, f64)> = vars
+    .iter()
+    .filter(|(name, _)| in_namespace(name, namespace))
+    .map(|(name, variable)| (name.clone(), variable.value))
+    .collect();
+  result.sort_by(|a, b| a.0.cmp(&b.0));
+  Ok(result)
+}
+
+#[cfg(feature = "std")]
+/// Removes the variables and [`formulas`] in `namespace`, also from the saved
+/// ones, and returns how many there were
+pub fn clear_namespace(namespace: &str) -> io::Result<usize> {
+  let formulas: Vec<String> = formulas::list()
+    .into_iter()
+    .map(|formula| formula.name)
+    .filter(|name| in_namespace(name, namespace))
+    .collect();
+  for name in &formulas {
+    formulas::remove(name)?;
   }
 
-  Ok(())
+  let mut vars = VARIABLES.lock().unwrap();
+  let removed: Vec<String> = vars
+    .keys()
+    .filter(|name| in_namespace(name, namespace))
+    .cloned()
+    .collect();
+  if removed.is_empty() {
+    return Ok(formulas.len());
+  }
+  vars.retain(|name, _| !in_namespace(name, namespace));
+  storage::save_variables(&vars)?;
+  drop(vars);
+  for name in &removed {
+    formulas::recalculate_from(name);
+  }
+  Ok(formulas.len() + removed.len())
 }
 
-/// Get the path to the variables file
-fn get_variables_path() -> String {
-  if let Some(proj_dirs) = directories::ProjectDirs::from("com", "thickkoezz", "rust-calculator") {
-    let data_dir = proj_dirs.data_dir();
-    // Create directory if it doesn't exist
-    std::fs::create_dir_all(data_dir).unwrap_or_default();
-    format!("{}/calculator_variables.txt", data_dir.display())
-  } else {
-    // Fallback to current directory
-    "calculator_variables.txt".to_string()
-  }
+/// Checks whether the variable `name` is in `namespace`, as `physics.c` is in
+/// `physics`
+pub fn in_namespace(name: &str, namespace: &str) -> bool {
+  let namespace = namespace.trim().trim_end_matches('.').to_lowercase();
+  name
+    .to_lowercase()
+    .strip_prefix(&namespace)
+    .is_some_and(|rest| rest.starts_with('.'))
+}
+
+#[cfg(feature = "std")]
+/// Loads the variables, [`formulas`], user-defined [`functions`] and memory
+/// saved by earlier sessions; there are none without the `persistence`
+/// feature.
+///
+/// Whatever can be read is loaded even if something else can't, and the
+/// first problem is then returned. Damaged entries in the variables file
+/// are left out and named in the error, and the file as it was is kept with
+/// a `.bak` extension.
+pub fn load_variables() -> io::Result<()> {
+  let memory = memory::load();
+  let variables = storage::read_variables().and_then(|saved| {
+    VARIABLES.lock().unwrap().extend(saved.variables);
+    if saved.damaged.is_empty() {
+      Ok(())
+    } else {
+      Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Left out damaged variables: {}", saved.damaged.join(", ")),
+      ))
+    }
+  });
+  // Formulas are worked out as they load, which may call functions
+  let functions = functions::load();
+  let formulas = formulas::load();
+  memory.and(variables).and(functions).and(formulas)
+}
+
+#[cfg(feature = "std")]
+/// Runs `f` with the history, variables, formulas, functions and memory
+/// files kept in `dir` instead of the data directory, for this thread only,
+/// such as to keep the files of one test or profile apart from another's.
+/// What's already loaded stays loaded.
+///
+/// # Examples
+///
+/// ```
+/// let dir = std::env::temp_dir().join("calculator_doc_data");
+/// rust_calculator::with_data_dir(&dir, || {
+///   rust_calculator::set_variable("doc_rate", 0.2).unwrap();
+/// });
+/// # #[cfg(feature = "persistence")]
+/// assert!(dir.join("calculator_variables.toml").exists());
+/// # std::fs::remove_dir_all(&dir).ok();
+/// ```
+pub fn with_data_dir<T>(dir: impl Into<std::path::PathBuf>, f: impl FnOnce() -> T) -> T {
+  storage::with_data_dir(dir.into(), f)
 }
 
 /// Custom error type for calculator operations
+///
+/// Each error also has a stable [`ErrorCode`], from [`code`](Self::code),
+/// for handling errors without reading their messages. With the `serde`
+/// feature, errors serialize to their kind, message and span.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum CalculatorError {
   /// Error when parsing input
   ParseError(String),
@@ -163,23 +782,110 @@ pub enum CalculatorError {
   SyntaxError(String),
   /// Error with function arguments (out of bounds, etc.)
   ArgumentError(String),
+  /// A result overflowed to infinity or NaN although the inputs were finite
+  Overflow(String),
+  /// An evaluation went past one of its [`limits`]
+  LimitExceeded(String),
+  /// A variable, function or constant can't be defined with the name, as it
+  /// belongs to something built in or already defined
+  ReservedName(String),
   /// Other errors
   Other(String),
+  /// An error located in the expression, at a byte range of its input
+  Spanned {
+    error: Box<CalculatorError>,
+    span: Range<usize>,
+  },
 }
 
-impl std::fmt::Display for CalculatorError {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl CalculatorError {
+  /// Byte range of the part of the expression the error is about, if known.
+  ///
+  /// Parse and syntax errors point at the offending token, such as an unknown
+  /// name, an unmatched parenthesis or an operator missing an operand. Other
+  /// errors aren't located.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// let err = rust_calculator::evaluate_expression("2 + xyz").unwrap_err();
+  /// assert_eq!(err.span(), Some(4..7));
+  /// ```
+  pub fn span(&self) -> Option<Range<usize>> {
+    match self {
+      CalculatorError::Spanned { span, .. } => Some(span.clone()),
+      _ => None,
+    }
+  }
+
+  /// Like [`span`](Self::span), but counted in characters of `expression`
+  pub fn char_span(&self, expression: &str) -> Option<Range<usize>> {
+    let span = self.span()?;
+    let chars = |byte: usize| expression.get(..byte).map(|prefix| prefix.chars().count());
+    Some(chars(span.start)?..chars(span.end)?)
+  }
+
+  /// The error without its location
+  pub fn unspanned(&self) -> &CalculatorError {
+    match self {
+      CalculatorError::Spanned { error, .. } => error.unspanned(),
+      error => error,
+    }
+  }
+
+  /// Locates a parse or syntax error at `span`; other errors, and errors
+  /// already located, are returned unchanged
+  fn at(self, span: Range<usize>) -> Self {
+    match self {
+      CalculatorError::ParseError(_) | CalculatorError::SyntaxError(_) => {
+        CalculatorError::Spanned {
+          error: Box::new(self),
+          span,
+        }
+      }
+      error => error,
+    }
+  }
+
+  /// Moves the location along by `offset`, for errors in a part of the
+  /// expression starting there
+  #[cfg(feature = "std")]
+  fn offset(self, offset: usize) -> Self {
+    match self {
+      CalculatorError::Spanned { error, span } => CalculatorError::Spanned {
+        error,
+        span: span.start + offset..span.end + offset,
+      },
+      error => error,
+    }
+  }
+
+  /// Drops the location, for errors in text rewritten before evaluation
+  fn without_span(self) -> Self {
+    match self {
+      CalculatorError::Spanned { error, .. } => error.without_span(),
+      error => error,
+    }
+  }
+}
+
+impl core::fmt::Display for CalculatorError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
     match self {
       CalculatorError::ParseError(msg) => write!(f, "Parse error: {}", msg),
       CalculatorError::MathError(msg) => write!(f, "Math error: {}", msg),
       CalculatorError::SyntaxError(msg) => write!(f, "Syntax error: {}", msg),
       CalculatorError::ArgumentError(msg) => write!(f, "Argument error: {}", msg),
+      CalculatorError::Overflow(msg) => write!(f, "Overflow: {}", msg),
+      CalculatorError::LimitExceeded(msg) => write!(f, "Limit exceeded: {}", msg),
+      CalculatorError::ReservedName(name) => write!(f, "Reserved name: {} is already taken", name),
       CalculatorError::Other(msg) => write!(f, "{}", msg),
+      CalculatorError::Spanned { error, .. } => write!(f, "{}", error),
     }
   }
 }
 
-impl std::error::Error for CalculatorError {}
+impl core::error::Error for CalculatorError {}
 
 /// Evaluates a mathematical expression and returns the result.
 ///
@@ -200,293 +906,1348 @@ impl std::error::Error for CalculatorError {}
 /// let result = rust_calculator::evaluate_expression("sqrt 16");
 /// assert_eq!(result.unwrap(), 4.0);
 /// ```
+///
+/// An assignment such as `x = 3 * pi` stores the value in a variable, as
+/// [`set_variable`] does, and returns it:
+///
+/// ```
+/// let result = rust_calculator::evaluate_expression("width = 2 * 3");
+/// assert_eq!(result.unwrap(), 6.0);
+/// assert_eq!(rust_calculator::evaluate_expression("width ^ 2").unwrap(), 36.0);
+/// ```
+///
+/// The result becomes the value of `ans`:
+///
+/// ```
+/// rust_calculator::evaluate_expression("6 * 7").unwrap();
+/// assert_eq!(rust_calculator::last_answer(), Some(42.0));
+/// assert_eq!(rust_calculator::evaluate_expression("ans + 1").unwrap(), 43.0);
+/// ```
 pub fn evaluate_expression(expression: &str) -> Result<f64, CalculatorError> {
-  // Handle special cases first
-  let trimmed = expression.trim();
+  let value = expression_value(expression)?;
+  #[cfg(feature = "std")]
+  record_answer(value);
+  Ok(value)
+}
 
-  // Memory commands without arguments
-  if trimmed == "mr" {
-    let memory = MEMORY.read().unwrap();
-    return Ok(*memory);
+/// Evaluates an expression as [`evaluate_expression`] does without making the
+/// result the value of `ans`, for parts of a larger evaluation
+pub(crate) fn expression_value(expression: &str) -> Result<f64, CalculatorError> {
+  #[cfg(feature = "std")]
+  if let Some((name, start)) = assignment(expression)? {
+    let value = expression_value(&expression[start..]).map_err(|err| err.offset(start))?;
+    set_variable(name, value)
+      .map_err(|err| CalculatorError::Other(format!("Error saving variables: {}", err)))?;
+    return Ok(value);
   }
-  if trimmed == "mc" {
-    let mut memory = MEMORY.write().unwrap();
-    *memory = 0.0;
-    return Ok(0.0);
+  match continue_answer(expression) {
+    // Spans would point into the continued text, which the caller never saw
+    Some(continued) => {
+      evaluate_with_resolver(&continued, &get_variable).map_err(CalculatorError::without_span)
+    }
+    None => evaluate_with_resolver(expression, &get_variable),
   }
+}
 
-  // Memory commands with arguments
-  if let Some(rest) = trimmed.strip_suffix("m+") {
-    let num = rest.trim();
-    if let Ok(value) = num.parse::<f64>() {
-      let mut memory = MEMORY.write().unwrap();
-      *memory += value;
-      return Ok(*memory);
-    }
+/// Splits an assignment such as `x = 3 * pi` into the variable's name and
+/// where its value starts, or gives `None` for any other expression. Fails if
+/// the name is taken by something built in, a constant or a function.
+#[cfg(feature = "std")]
+pub(crate) fn assignment(expression: &str) -> Result<Option<(&str, usize)>, CalculatorError> {
+  let Some(equals) = expression.find('=') else {
+    return Ok(None);
+  };
+  let name = expression[..equals].trim();
+  // ==, <=, >= and != are comparisons
+  if expression[equals + 1..].starts_with('=') || !is_identifier(name) {
+    return Ok(None);
   }
+  reserved_name(name)?;
+  Ok(Some((name, equals + 1)))
+}
 
-  if let Some(rest) = trimmed.strip_suffix("m-") {
-    let num = rest.trim();
-    if let Ok(value) = num.parse::<f64>() {
-      let mut memory = MEMORY.write().unwrap();
-      *memory -= value;
-      return Ok(*memory);
-    }
+/// With the `continue` setting on, puts `ans` before an expression that
+/// starts with a binary operator, so `* 2` doubles the last result. A `-`
+/// right before what follows is still a sign: `-5` is minus five, while `- 5`
+/// takes five from the last result.
+pub(crate) fn continue_answer(expression: &str) -> Option<String> {
+  if !settings::current().continuation {
+    return None;
   }
+  let expression = expression.trim_start();
+  let continues = match operator_at(expression)? {
+    "," => false,
+    "-" => expression[1..].starts_with(char::is_whitespace),
+    _ => true,
+  };
+  continues.then(|| format!("ans {}", expression))
+}
 
-  // Unit conversions with various formats
-  let conversions = [
-    "km_to_mi",
-    "mi_to_km",
-    "kg_to_lb",
-    "lb_to_kg",
-    "c_to_f",
-    "f_to_c",
-    "rad_to_deg",
-    "deg_to_rad",
-    "in_to_cm",
-    "cm_to_in",
-    "gal_to_l",
-    "l_to_gal",
-  ];
+#[cfg(feature = "std")]
+/// Evaluates an expression as [`evaluate_expression`] does, reusing the
+/// program compiled the last time the same text was evaluated, which makes
+/// repeated evaluations much faster.
+///
+/// Variables are read when the program runs, so changing them is seen. Errors
+/// found while running aren't located by [`CalculatorError::span`].
+///
+/// # Examples
+///
+/// ```
+/// rust_calculator::set_variable("side", 3.0).unwrap();
+/// assert_eq!(rust_calculator::evaluate_cached("side ^ 2").unwrap(), 9.0);
+///
+/// rust_calculator::set_variable("side", 4.0).unwrap();
+/// assert_eq!(rust_calculator::evaluate_cached("side ^ 2").unwrap(), 16.0);
+/// ```
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    fields(elapsed_us = tracing::field::Empty),
+    ret,
+    err(level = "debug")
+  )
+)]
+pub fn evaluate_cached(expression: &str) -> Result<f64, CalculatorError> {
+  #[cfg(feature = "tracing")]
+  let _timer = Timer::start();
+
+  // Promoting to complex numbers needs the whole expression again, integer
+  // mode doesn't compile, and a continued expression reads the last result
+  let settings = settings::current();
+  if settings.domain == DomainPolicy::Complex
+    || settings.integer.is_some()
+    || continue_answer(expression).is_some()
+  {
+    return evaluate_expression(expression);
+  }
 
-  // Case 1: Format like "10 km_to_mi" (with space)
-  let parts: Vec<&str> = trimmed.split_whitespace().collect();
-  if parts.len() == 2 {
-    // Try parsing first part as number
-    if let Ok(value) = parts[0].parse::<f64>() {
-      if conversions.contains(&parts[1]) {
-        return evaluate_function(parts[1], value);
-      }
-    }
-    // Special case for "pi rad_to_deg" and similar
-    else if parts[0].to_lowercase() == "pi" && conversions.contains(&parts[1]) {
-      return evaluate_function(parts[1], std::f64::consts::PI);
-    }
-    // Check for other constants
-    else if parts[0].to_lowercase() == "e" && conversions.contains(&parts[1]) {
-      return evaluate_function(parts[1], std::f64::consts::E);
+  match CompiledExpression::cached(expression) {
+    Ok(compiled) => {
+      let value = compiled.evaluate_with(&get_variable)?;
+      record_answer(value);
+      Ok(value)
     }
+    // Nothing has run yet, so this only locates the error
+    Err(_) => evaluate_expression(expression),
   }
+}
 
-  // Case 2: Format like "10km_to_mi" (without space)
-  for conv in &conversions {
-    if let Some(rest) = trimmed.strip_suffix(conv) {
-      if let Ok(value) = rest.trim().parse::<f64>() {
-        return evaluate_function(conv, value);
-      }
+/// Evaluates an expression to a [`Value`], which isn't limited to a single
+/// number as the result of [`evaluate_expression`] is. A date can be written
+/// without quotes here: `2024-07-15` is a date rather than a subtraction.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::{Value, evaluate};
+///
+/// assert_eq!(evaluate("5 + 3").unwrap(), Value::Number(8.0));
+/// assert_eq!(evaluate("5 + 3").unwrap().to_string(), "8");
+/// assert_eq!(evaluate("2 ^ 10 == 1024").unwrap(), Value::Boolean(true));
+/// ```
+pub fn evaluate(expression: &str) -> Result<Value, CalculatorError> {
+  // A bare date such as 2024-07-15 is a date here, rather than a subtraction
+  if let Cow::Owned(quoted) = dates::quote_literals(expression) {
+    return evaluate(&quoted).map_err(CalculatorError::without_span);
+  }
+  let value = evaluate_value_with_resolver(expression, &get_variable)?;
+  #[cfg(feature = "std")]
+  if let Value::Number(number) = value {
+    record_answer(number);
+  }
+  Ok(value)
+}
+
+/// Checks an expression for errors without evaluating it.
+///
+/// Reports what would stop [`evaluate_expression`] before it computes
+/// anything: unknown tokens and variables, unknown functions, operators and
+/// functions missing operands, and unmatched parentheses, each located as by
+/// [`CalculatorError::span`]. Errors that depend on the values, such as
+/// division by zero, aren't found.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::validate_expression;
+///
+/// assert!(validate_expression("2 * (3 + 4)").is_ok());
+/// assert!(validate_expression("1 / 0").is_ok());
+/// assert_eq!(validate_expression("2 * (3 +").unwrap_err().span(), Some(7..8));
+/// assert_eq!(validate_expression("foo(3)").unwrap_err().to_string(), "Syntax error: Unknown function: foo");
+/// ```
+pub fn validate_expression(expression: &str) -> Result<(), CalculatorError> {
+  #[cfg(feature = "std")]
+  if let Some((_, start)) = assignment(expression)? {
+    return validate_expression(&expression[start..]).map_err(|err| err.offset(start));
+  }
+  match continue_answer(expression) {
+    Some(continued) => {
+      validate_with_resolver(&continued, &get_variable).map_err(CalculatorError::without_span)
     }
+    None => validate_with_resolver(expression, &get_variable),
   }
+}
 
-  // Continue with normal tokenization for other expressions
-  let tokens = tokenize(expression)?;
+/// Converts an infix expression to the postfix order the evaluator applies
+/// it in, as [`rpn::to_rpn`] does, which shows how precedence and
+/// associativity were read.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::{to_infix, to_postfix};
+///
+/// assert_eq!(to_postfix("2 + 3 * 4").unwrap(), "2 3 4 * +");
+/// assert_eq!(to_postfix("2 ^ 3 ^ 2").unwrap(), "2 3 2 ^ ^");
+/// assert_eq!(to_infix(&to_postfix("(2 + 3) * 4").unwrap()).unwrap(), "(2 + 3) * 4");
+/// ```
+pub fn to_postfix(expression: &str) -> Result<String, CalculatorError> {
+  rpn::to_rpn(expression)
+}
 
-  // If there are no tokens, return an error
-  if tokens.is_empty() {
-    return Err(CalculatorError::SyntaxError("Empty expression".to_string()));
+/// Converts a postfix expression back to infix with only the parentheses it
+/// needs, as [`rpn::from_rpn`] does.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(rust_calculator::to_infix("10 2 3 - -").unwrap(), "10 - (2 - 3)");
+/// assert_eq!(rust_calculator::to_infix("16 sqrt 2 ^").unwrap(), "sqrt(16) ^ 2");
+/// ```
+pub fn to_infix(postfix: &str) -> Result<String, CalculatorError> {
+  rpn::from_rpn(postfix)
+}
+
+/// Checks an expression for errors without evaluating it, resolving
+/// identifiers through `resolve`
+pub(crate) fn validate_with_resolver(
+  expression: &str,
+  resolve: Resolver,
+) -> Result<(), CalculatorError> {
+  let expanded = roman::expand_literals(expression)?;
+  let result = check_syntax(&expanded, resolve);
+
+  // Spans would point into the expanded text, which the caller never saw
+  match expanded {
+    Cow::Owned(_) => result.map_err(CalculatorError::without_span),
+    Cow::Borrowed(_) => result,
   }
+}
 
-  // Handle unary operations (functions)
-  if tokens.len() == 2 && tokens[0].is_function() {
-    let function = tokens[0].get_function()?;
-    let value = tokens[1].get_number()?;
-    return evaluate_function(function, value);
+/// Parses an expression and checks that its functions exist
+fn check_syntax(expression: &str, resolve: Resolver) -> Result<(), CalculatorError> {
+  let tokens = tokenize_spanned(expression, resolve)?;
+  if evaluated_as_tree(&tokens) {
+    check_functions(&tokens)?;
+    return series::check(expression, &tokens, resolve);
   }
+  check_identifiers(&tokens)?;
+  if tokens.is_empty() {
+    return Err(
+      CalculatorError::SyntaxError("Empty expression".to_string()).at(0..expression.len()),
+    );
+  }
+  check_functions(&tokens)?;
+  parser::to_postfix(tokens).map(|_| ())
+}
 
-  // Handle simple binary operations
-  if tokens.len() == 3 && tokens[1].is_operator() {
-    let left = tokens[0].get_number()?;
-    let operator = tokens[1].get_operator()?;
-    let right = tokens[2].get_number()?;
-    return evaluate_binary_operation(left, operator, right);
+/// Checks that every function called exists
+fn check_functions(tokens: &[SpannedToken]) -> Result<(), CalculatorError> {
+  if let Some((Token::Function(name), span)) = tokens.iter().find(|(token, _)| {
+    matches!(token, Token::Function(name)
+      if !is_math_function(name)
+        && !parser::is_postfix_function(name)
+        && !parser::is_nullary_function(name)
+        && !is_custom_function(name))
+  }) {
+    return Err(
+      CalculatorError::SyntaxError(format!("Unknown function: {}", name)).at(span.clone()),
+    );
   }
+  Ok(())
+}
 
-  // Handle complex expressions with operator precedence
-  if tokens.len() > 3 {
-    return evaluate_complex_expression(tokens);
+/// Evaluates an expression to a [`Value`], resolving unknown identifiers through `resolve`
+pub(crate) fn evaluate_value_with_resolver(
+  expression: &str,
+  resolve: Resolver,
+) -> Result<Value, CalculatorError> {
+  let tree = series::parse(expression, resolve);
+  if let Ok(tree) = &tree {
+    if lists::is_list(tree) {
+      return tree.evaluate_value_with(resolve);
+    }
+  }
+  let result = evaluate_with_resolver(expression, resolve)?;
+  // A comparison is true or false rather than 1 or 0
+  match tree {
+    Ok(tree) if is_boolean(&tree) => Ok(Value::Boolean(result != 0.0)),
+    Ok(tree) if dates::is_date(&tree) => dates::to_date(result).map(Value::Date),
+    _ => Ok(Value::Number(result)),
   }
+}
+
+#[cfg(feature = "std")]
+/// Evaluates an expression with `settings` in place of the process-wide
+/// defaults from [`settings::get_default`].
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::settings::{DomainPolicy, Settings};
+///
+/// let settings = Settings { domain: DomainPolicy::NaN, ..Settings::default() };
+/// let result = rust_calculator::evaluate_expression_with_settings("sqrt(-4)", &settings);
+/// assert!(result.unwrap().is_nan());
+///
+/// let settings = Settings { domain: DomainPolicy::Complex, ..Settings::default() };
+/// let result = rust_calculator::evaluate_expression_with_settings("sqrt(-4) * sqrt(-4)", &settings);
+/// assert_eq!(result.unwrap(), -4.0);
+/// ```
+pub fn evaluate_expression_with_settings(
+  expression: &str,
+  settings: &Settings,
+) -> Result<f64, CalculatorError> {
+  settings::scoped(*settings, || evaluate_expression(expression))
+}
 
-  // If we reach here with exactly one token, it must be a number
-  if tokens.len() == 1 {
-    return tokens[0].get_number();
+#[cfg(feature = "std")]
+/// Evaluates an expression within the limits of `options`, failing with
+/// [`CalculatorError::LimitExceeded`] as soon as it goes past one of them.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::limits::EvalOptions;
+///
+/// let options = EvalOptions { max_depth: Some(2), ..EvalOptions::default() };
+/// assert_eq!(rust_calculator::evaluate_expression_with_options("((1 + 2))", &options).unwrap(), 3.0);
+/// assert!(rust_calculator::evaluate_expression_with_options("(((1 + 2)))", &options).is_err());
+/// ```
+pub fn evaluate_expression_with_options(
+  expression: &str,
+  options: &limits::EvalOptions,
+) -> Result<f64, CalculatorError> {
+  options.check_expression(expression)?;
+  limits::scoped(*options, || evaluate_expression(expression))
+}
+
+/// Evaluates an expression written in Reverse Polish Notation, with operands
+/// separated by spaces and each operator or function after its operands.
+///
+/// Numbers, constants, variables, functions and memory operations are the
+/// same as in [`evaluate_expression`]; only their order differs, so no
+/// parentheses are needed.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(rust_calculator::evaluate_rpn_expression("3 4 + 2 *").unwrap(), 14.0);
+/// assert_eq!(rust_calculator::evaluate_rpn_expression("16 sqrt pi *").unwrap(), 4.0 * std::f64::consts::PI);
+/// assert!(rust_calculator::evaluate_rpn_expression("3 +").is_err());
+/// ```
+pub fn evaluate_rpn_expression(expression: &str) -> Result<f64, CalculatorError> {
+  let tokens = rpn::tokenize_postfix(expression, &get_variable)?;
+  if tokens.is_empty() {
+    return Err(
+      CalculatorError::SyntaxError("Empty expression".to_string()).at(0..expression.len()),
+    );
   }
 
-  Err(CalculatorError::SyntaxError(
-    "Invalid expression format".to_string(),
-  ))
+  let value = evaluate_rpn(tokens, &get_variable)?;
+  #[cfg(feature = "std")]
+  record_answer(value);
+  Ok(value)
 }
 
-/// Token enum to represent different parts of an expression
-#[derive(Debug, Clone)]
-enum Token {
-  Number(f64),
-  Operator(String),
-  Function(String),
-  LeftParen,
-  RightParen,
+#[cfg(feature = "std")]
+/// Evaluates an expression with `variables` as the only variables, matched
+/// case-insensitively.
+///
+/// The stored variables aren't visible, including to user-defined functions,
+/// whose bodies read `variables` as well, and memory functions start from
+/// zero and are discarded afterwards, so calls on different threads can't
+/// affect each other. User-defined functions and registered constants are
+/// shared with the rest of the program.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// let variables = HashMap::from([("price".to_string(), 80.0), ("qty".to_string(), 3.0)]);
+/// let result = rust_calculator::evaluate_with_variables("price * qty", &variables);
+/// assert_eq!(result.unwrap(), 240.0);
+/// ```
+pub fn evaluate_with_variables(
+  expression: &str,
+  variables: &HashMap<String, f64>,
+) -> Result<f64, CalculatorError> {
+  let resolve = |name: &str| {
+    variables
+      .get(name)
+      .or_else(|| {
+        variables
+          .iter()
+          .find(|(key, _)| key.eq_ignore_ascii_case(name))
+          .map(|(_, value)| value)
+      })
+      .copied()
+  };
+  with_session(memory::Memory::new(), None, || {
+    evaluate_with_resolver(expression, &resolve)
+  })
+  .0
 }
 
-impl Token {
-  fn is_function(&self) -> bool {
-    match self {
-      Token::Function(_) => true,
-      _ => false,
+#[cfg(feature = "std")]
+/// Evaluates an expression with trigonometric functions working in
+/// `angle_mode`, regardless of the default settings.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::settings::AngleMode;
+///
+/// let result = rust_calculator::evaluate_expression_with("sin(pi / 2)", AngleMode::Radians);
+/// assert_eq!(result.unwrap(), 1.0);
+/// ```
+pub fn evaluate_expression_with(
+  expression: &str,
+  angle_mode: AngleMode,
+) -> Result<f64, CalculatorError> {
+  let settings = Settings {
+    angle_mode,
+    ..settings::current()
+  };
+  evaluate_expression_with_settings(expression, &settings)
+}
+
+/// Evaluates an expression in complex arithmetic.
+///
+/// # Examples
+///
+/// ```
+/// let result = rust_calculator::evaluate_complex("sqrt(-4) + 1").unwrap();
+/// assert_eq!(result.to_string(), "1 + 2i");
+/// ```
+pub fn evaluate_complex(expression: &str) -> Result<complex::Complex, CalculatorError> {
+  let expanded = roman::expand_literals(expression)?;
+  complex::evaluate(&ast::parse_with_resolver(&expanded, &get_variable)?)
+}
+
+/// Evaluates an expression in double-double arithmetic, with about 31
+/// significant digits instead of the 15 to 17 of `f64`; see [`real`] for
+/// what it supports.
+///
+/// # Examples
+///
+/// ```
+/// let root = rust_calculator::evaluate_extended("sqrt 2").unwrap();
+/// assert_eq!(root.to_string(), "1.41421356237309504880168872421");
+/// assert_eq!(rust_calculator::evaluate_extended("0.1 + 0.2").unwrap().to_string(), "0.3");
+/// ```
+#[cfg(feature = "double-double")]
+pub fn evaluate_extended(expression: &str) -> Result<DoubleDouble, CalculatorError> {
+  let expanded = roman::expand_literals(expression)?;
+  real::evaluate(&ast::parse_with_resolver(&expanded, &get_variable)?)
+}
+
+/// Formats a result for display, showing whole numbers without a fractional
+/// part and others with up to 15 significant digits, as [`format_result`]
+/// does by default.
+///
+/// When the `sigfigs` setting is on, the result is shown with that many
+/// significant figures instead, as by [`format_significant`]. Otherwise,
+/// when the `decimals` setting is on, it's shown with that many decimals.
+/// Digits are grouped and separated as the `locale` setting says.
+pub fn format_value(value: f64) -> String {
+  let settings = settings::current();
+  let options = match (settings.sigfigs, settings.decimals) {
+    (None, Some(decimals)) => FormatOptions {
+      notation: Notation::Fixed,
+      precision: Some(decimals),
+    },
+    (sigfigs, _) => FormatOptions {
+      precision: sigfigs,
+      ..FormatOptions::default()
+    },
+  };
+  format::localize(&format_result(value, &options), settings.locale)
+}
+
+/// Formats a number with `digits` significant figures, rounded with the
+/// current rounding mode.
+///
+/// Scientific notation is used when trailing zeros would otherwise look
+/// significant or the number is very small.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::format_significant;
+///
+/// assert_eq!(format_significant(12345.0, 3), "1.23e4");
+/// assert_eq!(format_significant(3.14159, 3), "3.14");
+/// assert_eq!(format_significant(0.00123456, 2), "0.0012");
+/// ```
+pub fn format_significant(value: f64, digits: u32) -> String {
+  format_result(
+    value,
+    &FormatOptions {
+      precision: Some(digits),
+      ..FormatOptions::default()
+    },
+  )
+}
+
+/// Evaluates an expression with exact integer arithmetic, keeping every digit
+/// of results too large for an `f64` to hold exactly.
+///
+/// The expression may only use integers, `+`, `-`, `*`, `^` with a
+/// non-negative exponent, `nCr`, `nPr`, `fact` and `abs`; anything else
+/// fails, since it may not give an integer. So does a result of more than
+/// 10,000 digits.
+///
+/// # Examples
+///
+/// ```
+/// let exact = rust_calculator::evaluate_integer("fact 25 + 1").unwrap();
+/// assert_eq!(exact.to_string(), "15511210043330985984000001");
+/// assert_eq!(rust_calculator::evaluate_integer("100 nCr 50").unwrap().to_string(), "100891344545564193334812497256");
+/// assert!(rust_calculator::evaluate_integer("2 ^ 0.5").is_err());
+/// ```
+pub fn evaluate_integer(expression: &str) -> Result<BigInt, CalculatorError> {
+  let expanded = roman::expand_literals(expression)?;
+  let tree = ast::parse_with_resolver(&expanded, &get_variable)?;
+  bigint::evaluate(&tree)
+}
+
+/// Evaluates an expression with exact fractions, so `1/3 + 1/6` is exactly
+/// `1/2`; see [`rational`] for what it supports.
+///
+/// # Examples
+///
+/// ```
+/// let half = rust_calculator::evaluate_fraction("1/3 + 1/6").unwrap();
+/// assert_eq!((half.numerator(), half.denominator()), (1, 2));
+/// assert!(rust_calculator::evaluate_fraction("sqrt 2").is_err());
+/// ```
+pub fn evaluate_fraction(expression: &str) -> Result<Rational, CalculatorError> {
+  let expanded = roman::expand_literals(expression)?;
+  let tree = ast::parse_with_resolver(&expanded, &get_variable)?;
+  rational::evaluate(&tree)
+}
+
+/// Formats `result`, the value of `expression`, as [`format_value`] does,
+/// except that whole results beyond 2^53, where `f64` loses digits, are shown
+/// with all their digits when [`evaluate_integer`] can work them out.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::{evaluate_expression, format_exact};
+///
+/// let result = evaluate_expression("fact 25").unwrap();
+/// assert_eq!(format_exact("fact 25", result), "15511210043330985984000000");
+/// assert_eq!(format_exact("2 ^ 10", 1024.0), "1024");
+/// ```
+pub fn format_exact(expression: &str, result: f64) -> String {
+  let large = result.fract() == 0.0 && result.abs() > bigint::MAX_EXACT;
+  if let (true, Some(integer)) = (large, settings::current().integer) {
+    let overflow = settings::current().integer_overflow;
+    if let Ok(exact) = programmer::evaluate(expression, integer, overflow) {
+      return format::localize(&exact.to_string(), settings::current().locale);
     }
   }
-
-  fn is_operator(&self) -> bool {
-    match self {
-      Token::Operator(_) => true,
-      _ => false,
+  // Rounding to significant figures asks for fewer digits, not more
+  if large && settings::current().sigfigs.is_none() {
+    if let Ok(exact) = evaluate_integer(expression) {
+      return format::localize(&exact.to_string(), settings::current().locale);
     }
   }
+  format_value(result)
+}
 
-  fn get_number(&self) -> Result<f64, CalculatorError> {
-    match self {
-      Token::Number(n) => Ok(*n),
-      _ => Err(CalculatorError::ParseError("Expected a number".to_string())),
+/// Shows the value of an expression that overflowed an `f64` with all its
+/// digits, when [`evaluate_integer`] can work it out, as for `fact 200` or
+/// `2 ^ 2000`. Otherwise gives back `err`, the error evaluating it gave.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::{evaluate_expression, format_overflow};
+///
+/// let err = evaluate_expression("2 ^ 1100").unwrap_err();
+/// assert!(format_overflow("2 ^ 1100", err).unwrap().starts_with("13582985290493858492773514283592667786"));
+/// let err = evaluate_expression("10 ^ 400 / 3").unwrap_err();
+/// assert!(format_overflow("10 ^ 400 / 3", err).is_err());
+/// ```
+pub fn format_overflow(expression: &str, err: CalculatorError) -> Result<String, CalculatorError> {
+  // Integer mode has its own overflow handling
+  if !matches!(err, CalculatorError::Overflow(_)) || settings::current().integer.is_some() {
+    return Err(err);
+  }
+  match evaluate_integer(expression) {
+    Ok(exact) => Ok(format::localize(
+      &exact.to_string(),
+      settings::current().locale,
+    )),
+    // The limits apply to exact arithmetic too
+    Err(limit @ CalculatorError::LimitExceeded(_)) => Err(limit),
+    Err(_) => Err(err),
+  }
+}
+
+/// Evaluates an expression whose result may be text, such as `roman(14)`,
+/// `currency(1234.5, "USD")`, `to_dms(45.5)`, `factorize(360)`, `as_fraction(0.75)`,
+/// `continued_fraction(pi)`, `simplify(x * 1 + 0)`, `spell(42)` or a value with an uncertainty like
+/// `2 * (5 ± 0.1)`.
+///
+/// Numeric results are formatted with [`format_value`].
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(rust_calculator::evaluate_to_string("roman(10 + 4)").unwrap(), "XIV");
+/// assert_eq!(rust_calculator::evaluate_to_string("from_roman(\"XIV\") * 2").unwrap(), "28");
+/// assert_eq!(rust_calculator::evaluate_to_string("2 * (5 ± 0.1)").unwrap(), "10.00 ± 0.20");
+/// assert_eq!(rust_calculator::evaluate_to_string("as_fraction(pi)").unwrap(), "355/113");
+/// ```
+pub fn evaluate_to_string(expression: &str) -> Result<String, CalculatorError> {
+  // A bare date such as 2024-07-15 is a date here, as it is for evaluate
+  if let Cow::Owned(quoted) = dates::quote_literals(expression) {
+    return evaluate_to_string(&quoted).map_err(CalculatorError::without_span);
+  }
+  if let Some((name, arguments)) = text_call(expression) {
+    return call_text_function(&name, &arguments);
+  }
+  if uncertainty::contains_uncertainty(expression) {
+    return uncertainty::evaluate(expression).map(|result| result.to_string());
+  }
+  // Under the complex policy, solving a polynomial gives its complex roots too
+  if settings::current().domain == DomainPolicy::Complex {
+    if let Ok(Expr::Function { name, argument }) = series::parse(expression, &get_variable) {
+      if polynomials::is_solver(&name) {
+        check_arity(&name, argument.arguments().len())?;
+        let coefficients = argument
+          .arguments()
+          .into_iter()
+          .map(Expr::evaluate)
+          .collect::<Result<Vec<f64>, CalculatorError>>()?;
+        let roots: Vec<String> = polynomials::roots(&coefficients)?
+          .iter()
+          .map(ToString::to_string)
+          .collect();
+        return Ok(format!("[{}]", roots.join(", ")));
+      }
     }
   }
+  if returns_value(expression) {
+    return evaluate(expression).map(|value| value.to_string());
+  }
+  match evaluate_expression(expression) {
+    Ok(value) => Ok(format_exact(expression, value)),
+    Err(err @ CalculatorError::Overflow(_)) => format_overflow(expression, err),
+    // Non-real results can be shown as text under the complex policy
+    Err(err) if settings::current().domain == DomainPolicy::Complex => evaluate_complex(expression)
+      .map(|value| value.to_string())
+      .map_err(|_| err),
+    Err(err) => Err(err),
+  }
+}
 
-  fn get_operator(&self) -> Result<&str, CalculatorError> {
-    match self {
-      Token::Operator(op) => Ok(op),
-      _ => Err(CalculatorError::ParseError(
-        "Expected an operator".to_string(),
-      )),
+/// Functions whose results are text, which only [`evaluate_to_string`] gives
+const TEXT_FUNCTIONS: [&str; 9] = [
+  "roman",
+  "simplify",
+  "spell",
+  "currency",
+  "factorize",
+  "weekday",
+  "to_dms",
+  "as_fraction",
+  "continued_fraction",
+];
+
+/// Parses an expression that is a call of a text function, such as
+/// `roman(10 + 4)`, into the function's name and arguments
+fn text_call(expression: &str) -> Option<(String, Vec<Expr>)> {
+  match ast::parse(expression) {
+    Ok(Expr::Function { name, argument }) if TEXT_FUNCTIONS.contains(&name.as_str()) => {
+      let arguments = argument.arguments().into_iter().cloned().collect();
+      Some((name, arguments))
     }
+    _ => None,
   }
+}
 
-  fn get_function(&self) -> Result<&str, CalculatorError> {
-    match self {
-      Token::Function(fun) => Ok(fun),
-      _ => Err(CalculatorError::ParseError(
-        "Expected a function".to_string(),
-      )),
+/// Applies the text function `name` to its parsed arguments
+fn call_text_function(name: &str, arguments: &[Expr]) -> Result<String, CalculatorError> {
+  check_arity(name, arguments.len())?;
+  match (name, arguments) {
+    // The expression itself is simplified, so its variables stay as they are
+    ("simplify", [expression]) => Ok(expression.simplify().to_string()),
+    ("currency", [amount, Expr::Variable(code)]) => {
+      currency::format_currency(amount.evaluate()?, code)
+    }
+    ("currency", _) => Err(CalculatorError::ArgumentError(
+      "Usage: currency(amount, \"USD\")".to_string(),
+    )),
+    ("as_fraction", arguments) => {
+      let (value, max_denominator) = optional_argument(arguments, 1000.0)?;
+      let max_denominator = counting_number(max_denominator, "as_fraction's max_denominator")?;
+      rational::Rational::approximate(value, max_denominator as i128)
+        .map(|fraction| fraction.to_string())
+        .ok_or_else(|| {
+          CalculatorError::ArgumentError(format!("Cannot approximate {} as a fraction", value))
+        })
+    }
+    ("continued_fraction", arguments) => {
+      let (value, terms) = optional_argument(arguments, 20.0)?;
+      let terms = counting_number(terms, "continued_fraction's number of terms")?;
+      let terms = rational::continued_fraction(value, terms.min(1000) as usize)?;
+      let rest: Vec<String> = terms[1..].iter().map(|term| term.to_string()).collect();
+      Ok(if rest.is_empty() {
+        format!("[{}]", terms[0])
+      } else {
+        format!("[{}; {}]", terms[0], rest.join(", "))
+      })
     }
+    (name, [argument]) => {
+      let value = argument.evaluate()?;
+      match name {
+        "roman" => roman::to_roman(value),
+        "spell" => words::spell(value, words::Language::English),
+        "factorize" => primes::to_factorization(value),
+        "weekday" => dates::weekday(value),
+        _ => dms::to_dms(settings::current().angle_mode.to_degrees(value)),
+      }
+    }
+    _ => unreachable!("check_arity allows only 1 argument"),
   }
 }
 
-/// Tokenize an expression string into a vector of tokens
-fn tokenize(expression: &str) -> Result<Vec<Token>, CalculatorError> {
-  let mut tokens = Vec::new();
-  let mut current_token = String::new();
-  let mut chars = expression.chars().peekable();
+/// Evaluates the arguments of a function that takes a value and an optional
+/// second argument, which is `default` when left out
+fn optional_argument(arguments: &[Expr], default: f64) -> Result<(f64, f64), CalculatorError> {
+  match arguments {
+    [value, second] => Ok((value.evaluate()?, second.evaluate()?)),
+    [value] => Ok((value.evaluate()?, default)),
+    _ => unreachable!("check_arity allows only 1 or 2 arguments"),
+  }
+}
 
-  // Special case for memory operations and unit conversions that don't require parameters
-  if expression.trim() == "mr" || expression.trim() == "mc" {
-    return Ok(vec![
-      Token::Function(expression.trim().to_string()),
-      Token::Number(0.0),
-    ]);
+/// Checks that an argument is a whole number from 1 up to 2^53
+fn counting_number(value: f64, name: &str) -> Result<u64, CalculatorError> {
+  if value.fract() != 0.0 || !(1.0..=bigint::MAX_EXACT).contains(&value) {
+    return Err(CalculatorError::ArgumentError(format!(
+      "{} must be a positive integer, got {}",
+      name, value
+    )));
   }
+  Ok(value as u64)
+}
+
+/// Checks whether an expression produces text rather than a number
+pub fn returns_text(expression: &str) -> bool {
+  text_call(expression).is_some()
+    || uncertainty::contains_uncertainty(expression)
+    || returns_value(expression)
+}
+
+/// Checks whether an expression produces a list or a date, which are shown
+/// as text
+fn returns_value(expression: &str) -> bool {
+  series::parse(expression, &get_variable)
+    .is_ok_and(|tree| lists::is_list(&tree) || dates::is_date(&tree))
+}
 
-  while let Some(ch) = chars.next() {
+/// Evaluates an expression once for each value bound to `variable`.
+///
+/// Other identifiers resolve to user-defined variables as usual, and each
+/// evaluation fails or succeeds independently.
+///
+/// # Examples
+///
+/// ```
+/// let results = rust_calculator::evaluate_vectorized("x ^ 2", "x", &[1.0, 2.0, 3.0]);
+/// let values: Vec<f64> = results.into_iter().map(Result::unwrap).collect();
+/// assert_eq!(values, vec![1.0, 4.0, 9.0]);
+/// ```
+pub fn evaluate_vectorized(
+  expression: &str,
+  variable: &str,
+  values: &[f64],
+) -> Vec<Result<f64, CalculatorError>> {
+  values
+    .iter()
+    .map(|&value| {
+      let resolve = |name: &str| {
+        if name.eq_ignore_ascii_case(variable) {
+          Some(value)
+        } else {
+          get_variable(name)
+        }
+      };
+      evaluate_with_resolver(expression, &resolve)
+    })
+    .collect()
+}
+
+/// Records how long the current span has taken in its `elapsed_us` field
+/// when dropped
+#[cfg(all(feature = "tracing", feature = "std"))]
+struct Timer(std::time::Instant);
+
+#[cfg(all(feature = "tracing", feature = "std"))]
+impl Timer {
+  fn start() -> Timer {
+    Timer(std::time::Instant::now())
+  }
+}
+
+#[cfg(all(feature = "tracing", feature = "std"))]
+impl Drop for Timer {
+  fn drop(&mut self) {
+    let elapsed = self.0.elapsed().as_micros() as u64;
+    tracing::Span::current().record("elapsed_us", elapsed);
+  }
+}
+
+/// Looks up the value of an identifier that is not a built-in function or constant
+pub(crate) type Resolver<'a> = &'a dyn Fn(&str) -> Option<f64>;
+
+/// Evaluates an expression, resolving unknown identifiers through `resolve`
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    name = "evaluate",
+    level = "debug",
+    skip(resolve),
+    fields(elapsed_us = tracing::field::Empty),
+    ret,
+    err(level = "debug")
+  )
+)]
+pub(crate) fn evaluate_with_resolver(
+  expression: &str,
+  resolve: Resolver,
+) -> Result<f64, CalculatorError> {
+  #[cfg(all(feature = "tracing", feature = "std"))]
+  let _timer = Timer::start();
+
+  // Roman numeral literals become plain numbers before anything else sees them
+  let expanded = roman::expand_literals(expression)?;
+  let expression = expanded.as_ref();
+
+  // Integer mode replaces floating point altogether
+  let result = if settings::current().integer.is_some() {
+    programmer::evaluate_with_resolver(expression, resolve).map(|value| value as f64)
+  } else {
+    evaluate_real(expression, resolve)
+  };
+  let promote = match &result {
+    Err(_) => true,
+    Ok(value) => value.is_nan(),
+  };
+  let settings = settings::current();
+  let result = if promote && settings.domain == DomainPolicy::Complex && settings.integer.is_none()
+  {
+    complex::promote(expression, resolve, result)
+  } else {
+    result
+  };
+
+  // Spans would point into the expanded text, which the caller never saw
+  match expanded {
+    Cow::Owned(_) => result.map_err(CalculatorError::without_span),
+    Cow::Borrowed(_) => result,
+  }
+}
+
+/// Evaluates an expression in real arithmetic
+fn evaluate_real(expression: &str, resolve: Resolver) -> Result<f64, CalculatorError> {
+  let tokens = tokenize_spanned(expression, resolve)?;
+  if evaluated_as_tree(&tokens) {
+    return series::parse(expression, resolve)?
+      .evaluate_with(resolve)
+      .map(round_to_precision);
+  }
+  check_identifiers(&tokens)?;
+
+  // If there are no tokens, return an error
+  if tokens.is_empty() {
+    return Err(
+      CalculatorError::SyntaxError("Empty expression".to_string()).at(0..expression.len()),
+    );
+  }
+
+  evaluate_rpn(parser::to_postfix(tokens)?, resolve).map(round_to_precision)
+}
+
+/// Checks whether an expression has to be evaluated as a tree: sums and
+/// products bind variables, lists aren't numbers and `if` evaluates only the
+/// branch it takes
+fn evaluated_as_tree(tokens: &[SpannedToken]) -> bool {
+  tokens.iter().any(|(token, _)| {
+    matches!(token, Token::Function(name) if series::binds_variable(name) || name == "if")
+      || lists::uses_lists(token)
+  })
+}
+
+/// A token with the byte range of the expression it was read from
+pub type SpannedToken = (Token, Range<usize>);
+
+/// A lexical part of an expression, as produced by [`tokenize`]
+///
+/// With the `serde` feature, tokens serialize with their kind, e.g.
+/// `{"type": "operator", "value": "+"}`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
+#[non_exhaustive]
+pub enum Token {
+  /// A number, or a constant or variable already replaced by its value
+  Number(f64),
+  /// A name that isn't a function, constant or known variable
+  Identifier(String),
+  /// One of `+ - * / % ^ & | << >>`, a word operator such as `nCr` or `xor`,
+  /// or the `,` between function arguments
+  Operator(String),
+  /// A function, memory operation or unit conversion
+  Function(String),
+  LeftParen,
+  RightParen,
+}
+
+/// Splits an expression into tokens, each with the byte range of the
+/// expression it was read from, for syntax highlighting and other tooling.
+///
+/// Names that aren't functions, constants or known variables are kept as
+/// [`Token::Identifier`]s rather than rejected; only characters that can't
+/// start any token are errors. Roman numeral literals aren't expanded.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::{Token, tokenize};
+///
+/// let tokens = tokenize("sqrt(x) + 2").unwrap();
+/// assert_eq!(tokens[0], (Token::Function("sqrt".to_string()), 0..4));
+/// assert_eq!(tokens[2], (Token::Identifier("x".to_string()), 5..6));
+/// assert_eq!(tokens[4], (Token::Operator("+".to_string()), 8..9));
+/// assert_eq!(tokens[5], (Token::Number(2.0), 10..11));
+/// ```
+pub fn tokenize(expression: &str) -> Result<Vec<SpannedToken>, CalculatorError> {
+  tokenize_spanned(expression, &get_variable)
+}
+
+/// Tokenizes an expression, keeping the byte range each token was read from
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(name = "tokenize", level = "trace", skip_all, err(level = "debug"))
+)]
+fn tokenize_spanned(
+  expression: &str,
+  resolve: Resolver,
+) -> Result<Vec<SpannedToken>, CalculatorError> {
+  let mut tokens: Vec<SpannedToken> = Vec::new();
+  let mut current_token = String::new();
+  // Byte offset where the current token starts
+  let mut start = 0;
+  // End of a two-character operator, whose second character is already read
+  let mut skip_to = 0;
+  // Where each absolute value bar still waiting for its closing bar is, and
+  // how deep in parentheses, since a bar can't close outside them
+  let mut open_bars: Vec<(usize, usize)> = Vec::new();
+  let mut depth = 0;
+  // Where the equation in quotes still waiting for its closing quote
+  // starts, and whether its = has been read
+  let mut quote: Option<(usize, bool)> = None;
+
+  for (index, ch) in expression.char_indices() {
+    if index < skip_to {
+      continue;
+    }
     if ch.is_whitespace() {
-      if !current_token.is_empty() {
-        add_token(&mut tokens, &current_token)?;
-        current_token.clear();
+      add_pending_token(&mut tokens, &mut current_token, start..index, resolve)?;
+    } else if (ch == '"' || ch == '\'')
+      && match quote {
+        Some((open, _)) => expression[open..].starts_with(ch),
+        None => current_token.is_empty() && expects_operand(&tokens),
+      }
+    {
+      // An equation in quotes, as in solve("sin(x) = x/2", x, 1), reads as
+      // ((sin(x)) - (x/2)), and one without = as the expression itself.
+      // Single quotes work the same way.
+      add_pending_token(&mut tokens, &mut current_token, start..index, resolve)?;
+      let paren = if quote.is_some() {
+        quote = None;
+        depth -= 1;
+        Token::RightParen
+      } else {
+        quote = Some((index, false));
+        depth += 1;
+        Token::LeftParen
+      };
+      tokens.push((paren.clone(), index..index + 1));
+      tokens.push((paren, index..index + 1));
+    } else if ch == '=' && quote.is_some() && operator_at(&expression[index..]).is_none() {
+      add_pending_token(&mut tokens, &mut current_token, start..index, resolve)?;
+      if let Some((_, true)) = quote {
+        return Err(
+          CalculatorError::SyntaxError("An equation has only one '='".to_string())
+            .at(index..index + 1),
+        );
+      }
+      quote = quote.map(|(open, _)| (open, true));
+      tokens.push((Token::RightParen, index..index + 1));
+      tokens.push((Token::Operator("-".to_string()), index..index + 1));
+      tokens.push((Token::LeftParen, index..index + 1));
+    } else if ch.is_ascii_digit() || ch == '.' {
+      if current_token.is_empty() {
+        start = index;
       }
-    } else if ch.is_digit(10) || ch == '.' {
       current_token.push(ch);
-    } else if ch == '+' || ch == '-' || ch == '*' || ch == '/' || ch == '%' || ch == '^' {
-      // Handle special case for memory addition and subtraction
-      if (ch == '+' || ch == '-') && !current_token.is_empty() && current_token == "m" {
+    } else if let Some(operator) = operator_at(&expression[index..]) {
+      // The sign of an exponent, as in 2E-6, is part of the number
+      if (ch == '+' || ch == '-')
+        && is_mantissa(&current_token)
+        && expression[index + 1..].starts_with(|c: char| c.is_ascii_digit())
+      {
+        current_token.push(ch);
+        continue;
+      }
+
+      // Handle special case for memory addition and subtraction, also
+      // written straight after a number as in 5m+
+      if (ch == '+' || ch == '-')
+        && current_token
+          .strip_suffix('m')
+          .is_some_and(|number| number.is_empty() || parse_number(number).is_some())
+      {
         current_token.push(ch);
         // Add the m+ or m- token
-        add_token(&mut tokens, &current_token)?;
-        current_token.clear();
+        add_pending_token(&mut tokens, &mut current_token, start..index + 1, resolve)?;
         continue;
       }
 
       // If we have a pending token, add it first
-      if !current_token.is_empty() {
-        add_token(&mut tokens, &current_token)?;
-        current_token.clear();
+      add_pending_token(&mut tokens, &mut current_token, start..index, resolve)?;
+
+      // A % with no operand after it is a percentage, as in 50% or 200 + 10%
+      if ch == '%' && !operand_after(&expression[index + 1..]) {
+        tokens.push((Token::Function("percent".to_string()), index..index + 1));
+        continue;
       }
 
-      // Special handling for negative numbers
-      // If the token is '-' and it's either at the start or after another operator or left parenthesis
-      if ch == '-'
-        && (tokens.is_empty()
-          || matches!(
-            tokens.last(),
-            Some(Token::Operator(_)) | Some(Token::LeftParen) | Some(Token::Function(_))
-          ))
+      // Special handling for negative numbers: a '-' at the start or after
+      // another operator, a left parenthesis or a function starts a negative
+      // literal when a number follows, and is negation otherwise
+      let prefix = expects_operand(&tokens);
+      let number_follows =
+        expression[index + 1..].starts_with(|c: char| c.is_ascii_digit() || c == '.');
+      // A bar where an operand belongs opens an absolute value, as in |x - 1|,
+      // and one after an operand closes the innermost; any other is bitwise or
+      if ch == '|' && prefix {
+        open_bars.push((index, depth));
+        tokens.push((Token::Function("abs".to_string()), index..index + 1));
+        tokens.push((Token::LeftParen, index..index + 1));
+      } else if ch == '|'
+        && open_bars
+          .last()
+          .is_some_and(|&(_, bar_depth)| bar_depth == depth)
       {
+        open_bars.pop();
+        tokens.push((Token::RightParen, index..index + 1));
+      } else if ch == '-' && prefix && number_follows {
+        start = index;
         current_token.push(ch); // Start a negative number
       } else {
-        tokens.push(Token::Operator(ch.to_string()));
+        skip_to = index + operator.len();
+        // The symbols of the logical operators are read as their words
+        let operator = match operator {
+          "&&" => "and",
+          "||" => "or",
+          _ => operator,
+        };
+        tokens.push((Token::Operator(operator.to_string()), index..skip_to));
       }
+    } else if ch == '~' || ch == '!' {
+      // Bitwise and logical not, prefixes like functions without parentheses
+      add_pending_token(&mut tokens, &mut current_token, start..index, resolve)?;
+      let function = if ch == '~' { "~" } else { "not" };
+      tokens.push((Token::Function(function.to_string()), index..index + 1));
     } else if ch == '(' {
-      if !current_token.is_empty() {
+      if parse_number(&current_token).is_some() {
+        add_pending_token(&mut tokens, &mut current_token, start..index, resolve)?;
+      } else if !current_token.is_empty() {
         // If we have a token before left paren, it's a function
-        tokens.push(Token::Function(current_token.clone()));
-        current_token.clear();
-      }
-      tokens.push(Token::LeftParen);
-    } else if ch == ')' {
-      if !current_token.is_empty() {
-        add_token(&mut tokens, &current_token)?;
+        tokens.push((Token::Function(current_token.clone()), start..index));
         current_token.clear();
       }
-      tokens.push(Token::RightParen);
+      tokens.push((Token::LeftParen, index..index + 1));
+      depth += 1;
+    } else if ch == '[' {
+      // A list literal reads as a call of list, as in [1, 2, 3], and a
+      // bracket after an operand picks an item, as in sort(x)[1]
+      add_pending_token(&mut tokens, &mut current_token, start..index, resolve)?;
+      let bracket = if expects_operand(&tokens) {
+        Token::Function("list".to_string())
+      } else {
+        Token::Operator("at".to_string())
+      };
+      tokens.push((bracket, index..index + 1));
+      tokens.push((Token::LeftParen, index..index + 1));
+      depth += 1;
+    } else if ch == ')' || ch == ']' {
+      add_pending_token(&mut tokens, &mut current_token, start..index, resolve)?;
+      depth = depth.saturating_sub(1);
+      tokens.push((Token::RightParen, index..index + 1));
     } else {
       // Must be part of a function name or invalid character
+      if current_token.is_empty() {
+        start = index;
+      }
       current_token.push(ch);
     }
   }
 
   // Don't forget to add the last token if any
-  if !current_token.is_empty() {
-    add_token(&mut tokens, &current_token)?;
+  add_pending_token(
+    &mut tokens,
+    &mut current_token,
+    start..expression.len(),
+    resolve,
+  )?;
+  if let Some(&(bar, _)) = open_bars.last() {
+    return Err(
+      CalculatorError::SyntaxError("Absolute value bar '|' is never closed".to_string())
+        .at(bar..bar + 1),
+    );
+  }
+  if let Some((open, _)) = quote {
+    return Err(
+      CalculatorError::SyntaxError(format!(
+        "Quote '{}' is never closed",
+        &expression[open..open + 1]
+      ))
+      .at(open..open + 1),
+    );
   }
 
-  // Special case handling for memory operations and unit conversions
-  if tokens.len() >= 1 {
-    // Handle memory operations: m+ and m-
-    if tokens.len() == 1 && (tokens[0].is_function()) {
-      let func = tokens[0].get_function()?;
-      if func == "mr" || func == "mc" {
-        tokens.push(Token::Number(0.0));
-      }
+  let tokens = dates::read_literals(expression, tokens)?;
+  Ok(group_mixed_numbers(expression, tokens))
+}
+
+/// Checks whether an operand belongs after `tokens`, rather than an operator
+fn expects_operand(tokens: &[SpannedToken]) -> bool {
+  match tokens.last() {
+    None | Some((Token::Operator(_) | Token::LeftParen, _)) => true,
+    Some((Token::Function(name), _)) => {
+      !parser::is_postfix_function(name) && !parser::is_nullary_function(name)
     }
+    _ => false,
+  }
+}
 
-    // Handle unit conversions directly
-    if tokens.len() == 2 && tokens[1].is_function() {
-      let function = tokens[1].get_function()?;
-      if function.contains("_to_") {
-        let value = tokens[0].get_number()?;
-        return Ok(vec![
-          Token::Number(value),
-          Token::Function(function.to_string()),
-        ]);
+/// Reads a whole number followed by a fraction of whole numbers, as in
+/// `3 1/2`, as a mixed number, putting `(3 + 1/2)` in its place. Only
+/// numbers written as digits count, not constants or variables.
+fn group_mixed_numbers(expression: &str, tokens: Vec<SpannedToken>) -> Vec<SpannedToken> {
+  let digits = |span: &Range<usize>| {
+    let text = &expression[span.clone()];
+    !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit())
+  };
+  let mut grouped = Vec::with_capacity(tokens.len());
+  let mut index = 0;
+  while index < tokens.len() {
+    if let [
+      (Token::Number(_), whole),
+      (Token::Number(_), numerator),
+      (Token::Operator(slash), _),
+      (Token::Number(_), denominator),
+      rest @ ..,
+    ] = &tokens[index..]
+    {
+      let negative = expression[whole.clone()].starts_with('-');
+      let whole_digits = whole.start + negative as usize..whole.end;
+      // 3 1/2^2 is better left as the error it is than read as 3 + 1/4
+      let power_follows = matches!(rest.first(), Some((Token::Operator(op), _)) if op == "^");
+      if slash == "/"
+        && digits(&whole_digits)
+        && digits(numerator)
+        && digits(denominator)
+        && !power_follows
+      {
+        // -3 1/2 is -(3 + 1/2)
+        let sign = if negative { "-" } else { "+" };
+        grouped.push((Token::LeftParen, whole.start..whole.start));
+        grouped.push(tokens[index].clone());
+        grouped.push((
+          Token::Operator(sign.to_string()),
+          whole.end..numerator.start,
+        ));
+        grouped.extend_from_slice(&tokens[index + 1..index + 4]);
+        grouped.push((Token::RightParen, denominator.end..denominator.end));
+        index += 4;
+        continue;
       }
     }
+    grouped.push(tokens[index].clone());
+    index += 1;
   }
+  grouped
+}
 
-  // Special case handling for function calls without parentheses
-  if tokens.len() >= 2 && tokens[0].is_function() {
-    let next_token = &tokens[1];
-    if let Token::Number(_) = next_token {
-      // It's a function call without parentheses, like "sin 30"
-      return Ok(tokens);
-    }
+/// Reads a number literal: a decimal, or a whole number in hexadecimal,
+/// binary or octal after a `0x`, `0b` or `0o` prefix. Underscores can
+/// separate digits, as in `1_000_000` or `0xFFFF_FFFF`.
+fn parse_number(literal: &str) -> Option<f64> {
+  // Also inf and nan, as well as every decimal form
+  if let Ok(value) = literal.parse::<f64>() {
+    return Some(value);
+  }
+  let (sign, unsigned) = match literal.strip_prefix('-') {
+    Some(rest) => (-1.0, rest),
+    None => (1.0, literal),
+  };
+  let radix = match unsigned.get(..2) {
+    Some("0x" | "0X") => 16,
+    Some("0b" | "0B") => 2,
+    Some("0o" | "0O") => 8,
+    _ => 10,
+  };
+  let digits = if radix == 10 {
+    unsigned
+  } else {
+    &unsigned[2..]
+  };
+  // Every underscore must be between two digits
+  let is_digit = |ch: char| ch.is_digit(radix);
+  let separated = digits.char_indices().all(|(index, ch)| {
+    ch != '_' || (digits[..index].ends_with(is_digit) && digits[index + 1..].starts_with(is_digit))
+  });
+  if !separated || !digits.starts_with(|ch: char| is_digit(ch) || (radix == 10 && ch == '.')) {
+    return None;
+  }
+  let digits = digits.replace('_', "");
+  let magnitude = if radix == 10 {
+    digits.parse::<f64>().ok()?
+  } else {
+    u128::from_str_radix(&digits, radix).ok()? as f64
+  };
+  Some(sign * magnitude)
+}
+
+/// Whether a token is a number literal followed by the `e` or `E` of an exponent
+fn is_mantissa(token: &str) -> bool {
+  token.strip_suffix(['e', 'E']).is_some_and(|mantissa| {
+    mantissa
+      .trim_start_matches('-')
+      .starts_with(|c: char| c.is_ascii_digit() || c == '.')
+      && mantissa.replace('_', "").parse::<f64>().is_ok()
+  })
+}
+
+/// Whether `rest` goes on with an operand rather than an operator, a closing
+/// parenthesis, a comma or nothing. A minus sign directly before a number is
+/// the number's sign.
+fn operand_after(rest: &str) -> bool {
+  let rest = rest.trim_start();
+  if let Some(number) = rest.strip_prefix('-') {
+    return number.starts_with(|ch: char| ch.is_ascii_digit() || ch == '.');
   }
+  let word: String = rest.chars().take_while(|ch| ch.is_alphanumeric()).collect();
+  !rest.is_empty()
+    && !rest.starts_with([')', ','])
+    && operator_at(rest).is_none()
+    && !is_word_operator(&word)
+}
+
+/// Whether a word is a binary operator, in any case
+fn is_word_operator(word: &str) -> bool {
+  matches!(
+    word.to_lowercase().as_str(),
+    "ncr" | "npr" | "xor" | "and" | "or"
+  )
+}
+
+/// The symbolic binary operator `rest` starts with, if any
+fn operator_at(rest: &str) -> Option<&'static str> {
+  [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "^", "&",
+    "|", ",",
+  ]
+  .into_iter()
+  .find(|operator| rest.starts_with(operator))
+}
 
-  Ok(tokens)
+/// Adds the token read so far, if any, located at `span`
+fn add_pending_token(
+  tokens: &mut Vec<SpannedToken>,
+  current_token: &mut String,
+  span: Range<usize>,
+  resolve: Resolver,
+) -> Result<(), CalculatorError> {
+  if current_token.is_empty() {
+    return Ok(());
+  }
+  let mut added = Vec::new();
+  add_token(&mut added, current_token, resolve).map_err(|err| err.at(span.clone()))?;
+  tokens.extend(added.into_iter().map(|token| (token, span.clone())));
+  current_token.clear();
+  Ok(())
 }
 
 /// Helper function to add a token to the tokens vector
-fn add_token(tokens: &mut Vec<Token>, token_str: &str) -> Result<(), CalculatorError> {
+fn add_token(
+  tokens: &mut Vec<Token>,
+  token_str: &str,
+  resolve: Resolver,
+) -> Result<(), CalculatorError> {
   // Try to parse as a number first
-  if let Ok(num) = token_str.parse::<f64>() {
-    tokens.push(Token::Number(num));
+  if let Some(num) = parse_number(token_str) {
+    tokens.push(Token::Number(check_input(num)?));
+    return Ok(());
+  }
+
+  // An angle in degrees, minutes and seconds, such as 45°30'15"
+  if let Some(degrees) = dms::parse(token_str) {
+    let angle = settings::current().angle_mode.from_degrees(degrees);
+    tokens.push(Token::Number(check_input(angle)?));
+    return Ok(());
+  }
+
+  // A number directly followed by a memory operation or unit conversion,
+  // such as 5m+ or 10km_to_mi
+  if let Some(split) = token_str.find(|c: char| c.is_alphabetic()) {
+    let (number, function) = token_str.split_at(split);
+    if let Some(num) = parse_number(number) {
+      if parser::is_postfix_function(function) {
+        tokens.push(Token::Number(check_input(num)?));
+        tokens.push(Token::Function(function.to_string()));
+        return Ok(());
+      }
+    }
+  }
+
+  // Operators written as words, such as 10 nCr 3
+  let lowercase = token_str.to_lowercase();
+  if is_word_operator(&lowercase) {
+    tokens.push(Token::Operator(lowercase));
     return Ok(());
   }
 
   // Check for memory operations and unit conversions
-  if token_str == "m+" || token_str == "m-" || token_str == "mr" || token_str == "mc" {
+  if matches!(token_str, "m+" | "m-" | "mr" | "mc" | "mundo") {
     tokens.push(Token::Function(token_str.to_string()));
     return Ok(());
   }
@@ -497,53 +2258,224 @@ fn add_token(tokens: &mut Vec<Token>, token_str: &str) -> Result<(), CalculatorE
     return Ok(());
   }
 
-  // Check for mathematical constants
-  match token_str.to_lowercase().as_str() {
-    "pi" => {
-      tokens.push(Token::Number(std::f64::consts::PI));
-      return Ok(());
-    }
-    "e" => {
-      tokens.push(Token::Number(std::f64::consts::E));
-      return Ok(());
-    }
-    "tau" => {
-      tokens.push(Token::Number(std::f64::consts::TAU)); // 2π
-      return Ok(());
-    }
-    "phi" => {
-      tokens.push(Token::Number(1.618033988749895)); // Golden ratio
-      return Ok(());
-    }
-    "inf" | "infinity" => {
-      tokens.push(Token::Number(f64::INFINITY));
-      return Ok(());
-    }
-    _ => {}
+  // Check for constants, built in or registered
+  if let Some(value) = constants::lookup(token_str) {
+    tokens.push(Token::Number(value));
+    return Ok(());
+  }
+  if matches!(token_str.to_lowercase().as_str(), "inf" | "infinity") {
+    tokens.push(Token::Number(check_input(f64::INFINITY)?));
+    return Ok(());
   }
 
-  // Check if it's a recognized function
-  match token_str {
-    "sqrt" | "sin" | "cos" | "tan" | "asin" | "acos" | "atan" | "log" | "ln" | "exp" | "abs"
-    | "floor" | "ceil" | "fact" => {
-      tokens.push(Token::Function(token_str.to_string()));
-      Ok(())
-    }
-    _ => {
-      // Check if it's a user-defined variable
-      if let Some(value) = get_variable(token_str) {
-        tokens.push(Token::Number(value));
-        Ok(())
-      } else {
-        Err(CalculatorError::ParseError(format!(
-          "Unknown token: {}",
-          token_str
-        )))
-      }
-    }
+  // Check if it's a recognized function, built in or registered on an EvalContext
+  if is_math_function(token_str) || is_custom_function(token_str) {
+    tokens.push(Token::Function(token_str.to_string()));
+    return Ok(());
+  }
+
+  // Check if it's a user-defined variable
+  if let Some(value) = resolve(token_str) {
+    tokens.push(Token::Number(check_input(value)?));
+    Ok(())
+  } else if is_identifier(token_str) {
+    // Evaluation rejects it with check_identifiers, but parsing keeps the name
+    tokens.push(Token::Identifier(token_str.to_string()));
+    Ok(())
+  } else {
+    Err(unknown_token(token_str))
+  }
+}
+
+/// Checks whether a token looks like a variable name: a letter or underscore
+/// followed by letters, digits or underscores, or several of those joined by
+/// dots for a name in a namespace, as in `physics.c`
+pub(crate) fn is_identifier(token: &str) -> bool {
+  token.split('.').all(|part| {
+    let mut chars = part.chars();
+    chars
+      .next()
+      .is_some_and(|ch| ch.is_alphabetic() || ch == '_')
+      && chars.all(|ch| ch.is_alphanumeric() || ch == '_')
+  })
+}
+
+/// Fails on the first identifier that wasn't resolved to a value
+fn check_identifiers(tokens: &[SpannedToken]) -> Result<(), CalculatorError> {
+  match tokens.iter().find_map(|(token, span)| match token {
+    Token::Identifier(name) => Some((name, span)),
+    _ => None,
+  }) {
+    Some((name, span)) => Err(unresolved(name).at(span.clone())),
+    None => Ok(()),
+  }
+}
+
+/// The error for an identifier left unresolved, which is the formula's own
+/// error if it's a [formula](formulas) that can't be worked out
+#[cfg(feature = "std")]
+fn unresolved(name: &str) -> CalculatorError {
+  match formulas::value(name) {
+    Some(Err(err)) => err,
+    _ => unknown_token(name),
   }
 }
 
+/// Formulas need the `std` feature
+#[cfg(not(feature = "std"))]
+fn unresolved(name: &str) -> CalculatorError {
+  unknown_token(name)
+}
+
+fn unknown_token(token: &str) -> CalculatorError {
+  CalculatorError::ParseError(format!("Unknown token: {}", token))
+}
+
+/// Checks whether the evaluating [`EvalContext`] has a function called
+/// `name`, or the user has defined one
+#[cfg(feature = "std")]
+fn is_custom_function(name: &str) -> bool {
+  context::custom_function(name).is_some() || functions::find(name).is_some()
+}
+
+/// Custom functions need an `EvalContext`, which needs the `std` feature
+#[cfg(not(feature = "std"))]
+fn is_custom_function(_name: &str) -> bool {
+  false
+}
+
+/// Calls the evaluating [`EvalContext`]'s function called `name`, if it has one
+#[cfg(feature = "std")]
+fn call_custom_function(name: &str, value: f64) -> Option<f64> {
+  context::custom_function(name).map(|function| function(value))
+}
+
+/// Custom functions need an `EvalContext`, which needs the `std` feature
+#[cfg(not(feature = "std"))]
+fn call_custom_function(_name: &str, _value: f64) -> Option<f64> {
+  None
+}
+
+/// Calls the user-defined function called `name`, if there is one, with
+/// the names in its body other than parameters resolved through `resolve`
+#[cfg(feature = "std")]
+fn call_user_function(
+  name: &str,
+  arguments: &[f64],
+  resolve: Resolver,
+) -> Option<Result<f64, CalculatorError>> {
+  functions::call(name, arguments, resolve)
+}
+
+/// User-defined functions need the `std` feature
+#[cfg(not(feature = "std"))]
+fn call_user_function(
+  _name: &str,
+  _arguments: &[f64],
+  _resolve: Resolver,
+) -> Option<Result<f64, CalculatorError>> {
+  None
+}
+
+/// How many parameters the user-defined function called `name` has, if
+/// there is one
+#[cfg(feature = "std")]
+fn user_function_parameters(name: &str) -> Option<usize> {
+  functions::find(name).map(|function| function.parameters.len())
+}
+
+/// User-defined functions need the `std` feature
+#[cfg(not(feature = "std"))]
+fn user_function_parameters(_name: &str) -> Option<usize> {
+  None
+}
+
+/// Check if a string names a built-in mathematical function
+pub(crate) fn is_math_function(name: &str) -> bool {
+  matches!(
+    name,
+    "sqrt"
+      | "cbrt"
+      | "root"
+      | "sum"
+      | "prod"
+      | "list"
+      | "mean"
+      | "median"
+      | "mode"
+      | "var"
+      | "stdev"
+      | "count"
+      | "sort"
+      | "min"
+      | "max"
+      | "range"
+      | "linreg"
+      | "fv"
+      | "pmt"
+      | "npv"
+      | "irr"
+      | "date"
+      | "today"
+      | "ans"
+      | "days_between"
+      | "weekday"
+      | "simplify"
+      | "transpose"
+      | "det"
+      | "inv"
+      | "linsolve"
+      | "days"
+      | "weeks"
+      | "solvequad"
+      | "solvecubic"
+      | "solve"
+      | "limit"
+      | "minimize"
+      | "maximize"
+      | "map"
+      | "filter"
+      | "reduce"
+      | "mod"
+      | "neg"
+      | "sin"
+      | "cos"
+      | "tan"
+      | "asin"
+      | "acos"
+      | "atan"
+      | "log"
+      | "ln"
+      | "exp"
+      | "abs"
+      | "floor"
+      | "ceil"
+      | "round"
+      | "trunc"
+      | "sign"
+      | "frac"
+      | "clamp"
+      | "if"
+      | "fact"
+      | "erf"
+      | "erfc"
+      | "normpdf"
+      | "normcdf"
+      | "percent"
+      | "fib"
+      | "lucas"
+      | "triangular"
+      | "catalan"
+      | "isprime"
+      | "nextprime"
+      | "factorize"
+      | "roman"
+      | "spell"
+      | "~"
+      | "not"
+  )
+}
+
 /// Check if a string is a valid unit conversion function
 fn is_valid_conversion(conversion: &str) -> bool {
   matches!(
@@ -563,166 +2495,117 @@ fn is_valid_conversion(conversion: &str) -> bool {
   )
 }
 
-/// Evaluate a complex expression with multiple operations, respecting operator precedence
-fn evaluate_complex_expression(tokens: Vec<Token>) -> Result<f64, CalculatorError> {
-  // Special case handling for memory operations and unit conversions
-  if tokens.len() == 2 {
-    if let Token::Function(func) = &tokens[0] {
-      if func == "m+" || func == "m-" {
-        if let Token::Number(num) = tokens[1] {
-          return evaluate_function(func, num);
-        }
-      }
-    }
-
-    if let Token::Number(num) = tokens[0] {
-      if let Token::Function(func) = &tokens[1] {
-        if func.contains("_to_") {
-          return evaluate_function(func, num);
-        }
-      }
-    }
-  }
-
-  // Implementation of the Shunting Yard algorithm for expression evaluation
-  // with proper operator precedence
-
-  let mut output_queue: Vec<Token> = Vec::new();
-  let mut operator_stack: Vec<Token> = Vec::new();
-
-  for token in tokens {
-    match &token {
-      Token::Number(_) => output_queue.push(token),
-      Token::Function(_) => operator_stack.push(token),
-      Token::LeftParen => operator_stack.push(token),
-      Token::RightParen => {
-        // Pop operators until we find a left parenthesis
-        let mut found_left_paren = false;
-        while let Some(top) = operator_stack.last() {
-          match top {
-            Token::LeftParen => {
-              operator_stack.pop(); // Remove the left parenthesis
-              found_left_paren = true;
-              break;
-            }
-            _ => {
-              output_queue.push(operator_stack.pop().unwrap());
-            }
-          }
-        }
-
-        if !found_left_paren {
-          return Err(CalculatorError::SyntaxError(
-            "Mismatched parentheses: missing '('".to_string(),
-          ));
-        }
-
-        // If we have a function at the top of the stack, pop it too
-        if let Some(Token::Function(_)) = operator_stack.last() {
-          output_queue.push(operator_stack.pop().unwrap());
-        }
-      }
-      Token::Operator(op) => {
-        // Handle operator precedence
-        while let Some(top) = operator_stack.last() {
-          if let Token::Operator(top_op) = top {
-            // If the top operator has higher precedence, or equal precedence
-            // and current operator is left-associative, pop it to the output queue
-            if (get_precedence(op) <= get_precedence(top_op))
-              && (op != "^" || get_precedence(op) < get_precedence(top_op))
-            {
-              output_queue.push(operator_stack.pop().unwrap());
-            } else {
-              break;
-            }
-          } else if let Token::Function(_) = top {
-            output_queue.push(operator_stack.pop().unwrap());
-          } else {
-            break;
-          }
-        }
-        operator_stack.push(token);
-      }
-    }
-  }
-
-  // Pop any remaining operators to the output queue
-  while let Some(op) = operator_stack.pop() {
-    match op {
-      Token::LeftParen => {
-        return Err(CalculatorError::SyntaxError(
-          "Mismatched parentheses: missing ')'".to_string(),
-        ));
-      }
-      Token::RightParen => {
-        return Err(CalculatorError::SyntaxError(
-          "Mismatched parentheses: extra ')'".to_string(),
-        ));
-      }
-      _ => output_queue.push(op),
-    }
-  }
-
-  // Evaluate the Reverse Polish Notation expression
-  evaluate_rpn(output_queue)
-}
-
 /// Get the precedence level of an operator
 fn get_precedence(op: &str) -> u8 {
   match op {
-    "+" | "-" => 1,
-    "*" | "/" | "%" => 2,
-    "^" => 3,
+    // The comma between function arguments binds most loosely of all
+    "," => 1,
+    "or" => 2,
+    "and" => 3,
+    "|" => 4,
+    "xor" => 5,
+    "&" => 6,
+    "==" | "!=" => 7,
+    "<" | "<=" | ">" | ">=" => 8,
+    "<<" | ">>" => 9,
+    "+" | "-" => 10,
+    "*" | "/" | "%" | "ncr" | "npr" => 11,
+    "^" => 12,
+    // Picking an item from a list binds as tightly as a function argument
+    "at" => 13,
     _ => 0,
   }
 }
 
+/// Checks whether a token is a binary operator
+pub(crate) fn is_binary_operator(op: &str) -> bool {
+  get_precedence(op) > 0
+}
+
 /// Evaluate a Reverse Polish Notation expression
-fn evaluate_rpn(tokens: Vec<Token>) -> Result<f64, CalculatorError> {
-  let mut stack: Vec<f64> = Vec::new();
+fn evaluate_rpn(tokens: Vec<SpannedToken>, resolve: Resolver) -> Result<f64, CalculatorError> {
+  // Each value keeps the span of the subexpression it came from, and whether
+  // a comma joined it to the value before it as the next argument of a call
+  let mut stack: Vec<(f64, Range<usize>, bool)> = Vec::new();
 
-  for token in tokens {
+  for (token, span) in tokens {
     match token {
-      Token::Number(n) => stack.push(n),
+      Token::Number(n) => stack.push((n, span, false)),
       Token::Operator(op) => {
         if stack.len() < 2 {
-          return Err(CalculatorError::SyntaxError(
-            "Invalid expression: not enough operands".to_string(),
-          ));
+          return Err(
+            CalculatorError::SyntaxError("Invalid expression: not enough operands".to_string())
+              .at(span),
+          );
+        }
+
+        // Arguments stay on the stack until their function takes them all
+        if op == "," {
+          let (_, _, joined) = stack.last_mut().unwrap();
+          if *joined {
+            return Err(misplaced_comma().at(span));
+          }
+          *joined = true;
+          continue;
         }
 
-        let right = stack.pop().unwrap();
-        let left = stack.pop().unwrap();
+        let (right, right_span, right_joined) = stack.pop().unwrap();
+        let (left, left_span, _) = stack.pop().unwrap();
+        if right_joined {
+          return Err(misplaced_comma().at(right_span));
+        }
 
-        let result = evaluate_binary_operation(left, &op, right)?;
-        stack.push(result);
+        let result = evaluate_binary_operation(left, &op, right).map_err(|err| err.at(span))?;
+        stack.push((result, left_span.start..right_span.end, false));
       }
       Token::Function(func) => {
-        if stack.is_empty() {
-          return Err(CalculatorError::SyntaxError(
-            "Invalid expression: function without argument".to_string(),
-          ));
-        }
-
-        let arg = stack.pop().unwrap();
-        let result = evaluate_function(&func, arg)?;
-        stack.push(result);
+        // The first argument is the nearest value no comma joined to the one before
+        let Some(first) = stack.iter().rposition(|(_, _, joined)| !joined) else {
+          return Err(
+            CalculatorError::SyntaxError(
+              "Invalid expression: function without argument".to_string(),
+            )
+            .at(span),
+          );
+        };
+
+        let (result, arg_span) = if first == stack.len() - 1 {
+          let (arg, arg_span, _) = stack.pop().unwrap();
+          (evaluate_function(&func, arg, resolve), arg_span)
+        } else {
+          let arguments: Vec<f64> = stack[first..].iter().map(|(value, _, _)| *value).collect();
+          let arg_span = stack[first].1.start..stack[stack.len() - 1].1.end;
+          stack.truncate(first);
+          (evaluate_call(&func, &arguments, resolve), arg_span)
+        };
+        let result = result.map_err(|err| err.at(span.clone()))?;
+        stack.push((
+          result,
+          span.start.min(arg_span.start)..span.end.max(arg_span.end),
+          false,
+        ));
       }
       _ => {
-        return Err(CalculatorError::SyntaxError(
-          "Unexpected token in RPN evaluation".to_string(),
-        ));
+        return Err(
+          CalculatorError::SyntaxError("Unexpected token in RPN evaluation".to_string()).at(span),
+        );
       }
     }
   }
 
-  if stack.len() != 1 {
-    return Err(CalculatorError::SyntaxError(
+  if let Some((_, span, _)) = stack.iter().find(|(_, _, joined)| *joined) {
+    return Err(misplaced_comma().at(span.clone()));
+  }
+  match stack.as_slice() {
+    [(value, _, _)] => Ok(*value),
+    [_, (_, extra, _), ..] => Err(
+      CalculatorError::SyntaxError("Invalid expression: too many operands".to_string())
+        .at(extra.clone()),
+    ),
+    [] => Err(CalculatorError::SyntaxError(
       "Invalid expression: too many operands".to_string(),
-    ));
+    )),
   }
-
-  Ok(stack.pop().unwrap())
 }
 
 /// Evaluates a binary operation with specified operands and operator
@@ -731,6 +2614,17 @@ fn evaluate_binary_operation(
   operator: &str,
   right: f64,
 ) -> Result<f64, CalculatorError> {
+  // NaN propagates the same way through every operator, e.g. 1 ^ nan is nan
+  if left.is_nan() || right.is_nan() {
+    return Ok(f64::NAN);
+  }
+  limits::check_time()?;
+  let result = apply_binary_operation(left, operator, right)?;
+  check_overflow(result, left.is_finite() && right.is_finite()).map(round_to_precision)
+}
+
+/// Computes a binary operation without checking the result for overflow
+fn apply_binary_operation(left: f64, operator: &str, right: f64) -> Result<f64, CalculatorError> {
   match operator {
     "+" => Ok(left + right),
     "-" => Ok(left - right),
@@ -749,7 +2643,59 @@ fn evaluate_binary_operation(
         Ok(left % right)
       }
     }
-    "^" => Ok(left.powf(right)),
+    "^" => {
+      limits::check_exponent(right)?;
+      if left == 0.0 && right == 0.0 && settings::current().non_finite == NonFinitePolicy::Strict {
+        return Err(CalculatorError::MathError("0 ^ 0 is undefined".to_string()));
+      }
+      if left < 0.0 && right.fract() != 0.0 && right.is_finite() {
+        domain_violation("Cannot raise a negative number to a fractional power")
+      } else {
+        Ok(left.powf(right))
+      }
+    }
+    "ncr" | "npr" => {
+      let (n, k) = counts(left, right)?;
+      if k > n {
+        return Ok(0.0);
+      }
+      let (k, divide) = match operator {
+        "ncr" => (k.min(n - k), true),
+        _ => (k, false),
+      };
+      // Each partial result is itself a count, so this stays exact while it's small
+      let mut result = 1.0;
+      for i in 1..=k as u64 {
+        result *= n - k + i as f64;
+        if divide {
+          result /= i as f64;
+        }
+        if result.is_infinite() {
+          break;
+        }
+        if i % 65536 == 0 {
+          limits::check_time()?;
+        }
+      }
+      Ok(result.round())
+    }
+    "," => Err(misplaced_comma()),
+    "&" => Ok((whole(left)? & whole(right)?) as f64),
+    "|" => Ok((whole(left)? | whole(right)?) as f64),
+    "xor" => Ok((whole(left)? ^ whole(right)?) as f64),
+    // Shifting in 128 bits keeps every bit of a 64-bit integer shifted left
+    "<<" => Ok(((whole(left)? as i128) << shift(right)?) as f64),
+    ">>" => Ok((whole(left)? >> shift(right)?) as f64),
+    "==" | "!=" | "<" | "<=" | ">" | ">=" => Ok(if compare(left, operator, right) {
+      1.0
+    } else {
+      0.0
+    }),
+    "and" | "or" => Ok(if both(left != 0.0, operator, right != 0.0) {
+      1.0
+    } else {
+      0.0
+    }),
     _ => Err(CalculatorError::SyntaxError(format!(
       "Unknown operator: {}",
       operator
@@ -757,62 +2703,299 @@ fn evaluate_binary_operation(
   }
 }
 
+/// Whether a comparison operator holds between two values. Numbers compare
+/// exactly, so `0.1 + 0.2 == 0.3` is false, and NaN is unequal to everything.
+pub(crate) fn compare<T: PartialOrd>(left: T, operator: &str, right: T) -> bool {
+  match operator {
+    "==" => left == right,
+    "!=" => left != right,
+    "<" => left < right,
+    "<=" => left <= right,
+    ">" => left > right,
+    _ => left >= right,
+  }
+}
+
+/// Checks whether an operator compares its operands
+pub(crate) fn is_comparison(operator: &str) -> bool {
+  matches!(operator, "==" | "!=" | "<" | "<=" | ">" | ">=")
+}
+
+/// Whether `and` or `or` holds between two truth values
+pub(crate) fn both(left: bool, operator: &str, right: bool) -> bool {
+  match operator {
+    "and" => left && right,
+    _ => left || right,
+  }
+}
+
+/// Checks whether a tree's value is true or false, as a comparison or
+/// logical operation is
+fn is_boolean(tree: &Expr) -> bool {
+  match tree {
+    Expr::Binary { operator, .. } => {
+      is_comparison(operator) || operator == "and" || operator == "or"
+    }
+    Expr::Function { name, .. } => name == "not",
+    _ => false,
+  }
+}
+
+/// Reads an operand of a bitwise operator as the 64-bit integer it must be
+fn whole(value: f64) -> Result<i64, CalculatorError> {
+  // -2^63 is exact, so this is the range of i64
+  let limit = -(i64::MIN as f64);
+  if value.fract() != 0.0 || !(-limit..limit).contains(&value) {
+    return Err(CalculatorError::ArgumentError(format!(
+      "Bitwise operators require 64-bit integers, got {}",
+      value
+    )));
+  }
+  Ok(value as i64)
+}
+
+/// Reads the amount to shift by, which must be less than 64
+fn shift(value: f64) -> Result<u32, CalculatorError> {
+  match whole(value) {
+    Ok(amount @ 0..=63) => Ok(amount as u32),
+    _ => Err(CalculatorError::ArgumentError(format!(
+      "Shift amount must be an integer from 0 to 63, got {}",
+      value
+    ))),
+  }
+}
+
+/// Checks the operands of nCr and nPr, which count ways to pick `k` of `n` items
+fn counts(n: f64, k: f64) -> Result<(f64, f64), CalculatorError> {
+  if n < 0.0 || k < 0.0 || n.fract() != 0.0 || k.fract() != 0.0 || !n.is_finite() {
+    return Err(CalculatorError::ArgumentError(
+      "nCr and nPr require non-negative integers".to_string(),
+    ));
+  }
+  Ok((n, k))
+}
+
 /// Evaluates a mathematical function (unary operation)
-fn evaluate_function(function: &str, value: f64) -> Result<f64, CalculatorError> {
+fn evaluate_function(
+  function: &str,
+  value: f64,
+  resolve: Resolver,
+) -> Result<f64, CalculatorError> {
+  check_arity(function, 1)?;
+  // Likewise for functions, which would otherwise treat NaN inconsistently,
+  // except for memory functions that have to store it
+  if value.is_nan() && !matches!(function, "m+" | "m-" | "mr" | "mc" | "mundo") {
+    return Ok(f64::NAN);
+  }
+  limits::check_time()?;
+  let result = apply_function(function, value, resolve)?;
+  check_overflow(result, value.is_finite()).map(round_to_precision)
+}
+
+/// Evaluates a function called with several arguments, such as `clamp(x, 0, 1)`
+fn evaluate_call(
+  function: &str,
+  arguments: &[f64],
+  resolve: Resolver,
+) -> Result<f64, CalculatorError> {
+  check_arity(function, arguments.len())?;
+  if arguments.iter().any(|argument| argument.is_nan()) {
+    return Ok(f64::NAN);
+  }
+  limits::check_time()?;
+  let result = match (function, arguments) {
+    ("round", &[value, decimals]) => {
+      if decimals < 0.0 || decimals.fract() != 0.0 {
+        return Err(CalculatorError::ArgumentError(format!(
+          "Decimal places must be a non-negative integer, got {}",
+          decimals
+        )));
+      }
+      // Beyond 400 places no f64 has digits left to round
+      settings::current()
+        .rounding
+        .round_to(value, decimals.min(400.0) as u32)
+    }
+    ("root", &[degree, value]) => nth_root(degree, value)?,
+    ("fv" | "pmt", arguments) => finance::evaluate_call(function, arguments)?,
+    ("date", &[year, month, day]) => dates::date(year, month, day)?,
+    ("days_between", &[from, to]) => to - from,
+    ("mod", &[_, 0.0]) => {
+      return Err(CalculatorError::MathError("Modulo by zero".to_string()));
+    }
+    // The Euclidean remainder, from 0 up to |divisor|, unlike % which takes
+    // the sign of the dividend
+    ("mod", &[dividend, divisor]) => {
+      let remainder = dividend % divisor;
+      if remainder < 0.0 {
+        remainder + divisor.abs()
+      } else {
+        remainder
+      }
+    }
+    ("clamp", &[value, low, high]) => {
+      if low > high {
+        return Err(CalculatorError::ArgumentError(format!(
+          "clamp needs its lower bound first, got {} and {}",
+          low, high
+        )));
+      }
+      value.clamp(low, high)
+    }
+    ("if", &[condition, then, otherwise]) => {
+      if condition != 0.0 {
+        then
+      } else {
+        otherwise
+      }
+    }
+    (function, arguments) => match call_user_function(function, arguments, resolve) {
+      Some(result) => result?,
+      None => {
+        return Err(CalculatorError::SyntaxError(format!(
+          "Unknown function: {}",
+          function
+        )));
+      }
+    },
+  };
+  let finite_inputs = arguments.iter().all(|argument| argument.is_finite());
+  check_overflow(result, finite_inputs).map(round_to_precision)
+}
+
+/// The `degree`th root of `value`, which is real for negative values when
+/// the degree is odd
+fn nth_root(degree: f64, value: f64) -> Result<f64, CalculatorError> {
+  if degree == 0.0 {
+    return Err(CalculatorError::ArgumentError(
+      "Cannot take a root of degree zero".to_string(),
+    ));
+  }
+  let whole_degree = degree.fract() == 0.0;
+  if value < 0.0 && !(whole_degree && degree % 2.0 != 0.0) {
+    return domain_violation("Cannot take an even or fractional root of a negative number");
+  }
+  let root = if degree == 3.0 {
+    value.cbrt()
+  } else {
+    value.abs().powf(1.0 / degree).copysign(value)
+  };
+  // 1 / degree is rounded, so powf can miss a whole root by a little, as in root(3, 1000)
+  let whole = root.round();
+  if whole_degree && whole.powf(degree) == value {
+    return Ok(whole);
+  }
+  Ok(root)
+}
+
+/// The fewest and most arguments a function takes
+pub(crate) fn arity(function: &str) -> (usize, usize) {
+  match function {
+    "round" => (1, 2),
+    "root" => (2, 2),
+    "mod" => (2, 2),
+    "clamp" => (3, 3),
+    "if" => (3, 3),
+    "sum" | "prod" => (4, 4),
+    "linreg" => (2, 2),
+    "fv" | "pmt" => (3, 3),
+    "npv" => (2, 2),
+    "date" => (3, 3),
+    "solve" => (3, 3),
+    "limit" => (3, 4),
+    "minimize" | "maximize" => (4, 4),
+    "map" | "filter" => (2, 2),
+    "reduce" => (3, 3),
+    "transpose" | "det" | "inv" => (1, 1),
+    "linsolve" => (2, 2),
+    "solvequad" => (3, 3),
+    "solvecubic" => (4, 4),
+    "days_between" => (2, 2),
+    "currency" => (2, 2),
+    "as_fraction" | "continued_fraction" => (1, 2),
+    _ => match user_function_parameters(function) {
+      Some(count) => (count, count),
+      None => (1, 1),
+    },
+  }
+}
+
+/// Fails unless `function` takes `count` arguments
+pub(crate) fn check_arity(function: &str, count: usize) -> Result<(), CalculatorError> {
+  let (fewest, most) = arity(function);
+  if (fewest..=most).contains(&count) {
+    return Ok(());
+  }
+  let expected = match (fewest, most) {
+    (1, 1) => "1 argument".to_string(),
+    _ if fewest == most => format!("{} arguments", fewest),
+    _ => format!("{} to {} arguments", fewest, most),
+  };
+  Err(CalculatorError::ArgumentError(format!(
+    "{} takes {}, got {}",
+    function, expected, count
+  )))
+}
+
+/// The error for a comma outside the arguments of a function call
+pub(crate) fn misplaced_comma() -> CalculatorError {
+  CalculatorError::SyntaxError("A comma can only separate the arguments of a function".to_string())
+}
+
+/// Computes a function without checking the result for overflow
+fn apply_function(function: &str, value: f64, resolve: Resolver) -> Result<f64, CalculatorError> {
   match function {
     "sqrt" => {
       if value < 0.0 {
-        Err(CalculatorError::ArgumentError(
-          "Cannot calculate square root of negative number".to_string(),
-        ))
+        domain_violation("Cannot calculate square root of negative number")
       } else {
         Ok(value.sqrt())
       }
     }
-    "sin" => Ok(value.to_radians().sin()),
-    "cos" => Ok(value.to_radians().cos()),
-    "tan" => Ok(value.to_radians().tan()),
+    "sin" => Ok(angle_mode().to_radians(value).sin()),
+    "cos" => Ok(angle_mode().to_radians(value).cos()),
+    "tan" => Ok(angle_mode().to_radians(value).tan()),
     "asin" => {
-      if value < -1.0 || value > 1.0 {
-        Err(CalculatorError::ArgumentError(
-          "Inverse sine argument must be between -1 and 1".to_string(),
-        ))
+      if !(-1.0..=1.0).contains(&value) {
+        domain_violation("Inverse sine argument must be between -1 and 1")
       } else {
-        Ok(value.asin().to_degrees())
+        Ok(angle_mode().from_radians(value.asin()))
       }
     }
     "acos" => {
-      if value < -1.0 || value > 1.0 {
-        Err(CalculatorError::ArgumentError(
-          "Inverse cosine argument must be between -1 and 1".to_string(),
-        ))
+      if !(-1.0..=1.0).contains(&value) {
+        domain_violation("Inverse cosine argument must be between -1 and 1")
       } else {
-        Ok(value.acos().to_degrees())
+        Ok(angle_mode().from_radians(value.acos()))
       }
     }
-    "atan" => Ok(value.atan().to_degrees()),
+    "atan" => Ok(angle_mode().from_radians(value.atan())),
     "log" => {
       if value <= 0.0 {
-        Err(CalculatorError::ArgumentError(
-          "Cannot calculate logarithm of non-positive number".to_string(),
-        ))
+        domain_violation("Cannot calculate logarithm of non-positive number")
       } else {
         Ok(value.log10())
       }
     }
     "ln" => {
       if value <= 0.0 {
-        Err(CalculatorError::ArgumentError(
-          "Cannot calculate natural logarithm of non-positive number".to_string(),
-        ))
+        domain_violation("Cannot calculate natural logarithm of non-positive number")
       } else {
         Ok(value.ln())
       }
     }
     "exp" => Ok(value.exp()),
+    "neg" => Ok(-value),
     "abs" => Ok(value.abs()),
     "floor" => Ok(value.floor()),
     "ceil" => Ok(value.ceil()),
+    "round" => Ok(settings::current().rounding.round(value)),
+    "cbrt" => Ok(value.cbrt()),
+    "trunc" => Ok(value.trunc()),
+    // Zero keeps its own sign, where signum would give ±1
+    "sign" if value == 0.0 => Ok(value),
+    "sign" => Ok(value.signum()),
+    "frac" => Ok(value.fract()),
     "fact" => {
       // Factorial only works on non-negative integers
       if value < 0.0 {
@@ -821,6 +3004,11 @@ fn evaluate_function(function: &str, value: f64) -> Result<f64, CalculatorError>
         ));
       }
 
+      limits::check_factorial(value)?;
+      if value.is_infinite() {
+        return Ok(f64::INFINITY);
+      }
+
       // Check if the value is an integer
       if value.fract() != 0.0 {
         return Err(CalculatorError::ArgumentError(
@@ -832,28 +3020,21 @@ fn evaluate_function(function: &str, value: f64) -> Result<f64, CalculatorError>
       let mut result = 1.0;
       for i in 2..=value as u64 {
         result *= i as f64;
+        if i % 65536 == 0 {
+          limits::check_time()?;
+        }
       }
       Ok(result)
     }
-    "m+" => {
-      let mut memory = MEMORY.write().unwrap();
-      *memory += value;
-      Ok(*memory)
-    }
-    "m-" => {
-      let mut memory = MEMORY.write().unwrap();
-      *memory -= value;
-      Ok(*memory)
-    }
-    "mr" => {
-      let memory = MEMORY.read().unwrap();
-      Ok(*memory)
-    }
-    "mc" => {
-      let mut memory = MEMORY.write().unwrap();
-      *memory = 0.0;
-      Ok(*memory)
-    }
+    "erf" => Ok(libm::erf(value)),
+    "erfc" => Ok(libm::erfc(value)),
+    // The standard normal distribution; erfc keeps the digits of small tails
+    "normpdf" => Ok((-value * value / 2.0).exp() / core::f64::consts::TAU.sqrt()),
+    "normcdf" => Ok(libm::erfc(-value / core::f64::consts::SQRT_2) / 2.0),
+    "~" => Ok(!whole(value)? as f64),
+    "not" => Ok(if value == 0.0 { 1.0 } else { 0.0 }),
+    "m+" | "m-" | "mr" | "mc" | "mundo" => update_memory(function, value),
+    "ans" => answer(),
     // Unit conversion functions
     "km_to_mi" => Ok(value * 0.621371), // Kilometers to miles
     "mi_to_km" => Ok(value * 1.60934),  // Miles to kilometers
@@ -861,15 +3042,113 @@ fn evaluate_function(function: &str, value: f64) -> Result<f64, CalculatorError>
     "lb_to_kg" => Ok(value * 0.453592), // Pounds to kilograms
     "c_to_f" => Ok(value * 9.0 / 5.0 + 32.0), // Celsius to Fahrenheit
     "f_to_c" => Ok((value - 32.0) * 5.0 / 9.0), // Fahrenheit to Celsius
-    "rad_to_deg" => Ok(value * 180.0 / std::f64::consts::PI), // Radians to degrees
-    "deg_to_rad" => Ok(value * std::f64::consts::PI / 180.0), // Degrees to radians
+    "rad_to_deg" => Ok(value * 180.0 / core::f64::consts::PI), // Radians to degrees
+    "deg_to_rad" => Ok(value * core::f64::consts::PI / 180.0), // Degrees to radians
     "in_to_cm" => Ok(value * 2.54),     // Inches to centimeters
     "cm_to_in" => Ok(value / 2.54),     // Centimeters to inches
     "gal_to_l" => Ok(value * 3.78541),  // US gallons to liters
     "l_to_gal" => Ok(value / 3.78541),  // Liters to US gallons
-    _ => Err(CalculatorError::SyntaxError(format!(
-      "Unknown function: {}",
-      function
-    ))),
+    "percent" => Ok(value / 100.0),
+    "fib" => Ok(sequences::fibonacci(sequences::index(value, function)?)),
+    "lucas" => Ok(sequences::lucas(sequences::index(value, function)?)),
+    "triangular" => Ok(sequences::triangular(sequences::index(value, function)?)),
+    "catalan" => Ok(sequences::catalan(sequences::index(value, function)?)),
+    "isprime" => Ok(
+      if primes::is_prime(primes::whole_argument(value, function)?) {
+        1.0
+      } else {
+        0.0
+      },
+    ),
+    "nextprime" => match primes::next_prime(primes::whole_argument(value, function)?) {
+      Some(prime) => Ok(prime as f64),
+      None => Err(CalculatorError::Overflow(
+        "The next prime is beyond 2^53".to_string(),
+      )),
+    },
+    // Text-valued functions are handled by evaluate_to_string
+    "today" => dates::today(),
+    "days" => Ok(value),
+    "weeks" => Ok(value * 7.0),
+    "roman" | "factorize" | "weekday" | "simplify" | "spell" => {
+      Err(CalculatorError::ArgumentError(format!(
+        "{}() produces text, which is only available through evaluate_to_string",
+        function
+      )))
+    }
+    _ => match call_custom_function(function, value) {
+      Some(result) => Ok(result),
+      None => call_user_function(function, &[value], resolve).unwrap_or_else(|| {
+        Err(CalculatorError::SyntaxError(format!(
+          "Unknown function: {}",
+          function
+        )))
+      }),
+    },
+  }
+}
+
+/// Angle unit of the trigonometric functions in the current settings
+fn angle_mode() -> AngleMode {
+  settings::current().angle_mode
+}
+
+/// Result of a function given an argument outside its real domain, according
+/// to the current [`DomainPolicy`]
+fn domain_violation(message: &str) -> Result<f64, CalculatorError> {
+  match settings::current().domain {
+    DomainPolicy::NaN => Ok(f64::NAN),
+    // The complex policy retries the whole expression after the error
+    DomainPolicy::Error | DomainPolicy::Complex => {
+      Err(CalculatorError::ArgumentError(message.to_string()))
+    }
+  }
+}
+
+/// Rejects infinite or NaN results of finite inputs unless the current
+/// [`OverflowPolicy`] allows IEEE propagation
+pub(crate) fn check_overflow(result: f64, finite_inputs: bool) -> Result<f64, CalculatorError> {
+  let settings = settings::current();
+  // NaN is an accepted answer for domain violations under the NaN policy
+  let allowed = settings.overflow == OverflowPolicy::Ieee
+    || (result.is_nan() && settings.domain == DomainPolicy::NaN);
+  if result.is_finite() {
+    return Ok(result);
+  }
+  if finite_inputs && !allowed {
+    return Err(CalculatorError::Overflow(if result.is_nan() {
+      "Result is not a number".to_string()
+    } else {
+      "Result is too large to represent".to_string()
+    }));
+  }
+  if settings.non_finite == NonFinitePolicy::Strict {
+    return Err(CalculatorError::MathError(if result.is_nan() {
+      "Result is not a number".to_string()
+    } else {
+      "Result is infinite".to_string()
+    }));
+  }
+  Ok(result)
+}
+
+/// Rounds a result to the working precision of the `decimals` setting, if any
+pub(crate) fn round_to_precision(value: f64) -> f64 {
+  let settings = settings::current();
+  match settings.decimals {
+    Some(decimals) => settings.rounding.round_to(value, decimals),
+    None => value,
+  }
+}
+
+/// Rejects infinite and NaN input numbers under [`NonFinitePolicy::Reject`]
+/// and [`NonFinitePolicy::Strict`]
+fn check_input(value: f64) -> Result<f64, CalculatorError> {
+  if !value.is_finite() && settings::current().non_finite != NonFinitePolicy::Propagate {
+    return Err(CalculatorError::ArgumentError(format!(
+      "Non-finite numbers are not allowed: {}",
+      value
+    )));
   }
+  Ok(value)
 }
@@ -0,0 +1,181 @@
+//! # Evaluation Limits
+//!
+//! Bounds on the work a single evaluation may do, for evaluating expressions
+//! from untrusted sources. [`evaluate_expression_with_options`] applies an
+//! [`EvalOptions`] to one call, and an evaluation that would go past one of
+//! its limits fails with [`CalculatorError::LimitExceeded`] instead of running
+//! on.
+//!
+//! The limits are only enforced with the `std` feature.
+//!
+//! [`evaluate_expression_with_options`]: crate::evaluate_expression_with_options
+
+use crate::CalculatorError;
+use crate::prelude::*;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::cell::Cell;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// Limits for a single evaluation; every limit is off by default
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::limits::EvalOptions;
+/// use std::time::Duration;
+///
+/// let options = EvalOptions {
+///   max_length: Some(200),
+///   max_factorial: Some(1000),
+///   time_budget: Some(Duration::from_millis(50)),
+///   ..EvalOptions::default()
+/// };
+/// let err = rust_calculator::evaluate_expression_with_options("fact 1e9", &options).unwrap_err();
+/// assert_eq!(err.to_string(), "Limit exceeded: factorial argument 1000000000 is above 1000");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EvalOptions {
+  /// Longest expression accepted, in bytes
+  pub max_length: Option<usize>,
+  /// Deepest nesting accepted, counting parentheses, brackets, absolute
+  /// value bars, signs and function arguments, each of which the parser
+  /// recurses into
+  pub max_depth: Option<usize>,
+  /// Largest argument `fact` accepts
+  pub max_factorial: Option<u64>,
+  /// Largest magnitude of an exponent accepted by `^`
+  pub max_exponent: Option<f64>,
+  /// Time the evaluation may take
+  pub time_budget: Option<Duration>,
+}
+
+impl EvalOptions {
+  /// Checks the limits that apply to the text of the expression, which is
+  /// its length; the nesting is checked as the expression is parsed
+  pub fn check_expression(&self, expression: &str) -> Result<(), CalculatorError> {
+    if let Some(max_length) = self.max_length {
+      if expression.len() > max_length {
+        return Err(exceeded(format!(
+          "expression is {} bytes long, the maximum is {}",
+          expression.len(),
+          max_length
+        )));
+      }
+    }
+
+    Ok(())
+  }
+}
+
+/// The limits of the evaluation running on this thread, with the time its
+/// budget runs out
+#[cfg(feature = "std")]
+#[derive(Clone, Copy)]
+struct Active {
+  options: EvalOptions,
+  deadline: Option<Instant>,
+}
+
+#[cfg(feature = "std")]
+thread_local! {
+  static ACTIVE: Cell<Option<Active>> = const { Cell::new(None) };
+}
+
+/// Runs `f` with `options` in effect on this thread, restoring the previous
+/// limits afterwards, even if `f` panics
+#[cfg(feature = "std")]
+pub(crate) fn scoped<T>(options: EvalOptions, f: impl FnOnce() -> T) -> T {
+  struct Restore(Option<Active>);
+
+  impl Drop for Restore {
+    fn drop(&mut self) {
+      ACTIVE.with(|cell| cell.set(self.0));
+    }
+  }
+
+  let active = Active {
+    options,
+    deadline: options
+      .time_budget
+      .and_then(|budget| Instant::now().checked_add(budget)),
+  };
+  let _restore = Restore(ACTIVE.with(|cell| cell.replace(Some(active))));
+  f()
+}
+
+/// Limits in effect for the current evaluation
+#[cfg(feature = "std")]
+fn active() -> Option<Active> {
+  ACTIVE.with(Cell::get)
+}
+
+/// Deepest nesting the current evaluation may parse
+#[cfg(feature = "std")]
+pub(crate) fn max_depth() -> Option<usize> {
+  active().and_then(|active| active.options.max_depth)
+}
+
+/// Fails once the time budget of the current evaluation has run out
+#[cfg(feature = "std")]
+pub(crate) fn check_time() -> Result<(), CalculatorError> {
+  match active().and_then(|active| active.deadline) {
+    Some(deadline) if Instant::now() > deadline => {
+      Err(exceeded("time budget exhausted".to_string()))
+    }
+    _ => Ok(()),
+  }
+}
+
+/// Fails if `value` is above the factorial limit of the current evaluation
+#[cfg(feature = "std")]
+pub(crate) fn check_factorial(value: f64) -> Result<(), CalculatorError> {
+  match active().and_then(|active| active.options.max_factorial) {
+    Some(max) if value > max as f64 => Err(exceeded(format!(
+      "factorial argument {} is above {}",
+      value, max
+    ))),
+    _ => Ok(()),
+  }
+}
+
+/// Fails if `exponent` is larger than the exponent limit of the current evaluation
+#[cfg(feature = "std")]
+pub(crate) fn check_exponent(exponent: f64) -> Result<(), CalculatorError> {
+  match active().and_then(|active| active.options.max_exponent) {
+    Some(max) if exponent.abs() > max => Err(exceeded(format!(
+      "exponent {} is larger than {}",
+      exponent, max
+    ))),
+    _ => Ok(()),
+  }
+}
+
+/// Limits can't be set without the `std` feature
+#[cfg(not(feature = "std"))]
+pub(crate) fn max_depth() -> Option<usize> {
+  None
+}
+
+/// Limits can't be set without the `std` feature
+#[cfg(not(feature = "std"))]
+pub(crate) fn check_time() -> Result<(), CalculatorError> {
+  Ok(())
+}
+
+/// Limits can't be set without the `std` feature
+#[cfg(not(feature = "std"))]
+pub(crate) fn check_factorial(_value: f64) -> Result<(), CalculatorError> {
+  Ok(())
+}
+
+/// Limits can't be set without the `std` feature
+#[cfg(not(feature = "std"))]
+pub(crate) fn check_exponent(_exponent: f64) -> Result<(), CalculatorError> {
+  Ok(())
+}
+
+fn exceeded(message: String) -> CalculatorError {
+  CalculatorError::LimitExceeded(message)
+}
@@ -0,0 +1,348 @@
+//! # Lists and Statistics
+//!
+//! A list is written in brackets, as in `[3, 5, 8, 13]`, and evaluates to a
+//! [`Value::List`]. The statistics functions take a list and reduce it to a
+//! number:
+//!
+//! - `mean` and `median`
+//! - `mode`, the most frequent value, or the smallest of those tied
+//! - `var` and `stdev`, the sample variance and standard deviation, which
+//!   divide by `n - 1`
+//! - `sum` and `count`
+//! - `min`, `max` and `range`, the difference between them
+//!
+//! `sort(list)` gives the list in ascending order, and brackets after a list
+//! pick an item by its position, counting from 1, so `sort([5, 2, 9])[1]` is
+//! 2.
+//!
+//! `map(f, list)` applies the function `f` to each item, `filter(f, list)`
+//! keeps the items for which `f` is not 0, and `reduce(f, list, init)`
+//! combines the items into one number, from `f(init, first item)` onwards.
+//! `f` is the name of a function of your own or a built-in one, such as
+//! `sqrt`.
+//!
+//! `linreg(xs, ys)` fits a line `y = a x + b` to the points `(xs[i], ys[i])`
+//! by least squares, giving the list `[a, b, r2]` of its slope, its
+//! intercept and the coefficient of determination.
+//!
+//! ```
+//! use rust_calculator::{Value, evaluate, evaluate_expression, lists};
+//!
+//! assert_eq!(evaluate("[3, 5, 8, 13]").unwrap(), Value::from(vec![3.0, 5.0, 8.0, 13.0]));
+//! assert_eq!(evaluate_expression("mean([3, 5, 8, 13])").unwrap(), 7.25);
+//! assert_eq!(evaluate_expression("2 * median([3, 1, 2])").unwrap(), 4.0);
+//! assert_eq!(evaluate_expression("range([3, 5, 8, 13])").unwrap(), 10.0);
+//! assert_eq!(evaluate("sort([5, 2, 9])").unwrap(), Value::from(vec![2.0, 5.0, 9.0]));
+//! assert_eq!(evaluate_expression("[5, 2, 9][3]").unwrap(), 9.0);
+//! assert_eq!(lists::mode(&[1.0, 2.0, 2.0, 3.0]), Some(2.0));
+//! assert_eq!(evaluate("linreg([1, 2, 3], [3, 5, 7])").unwrap(), Value::from(vec![2.0, 1.0, 1.0]));
+//! assert_eq!(evaluate("map(sqrt, [1, 4, 9])").unwrap(), Value::from(vec![1.0, 2.0, 3.0]));
+//! assert_eq!(evaluate("filter(isprime, [4, 5, 6, 7])").unwrap(), Value::from(vec![5.0, 7.0]));
+//! ```
+
+use crate::ast::Expr;
+use crate::prelude::*;
+use crate::{CalculatorError, Token, Value};
+
+/// Checks whether `name` is a statistics function, which takes a list
+pub(crate) fn is_aggregate(name: &str) -> bool {
+  matches!(
+    name,
+    "mean" | "median" | "mode" | "var" | "stdev" | "sum" | "count" | "min" | "max" | "range"
+  )
+}
+
+/// Checks whether `name` is a function taking the name of a function as
+/// its first argument
+pub(crate) fn takes_function(name: &str) -> bool {
+  matches!(name, "map" | "filter" | "reduce")
+}
+
+/// Checks whether `name` is a function whose result is a list
+pub(crate) fn returns_list(name: &str) -> bool {
+  matches!(name, "list" | "sort" | "linreg" | "map" | "filter")
+    || crate::polynomials::is_solver(name)
+    || crate::matrices::returns_matrix(name)
+}
+
+/// Checks whether a token takes or makes lists, so expressions with it are
+/// evaluated as trees of [`Value`]s
+pub(crate) fn uses_lists(token: &Token) -> bool {
+  match token {
+    Token::Function(name) => {
+      returns_list(name)
+        || takes_function(name)
+        || is_aggregate(name)
+        || crate::finance::takes_list(name)
+        || crate::matrices::is_matrix_function(name)
+    }
+    Token::Operator(operator) => operator == "at",
+    _ => false,
+  }
+}
+
+/// Checks whether a tree can evaluate to a list or a matrix rather than a
+/// number. An item of a list may be a list itself, and an operation on a
+/// matrix may give a matrix.
+pub(crate) fn is_list(tree: &Expr) -> bool {
+  match tree {
+    Expr::Function { name, .. } => returns_list(name),
+    Expr::Binary {
+      operator,
+      left,
+      right,
+    } => {
+      operator == "at"
+        || (matches!(operator.as_str(), "+" | "-" | "*") && (is_list(left) || is_list(right)))
+    }
+    _ => false,
+  }
+}
+
+/// The arithmetic mean, or `None` for an empty list
+pub fn mean(values: &[f64]) -> Option<f64> {
+  match values.len() {
+    0 => None,
+    count => Some(values.iter().sum::<f64>() / count as f64),
+  }
+}
+
+/// The middle value, or the mean of the two middle values of a list of even
+/// length; `None` for an empty list
+pub fn median(values: &[f64]) -> Option<f64> {
+  let sorted = sorted(values);
+  let middle = sorted.len() / 2;
+  match sorted.len() {
+    0 => None,
+    count if count % 2 == 1 => Some(sorted[middle]),
+    _ => Some((sorted[middle - 1] + sorted[middle]) / 2.0),
+  }
+}
+
+/// The most frequent value, or the smallest of those that are equally
+/// frequent; `None` for an empty list
+pub fn mode(values: &[f64]) -> Option<f64> {
+  let sorted = sorted(values);
+  let mut best: Option<(f64, usize)> = None;
+  for run in sorted.chunk_by(|a, b| a == b) {
+    if best.is_none_or(|(_, count)| run.len() > count) {
+      best = Some((run[0], run.len()));
+    }
+  }
+  best.map(|(value, _)| value)
+}
+
+/// The sample variance, dividing by `n - 1`; `None` for fewer than two
+/// values
+pub fn variance(values: &[f64]) -> Option<f64> {
+  if values.len() < 2 {
+    return None;
+  }
+  let mean = mean(values)?;
+  let squares: f64 = values.iter().map(|value| (value - mean).powi(2)).sum();
+  Some(squares / (values.len() - 1) as f64)
+}
+
+/// The sample standard deviation; `None` for fewer than two values
+pub fn stdev(values: &[f64]) -> Option<f64> {
+  variance(values).map(|variance| variance.sqrt())
+}
+
+/// The items of a list in ascending order
+pub fn sort(values: &[f64]) -> Vec<f64> {
+  sorted(values)
+}
+
+/// A straight line fitted to a set of points by least squares
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Regression {
+  pub slope: f64,
+  pub intercept: f64,
+  /// The coefficient of determination, from 0 for no fit to 1 for points
+  /// all on the line
+  pub r2: f64,
+}
+
+/// Fits a line through the points `(xs[i], ys[i])` by least squares, or
+/// `None` unless there are as many of each and at least two distinct `xs`
+pub fn linear_regression(xs: &[f64], ys: &[f64]) -> Option<Regression> {
+  if xs.len() != ys.len() {
+    return None;
+  }
+  let (mean_x, mean_y) = (mean(xs)?, mean(ys)?);
+  let (mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0);
+  for (x, y) in xs.iter().zip(ys) {
+    let (dx, dy) = (x - mean_x, y - mean_y);
+    sxx += dx * dx;
+    sxy += dx * dy;
+    syy += dy * dy;
+  }
+  if sxx == 0.0 {
+    return None;
+  }
+  let slope = sxy / sxx;
+  // Points at one height lie exactly on the flat line through them
+  let r2 = if syy == 0.0 {
+    1.0
+  } else {
+    sxy * sxy / (sxx * syy)
+  };
+  Some(Regression {
+    slope,
+    intercept: mean_y - slope * mean_x,
+    r2,
+  })
+}
+
+/// Applies a function returning a list to the values of its arguments
+pub(crate) fn apply(function: &str, arguments: Vec<Value>) -> Result<Value, CalculatorError> {
+  crate::check_arity(function, arguments.len())?;
+  if crate::matrices::returns_matrix(function) {
+    return crate::matrices::apply(function, arguments);
+  }
+  if crate::polynomials::is_solver(function) {
+    let coefficients = arguments
+      .into_iter()
+      .map(Value::into_number)
+      .collect::<Result<Vec<f64>, CalculatorError>>()?;
+    return crate::polynomials::real_roots(&coefficients).map(Value::from);
+  }
+  let lists = arguments
+    .into_iter()
+    .map(|argument| numbers(function, argument))
+    .collect::<Result<Vec<_>, CalculatorError>>()?;
+  match (function, lists.as_slice()) {
+    ("sort", [list]) => Ok(Value::from(sort(list))),
+    ("linreg", [xs, ys]) => {
+      let fit = linear_regression(xs, ys).ok_or_else(|| {
+        CalculatorError::ArgumentError(
+          "linreg needs two lists of the same length with at least two different x values"
+            .to_string(),
+        )
+      })?;
+      Ok(Value::from(vec![fit.slope, fit.intercept, fit.r2]))
+    }
+    _ => Err(CalculatorError::ParseError(format!(
+      "Unknown function: {}",
+      function
+    ))),
+  }
+}
+
+/// The item of a list at `position`, counting from 1, or the row of a
+/// matrix as a list
+pub(crate) fn item(list: Value, position: f64) -> Result<Value, CalculatorError> {
+  let mut items = match list {
+    Value::List(items) => items,
+    Value::Matrix(rows) => rows.into_iter().map(Value::from).collect(),
+    other => {
+      return Err(CalculatorError::ArgumentError(format!(
+        "Only a list has items, not a {}",
+        other.type_name()
+      )));
+    }
+  };
+  if position.fract() != 0.0 || position < 1.0 || position > items.len() as f64 {
+    return Err(CalculatorError::ArgumentError(format!(
+      "No item {} in a list of {}",
+      position,
+      items.len()
+    )));
+  }
+  Ok(items.swap_remove(position as usize - 1))
+}
+
+/// Applies a statistics function to the value of its argument
+pub(crate) fn aggregate(function: &str, list: Value) -> Result<f64, CalculatorError> {
+  let values = numbers(function, list)?;
+  let result = match function {
+    "sum" => Some(values.iter().sum()),
+    "count" => Some(values.len() as f64),
+    "mean" => mean(&values),
+    "median" => median(&values),
+    "mode" => mode(&values),
+    "var" => variance(&values),
+    "stdev" => stdev(&values),
+    "min" => values.iter().copied().reduce(f64::min),
+    "max" => values.iter().copied().reduce(f64::max),
+    "range" => values
+      .iter()
+      .copied()
+      .reduce(f64::max)
+      .zip(values.iter().copied().reduce(f64::min))
+      .map(|(max, min)| max - min),
+    _ => {
+      return Err(CalculatorError::ParseError(format!(
+        "Unknown function: {}",
+        function
+      )));
+    }
+  };
+  result.ok_or_else(|| {
+    let needed = match function {
+      "var" | "stdev" => "two values",
+      _ => "one value",
+    };
+    CalculatorError::ArgumentError(format!("{} needs at least {}", function, needed))
+  })
+}
+
+/// The numbers in a list passed to `function`
+/// Applies `map`, `filter` or `reduce` to the unevaluated `arguments` of
+/// the call, the first of which names the function to apply
+pub(crate) fn apply_function(
+  function: &str,
+  arguments: &[&Expr],
+  resolve: crate::Resolver,
+) -> Result<Value, CalculatorError> {
+  crate::check_arity(function, arguments.len())?;
+  let Expr::Variable(applied) = arguments[0] else {
+    return Err(CalculatorError::ArgumentError(format!(
+      "{} needs the name of a function as its first argument",
+      function
+    )));
+  };
+  let items = numbers(function, arguments[1].evaluate_value_with(resolve)?)?;
+  match function {
+    "map" => items
+      .into_iter()
+      .map(|item| crate::evaluate_function(applied, item, resolve))
+      .collect::<Result<Vec<f64>, CalculatorError>>()
+      .map(Value::from),
+    "filter" => {
+      let mut kept = Vec::new();
+      for item in items {
+        if crate::evaluate_function(applied, item, resolve)? != 0.0 {
+          kept.push(item);
+        }
+      }
+      Ok(Value::from(kept))
+    }
+    _ => {
+      let init = arguments[2].evaluate_with(resolve)?;
+      items
+        .into_iter()
+        .try_fold(init, |total, item| {
+          crate::evaluate_call(applied, &[total, item], resolve)
+        })
+        .map(Value::Number)
+    }
+  }
+}
+
+fn numbers(function: &str, list: Value) -> Result<Vec<f64>, CalculatorError> {
+  match list {
+    Value::List(items) => items.into_iter().map(Value::into_number).collect(),
+    other => Err(CalculatorError::ArgumentError(format!(
+      "{} needs a list, got a {}",
+      function,
+      other.type_name()
+    ))),
+  }
+}
+
+fn sorted(values: &[f64]) -> Vec<f64> {
+  let mut sorted = values.to_vec();
+  sorted.sort_by(f64::total_cmp);
+  sorted
+}
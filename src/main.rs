@@ -5,6 +5,7 @@
 
 use rustyline::error::ReadlineError;
 use rustyline::{DefaultEditor, Result};
+use std::io;
 use std::process;
 
 /// The main function that runs the calculator application.
@@ -12,6 +13,60 @@ use std::process;
 /// Provides a command-line interface where users can enter mathematical expressions
 /// and receive immediate results. The program runs until the user types "exit".
 fn main() -> Result<()> {
+  // Non-interactive modes for embedding the calculator in other programs
+  let args: Vec<String> = std::env::args().skip(1).collect();
+
+  // Infinite and NaN results are errors unless asked for with set nonfinite
+  let mut defaults = rust_calculator::settings::get_default();
+  defaults.non_finite = rust_calculator::settings::NonFinitePolicy::Strict;
+  rust_calculator::settings::set_default(defaults);
+
+  // Preferences from the configuration file apply to every mode
+  #[cfg(feature = "config")]
+  match rust_calculator::config::Config::load() {
+    Ok(config) => config.apply(),
+    Err(err) => eprintln!("Error loading configuration: {}", err),
+  }
+
+  // Serve Prometheus metrics for monitoring the server modes
+  if let Some(addr) = option_value(&args, "--metrics") {
+    if let Err(err) = rust_calculator::metrics::serve(addr) {
+      eprintln!("Error starting metrics server: {}", err);
+    }
+  }
+
+  if args.iter().any(|arg| arg == "--jsonrpc") {
+    return run_jsonrpc();
+  }
+  if args.iter().any(|arg| arg == "--mcp") {
+    return run_mcp();
+  }
+  if args.iter().any(|arg| arg == "--dbus") {
+    return run_dbus();
+  }
+  if args.iter().any(|arg| arg == "--daemon") {
+    return run_daemon(option_value(&args, "--socket"));
+  }
+
+  // Optionally mirror results to remote front-ends
+  #[cfg(feature = "websocket")]
+  let remote = option_value(&args, "--websocket").and_then(|addr| {
+    match rust_calculator::websocket::serve(addr) {
+      Ok(broadcaster) => {
+        println!("WebSocket server listening on {}", addr);
+        Some(broadcaster)
+      }
+      Err(err) => {
+        eprintln!("Error starting WebSocket server: {}", err);
+        None
+      }
+    }
+  });
+  #[cfg(not(feature = "websocket"))]
+  if option_value(&args, "--websocket").is_some() {
+    eprintln!("WebSocket support is not enabled in this build (feature \"websocket\")");
+  }
+
   println!("Rust Calculator");
   println!("Enter expressions like '2 + 3 * 4' or 'sin(30) + 5', 'exit' to quit");
   println!("Supported operators: +, -, *, /, %, ^ (in order of precedence)");
@@ -40,6 +95,22 @@ fn main() -> Result<()> {
     eprintln!("Error loading variables: {}", err);
   }
 
+  // Spreadsheet cells for the current session
+  let mut sheet = rust_calculator::sheet::Sheet::new();
+
+  // Currency used to display results, set with "currency USD"
+  let mut display_currency: Option<String> = None;
+
+  // Whether input is Reverse Polish Notation, set with "notation rpn"
+  let mut rpn_input = false;
+
+  // Whether results are shown as exact fractions, set with "frac" and "dec"
+  let mut show_fractions = false;
+
+  // Last successful result, for the copy command
+  #[cfg(feature = "clipboard")]
+  let mut last_result: Option<f64> = None;
+
   loop {
     let readline = rl.readline("> ");
     match readline {
@@ -54,6 +125,32 @@ fn main() -> Result<()> {
         // Add line to history
         rl.add_history_entry(input)?;
 
+        #[cfg(feature = "clipboard")]
+        if input == "copy" {
+          copy_result(last_result);
+          continue;
+        }
+
+        // Pasting evaluates the clipboard contents as if they were typed
+        #[cfg(feature = "clipboard")]
+        let pasted;
+        #[cfg(feature = "clipboard")]
+        let input = if input == "paste" {
+          match rust_calculator::clipboard::paste_expression() {
+            Ok(expression) => {
+              println!("{}", expression);
+              pasted = expression;
+              pasted.as_str()
+            }
+            Err(err) => {
+              eprintln!("Error: {}", err);
+              continue;
+            }
+          }
+        } else {
+          input
+        };
+
         // Handle special commands
         if input == "exit" {
           break;
@@ -64,28 +161,142 @@ fn main() -> Result<()> {
           print!("\x1B[2J\x1B[1;1H");
         } else if input == "history" {
           display_history(10); // Show last 10 calculations
+        } else if input == "mhistory" {
+          display_memory_changes();
         } else if input == "clearhistory" {
           if let Err(err) = rust_calculator::clear_history() {
             eprintln!("Error clearing history: {}", err);
           } else {
             println!("History cleared");
           }
+        } else if input == "config" {
+          show_config();
         } else if input == "vars" {
           list_variables();
-        } else if input.starts_with("let ") {
+        } else if input == "constants" {
+          list_constants();
+        } else if input == "functions" {
+          list_functions();
+        } else if let Some(path) = input.strip_prefix("vars import ") {
+          import_variables(path.trim());
+        } else if let Some(namespace) = input.strip_prefix("vars clear ") {
+          clear_namespace(namespace.trim());
+        } else if let Some(namespace) = input.strip_prefix("vars ") {
+          list_namespace(namespace.trim());
+        } else if let Some(definition) = input.strip_prefix("describe ") {
+          describe_variable(definition.trim());
+        } else if let Some(name) = input.strip_prefix("unset ") {
+          // Handle variable removal: unset varname
+          unset_variable(name.trim());
+        } else if let Some(definition) = input.strip_prefix("let ") {
           // Handle variable assignment: let varname = value
-          define_variable(&input[4..]);
+          define_variable(definition);
+        } else if let Some(definition) = input.strip_prefix("def ") {
+          // Handle function definitions: def f(x) = expression
+          define_function(definition);
+        } else if let Some(spec) = input.strip_prefix("table ") {
+          // Handle parameter sweeps: table expr x from 0 to 10 step 0.5
+          display_table(spec);
+        } else if let Some(spec) = input.strip_prefix("plot ") {
+          // Handle plots: plot expr x from 0 to 10 --out chart.svg
+          plot(spec);
+        } else if let Some(expression) = input.strip_prefix("rpn ") {
+          // Show the postfix form the evaluator uses
+          match rust_calculator::rpn::to_rpn(expression) {
+            Ok(rpn) => println!("{}", rpn),
+            Err(err) => eprintln!("Error: {}", err),
+          }
+        } else if let Some(expression) = input.strip_prefix("ast ") {
+          // Show the parsed expression tree as JSON
+          match rust_calculator::ast::parse(expression) {
+            Ok(tree) => println!("{:#}", tree.to_json()),
+            Err(err) => eprintln!("Error: {}", err),
+          }
+        } else if let Some(rpn) = input.strip_prefix("infix ") {
+          match rust_calculator::rpn::from_rpn(rpn) {
+            Ok(expression) => println!("{}", expression),
+            Err(err) => eprintln!("Error: {}", err),
+          }
+        } else if input == "grid" {
+          display_grid(&sheet);
+        } else if let Some(definition) = input.strip_prefix("cell ") {
+          // Handle cell formulas: cell A1 = expression
+          define_cell(&mut sheet, definition);
+        } else if let Some(code) = input
+          .strip_prefix("currency ")
+          .filter(|code| code.trim().chars().all(|ch| ch.is_ascii_alphabetic()))
+        {
+          set_display_currency(&mut display_currency, code.trim());
+        } else if input == "frac" || input == "dec" {
+          show_fractions = input == "frac";
+          println!(
+            "Results shown as {}",
+            if show_fractions {
+              "fractions"
+            } else {
+              "decimals"
+            }
+          );
+        } else if let Some(notation) = input.strip_prefix("notation ") {
+          set_notation(&mut rpn_input, notation.trim());
+        } else if input == "set" || input.starts_with("set ") {
+          change_setting(input["set".len()..].trim());
+        } else if !rpn_input && rust_calculator::returns_text(input) {
+          // Text results such as roman(14) aren't numbers, so they skip history
+          match rust_calculator::evaluate_to_string(input) {
+            Ok(text) => {
+              println!("= {}", text);
+              store_regression(input);
+            }
+            Err(err) => eprintln!("Error: {}", err),
+          }
         } else {
-          // Evaluate expression
-          match rust_calculator::evaluate_expression(input) {
+          // Evaluate expression, which makes its result the value of ans
+          let previous = rust_calculator::last_answer();
+          let result = if rpn_input {
+            rust_calculator::evaluate_rpn_expression(input)
+          } else {
+            rust_calculator::evaluate_expression(input)
+          };
+          #[cfg(feature = "websocket")]
+          if let Some(remote) = &remote {
+            remote.publish(input, &result);
+          }
+
+          match result {
             Ok(result) => {
-              println!("= {}", result);
+              // Showing the input may evaluate it again, with ans as it was
+              rust_calculator::with_answer(previous, || match &display_currency {
+                Some(code) => match rust_calculator::currency::format_currency(result, code) {
+                  Ok(text) => println!("= {}", text),
+                  Err(_) => println!("= {}", rust_calculator::format_value(result)),
+                },
+                None if rpn_input => println!("= {}", rust_calculator::format_value(result)),
+                None if show_fractions => match rust_calculator::evaluate_fraction(input) {
+                  Ok(fraction) => println!("= {}", fraction),
+                  Err(_) => println!("= {}", rust_calculator::format_exact(input, result)),
+                },
+                None => println!("= {}", rust_calculator::format_exact(input, result)),
+              });
+              #[cfg(feature = "clipboard")]
+              {
+                last_result = Some(result);
+              }
               // Add to history
               if let Err(err) = rust_calculator::add_to_history(input, result) {
                 eprintln!("Error saving to history: {}", err);
               }
             }
-            Err(err) => eprintln!("Error: {}", err),
+            // Under the complex domain policy, non-real results are shown as a + bi
+            Err(err) => match rust_calculator::evaluate_complex(input) {
+              Ok(value) if !rpn_input && complex_results() => println!("= {}", value),
+              _ if rpn_input => report_error(input, &err),
+              // Integers too large for floating point, such as fact 200, are shown exactly
+              _ => match rust_calculator::format_overflow(input, err) {
+                Ok(text) => println!("= {}", text),
+                Err(err) => report_error(input, &err),
+              },
+            },
           }
         }
       }
@@ -105,14 +316,107 @@ fn main() -> Result<()> {
   }
 
   // Save history
-  let history_path = rust_calculator::get_history_path();
-  if let Err(err) = rl.save_history(&history_path) {
+  #[cfg(feature = "persistence")]
+  if let Err(err) = rl.save_history(&rust_calculator::get_history_path()) {
     eprintln!("Error saving history: {}", err);
   }
 
   Ok(())
 }
 
+/// Returns the argument following `flag`, if present
+fn option_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+  args
+    .iter()
+    .position(|arg| arg == flag)
+    .and_then(|index| args.get(index + 1))
+    .map(String::as_str)
+}
+
+/// Serve line-delimited JSON-RPC requests on stdin/stdout until EOF
+fn run_jsonrpc() -> Result<()> {
+  // Diagnostics go to stderr so stdout only carries protocol messages
+  if let Err(err) = rust_calculator::load_variables() {
+    eprintln!("Error loading variables: {}", err);
+  }
+
+  rust_calculator::jsonrpc::serve(io::stdin().lock(), io::stdout().lock())?;
+  Ok(())
+}
+
+/// Serve the calculator as a Model Context Protocol tool server on stdin/stdout
+fn run_mcp() -> Result<()> {
+  if let Err(err) = rust_calculator::load_variables() {
+    eprintln!("Error loading variables: {}", err);
+  }
+
+  rust_calculator::mcp::serve(io::stdin().lock(), io::stdout().lock())?;
+  Ok(())
+}
+
+/// Serve the calculator on the D-Bus session bus until the process is killed
+#[cfg(feature = "dbus")]
+fn run_dbus() -> Result<()> {
+  if let Err(err) = rust_calculator::load_variables() {
+    eprintln!("Error loading variables: {}", err);
+  }
+
+  match rust_calculator::dbus::serve() {
+    Ok(_connection) => {
+      println!(
+        "D-Bus service {} running at {}",
+        rust_calculator::dbus::BUS_NAME,
+        rust_calculator::dbus::OBJECT_PATH
+      );
+      // Requests are handled on zbus' own threads while the connection lives
+      loop {
+        std::thread::park();
+      }
+    }
+    Err(err) => {
+      eprintln!("Error starting D-Bus service: {}", err);
+      process::exit(1);
+    }
+  }
+}
+
+/// Report that D-Bus support was left out of this build
+#[cfg(not(feature = "dbus"))]
+fn run_dbus() -> Result<()> {
+  eprintln!("D-Bus support is not enabled in this build (feature \"dbus\")");
+  process::exit(1);
+}
+
+/// Answer queries on a local socket until the process is killed
+#[cfg(unix)]
+fn run_daemon(socket: Option<&str>) -> Result<()> {
+  if let Err(err) = rust_calculator::load_variables() {
+    eprintln!("Error loading variables: {}", err);
+  }
+
+  let path = socket
+    .map(std::path::PathBuf::from)
+    .unwrap_or_else(rust_calculator::daemon::default_socket_path);
+  let listener = match rust_calculator::daemon::bind(&path) {
+    Ok(listener) => listener,
+    Err(err) => {
+      eprintln!("Error binding {}: {}", path.display(), err);
+      process::exit(1);
+    }
+  };
+
+  println!("Listening on {}", path.display());
+  rust_calculator::daemon::serve(listener)?;
+  Ok(())
+}
+
+/// Report that daemon mode needs Unix domain sockets
+#[cfg(not(unix))]
+fn run_daemon(_socket: Option<&str>) -> Result<()> {
+  eprintln!("Daemon mode is only supported on Unix platforms");
+  process::exit(1);
+}
+
 /// Display the last n entries from the calculation history
 fn display_history(count: usize) {
   match rust_calculator::get_history(count) {
@@ -130,8 +434,40 @@ fn display_history(count: usize) {
   }
 }
 
-/// Define a variable with the format "let varname = expression"
+/// Display the changes made to the memory, oldest first, so it's clear what
+/// `mundo` would revert
+fn display_memory_changes() {
+  let changes = rust_calculator::memory::changes();
+  if changes.is_empty() {
+    println!("No memory changes");
+    return;
+  }
+  println!("Memory Changes:");
+  for change in changes {
+    match change.operand {
+      Some(operand) => println!(
+        "  {} {} (was {})",
+        operand, change.operation, change.previous
+      ),
+      None => println!("  {} (was {})", change.operation, change.previous),
+    }
+  }
+}
+
+/// Define a variable with the format "let varname = expression", or a
+/// formula with "let varname := expression"
 fn define_variable(input: &str) {
+  if let Some((name, expression)) = input.split_once(":=") {
+    match rust_calculator::formulas::define(name, expression) {
+      Ok(formula) => {
+        println!("Formula {}", formula);
+        show_dependents(&formula.name);
+      }
+      Err(err) => eprintln!("Error defining formula: {}", err),
+    }
+    return;
+  }
+
   // Parse the variable definition
   let parts: Vec<&str> = input.splitn(2, '=').collect();
   if parts.len() != 2 {
@@ -150,27 +486,389 @@ fn define_variable(input: &str) {
         eprintln!("Error setting variable: {}", err);
       } else {
         println!("Variable {} = {}", var_name, value);
+        show_dependents(var_name);
       }
     }
     Err(err) => eprintln!("Error evaluating expression: {}", err),
   }
 }
 
+/// Show the formulas recalculated after `name` changed, with their new values
+fn show_dependents(name: &str) {
+  for formula in rust_calculator::formulas::dependents(name) {
+    match formula.value {
+      Ok(value) => println!("  {} = {}", formula.name, value),
+      Err(err) => println!("  {}: {}", formula.name, err),
+    }
+  }
+}
+
+/// Remove a variable or formula, also from the saved ones
+fn unset_variable(name: &str) {
+  let removed = match rust_calculator::delete_variable(name) {
+    Ok(None) => rust_calculator::formulas::remove(name).map(|formula| formula.map(|_| ())),
+    removed => removed.map(|value| value.map(|_| ())),
+  };
+  match removed {
+    Ok(Some(())) => {
+      println!("Variable {} removed", name);
+      show_dependents(name);
+    }
+    Ok(None) => eprintln!("Error: No variable named {}", name),
+    Err(err) => eprintln!("Error removing variable: {}", err),
+  }
+}
+
+/// Define a function with the format "def f(x, y) = expression"
+fn define_function(input: &str) {
+  match rust_calculator::functions::define(input) {
+    Ok(function) => println!("Function {}", function),
+    Err(err) => eprintln!("Error defining function: {}", err),
+  }
+}
+
+/// Keeps the slope, intercept and r² of a linear regression as the
+/// variables a, b and r2, as scientific calculators do
+fn store_regression(input: &str) {
+  if !input.trim_start().starts_with("linreg") {
+    return;
+  }
+  if let Ok(rust_calculator::Value::List(items)) = rust_calculator::evaluate(input) {
+    for (name, item) in ["a", "b", "r2"].into_iter().zip(items) {
+      if let Some(value) = item.as_number() {
+        if let Err(err) = rust_calculator::set_variable(name, value) {
+          eprintln!("Error setting variable: {}", err);
+          return;
+        }
+      }
+    }
+    println!("Stored as a, b and r2 for y = a x + b");
+  }
+}
+
+/// Set or clear a spreadsheet cell with the format "cell A1 = expression"
+fn define_cell(sheet: &mut rust_calculator::sheet::Sheet, input: &str) {
+  let result = match input.split_once('=') {
+    Some((name, formula)) if formula.trim().is_empty() => sheet.clear(name),
+    Some((name, formula)) => sheet.set(name, formula),
+    None => {
+      // Without a formula, just show the cell
+      match sheet.value(input) {
+        Ok(value) => println!("{} = {}", input.trim().to_uppercase(), value),
+        Err(err) => eprintln!("Error: {}", err),
+      }
+      return;
+    }
+  };
+
+  match result {
+    Ok(updated) => {
+      for name in updated {
+        match sheet.value(&name) {
+          Ok(value) => println!("{} = {}", name, value),
+          Err(err) => println!("{} = Error: {}", name, err),
+        }
+      }
+    }
+    Err(err) => eprintln!("Error: {}", err),
+  }
+}
+
+/// Display all non-empty spreadsheet cells
+fn display_grid(sheet: &rust_calculator::sheet::Sheet) {
+  let mut cells = sheet.cells().peekable();
+  if cells.peek().is_none() {
+    println!("No cells defined");
+    return;
+  }
+
+  println!("Cells:");
+  for (name, cell) in cells {
+    match &cell.value {
+      Ok(value) => println!("  {:<6} {:<24} = {}", name, cell.formula, value),
+      Err(err) => println!("  {:<6} {:<24} = Error: {}", name, cell.formula, err),
+    }
+  }
+}
+
+/// Copy the last result to the clipboard
+#[cfg(feature = "clipboard")]
+fn copy_result(last_result: Option<f64>) {
+  match last_result {
+    Some(value) => match rust_calculator::clipboard::copy_result(value) {
+      Ok(text) => println!("Copied {}", text),
+      Err(err) => eprintln!("Error: {}", err),
+    },
+    None => eprintln!("Error: No result to copy"),
+  }
+}
+
+/// Evaluate an expression over a range, e.g. "x^2 x from 0 to 10 step 0.5 --output csv"
+fn display_table(input: &str) {
+  let (spec, format) = match input.split_once("--output") {
+    Some((spec, format)) => match format.parse::<rust_calculator::table::OutputFormat>() {
+      Ok(format) => (spec, format),
+      Err(err) => {
+        eprintln!("Error: {}", err);
+        return;
+      }
+    },
+    None => (input, rust_calculator::table::OutputFormat::Aligned),
+  };
+
+  match rust_calculator::table::Sweep::parse(spec).and_then(|sweep| sweep.evaluate()) {
+    Ok(table) => print!("{}", table.render(format)),
+    Err(err) => eprintln!("Error: {}", err),
+  }
+}
+
+/// Render an expression over a range to an image, e.g. "sin(x) x from 0 to 360 --out chart.svg"
+#[cfg(feature = "plot")]
+fn plot(input: &str) {
+  let Some((spec, path)) = input.split_once("--out") else {
+    eprintln!("Error: Missing output file. Format: plot expr x from 0 to 10 --out chart.svg");
+    return;
+  };
+
+  let path = path.trim();
+  match rust_calculator::plot::parse(spec)
+    .and_then(|sweep| rust_calculator::plot::render(&sweep, path))
+  {
+    Ok(()) => println!("Plot written to {}", path),
+    Err(err) => eprintln!("Error: {}", err),
+  }
+}
+
+/// Report that plotting was left out of this build
+#[cfg(not(feature = "plot"))]
+fn plot(_input: &str) {
+  eprintln!("Plotting is not enabled in this build (feature \"plot\")");
+}
+
+/// Change the currency results are displayed in, or turn it off with "off"
+fn set_display_currency(display_currency: &mut Option<String>, code: &str) {
+  if code.eq_ignore_ascii_case("off") {
+    *display_currency = None;
+    println!("Currency display off");
+    return;
+  }
+
+  // Validate the code by formatting a sample amount
+  match rust_calculator::currency::format_currency(1234.5, code) {
+    Ok(sample) => {
+      println!(
+        "Results will be shown as {} (e.g. {})",
+        code.to_uppercase(),
+        sample
+      );
+      *display_currency = Some(code.to_uppercase());
+    }
+    Err(err) => {
+      let supported: Vec<&str> = rust_calculator::currency::supported_currencies().collect();
+      eprintln!("Error: {}. Supported: {}", err, supported.join(", "));
+    }
+  }
+}
+
+/// Print an evaluation error, underlining the part of the input it's about
+fn report_error(input: &str, err: &rust_calculator::CalculatorError) {
+  eprintln!("Error: {}", err);
+  if let Some(span) = err.char_span(input) {
+    eprintln!("  {}", input);
+    eprintln!(
+      "  {}{}",
+      " ".repeat(span.start),
+      "^".repeat((span.end - span.start).max(1))
+    );
+  }
+}
+
+/// Whether results may be complex numbers
+fn complex_results() -> bool {
+  use rust_calculator::settings::{self, DomainPolicy};
+  settings::current().domain == DomainPolicy::Complex
+}
+
+/// List the evaluation settings, or change one with "set NAME VALUE"
+fn change_setting(input: &str) {
+  use rust_calculator::settings;
+
+  let mut defaults = settings::get_default();
+  if input.is_empty() {
+    for (name, value) in defaults.entries() {
+      println!("  {} = {}", name, value);
+    }
+    return;
+  }
+
+  let Some((name, value)) = input.split_once(char::is_whitespace) else {
+    // A name on its own shows that setting
+    match defaults
+      .entries()
+      .into_iter()
+      .find(|(name, _)| name.eq_ignore_ascii_case(input))
+    {
+      Some((name, value)) => println!("{} = {}", name, value),
+      None => eprintln!("Usage: set NAME VALUE"),
+    }
+    return;
+  };
+  match defaults.set(name, value) {
+    Ok(()) => {
+      settings::set_default(defaults);
+      println!("{} = {}", name.to_lowercase(), value.trim().to_lowercase());
+    }
+    Err(err) => eprintln!("Error: {}", err),
+  }
+}
+
+/// Show where the configuration file is and what it sets
+fn show_config() {
+  #[cfg(feature = "config")]
+  {
+    use rust_calculator::config::Config;
+
+    match Config::path() {
+      Some(path) => println!("Configuration file: {}", path.display()),
+      None => println!("No configuration directory on this platform"),
+    }
+    match Config::load() {
+      Ok(config) => print!("{}", config.to_toml()),
+      Err(err) => eprintln!("Error loading configuration: {}", err),
+    }
+  }
+  #[cfg(not(feature = "config"))]
+  eprintln!("Configuration files are not enabled in this build (feature \"config\")");
+}
+
+/// Switch between infix and Reverse Polish Notation input
+fn set_notation(rpn_input: &mut bool, notation: &str) {
+  match notation.to_lowercase().as_str() {
+    "rpn" | "postfix" => *rpn_input = true,
+    "infix" => *rpn_input = false,
+    _ => {
+      eprintln!("Usage: notation infix|rpn");
+      return;
+    }
+  }
+  println!("notation = {}", if *rpn_input { "rpn" } else { "infix" });
+}
+
+/// Import variables from a JSON or .env file
+fn import_variables(path: &str) {
+  match rust_calculator::import_variables(path) {
+    Ok(imported) => {
+      if imported.is_empty() {
+        println!("No numeric variables found in {}", path);
+      } else {
+        println!("Imported {} variable(s):", imported.len());
+        for (name, value) in imported {
+          println!("  {} = {}", name, value);
+        }
+      }
+    }
+    Err(err) => eprintln!("Error importing variables: {}", err),
+  }
+}
+
 /// List all defined variables
 fn list_variables() {
   println!("Defined Variables:");
+  let formulas = rust_calculator::formulas::list();
   match rust_calculator::get_all_variables() {
     Ok(vars) => {
-      if vars.is_empty() {
+      if vars.is_empty() && formulas.is_empty() {
         println!("  No variables defined");
       } else {
         for (name, value) in vars {
-          println!("  {} = {}", name, value);
+          show_variable(&name, value);
         }
       }
     }
     Err(err) => eprintln!("Error retrieving variables: {}", err),
   }
+  // Formulas are shown with their last values
+  for formula in formulas {
+    match &formula.value {
+      Ok(value) => println!("  {} = {}", formula, value),
+      Err(err) => println!("  {} ({})", formula, err),
+    }
+  }
+}
+
+/// Show a variable in a listing, with its description if it has one
+fn show_variable(name: &str, value: f64) {
+  match rust_calculator::get_variable_info(name).and_then(|variable| variable.description) {
+    Some(description) => println!("  {} = {}  # {}", name, value, description),
+    None => println!("  {} = {}", name, value),
+  }
+}
+
+/// Describe what a variable is for with "describe varname text", or remove
+/// its description with "describe varname"
+fn describe_variable(input: &str) {
+  let (name, description) = input.split_once(' ').unwrap_or((input, ""));
+  match rust_calculator::describe_variable(name, description) {
+    Ok(()) if description.trim().is_empty() => println!("Description of {} removed", name),
+    Ok(()) => println!("Variable {} described", name),
+    Err(err) => eprintln!("Error: {}", err),
+  }
+}
+
+/// List the variables and formulas in a namespace, such as `physics` for
+/// `physics.c`
+fn list_namespace(namespace: &str) {
+  println!("Variables in {}:", namespace);
+  let formulas: Vec<_> = rust_calculator::formulas::list()
+    .into_iter()
+    .filter(|formula| rust_calculator::in_namespace(&formula.name, namespace))
+    .collect();
+  match rust_calculator::get_namespace(namespace) {
+    Ok(vars) => {
+      if vars.is_empty() && formulas.is_empty() {
+        println!("  No variables defined");
+      }
+      for (name, value) in vars {
+        show_variable(&name, value);
+      }
+    }
+    Err(err) => eprintln!("Error retrieving variables: {}", err),
+  }
+  for formula in formulas {
+    match &formula.value {
+      Ok(value) => println!("  {} = {}", formula, value),
+      Err(err) => println!("  {} ({})", formula, err),
+    }
+  }
+}
+
+/// Remove every variable and formula in a namespace
+fn clear_namespace(namespace: &str) {
+  match rust_calculator::clear_namespace(namespace) {
+    Ok(0) => eprintln!("Error: No variables in {}", namespace),
+    Ok(count) => println!("Removed {} variable(s) from {}", count, namespace),
+    Err(err) => eprintln!("Error removing variables: {}", err),
+  }
+}
+
+/// List the user-defined functions
+fn list_functions() {
+  println!("Defined Functions:");
+  let functions = rust_calculator::functions::list();
+  if functions.is_empty() {
+    println!("  No functions defined");
+  }
+  for function in functions {
+    println!("  {}", function);
+  }
+}
+
+/// List the constants expressions can use
+fn list_constants() {
+  println!("Constants:");
+  for (name, value) in rust_calculator::constants::list() {
+    println!("  {} = {}", name, rust_calculator::format_value(value));
+  }
 }
 
 /// Display help information
@@ -183,13 +881,49 @@ fn display_help() {
   println!("    * clear        - Clear the screen");
   println!("    * history      - Show calculation history");
   println!("    * clearhistory - Clear calculation history");
+  println!("    * mhistory     - Show the changes made to memory");
   println!("    * vars         - List all defined variables");
+  println!("    * vars import  - Import variables from a JSON or .env file");
+  println!("    * vars ns      - List the variables in a namespace, e.g. vars physics");
+  println!("    * vars clear ns - Remove the variables in a namespace");
+  println!("    * constants    - List the constants expressions can use");
   println!("    * let x = expr - Define a variable");
+  println!("    * let x := expr - Define a formula that follows the variables it uses");
+  println!("    * unset x      - Remove a variable");
+  println!("    * describe x text - Note what a variable is for, shown by vars");
+  println!("    * def f(x) = expr - Define a function, e.g. def f(x, y) = x^2 + y");
+  println!("    * functions    - List all defined functions");
+  println!("    * table        - Tabulate an expression over a range of values");
+  println!("    * rpn expr     - Show an expression in Reverse Polish Notation");
+  println!("    * infix rpn    - Convert Reverse Polish Notation back to infix");
+  println!("    * notation rpn - Type expressions in RPN, e.g. 3 4 + 2 * (notation infix to stop)");
+  println!("    * frac, dec    - Show results as exact fractions such as 1/2, or as decimals");
+  println!("    * ast expr     - Show the parsed expression tree as JSON");
+  println!("    * currency USD - Show results as money (currency off to stop)");
+  println!("    * config       - Show the configuration file and its settings");
+  println!("    * set          - List evaluation settings");
+  println!("    * set NAME     - Show one setting, e.g. set angle");
+  println!("    * set NAME VALUE - Change a setting, e.g. set rounding half-even");
+  #[cfg(feature = "clipboard")]
+  {
+    println!("    * copy         - Copy the last result to the clipboard");
+    println!("    * paste        - Evaluate the expression on the clipboard");
+  }
   println!();
   println!("  Operators (in order of precedence):");
+  println!("    * x% - Percent; 200 + 10% is 220");
   println!("    * ^  - Exponentiation (right associative)");
-  println!("    * *, /, % - Multiplication, division, modulo");
+  println!("    * *, /, % - Multiplication, division, modulo (with the sign of the dividend)");
   println!("    * +, - - Addition, subtraction");
+  println!("    * <<, >> - Shifts left and right");
+  println!("    * <, <=, >, >= - Comparisons, 1 if true and 0 if false");
+  println!("    * ==, != - Equal and not equal");
+  println!("    * and (&&), or (||) - Logical and and or, 1 if true and 0 if false");
+  println!("    * not x, !x - Logical not (binds like a function)");
+  println!("    * & - Bitwise and");
+  println!("    * xor - Bitwise exclusive or");
+  println!("    * | - Bitwise or");
+  println!("    * ~x - Bitwise not (binds like -x)");
   println!();
   println!("  Functions:");
   println!("    * sqrt(x)   - Square root");
@@ -202,16 +936,76 @@ fn display_help() {
   println!("    * log(x)    - Base-10 logarithm");
   println!("    * ln(x)     - Natural logarithm");
   println!("    * exp(x)    - e raised to the power of x");
-  println!("    * abs(x)    - Absolute value");
+  println!("    * cbrt(x)   - Cube root, negative for negative x");
+  println!("    * root(n, x) - The nth root of x, real for odd n and negative x");
+  println!("    * abs(x), |x| - Absolute value");
   println!("    * floor(x)  - Round down to nearest integer");
   println!("    * ceil(x)   - Round up to nearest integer");
+  println!("    * round(x)  - Round to nearest integer (see set rounding)");
+  println!("    * round(x, n) - Round to n decimal places");
+  println!("    * trunc(x)  - Drop the fractional part");
+  println!("    * frac(x)   - The fractional part, x - trunc(x)");
+  println!("    * sign(x)   - -1, 0 or 1 as x is negative, zero or positive");
+  println!("    * clamp(x, lo, hi) - x limited to the range lo to hi");
+  println!("    * if(cond, a, b) - a if cond isn't zero, otherwise b");
+  println!("    * mod(a, b) - Euclidean remainder, never negative: mod(-7, 3) is 2");
   println!("    * fact(x)   - Factorial (x must be non-negative integer)");
+  println!("    * erf(x), erfc(x) - Error function and its complement");
+  println!("    * normpdf(x), normcdf(x) - Standard normal density and cumulative probability");
+  println!("    * n nCr k, n nPr k - Combinations and permutations of k of n items");
+  println!("    * fib(n), lucas(n) - The nth Fibonacci and Lucas numbers");
+  println!("    * triangular(n), catalan(n) - The nth triangular and Catalan numbers");
+  println!("    * isprime(n) - 1 if n is prime, 0 if not");
+  println!("    * nextprime(n) - The smallest prime greater than n");
+  println!("    * factorize(n) - Prime factorization of n, e.g. 2^3 * 3^2 * 5");
+  println!("    * sum(i, a, b, expr) - Sum of expr for i from a to b: sum(i, 1, 100, i^2)");
+  println!("    * prod(i, a, b, expr) - Product of expr for i from a to b");
+  println!("    * [3, 5, 8] - A list of values");
+  println!(
+    "    * mean, median, mode, var, stdev, sum, count - Statistics of a list: mean([3, 5, 8])"
+  );
+  println!("    * min, max, range - Smallest and largest items of a list, and their difference");
+  println!("    * sort(list) - The list in ascending order");
+  println!("    * map(f, list), filter(f, list) - f of each item, or the items where f isn't 0");
+  println!("    * reduce(f, list, init) - The items combined by f(total, item), from init");
+  println!("    * list[n]   - The nth item of a list, counting from 1: sort([5, 2, 9])[1] is 2");
+  println!("    * \"2024-07-15\", date(y, m, d), today - Dates; date + 90d is 90 days later");
+  println!("    * days_between(a, b) - Days from date a to date b, also b - a");
+  println!("    * weekday(date) - The day of the week of a date");
+  println!("    * solvequad(a, b, c), solvecubic(a, b, c, d) - Real roots of a polynomial");
+  println!("    * solve(\"lhs = rhs\", x, guess) - Solve an equation for x near a guess");
+  println!("    * limit(expr, x, a) - Limit of expr as x approaches a, from one side with 1 or -1");
+  println!(
+    "    * minimize(expr, x, a, b), maximize(...) - The x between a and b giving the least or greatest expr"
+  );
+  println!("    * simplify(expr) - Fold constants and combine like terms, e.g. 2*x + 3*x");
+  println!(
+    "    * [[1, 2], [3, 4]] - Matrices, with +, -, *, transpose, det, inv and linsolve(a, b)"
+  );
+  println!("    * fv(rate, n, pv) - Value of pv after n periods of compound interest");
+  println!("    * pmt(rate, n, pv) - Payment per period paying off a loan of pv in n periods");
+  println!("    * npv(rate, [cashflows]) - Net present value, the first cash flow undiscounted");
+  println!("    * irr([cashflows]) - Internal rate of return of the cash flows");
+  println!(
+    "    * linreg(xs, ys) - Least-squares line y = a x + b as [a, b, r2], stored in a, b and r2"
+  );
+  println!("    * roman(x)  - Roman numeral for x (1 to 3999)");
+  println!("    * from_roman(\"XIV\") - Value of a Roman numeral");
+  println!("    * spell(x) - x in words, e.g. one thousand two hundred thirty-four");
+  println!("    * currency(x, \"USD\") - Format x as money");
+  println!("    * 45°30'15\" or 45d30m15s - An angle in degrees, minutes and seconds");
+  println!("    * to_dms(x) - Angle x in degrees, minutes and seconds");
+  println!("    * as_fraction(x, 1000) - Closest fraction with a denominator up to 1000");
+  println!("    * continued_fraction(x, 20) - Up to 20 terms of the continued fraction of x");
+  println!("    * 5.0 ± 0.1 or val(5.0, 0.1) - A value with an uncertainty (also +/-)");
   println!();
   println!("  Memory Functions:");
   println!("    * x m+      - Add x to memory");
   println!("    * x m-      - Subtract x from memory");
   println!("    * mr        - Recall memory value");
   println!("    * mc        - Clear memory");
+  println!("    * mundo     - Undo the last m+, m- or mc");
+  println!("    * ans       - The result of the last calculation");
   println!();
   println!("  Unit Conversions:");
   println!("    * x km_to_mi - Convert kilometers to miles");
@@ -225,9 +1019,48 @@ fn display_help() {
   println!("    * e   - The mathematical constant e (2.71828...)");
   println!("    * tau - 2π (6.28318...)");
   println!("    * phi - Golden ratio (1.61803...)");
+  println!("    * c, G, h, k_B, N_A, q_e - Physical constants (physics feature)");
+  println!("    * List: constants");
   println!();
   println!("  Variables:");
-  println!("    * Define: let varname = expression");
+  println!("    * Define: let varname = expression, or varname = expression");
+  println!("    * Formula: let varname := expression (recalculated when what it uses changes)");
   println!("    * Use: varname (directly in expressions)");
+  println!("    * Remove: unset varname");
+  println!("    * Describe: describe varname what it's for");
   println!("    * List: vars");
+  println!("    * Import: vars import file.json (or a KEY=VALUE .env file)");
+  println!("    * Namespaces: let project1.rate = 0.05, then vars project1 or vars clear project1");
+  println!();
+  println!("  Functions of your own:");
+  println!("    * Define: def f(x, y) = expression");
+  println!("    * Use: f(2, 3), like a built-in function");
+  println!("    * List: functions");
+  println!();
+  println!("  Spreadsheet Cells:");
+  println!("    * Define: cell A1 = expression (may reference other cells, e.g. A1 * B2)");
+  println!("    * Show: cell A1");
+  println!("    * Clear: cell A1 =");
+  println!("    * List: grid");
+  println!();
+  println!("  Tables:");
+  println!("    * table expr x from 0 to 10 step 0.5  - Evaluate expr for each x");
+  println!("    * Append --output csv for comma-separated output");
+  println!("    * plot expr x from 0 to 10 --out chart.svg  - Render the curve to SVG or PNG");
+  println!();
+  println!("  Settings (set NAME VALUE):");
+  println!("    * domain    - error, nan or complex results for sqrt(-1), log(0), ...");
+  println!("    * angle     - degrees, radians or gradians for trigonometric functions");
+  println!("    * overflow  - error or ieee (inf/NaN) for results too large to represent");
+  println!("    * nonfinite - strict (default), reject or propagate inf and nan");
+  println!("    * rounding  - half-up, half-even, floor or ceiling for round() and output");
+  println!("    * sigfigs   - show results to N significant figures, or off");
+  println!("    * decimals  - round every result to N decimal places, or off");
+  println!("    * integer   - evaluate in u8, u16, u32, u64, i8, i16, i32 or i64, or off");
+  println!("    * intoverflow - error or wrap for integer results out of range");
+  println!("    * locale    - plain, en, de, fr or ch separators in results");
+  println!("    * recursion - how deep user functions may call themselves, up to 1000");
+  println!(
+    "    * continue  - on to start from the last result when input begins with * 2, + 5, ..."
+  );
 }
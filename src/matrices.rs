@@ -0,0 +1,302 @@
+//! # Matrices
+//!
+//! A list of equally long lists of numbers, such as `[[1, 2], [3, 4]]`, is
+//! a matrix, one row per inner list. Matrices add and subtract entry by
+//! entry, and `*` multiplies two matrices, or a matrix and a number. The
+//! functions on them are:
+//!
+//! - `transpose(m)`, swapping rows and columns
+//! - `det(m)`, the determinant of a square matrix
+//! - `inv(m)`, the inverse of a square matrix
+//! - `linsolve(a, b)`, the solution `x` of the linear system `a x = b`, for
+//!   a list `b` of one value per row of `a`
+//!
+//! As with a list, brackets after a matrix pick a row, so `m[2][1]` is the
+//! first entry of the second row.
+//!
+//! ```
+//! use rust_calculator::{Value, evaluate, evaluate_expression};
+//!
+//! let identity = Value::from(vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+//! assert_eq!(evaluate("[[1, 2], [3, 4]] * [[1, 0], [0, 1]]").unwrap(), evaluate("[[1, 2], [3, 4]]").unwrap());
+//! assert_eq!(evaluate("[[2, 0], [0, 2]] * inv([[2, 0], [0, 2]])").unwrap(), identity);
+//! assert_eq!(evaluate_expression("det([[1, 2], [3, 4]])").unwrap(), -2.0);
+//! assert_eq!(evaluate("linsolve([[2, 1], [1, 3]], [3, 5])").unwrap(), Value::from(vec![0.8, 1.4]));
+//! ```
+
+use crate::prelude::*;
+use crate::{CalculatorError, Value};
+
+/// Checks whether `name` is a function taking matrices
+pub(crate) fn is_matrix_function(name: &str) -> bool {
+  name == "det" || returns_matrix(name)
+}
+
+/// Checks whether `name` is a function whose result is a matrix, or the
+/// list solving a linear system
+pub(crate) fn returns_matrix(name: &str) -> bool {
+  matches!(name, "transpose" | "inv" | "linsolve")
+}
+
+/// Makes the items of a list literal a matrix if they're equally long lists
+/// of numbers, and leaves them a list otherwise
+pub(crate) fn from_items(items: Vec<Value>) -> Value {
+  let rows: Option<Vec<Vec<f64>>> = items
+    .iter()
+    .map(|item| match item {
+      Value::List(row) => row.iter().map(Value::as_number).collect(),
+      _ => None,
+    })
+    .collect();
+  match rows {
+    Some(rows)
+      if !rows.is_empty()
+        && rows
+          .iter()
+          .all(|row| !row.is_empty() && row.len() == rows[0].len()) =>
+    {
+      Value::Matrix(rows)
+    }
+    _ => Value::List(items),
+  }
+}
+
+/// The matrix product of `a` and `b`, or `None` unless `a` has as many
+/// columns as `b` has rows
+pub fn multiply(a: &[Vec<f64>], b: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+  if a[0].len() != b.len() {
+    return None;
+  }
+  let product = a
+    .iter()
+    .map(|row| {
+      (0..b[0].len())
+        .map(|column| row.iter().zip(b).map(|(x, b_row)| x * b_row[column]).sum())
+        .collect()
+    })
+    .collect();
+  Some(product)
+}
+
+/// The matrix with the rows of `m` as its columns
+pub fn transpose(m: &[Vec<f64>]) -> Vec<Vec<f64>> {
+  (0..m[0].len())
+    .map(|column| m.iter().map(|row| row[column]).collect())
+    .collect()
+}
+
+/// The determinant of a square matrix, by fraction-free elimination, which
+/// keeps it exact for matrices of small whole numbers
+pub fn determinant(m: &[Vec<f64>]) -> f64 {
+  let n = m.len();
+  let mut m = m.to_vec();
+  let mut sign = 1.0;
+  let mut previous = 1.0;
+  for k in 0..n.saturating_sub(1) {
+    if m[k][k] == 0.0 {
+      let Some(swap) = (k + 1..n).find(|&row| m[row][k] != 0.0) else {
+        return 0.0;
+      };
+      m.swap(k, swap);
+      sign = -sign;
+    }
+    for i in k + 1..n {
+      for j in k + 1..n {
+        m[i][j] = (m[i][j] * m[k][k] - m[i][k] * m[k][j]) / previous;
+      }
+    }
+    previous = m[k][k];
+  }
+  sign * m[n - 1][n - 1]
+}
+
+/// The inverse of a square matrix, or `None` if it's singular
+pub fn inverse(m: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+  let n = m.len();
+  let identity = (0..n)
+    .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+    .collect::<Vec<_>>();
+  eliminate(m, identity)
+}
+
+/// The solution `x` of `a x = b` for a square matrix `a`, or `None` if `a`
+/// is singular
+pub fn solve(a: &[Vec<f64>], b: &[f64]) -> Option<Vec<f64>> {
+  let columns = b.iter().map(|value| vec![*value]).collect();
+  let solution = eliminate(a, columns)?;
+  Some(solution.into_iter().map(|row| row[0]).collect())
+}
+
+/// Gauss-Jordan elimination with partial pivoting, reducing `a` to the
+/// identity while applying the same steps to `b`, which ends up as `a⁻¹ b`
+fn eliminate(a: &[Vec<f64>], mut b: Vec<Vec<f64>>) -> Option<Vec<Vec<f64>>> {
+  let n = a.len();
+  let mut a = a.to_vec();
+  let scale = a
+    .iter()
+    .flatten()
+    .fold(0.0, |largest: f64, x| largest.max(x.abs()));
+  for k in 0..n {
+    let pivot = (k..n).max_by(|&i, &j| a[i][k].abs().total_cmp(&a[j][k].abs()))?;
+    // A pivot this small next to the other entries is rounding of a 0
+    if a[pivot][k].abs() <= scale * n as f64 * f64::EPSILON {
+      return None;
+    }
+    a.swap(k, pivot);
+    b.swap(k, pivot);
+    let divisor = a[k][k];
+    a[k].iter_mut().for_each(|x| *x /= divisor);
+    b[k].iter_mut().for_each(|x| *x /= divisor);
+    let (pivot_a, pivot_b) = (a[k].clone(), b[k].clone());
+    for i in (0..n).filter(|&i| i != k) {
+      let factor = a[i][k];
+      if factor == 0.0 {
+        continue;
+      }
+      for (x, pivot) in a[i].iter_mut().zip(&pivot_a) {
+        *x -= factor * pivot;
+      }
+      for (x, pivot) in b[i].iter_mut().zip(&pivot_b) {
+        *x -= factor * pivot;
+      }
+    }
+  }
+  Some(b)
+}
+
+/// Applies `+`, `-` or `*` to values of which at least one is a matrix
+pub(crate) fn binary(left: Value, operator: &str, right: Value) -> Result<Value, CalculatorError> {
+  let result = match (left, operator, right) {
+    (Value::Matrix(a), "*", Value::Matrix(b)) => multiply(&a, &b).ok_or_else(|| {
+      CalculatorError::ArgumentError(format!(
+        "Cannot multiply a {} matrix by a {} matrix",
+        size(&a),
+        size(&b)
+      ))
+    })?,
+    (Value::Matrix(m), "*", Value::Number(k)) | (Value::Number(k), "*", Value::Matrix(m)) => {
+      map(&m, |x| x * k)
+    }
+    (Value::Matrix(a), "+" | "-", Value::Matrix(b)) => {
+      if size(&a) != size(&b) {
+        return Err(CalculatorError::ArgumentError(format!(
+          "Cannot {} a {} matrix and a {} matrix",
+          if operator == "+" { "add" } else { "subtract" },
+          size(&a),
+          size(&b)
+        )));
+      }
+      let sign = if operator == "+" { 1.0 } else { -1.0 };
+      a.iter()
+        .zip(&b)
+        .map(|(a, b)| a.iter().zip(b).map(|(x, y)| x + sign * y).collect())
+        .collect()
+    }
+    (left, operator, right) => {
+      return Err(CalculatorError::ArgumentError(format!(
+        "Cannot apply {} to a {} and a {}",
+        operator,
+        left.type_name(),
+        right.type_name()
+      )));
+    }
+  };
+  check_entries(&result)?;
+  Ok(Value::Matrix(result))
+}
+
+/// Applies `transpose`, `inv` or `linsolve` to the values of its arguments
+pub(crate) fn apply(function: &str, arguments: Vec<Value>) -> Result<Value, CalculatorError> {
+  crate::check_arity(function, arguments.len())?;
+  let mut arguments = arguments.into_iter();
+  let m = matrix(function, arguments.next())?;
+  match function {
+    "transpose" => Ok(Value::Matrix(transpose(&m))),
+    "inv" => {
+      square(function, &m)?;
+      let inverse = inverse(&m).ok_or_else(singular)?;
+      check_entries(&inverse)?;
+      Ok(Value::Matrix(inverse))
+    }
+    _ => {
+      square(function, &m)?;
+      let b = match arguments.next() {
+        Some(Value::List(items)) => items
+          .into_iter()
+          .map(Value::into_number)
+          .collect::<Result<Vec<f64>, CalculatorError>>()?,
+        other => {
+          return Err(CalculatorError::ArgumentError(format!(
+            "{} needs a list of one value per row, got a {}",
+            function,
+            other.map_or("nothing", |value| value.type_name())
+          )));
+        }
+      };
+      if b.len() != m.len() {
+        return Err(CalculatorError::ArgumentError(format!(
+          "{} needs a list of {} values, one per row, got {}",
+          function,
+          m.len(),
+          b.len()
+        )));
+      }
+      let x = solve(&m, &b).ok_or_else(singular)?;
+      check_entries(core::slice::from_ref(&x))?;
+      Ok(Value::from(x))
+    }
+  }
+}
+
+/// Evaluates `det` of the value of its argument
+pub(crate) fn det(value: Value) -> Result<f64, CalculatorError> {
+  let m = matrix("det", Some(value))?;
+  square("det", &m)?;
+  crate::check_overflow(determinant(&m), true)
+}
+
+/// The matrix passed to `function`
+fn matrix(function: &str, value: Option<Value>) -> Result<Vec<Vec<f64>>, CalculatorError> {
+  match value {
+    Some(Value::Matrix(rows)) => Ok(rows),
+    other => Err(CalculatorError::ArgumentError(format!(
+      "{} needs a matrix, got a {}",
+      function,
+      other.map_or("nothing", |value| value.type_name())
+    ))),
+  }
+}
+
+/// Checks that the matrix passed to `function` is square
+fn square(function: &str, m: &[Vec<f64>]) -> Result<(), CalculatorError> {
+  if m.len() != m[0].len() {
+    return Err(CalculatorError::ArgumentError(format!(
+      "{} needs a square matrix, got a {} matrix",
+      function,
+      size(m)
+    )));
+  }
+  Ok(())
+}
+
+/// Checks that every entry of a result is finite
+fn check_entries(m: &[Vec<f64>]) -> Result<(), CalculatorError> {
+  m.iter()
+    .flatten()
+    .try_for_each(|x| crate::check_overflow(*x, true).map(|_| ()))
+}
+
+fn map(m: &[Vec<f64>], f: impl Fn(f64) -> f64) -> Vec<Vec<f64>> {
+  m.iter()
+    .map(|row| row.iter().map(|x| f(*x)).collect())
+    .collect()
+}
+
+/// The size of a matrix, such as `2x3` for 2 rows of 3 entries
+fn size(m: &[Vec<f64>]) -> String {
+  format!("{}x{}", m.len(), m[0].len())
+}
+
+fn singular() -> CalculatorError {
+  CalculatorError::MathError("The matrix is singular".to_string())
+}
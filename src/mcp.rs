@@ -0,0 +1,227 @@
+//! # Model Context Protocol Server
+//!
+//! Exposes the calculator as a Model Context Protocol (MCP) tool server over
+//! stdio, so AI assistants can delegate arithmetic to it instead of guessing.
+//! MCP uses the same line-delimited JSON-RPC 2.0 framing as the
+//! [`jsonrpc`](crate::jsonrpc) module.
+//!
+//! ## Tools
+//!
+//! - `evaluate` - evaluate a mathematical expression
+//! - `convert_units` - convert a value between supported units
+//! - `define_variable` - store the result of an expression in a variable
+
+use crate::jsonrpc::{self, INVALID_PARAMS, METHOD_NOT_FOUND, RpcError};
+use serde_json::{Value, json};
+use std::io::{self, BufRead, Write};
+
+/// Protocol revision implemented by this server
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// An MCP session over a single client connection
+#[derive(Debug, Default)]
+pub struct Session {
+  initialized: bool,
+}
+
+impl Session {
+  /// Creates a new, uninitialized session
+  pub fn new() -> Self {
+    Session::default()
+  }
+
+  /// Handles a single line of input and returns the lines to write back
+  pub fn handle_line(&mut self, line: &str) -> Vec<String> {
+    let request = match jsonrpc::parse_request(line) {
+      Ok(request) => request,
+      Err(response) => return vec![response],
+    };
+
+    let result = self.dispatch(&request.method, &request.params);
+
+    // Notifications such as `notifications/initialized` never get a reply
+    match request.id {
+      Some(id) => vec![jsonrpc::response(id, &result)],
+      None => Vec::new(),
+    }
+  }
+
+  /// Routes a method call to its handler
+  fn dispatch(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+      "initialize" => {
+        self.initialized = true;
+        Ok(json!({
+          "protocolVersion": PROTOCOL_VERSION,
+          "capabilities": { "tools": {} },
+          "serverInfo": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+          },
+        }))
+      }
+      "notifications/initialized" | "ping" => Ok(json!({})),
+      "tools/list" => Ok(json!({ "tools": tool_definitions() })),
+      "tools/call" => call_tool(params),
+      _ => Err(RpcError::new(
+        METHOD_NOT_FOUND,
+        format!("Method not found: {}", method),
+      )),
+    }
+  }
+}
+
+/// Runs an MCP session, reading requests from `reader` until EOF
+pub fn serve<R: BufRead, W: Write>(reader: R, writer: W) -> io::Result<()> {
+  let mut session = Session::new();
+  jsonrpc::serve_lines(reader, writer, |line| session.handle_line(line))
+}
+
+/// Describes the tools offered by this server, including their input schemas
+fn tool_definitions() -> Value {
+  json!([
+    {
+      "name": "evaluate",
+      "description": "Evaluate a mathematical expression exactly as the calculator would. \
+        The result is a number, a list such as [1, 2, 3], a matrix such as [[1, 2], [3, 4]], \
+        a date such as 2024-10-13, or text. \
+        Supports + - * / % ^, comparisons (== != < <= > >=), logic (and or not), parentheses, \
+        functions (sqrt, cbrt, root, sin, cos, tan, asin, acos, atan, log, ln, exp, abs, floor, \
+        ceil, round, trunc, frac, sign, clamp, if, mod, fact, erf, erfc, normpdf, normcdf, fib, \
+        lucas, triangular, catalan, isprime, nextprime), sums and products over ranges \
+        (sum(i, 1, 10, i^2), prod(k, 1, 5, k)), lists ([3, 5, 8]) with statistics (mean, \
+        median, mode, var, stdev, sum, count, min, max, range), sort(list), map(f, list), \
+        filter(f, list), reduce(f, list, init), list[n] for the nth item, linreg(xs, ys) \
+        giving [slope, intercept, r2], finance (fv(rate, nper, pv), pmt(rate, nper, pv), \
+        npv(rate, [cashflows]), irr([cashflows])), dates (\"2024-07-15\" in quotes, today, \
+        date + 90d, days_between(a, b), weekday(date)), polynomial roots (solvequad(a, b, c), \
+        solvecubic(a, b, c, d)), equations (solve(\"sin(x) = x/2\", x, guess)), \
+        limit(expr, x, a), minimize(expr, x, a, b) and maximize(expr, x, a, b), \
+        simplify(expr), spell(x) in words, matrices ([[1, 2], [3, 4]] with +, -, *, \
+        transpose, det, inv, linsolve(a, b)), nCr and nPr, constants (pi, e, tau, phi) and \
+        user variables. Large integer results such as fact(30) are given exactly. \
+        Trigonometric functions use degrees.",
+      "inputSchema": {
+        "type": "object",
+        "properties": {
+          "expression": { "type": "string", "description": "Expression such as '2 + 3 * 4'" },
+        },
+        "required": ["expression"],
+      },
+    },
+    {
+      "name": "convert_units",
+      "description": "Convert a value between units. Supported pairs: km/mi, kg/lb, c/f, \
+        rad/deg, in/cm, gal/l.",
+      "inputSchema": {
+        "type": "object",
+        "properties": {
+          "value": { "type": "number" },
+          "from": { "type": "string", "description": "Source unit, e.g. 'km'" },
+          "to": { "type": "string", "description": "Target unit, e.g. 'mi'" },
+        },
+        "required": ["value", "from", "to"],
+      },
+    },
+    {
+      "name": "define_variable",
+      "description": "Evaluate an expression and store the result in a named variable that \
+        later expressions can use.",
+      "inputSchema": {
+        "type": "object",
+        "properties": {
+          "name": { "type": "string" },
+          "expression": { "type": "string" },
+        },
+        "required": ["name", "expression"],
+      },
+    },
+  ])
+}
+
+/// Executes a `tools/call` request.
+///
+/// Failures inside a tool are reported as a successful response with `isError`
+/// set, as the protocol requires; only malformed calls become JSON-RPC errors.
+fn call_tool(params: &Value) -> Result<Value, RpcError> {
+  let name = params
+    .get("name")
+    .and_then(Value::as_str)
+    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing tool name"))?;
+  let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+
+  let outcome = match name {
+    "evaluate" => evaluate(&arguments)?,
+    "convert_units" => convert_units(&arguments)?,
+    "define_variable" => define_variable(&arguments)?,
+    _ => {
+      return Err(RpcError::new(
+        INVALID_PARAMS,
+        format!("Unknown tool: {}", name),
+      ));
+    }
+  };
+
+  Ok(match outcome {
+    Ok(text) => json!({ "content": [{ "type": "text", "text": text }], "isError": false }),
+    Err(text) => json!({ "content": [{ "type": "text", "text": text }], "isError": true }),
+  })
+}
+
+/// Reads a required string argument
+fn string_argument<'a>(arguments: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+  arguments.get(name).and_then(Value::as_str).ok_or_else(|| {
+    RpcError::new(
+      INVALID_PARAMS,
+      format!("Missing string argument '{}'", name),
+    )
+  })
+}
+
+fn evaluate(arguments: &Value) -> Result<Result<String, String>, RpcError> {
+  let expression = string_argument(arguments, "expression")?;
+  Ok(crate::metrics::evaluate_to_string(expression).map_err(|err| err.to_string()))
+}
+
+fn convert_units(arguments: &Value) -> Result<Result<String, String>, RpcError> {
+  let value = arguments
+    .get("value")
+    .and_then(Value::as_f64)
+    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing numeric argument 'value'"))?;
+  let from = string_argument(arguments, "from")?.to_lowercase();
+  let to = string_argument(arguments, "to")?.to_lowercase();
+
+  let conversion = format!("{}_to_{}", from, to);
+  if !crate::is_valid_conversion(&conversion) {
+    return Ok(Err(format!(
+      "Unsupported conversion from '{}' to '{}'",
+      from, to
+    )));
+  }
+
+  Ok(
+    crate::evaluate_function(&conversion, value, &crate::get_variable)
+      .map(|result| format!("{} {} = {} {}", value, from, result, to))
+      .map_err(|err| err.to_string()),
+  )
+}
+
+fn define_variable(arguments: &Value) -> Result<Result<String, String>, RpcError> {
+  let name = string_argument(arguments, "name")?;
+  let expression = string_argument(arguments, "expression")?;
+  // Saving would leave out a variable whose name isn't an identifier
+  if !crate::is_identifier(name) {
+    return Ok(Err(format!("Invalid variable name: {}", name)));
+  }
+
+  let value = match crate::metrics::evaluate(expression) {
+    Ok(value) => value,
+    Err(err) => return Ok(Err(err.to_string())),
+  };
+
+  Ok(
+    crate::set_variable(name, value)
+      .map(|_| format!("{} = {}", name.to_lowercase(), value))
+      .map_err(|err| err.to_string()),
+  )
+}
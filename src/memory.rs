@@ -0,0 +1,191 @@
+//! # Memory Register
+//!
+//! `x m+`, `x m-` and `mc` change the memory, and each change is recorded
+//! with its operand and the value the memory had before, so `mundo` can put
+//! the memory back as it was before the last change, and before the one
+//! ahead of that if used again. `mr` only reads the memory and isn't
+//! recorded. The last [`MAX_CHANGES`] changes are kept.
+//!
+//! The global memory used by the free functions is saved along with its
+//! changes with the `persistence` feature, so `mundo` still works after a
+//! restart. A [`Calculator`](crate::Calculator) session and an
+//! [`EvalContext`](crate::EvalContext) each have a register of their own.
+//!
+//! ```
+//! use rust_calculator::Calculator;
+//! use rust_calculator::memory::MemoryOperation;
+//!
+//! let mut calc = Calculator::new();
+//! calc.evaluate("8 m+").unwrap();
+//! calc.evaluate("mc").unwrap();
+//! assert_eq!(calc.memory_changes()[1].operation, MemoryOperation::Clear);
+//! assert_eq!(calc.evaluate("mundo").unwrap(), 8.0);
+//! assert_eq!(calc.memory(), 8.0);
+//! ```
+
+use crate::{CalculatorError, storage};
+use std::fmt;
+use std::sync::RwLock;
+
+/// The most changes a register keeps for `mundo`
+pub const MAX_CHANGES: usize = 100;
+
+/// A change made to the memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryOperation {
+  /// `m+`
+  Add,
+  /// `m-`
+  Subtract,
+  /// `mc`
+  Clear,
+}
+
+impl MemoryOperation {
+  /// The function that makes the change, such as `m+`
+  pub fn name(self) -> &'static str {
+    match self {
+      MemoryOperation::Add => "m+",
+      MemoryOperation::Subtract => "m-",
+      MemoryOperation::Clear => "mc",
+    }
+  }
+
+  /// The operation made by the function `name`
+  #[cfg(feature = "persistence")]
+  pub(crate) fn from_name(name: &str) -> Option<MemoryOperation> {
+    [
+      MemoryOperation::Add,
+      MemoryOperation::Subtract,
+      MemoryOperation::Clear,
+    ]
+    .into_iter()
+    .find(|operation| operation.name() == name)
+  }
+}
+
+impl fmt::Display for MemoryOperation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+/// A recorded change to the memory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryChange {
+  pub operation: MemoryOperation,
+  /// The number added or subtracted, which `mc` doesn't have
+  pub operand: Option<f64>,
+  /// The value in memory before the change
+  pub previous: f64,
+}
+
+/// A memory register and the changes made to it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Memory {
+  pub(crate) value: f64,
+  /// The changes, oldest first
+  pub(crate) changes: Vec<MemoryChange>,
+}
+
+impl Memory {
+  /// An empty register
+  pub const fn new() -> Memory {
+    Memory {
+      value: 0.0,
+      changes: Vec::new(),
+    }
+  }
+
+  /// The value in memory
+  pub fn value(&self) -> f64 {
+    self.value
+  }
+
+  /// The recorded changes, oldest first
+  pub fn changes(&self) -> &[MemoryChange] {
+    &self.changes
+  }
+
+  /// Adds `operand` to the memory, as `m+` does, and returns the new value
+  pub fn add(&mut self, operand: f64) -> f64 {
+    self.change(MemoryOperation::Add, Some(operand), self.value + operand)
+  }
+
+  /// Subtracts `operand` from the memory, as `m-` does, and returns the new
+  /// value
+  pub fn subtract(&mut self, operand: f64) -> f64 {
+    self.change(
+      MemoryOperation::Subtract,
+      Some(operand),
+      self.value - operand,
+    )
+  }
+
+  /// Resets the memory to zero, as `mc` does
+  pub fn clear(&mut self) -> f64 {
+    self.change(MemoryOperation::Clear, None, 0.0)
+  }
+
+  /// Reverts the last change, as `mundo` does, and returns it, or `None` if
+  /// there's no change left to undo
+  pub fn undo(&mut self) -> Option<MemoryChange> {
+    let change = self.changes.pop()?;
+    self.value = change.previous;
+    Some(change)
+  }
+
+  fn change(&mut self, operation: MemoryOperation, operand: Option<f64>, value: f64) -> f64 {
+    if self.changes.len() == MAX_CHANGES {
+      self.changes.remove(0);
+    }
+    self.changes.push(MemoryChange {
+      operation,
+      operand,
+      previous: self.value,
+    });
+    self.value = value;
+    value
+  }
+}
+
+/// The memory used outside of [`Calculator`](crate::Calculator) sessions
+static MEMORY: RwLock<Memory> = RwLock::new(Memory::new());
+
+/// The changes made to the global memory, oldest first
+pub fn changes() -> Vec<MemoryChange> {
+  MEMORY.read().unwrap().changes.clone()
+}
+
+/// A copy of the global memory
+pub(crate) fn current() -> Memory {
+  MEMORY.read().unwrap().clone()
+}
+
+/// Applies `change` to the global memory, saving the memory if it changed
+pub(crate) fn update(
+  change: impl FnOnce(&mut Memory) -> Result<f64, CalculatorError>,
+) -> Result<f64, CalculatorError> {
+  let mut memory = MEMORY.write().unwrap();
+  let mut changed = memory.clone();
+  let value = change(&mut changed)?;
+  if changed != *memory {
+    storage::save_memory(&changed)
+      .map_err(|err| CalculatorError::Other(format!("Error saving memory: {}", err)))?;
+    *memory = changed;
+  }
+  Ok(value)
+}
+
+/// Loads the global memory saved by an earlier session, if there is one
+pub(crate) fn load() -> std::io::Result<()> {
+  if let Some(memory) = storage::read_memory()? {
+    *MEMORY.write().unwrap() = memory;
+  }
+  Ok(())
+}
+
+/// The error for `mundo` with nothing to undo
+pub(crate) fn nothing_to_undo() -> CalculatorError {
+  CalculatorError::ArgumentError("There is no memory change to undo".to_string())
+}
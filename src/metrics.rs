@@ -0,0 +1,179 @@
+//! # Metrics
+//!
+//! Prometheus-style metrics for the server modes (JSON-RPC, MCP, daemon,
+//! WebSocket, D-Bus), so operators can monitor a running service:
+//!
+//! - `calculator_evaluations_total` - evaluations served (use `rate()` for per-second)
+//! - `calculator_errors_total{kind="..."}` - failed evaluations by error type
+//! - `calculator_evaluation_duration_seconds` - latency histogram
+//!
+//! [`serve`] exposes them over HTTP at `/metrics` in the text exposition format.
+
+use crate::CalculatorError;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency histogram buckets, in seconds
+const BUCKETS: [f64; 9] = [
+  0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1,
+];
+
+/// Error kinds, in the order their counters are stored
+const ERROR_KINDS: [&str; 8] = [
+  "parse", "math", "syntax", "argument", "overflow", "limit", "reserved", "other",
+];
+
+/// Counters collected since the process started
+struct Metrics {
+  evaluations: u64,
+  errors: [u64; ERROR_KINDS.len()],
+  buckets: [u64; BUCKETS.len()],
+  duration_sum: f64,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+  evaluations: 0,
+  errors: [0; ERROR_KINDS.len()],
+  buckets: [0; BUCKETS.len()],
+  duration_sum: 0.0,
+});
+
+/// Evaluates an expression, recording its latency and outcome
+pub fn evaluate(expression: &str) -> Result<f64, CalculatorError> {
+  let start = Instant::now();
+  let result = crate::evaluate_expression(expression);
+  record(start.elapsed(), &result);
+  result
+}
+
+/// Evaluates an expression whose result may be text, as
+/// [`evaluate_to_string`](crate::evaluate_to_string) does, recording its
+/// latency and outcome
+pub fn evaluate_to_string(expression: &str) -> Result<String, CalculatorError> {
+  let start = Instant::now();
+  let result = crate::evaluate_to_string(expression);
+  record(start.elapsed(), &result);
+  result
+}
+
+/// Records one evaluation that took `duration`
+pub fn record<T>(duration: Duration, result: &Result<T, CalculatorError>) {
+  let seconds = duration.as_secs_f64();
+  let mut metrics = METRICS.lock().unwrap();
+
+  metrics.evaluations += 1;
+  metrics.duration_sum += seconds;
+  for (bucket, bound) in metrics.buckets.iter_mut().zip(BUCKETS) {
+    if seconds <= bound {
+      *bucket += 1;
+    }
+  }
+  if let Err(err) = result {
+    metrics.errors[error_kind(err)] += 1;
+  }
+}
+
+/// Renders all metrics in the Prometheus text exposition format
+pub fn render() -> String {
+  let metrics = METRICS.lock().unwrap();
+  let mut text = String::new();
+
+  // Writing to a String can't fail
+  let _ = writeln!(
+    text,
+    "# HELP calculator_evaluations_total Total number of evaluations.\n\
+     # TYPE calculator_evaluations_total counter\n\
+     calculator_evaluations_total {}",
+    metrics.evaluations
+  );
+
+  let _ = writeln!(
+    text,
+    "# HELP calculator_errors_total Failed evaluations by error type.\n\
+     # TYPE calculator_errors_total counter"
+  );
+  for (kind, count) in ERROR_KINDS.iter().zip(metrics.errors) {
+    let _ = writeln!(
+      text,
+      "calculator_errors_total{{kind=\"{}\"}} {}",
+      kind, count
+    );
+  }
+
+  let _ = writeln!(
+    text,
+    "# HELP calculator_evaluation_duration_seconds Time spent evaluating expressions.\n\
+     # TYPE calculator_evaluation_duration_seconds histogram"
+  );
+  for (bound, count) in BUCKETS.iter().zip(metrics.buckets) {
+    let _ = writeln!(
+      text,
+      "calculator_evaluation_duration_seconds_bucket{{le=\"{}\"}} {}",
+      bound, count
+    );
+  }
+  let _ = writeln!(
+    text,
+    "calculator_evaluation_duration_seconds_bucket{{le=\"+Inf\"}} {}\n\
+     calculator_evaluation_duration_seconds_sum {}\n\
+     calculator_evaluation_duration_seconds_count {}",
+    metrics.evaluations, metrics.duration_sum, metrics.evaluations
+  );
+
+  text
+}
+
+/// Starts an HTTP server on `addr` in a background thread that serves
+/// [`render`] at `/metrics`
+pub fn serve<A: ToSocketAddrs>(addr: A) -> io::Result<()> {
+  let listener = TcpListener::bind(addr)?;
+  thread::spawn(move || {
+    for stream in listener.incoming().flatten() {
+      if let Err(err) = handle_request(stream) {
+        eprintln!("Metrics request error: {}", err);
+      }
+    }
+  });
+  Ok(())
+}
+
+/// Answers a single HTTP request
+fn handle_request(mut stream: TcpStream) -> io::Result<()> {
+  let mut request_line = String::new();
+  BufReader::new(&stream).read_line(&mut request_line)?;
+
+  let path = request_line.split_whitespace().nth(1).unwrap_or("");
+  let (status, body) = if path == "/metrics" {
+    ("200 OK", render())
+  } else {
+    ("404 Not Found", "Not found\n".to_string())
+  };
+
+  write!(
+    stream,
+    "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status,
+    body.len(),
+    body
+  )?;
+  stream.flush()
+}
+
+/// Index of the error's counter in [`ERROR_KINDS`]
+fn error_kind(err: &CalculatorError) -> usize {
+  match err {
+    CalculatorError::ParseError(_) => 0,
+    CalculatorError::MathError(_) => 1,
+    CalculatorError::SyntaxError(_) => 2,
+    CalculatorError::ArgumentError(_) => 3,
+    CalculatorError::Overflow(_) => 4,
+    CalculatorError::LimitExceeded(_) => 5,
+    CalculatorError::ReservedName(_) => 6,
+    CalculatorError::Other(_) => 7,
+    CalculatorError::Spanned { error, .. } => error_kind(error),
+  }
+}
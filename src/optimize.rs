@@ -0,0 +1,143 @@
+//! # Optimization
+//!
+//! `minimize(expr, x, a, b)` finds the value of `x` between `a` and `b` at
+//! which `expr` is smallest, and `maximize(expr, x, a, b)` the one at which
+//! it's largest. As with [`solve`](crate::solver), `x` hides any stored
+//! variable of that name, and the answer is the value of `x`, not of `expr`.
+//!
+//! The search is a golden-section search, which narrows the interval down
+//! around a single smallest value. If `expr` goes down and up more than
+//! once between `a` and `b`, the one found may be a local minimum rather
+//! than the smallest. The ends of the interval count too, so a function
+//! that only falls gives `b`. Golden-section search can't pin a minimum
+//! down closer than about seven significant digits, so the answer is the
+//! roundest number that close that's at least as good.
+//!
+//! ```
+//! use rust_calculator::evaluate_expression;
+//!
+//! assert_eq!(evaluate_expression("minimize(x^2 - 4*x, x, 0, 5)").unwrap(), 2.0);
+//! assert_eq!(evaluate_expression("maximize(x * (10 - x), x, 0, 10)").unwrap(), 5.0);
+//! assert_eq!(evaluate_expression("minimize(x, x, -1, 1)").unwrap(), -1.0);
+//! ```
+
+use crate::ast::Expr;
+use crate::prelude::*;
+use crate::{CalculatorError, Resolver, limits};
+
+/// The most steps the search takes to narrow down the interval
+pub const MAX_ITERATIONS: usize = 200;
+
+/// The fraction of the interval kept by each step, `1 / φ`
+const RATIO: f64 = 0.618_033_988_749_894_9;
+
+/// Evaluates a call of `minimize` or `maximize` with `arguments` already
+/// split at their commas
+pub(crate) fn evaluate_call(
+  name: &str,
+  arguments: &[&Expr],
+  resolve: Resolver,
+) -> Result<f64, CalculatorError> {
+  crate::check_arity(name, arguments.len())?;
+  let [expr, variable, a, b] = arguments else {
+    unreachable!("check_arity allows only 4 arguments");
+  };
+  let Expr::Variable(variable) = variable else {
+    return Err(CalculatorError::ArgumentError(format!(
+      "{} needs a variable name as its second argument",
+      name
+    )));
+  };
+  let (a, b) = (a.evaluate_with(resolve)?, b.evaluate_with(resolve)?);
+  if !a.is_finite() || !b.is_finite() {
+    return Err(CalculatorError::ArgumentError(format!(
+      "{} needs a finite interval",
+      name
+    )));
+  }
+  // Maximizing is minimizing the negated expression
+  let sign = if name == "maximize" { -1.0 } else { 1.0 };
+  let f = |x: f64| {
+    let binding = |name: &str| {
+      if name == variable {
+        Some(x)
+      } else {
+        resolve(name)
+      }
+    };
+    Ok(sign * expr.evaluate_with(&binding)?)
+  };
+  minimum(&f, a.min(b), a.max(b))
+}
+
+/// Finds where `f` is smallest between `low` and `high`
+fn minimum(
+  f: &dyn Fn(f64) -> Result<f64, CalculatorError>,
+  mut low: f64,
+  mut high: f64,
+) -> Result<f64, CalculatorError> {
+  let (start, end) = (low, high);
+  let mut left = high - RATIO * (high - low);
+  let mut right = low + RATIO * (high - low);
+  let (mut at_left, mut at_right) = (f(left)?, f(right)?);
+  for _ in 0..MAX_ITERATIONS {
+    limits::check_time()?;
+    if high - low <= 1e-12 * low.abs().max(high.abs()).max(1.0) {
+      break;
+    }
+    // Keep the part of the interval around the smaller of the two values,
+    // which holds on to one of them for the next step
+    if at_left <= at_right {
+      high = right;
+      (right, at_right) = (left, at_left);
+      left = high - RATIO * (high - low);
+      at_left = f(left)?;
+    } else {
+      low = left;
+      (left, at_left) = (right, at_right);
+      right = low + RATIO * (high - low);
+      at_right = f(right)?;
+    }
+  }
+  let (best, at_best) = if at_left <= at_right {
+    (left, at_left)
+  } else {
+    (right, at_right)
+  };
+  // An end of the interval that can't be evaluated, as 0 for 1/x, is left out
+  let ends = [start, end]
+    .into_iter()
+    .filter_map(|end| Some((end, f(end).ok()?)));
+  if let Some((end, _)) = ends
+    .filter(|(_, value)| *value < at_best)
+    .min_by(|a, b| a.1.total_cmp(&b.1))
+  {
+    return Ok(end);
+  }
+  // Near a minimum, the values are too flat to tell apart points closer
+  // than the square root of the rounding of f64
+  let tolerance = 1e-7 * best.abs().max(1.0);
+  let (low, high) = (start.max(best - tolerance), end.min(best + tolerance));
+  Ok(roundest(f, best, at_best, low, high))
+}
+
+/// The number with the fewest decimals between `low` and `high` at which
+/// `f` is no larger than at `best`, or `best` itself if there's none
+fn roundest(
+  f: &dyn Fn(f64) -> Result<f64, CalculatorError>,
+  best: f64,
+  at_best: f64,
+  low: f64,
+  high: f64,
+) -> f64 {
+  // Past 17 decimals, rounding no longer changes an f64 of any size
+  let digits = (-(high - low).log10().floor()).min(16.0) as i32;
+  for decimals in 0..=digits + 1 {
+    let factor = 10f64.powi(decimals);
+    let candidate = (best * factor).round() / factor;
+    if low <= candidate && candidate <= high && f(candidate).is_ok_and(|value| value <= at_best) {
+      return candidate;
+    }
+  }
+  best
+}
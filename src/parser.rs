@@ -0,0 +1,267 @@
+//! # Parser
+//!
+//! A Pratt (precedence-climbing) parser that puts the tokens of an
+//! expression into postfix order, which the evaluator, the tree builder in
+//! [`ast`](crate::ast) and [`rpn`](crate::rpn) all consume.
+//!
+//! Every construct is handled by the same two cases: what can start an
+//! operand (numbers, names, parentheses, prefix minus and function calls)
+//! and what can follow one (binary operators and postfix functions such as
+//! `10 km_to_mi` or `5 m+`). Each binary operator binds according to
+//! [`get_precedence`](crate::get_precedence), and `^` is right-associative.
+//!
+//! A leading minus binds tighter than every binary operator, just like the
+//! sign of a number literal, so `-x ^ 2` is `(-x) ^ 2` as `-3 ^ 2` is 9.
+//!
+//! A `%` with no operand after it is a postfix percentage, as in `50%`. Added
+//! to or subtracted from something, it's a share of that, so `200 + 10%` is
+//! put in the order of `200 * (100 + 10) / 100`, which is 220.
+//!
+//! The commas of `clamp(x, 0, 1)` are a binary operator too, binding more
+//! loosely than any other, so a call's argument is a chain of commas whose
+//! operands are its arguments. Evaluating a comma anywhere else fails.
+//!
+//! The parser recurses into every nested operand, so an expression nested
+//! more than 200 levels deep, as in a thousand parentheses or
+//! minus signs in a row, fails with [`CalculatorError::LimitExceeded`] rather
+//! than overflowing the stack. The `max_depth` of
+//! [`EvalOptions`](crate::limits::EvalOptions) lowers the nesting accepted,
+//! counting each parenthesized operand, sign and function argument.
+
+use crate::prelude::*;
+use crate::{CalculatorError, SpannedToken, Token};
+use core::iter::Peekable;
+use core::ops::Range;
+
+/// Binding power of prefix minus and of function arguments, above every
+/// binary operator
+const PREFIX: u8 = 26;
+
+/// Binding power of postfix functions, which apply to the operand just before them
+const POSTFIX: u8 = 27;
+
+/// Deepest the parser recurses, well within the stack of a thread
+const MAX_DEPTH: usize = 200;
+
+/// Puts the tokens of an expression into postfix order
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(name = "parse", level = "trace", skip_all, fields(tokens = tokens.len()), err(level = "debug"))
+)]
+pub(crate) fn to_postfix(tokens: Vec<SpannedToken>) -> Result<Vec<SpannedToken>, CalculatorError> {
+  let mut parser = Parser {
+    tokens: tokens.into_iter().peekable(),
+    output: Vec::new(),
+    depth: 0,
+    nesting: 0,
+    max_nesting: crate::limits::max_depth(),
+  };
+  parser.expression(0)?;
+  match parser.tokens.next() {
+    None => Ok(parser.output),
+    Some((_, span)) => {
+      Err(CalculatorError::SyntaxError("Mismatched parentheses: missing '('".to_string()).at(span))
+    }
+  }
+}
+
+/// Functions that can also follow their argument
+pub(crate) fn is_postfix_function(name: &str) -> bool {
+  matches!(name, "m+" | "m-" | "percent" | "days" | "weeks") || crate::is_valid_conversion(name)
+}
+
+/// Memory functions and others that take no argument
+pub(crate) fn is_nullary_function(name: &str) -> bool {
+  matches!(name, "mr" | "mc" | "mundo" | "today" | "ans")
+}
+
+struct Parser {
+  tokens: Peekable<alloc::vec::IntoIter<SpannedToken>>,
+  output: Vec<SpannedToken>,
+  /// Subexpressions being parsed, each a level of recursion
+  depth: usize,
+  /// Parenthesized operands, signs and function arguments being parsed
+  nesting: usize,
+  /// The `max_depth` of the evaluation's limits
+  max_nesting: Option<usize>,
+}
+
+impl Parser {
+  /// Parses an operand and every operator binding at least as tightly as
+  /// `min_power`, returning the span of the whole subexpression
+  fn expression(&mut self, min_power: u8) -> Result<Range<usize>, CalculatorError> {
+    if self.depth == MAX_DEPTH {
+      return Err(CalculatorError::LimitExceeded(format!(
+        "expression is nested more than {} levels deep",
+        MAX_DEPTH
+      )));
+    }
+    self.depth += 1;
+    let result = self.subexpression(min_power);
+    self.depth -= 1;
+    result
+  }
+
+  /// Parses what [`expression`](Self::expression) does, one level deeper
+  fn subexpression(&mut self, min_power: u8) -> Result<Range<usize>, CalculatorError> {
+    let mut span = self.operand()?;
+
+    while let Some((token, next_span)) = self.tokens.peek() {
+      match token {
+        Token::Operator(operator) => {
+          let (left_power, right_power) = binding_power(operator);
+          if left_power < min_power {
+            break;
+          }
+          let (operator, operator_span) = self.tokens.next().unwrap();
+          // Catch a missing right operand here, where the operator is still known
+          if !self.operand_follows() {
+            return Err(not_enough_operands().at(operator_span));
+          }
+          let right_start = self.output.len();
+          let right = self.expression(right_power)?;
+          let percent =
+            matches!(self.output.last(), Some((Token::Function(name), _)) if name == "percent");
+          if percent && matches!(&operator, Token::Operator(op) if op == "+" || op == "-") {
+            // Adding a percentage adds that share of the left operand, so
+            // a + b% is a * (100 + b) / 100, which is exact for whole a and b
+            self.output.pop();
+            self.output.insert(
+              right_start,
+              (Token::Number(100.0), right.start..right.start),
+            );
+            let end = right.end..right.end;
+            self.output.push((operator, operator_span.clone()));
+            self
+              .output
+              .push((Token::Operator("*".to_string()), operator_span));
+            self.output.push((Token::Number(100.0), end.clone()));
+            self.output.push((Token::Operator("/".to_string()), end));
+          } else {
+            self.output.push((operator, operator_span));
+          }
+          span = span.start..right.end;
+        }
+        Token::Function(name) if is_postfix_function(name) => {
+          if POSTFIX < min_power {
+            break;
+          }
+          let (function, function_span) = self.tokens.next().unwrap();
+          span = span.start..function_span.end;
+          self.output.push((function, function_span));
+        }
+        Token::RightParen => break,
+        _ => {
+          return Err(
+            CalculatorError::SyntaxError("Invalid expression: too many operands".to_string())
+              .at(next_span.clone()),
+          );
+        }
+      }
+    }
+
+    Ok(span)
+  }
+
+  /// Parses a number, a name, a parenthesized expression, a negation or a
+  /// function call
+  fn operand(&mut self) -> Result<Range<usize>, CalculatorError> {
+    let Some((token, span)) = self.tokens.next() else {
+      return Err(not_enough_operands());
+    };
+
+    match token {
+      Token::Number(_) | Token::Identifier(_) => {
+        self.output.push((token, span.clone()));
+        Ok(span)
+      }
+      Token::LeftParen => {
+        let inner = self.nested(|parser| parser.expression(0))?;
+        match self.tokens.next() {
+          Some((Token::RightParen, close)) => Ok(span.start.min(inner.start)..close.end),
+          _ => Err(
+            CalculatorError::SyntaxError("Mismatched parentheses: missing ')'".to_string())
+              .at(span),
+          ),
+        }
+      }
+      Token::Operator(operator) if operator == "-" => {
+        if !self.operand_follows() {
+          return Err(not_enough_operands().at(span));
+        }
+        let argument = self.nested(|parser| parser.expression(PREFIX))?;
+        self
+          .output
+          .push((Token::Function("neg".to_string()), span.clone()));
+        Ok(span.start..argument.end)
+      }
+      Token::Function(name) if is_nullary_function(&name) => {
+        // Memory recall and clear ignore their argument
+        self.output.push((Token::Number(0.0), span.end..span.end));
+        self.output.push((Token::Function(name), span.clone()));
+        Ok(span)
+      }
+      Token::Function(name) => {
+        if !self.operand_follows() {
+          return Err(
+            CalculatorError::SyntaxError(
+              "Invalid expression: function without argument".to_string(),
+            )
+            .at(span),
+          );
+        }
+        // A parenthesized argument is the whole call, as in sqrt(16) km_to_mi
+        let argument = if matches!(self.tokens.peek(), Some((Token::LeftParen, _))) {
+          self.operand()?
+        } else {
+          self.nested(|parser| parser.expression(PREFIX))?
+        };
+        self.output.push((Token::Function(name), span.clone()));
+        Ok(span.start..argument.end)
+      }
+      Token::Operator(_) | Token::RightParen => Err(not_enough_operands().at(span)),
+    }
+  }
+
+  /// Runs `parse` one level of nesting deeper, failing past the `max_depth`
+  /// of the evaluation's limits
+  fn nested(
+    &mut self,
+    parse: impl FnOnce(&mut Self) -> Result<Range<usize>, CalculatorError>,
+  ) -> Result<Range<usize>, CalculatorError> {
+    if let Some(max_nesting) = self.max_nesting.filter(|&max| self.nesting >= max) {
+      return Err(CalculatorError::LimitExceeded(format!(
+        "expression is nested more than {} levels deep",
+        max_nesting
+      )));
+    }
+    self.nesting += 1;
+    let result = parse(self);
+    self.nesting -= 1;
+    result
+  }
+
+  /// Whether the next token can start an operand
+  fn operand_follows(&mut self) -> bool {
+    match self.tokens.peek() {
+      None | Some((Token::RightParen, _)) => false,
+      Some((Token::Operator(operator), _)) => operator == "-",
+      Some(_) => true,
+    }
+  }
+}
+
+/// Binding powers of a binary operator on its left and right; the right one
+/// is lower for the right-associative `^`
+fn binding_power(operator: &str) -> (u8, u8) {
+  let power = crate::get_precedence(operator) * 2;
+  if operator == "^" {
+    (power + 1, power)
+  } else {
+    (power, power + 1)
+  }
+}
+
+fn not_enough_operands() -> CalculatorError {
+  CalculatorError::SyntaxError("Invalid expression: not enough operands".to_string())
+}
@@ -0,0 +1,157 @@
+//! # Plot Rendering
+//!
+//! Renders the curve of a [`Sweep`] to an SVG or PNG file for embedding in
+//! documents. Enabled with the `plot` cargo feature.
+//!
+//! SVG output is fully labelled. PNG output draws the curve and the axes
+//! only, since rasterizing text would require bundling a font.
+
+use crate::CalculatorError;
+use crate::table::{Sweep, Table};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use std::ops::Range;
+use std::path::Path;
+
+/// Number of points sampled when a plot doesn't specify a step
+pub const DEFAULT_SAMPLES: usize = 200;
+
+/// Image size in pixels
+const SIZE: (u32, u32) = (800, 600);
+
+/// Parses a sweep specification, sampling [`DEFAULT_SAMPLES`] points when no
+/// step is given so curves come out smooth
+pub fn parse(spec: &str) -> Result<Sweep, CalculatorError> {
+  let mut sweep = Sweep::parse(spec)?;
+  if !spec.contains(" step ") && sweep.end != sweep.start {
+    sweep.step = (sweep.end - sweep.start) / DEFAULT_SAMPLES as f64;
+  }
+  Ok(sweep)
+}
+
+/// Evaluates a sweep and writes the curve to `path`.
+///
+/// The format follows the file extension, which must be `svg` or `png`.
+pub fn render<P: AsRef<Path>>(sweep: &Sweep, path: P) -> Result<(), CalculatorError> {
+  let path = path.as_ref();
+  let table = sweep.evaluate()?;
+
+  let extension = path
+    .extension()
+    .and_then(|ext| ext.to_str())
+    .map(str::to_lowercase);
+  match extension.as_deref() {
+    Some("svg") => draw(
+      SVGBackend::new(path, SIZE).into_drawing_area(),
+      &table,
+      true,
+    )
+    .map_err(plot_error),
+    Some("png") => draw(
+      BitMapBackend::new(path, SIZE).into_drawing_area(),
+      &table,
+      false,
+    )
+    .map_err(plot_error),
+    _ => Err(CalculatorError::ArgumentError(format!(
+      "Unsupported image format: {} (use .svg or .png)",
+      path.display()
+    ))),
+  }
+}
+
+fn plot_error(err: impl std::fmt::Display) -> CalculatorError {
+  CalculatorError::Other(format!("Plot error: {}", err))
+}
+
+/// Draws the chart, with captions and tick labels when `labels` is set
+fn draw<DB: DrawingBackend>(
+  root: DrawingArea<DB, Shift>,
+  table: &Table,
+  labels: bool,
+) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+  let x_range = x_range(table);
+  let y_range = y_range(table);
+
+  root.fill(&WHITE)?;
+  let mut builder = ChartBuilder::on(&root);
+  builder.margin(20);
+  if labels {
+    builder
+      .caption(&table.expression, ("sans-serif", 24))
+      .x_label_area_size(40)
+      .y_label_area_size(60);
+  }
+  let mut chart = builder.build_cartesian_2d(x_range.clone(), y_range.clone())?;
+
+  if labels {
+    chart
+      .configure_mesh()
+      .x_desc(table.variable.as_str())
+      .y_desc(table.expression.as_str())
+      .draw()?;
+  } else {
+    // Without text, mark the axes wherever they fall inside the plot
+    let axis = BLACK.mix(0.4);
+    if y_range.contains(&0.0) {
+      chart.draw_series(LineSeries::new(
+        [(x_range.start, 0.0), (x_range.end, 0.0)],
+        axis,
+      ))?;
+    }
+    if x_range.contains(&0.0) {
+      chart.draw_series(LineSeries::new(
+        [(0.0, y_range.start), (0.0, y_range.end)],
+        axis,
+      ))?;
+    }
+  }
+
+  // Failed or infinite points break the curve rather than connecting across them
+  for segment in segments(table) {
+    chart.draw_series(LineSeries::new(segment, BLUE.stroke_width(2)))?;
+  }
+
+  root.present()
+}
+
+/// Splits the plottable points into runs of consecutive finite values
+fn segments(table: &Table) -> Vec<Vec<(f64, f64)>> {
+  let mut segments = vec![Vec::new()];
+  for (x, y) in &table.rows {
+    match y {
+      Ok(y) if y.is_finite() => segments.last_mut().unwrap().push((*x, *y)),
+      _ => segments.push(Vec::new()),
+    }
+  }
+  segments.retain(|segment| !segment.is_empty());
+  segments
+}
+
+fn x_range(table: &Table) -> Range<f64> {
+  let inputs = table.rows.iter().map(|(x, _)| *x);
+  padded_range(inputs)
+}
+
+fn y_range(table: &Table) -> Range<f64> {
+  let outputs = table
+    .rows
+    .iter()
+    .filter_map(|(_, y)| y.as_ref().ok().copied())
+    .filter(|y| y.is_finite());
+  padded_range(outputs)
+}
+
+/// Smallest range covering all values, widened when it would be empty
+fn padded_range(values: impl Iterator<Item = f64>) -> Range<f64> {
+  let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+    (min.min(value), max.max(value))
+  });
+  if min > max {
+    -1.0..1.0
+  } else if min == max {
+    min - 1.0..max + 1.0
+  } else {
+    min..max
+  }
+}
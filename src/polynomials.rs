@@ -0,0 +1,155 @@
+//! # Polynomial Roots
+//!
+//! `solvequad(a, b, c)` solves `a x^2 + b x + c = 0` and
+//! `solvecubic(a, b, c, d)` solves `a x^3 + b x^2 + c x + d = 0`. Each gives
+//! the list of distinct real roots in ascending order, which is empty if
+//! there are none. Under the complex domain policy,
+//! [`evaluate_to_string`](crate::evaluate_to_string) shows every root,
+//! complex ones included.
+//!
+//! ```
+//! use rust_calculator::polynomials;
+//! use rust_calculator::{Value, evaluate};
+//!
+//! assert_eq!(evaluate("solvequad(1, -3, 2)").unwrap(), Value::from(vec![1.0, 2.0]));
+//! assert_eq!(evaluate("solvecubic(1, -6, 11, -6)").unwrap(), Value::from(vec![1.0, 2.0, 3.0]));
+//! assert_eq!(polynomials::quadratic(1.0, 0.0, 1.0)[0].to_string(), "1i");
+//! ```
+
+use crate::CalculatorError;
+use crate::complex::Complex;
+use crate::prelude::*;
+use core::f64::consts::PI;
+
+/// Checks whether `name` is a function solving a polynomial equation
+pub(crate) fn is_solver(name: &str) -> bool {
+  matches!(name, "solvequad" | "solvecubic")
+}
+
+/// Both roots of `a x^2 + b x + c`, which must have `a` nonzero; a double
+/// root appears twice
+pub fn quadratic(a: f64, b: f64, c: f64) -> Vec<Complex> {
+  let discriminant = b * b - 4.0 * a * c;
+  if discriminant < 0.0 {
+    let (re, im) = (-b / (2.0 * a), (-discriminant).sqrt() / (2.0 * a));
+    return vec![Complex::new(re, im.abs()), Complex::new(re, -im.abs())];
+  }
+  // Adding numbers of the same sign avoids the cancellation of -b + sqrt(...)
+  let q = -(b + discriminant.sqrt().copysign(b)) / 2.0;
+  if q == 0.0 {
+    return vec![Complex::real(0.0), Complex::real(0.0)];
+  }
+  vec![Complex::real(q / a), Complex::real(c / q)]
+}
+
+/// The three roots of `a x^3 + b x^2 + c x + d`, which must have `a`
+/// nonzero; a repeated root appears as often as it's repeated
+pub fn cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<Complex> {
+  // x = t - b / 3a leaves t^3 + p t + q
+  let shift = -b / (3.0 * a);
+  let p = (3.0 * a * c - b * b) / (3.0 * a * a);
+  let q = (2.0 * b * b * b - 9.0 * a * b * c + 27.0 * a * a * d) / (27.0 * a * a * a);
+  let discriminant = q * q / 4.0 + p * p * p / 27.0;
+
+  let roots = if p == 0.0 && q == 0.0 {
+    vec![Complex::real(shift); 3]
+  } else if discriminant < 0.0 {
+    // Three real roots, found by trigonometry rather than complex cube roots
+    let radius = 2.0 * (-p / 3.0).sqrt();
+    let angle = (3.0 * q / (p * radius)).clamp(-1.0, 1.0).acos() / 3.0;
+    (0..3)
+      .map(|k| Complex::real(shift + radius * (angle - 2.0 * PI * k as f64 / 3.0).cos()))
+      .collect()
+  } else {
+    let root = discriminant.sqrt();
+    let (u, v) = ((-q / 2.0 + root).cbrt(), (-q / 2.0 - root).cbrt());
+    let real = Complex::real(shift + u + v);
+    let (re, im) = (shift - (u + v) / 2.0, 3f64.sqrt() / 2.0 * (u - v));
+    vec![
+      real,
+      Complex::new(re, im.abs()),
+      Complex::new(re, -im.abs()),
+    ]
+  };
+
+  // A Newton step takes up the rounding of the formulas
+  let coefficients = [a, b, c, d];
+  roots
+    .into_iter()
+    .map(|root| {
+      if root.is_real() {
+        Complex::real(polish(&coefficients, root.re))
+      } else {
+        root
+      }
+    })
+    .collect()
+}
+
+/// The roots of the polynomial with `coefficients`, from the highest power:
+/// the real ones in ascending order, then any complex ones
+pub fn roots(coefficients: &[f64]) -> Result<Vec<Complex>, CalculatorError> {
+  if coefficients.first() == Some(&0.0) {
+    return Err(CalculatorError::ArgumentError(
+      "The leading coefficient must not be 0".to_string(),
+    ));
+  }
+  let mut roots = match *coefficients {
+    [a, b, c] => quadratic(a, b, c),
+    [a, b, c, d] => cubic(a, b, c, d),
+    _ => {
+      return Err(CalculatorError::ArgumentError(format!(
+        "Cannot solve a polynomial with {} coefficients",
+        coefficients.len()
+      )));
+    }
+  };
+  roots.sort_by(|a, b| {
+    b.is_real()
+      .cmp(&a.is_real())
+      .then(a.re.total_cmp(&b.re))
+      .then(b.im.total_cmp(&a.im))
+  });
+  Ok(roots)
+}
+
+/// The distinct real roots of the polynomial with `coefficients`, from the
+/// highest power, in ascending order
+pub fn real_roots(coefficients: &[f64]) -> Result<Vec<f64>, CalculatorError> {
+  let mut real: Vec<f64> = roots(coefficients)?
+    .into_iter()
+    .filter(Complex::is_real)
+    .map(|root| root.re)
+    .collect();
+  real.dedup_by(|a, b| (*a - *b).abs() <= 1e-9 * b.abs().max(1.0));
+  Ok(real)
+}
+
+/// Improves an approximate real root of a polynomial by Newton's method,
+/// for as long as each step brings the polynomial closer to 0, and settles
+/// on a nearby whole number that's at least as good
+fn polish(coefficients: &[f64], root: f64) -> f64 {
+  // The value and slope of the polynomial at x, by Horner's method
+  let evaluate = |x: f64| {
+    coefficients
+      .iter()
+      .fold((0.0, 0.0), |(value, slope), &coefficient| {
+        (value * x + coefficient, slope * x + value)
+      })
+  };
+  let mut x = root;
+  for _ in 0..3 {
+    let (value, slope) = evaluate(x);
+    let next = x - value / slope;
+    if !next.is_finite() || evaluate(next).0.abs() >= value.abs() {
+      break;
+    }
+    x = next;
+  }
+  let whole = x.round();
+  if (whole - x).abs() < 1e-9 && evaluate(whole).0.abs() <= evaluate(x).0.abs() {
+    whole
+  } else {
+    x
+  }
+}
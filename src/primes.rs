@@ -0,0 +1,181 @@
+//! # Primes
+//!
+//! Primality and factorization of whole numbers up to 2^53, the largest
+//! range in which every whole `f64` is exact. `isprime(n)` is 1 for a prime
+//! and 0 otherwise, and `nextprime(n)` is the smallest prime greater than
+//! `n`; numbers below 2 are never prime, so `nextprime(-5)` is 2.
+//!
+//! `factorize(n)` writes `n` as a product of prime powers such as
+//! `2^3 * 3 * 5`, which reads back as the same number; like `roman(x)` it
+//! produces text, so it's only available through
+//! [`evaluate_to_string`](crate::evaluate_to_string).
+//!
+//! ```
+//! use rust_calculator::primes;
+//!
+//! assert!(primes::is_prime(97));
+//! assert_eq!(primes::next_prime(100), Some(101));
+//! assert_eq!(primes::factorize(360), vec![(2, 3), (3, 2), (5, 1)]);
+//! assert_eq!(primes::to_factorization(360.0).unwrap(), "2^3 * 3^2 * 5");
+//! ```
+
+use crate::prelude::*;
+use crate::{CalculatorError, bigint};
+
+/// Bases of the Miller-Rabin test that together decide every number below
+/// 2^64 without error
+const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+/// Checks whether `n` is prime
+pub fn is_prime(n: u64) -> bool {
+  if n < 2 {
+    return false;
+  }
+  for &p in &WITNESSES {
+    if n.is_multiple_of(p) {
+      return n == p;
+    }
+  }
+  // n - 1 = d * 2^s with d odd
+  let s = (n - 1).trailing_zeros();
+  let d = (n - 1) >> s;
+  WITNESSES.iter().all(|&a| {
+    let mut x = pow_mod(a, d, n);
+    if x == 1 || x == n - 1 {
+      return true;
+    }
+    for _ in 1..s {
+      x = mul_mod(x, x, n);
+      if x == n - 1 {
+        return true;
+      }
+    }
+    false
+  })
+}
+
+/// The smallest prime greater than `n`, or `None` if it would be beyond
+/// 2^53
+pub fn next_prime(n: u64) -> Option<u64> {
+  let limit = bigint::MAX_EXACT as u64;
+  (n.saturating_add(1)..=limit).find(|&candidate| is_prime(candidate))
+}
+
+/// The prime factors of `n` with their multiplicities, from smallest to
+/// largest. 0 and 1 have none.
+pub fn factorize(n: u64) -> Vec<(u64, u32)> {
+  let mut primes = Vec::new();
+  if n > 1 {
+    split(n, &mut primes);
+  }
+  primes.sort_unstable();
+  let mut factors: Vec<(u64, u32)> = Vec::new();
+  for prime in primes {
+    match factors.last_mut() {
+      Some((last, count)) if *last == prime => *count += 1,
+      _ => factors.push((prime, 1)),
+    }
+  }
+  factors
+}
+
+/// Writes a whole number from 1 up to 2^53 as a product of prime powers
+pub fn to_factorization(value: f64) -> Result<String, CalculatorError> {
+  let n = whole_argument(value, "factorize")?;
+  if n == 0 {
+    return Err(CalculatorError::ArgumentError(
+      "factorize needs a positive number".to_string(),
+    ));
+  }
+  if n == 1 {
+    return Ok("1".to_string());
+  }
+  let powers: Vec<String> = factorize(n)
+    .into_iter()
+    .map(|(prime, count)| match count {
+      1 => prime.to_string(),
+      _ => format!("{}^{}", prime, count),
+    })
+    .collect();
+  Ok(powers.join(" * "))
+}
+
+/// Reads the argument of a prime function, which must be a whole number up
+/// to 2^53. Negative numbers read as 0, since neither is prime.
+pub(crate) fn whole_argument(value: f64, function: &str) -> Result<u64, CalculatorError> {
+  if value.fract() != 0.0 || value.abs() > bigint::MAX_EXACT {
+    return Err(CalculatorError::ArgumentError(format!(
+      "{} needs a whole number up to 2^53, got {}",
+      function, value
+    )));
+  }
+  Ok(value.max(0.0) as u64)
+}
+
+/// Adds the prime factors of `n`, which is at least 2, to `primes`
+fn split(mut n: u64, primes: &mut Vec<u64>) {
+  // Small factors are quicker to divide out than to find by Pollard's rho
+  for p in (2..1000).filter(|&p| p == 2 || p % 2 == 1) {
+    if p * p > n {
+      break;
+    }
+    while n.is_multiple_of(p) {
+      primes.push(p);
+      n /= p;
+    }
+  }
+  if n == 1 {
+    return;
+  }
+  if is_prime(n) {
+    primes.push(n);
+    return;
+  }
+  let divisor = pollard_rho(n);
+  split(divisor, primes);
+  split(n / divisor, primes);
+}
+
+/// A nontrivial divisor of `n`, an odd composite number, by Pollard's rho
+/// method
+fn pollard_rho(n: u64) -> u64 {
+  let mut c = 1;
+  loop {
+    let step = |x: u64| (mul_mod(x, x, n) + c) % n;
+    let (mut x, mut y, mut divisor) = (2, 2, 1);
+    while divisor == 1 {
+      x = step(x);
+      y = step(step(y));
+      divisor = gcd(x.abs_diff(y), n);
+    }
+    // The sequence cycled without a divisor, so try another one
+    if divisor != n {
+      return divisor;
+    }
+    c += 1;
+  }
+}
+
+fn mul_mod(a: u64, b: u64, modulus: u64) -> u64 {
+  (a as u128 * b as u128 % modulus as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exponent: u64, modulus: u64) -> u64 {
+  let mut result = 1;
+  base %= modulus;
+  while exponent > 0 {
+    if exponent & 1 == 1 {
+      result = mul_mod(result, base, modulus);
+    }
+    base = mul_mod(base, base, modulus);
+    exponent >>= 1;
+  }
+  result
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+  while b != 0 {
+    (a, b) = (b, a % b);
+  }
+  a
+}
@@ -0,0 +1,255 @@
+//! # Integer Mode
+//!
+//! Evaluation in fixed-width integers, as a programmer's calculator does.
+//! With the `integer` setting set to a type such as `u8` or `i32`, every
+//! number must be whole and in the type's range, `/` divides and drops the
+//! remainder, and results outside the range fail or wrap around as the
+//! `intoverflow` setting says.
+//!
+//! Only `+`, `-`, `*`, `/`, `%`, `^` with a non-negative exponent, the
+//! bitwise `&`, `|`, `xor`, `~`, `<<` and `>>`, `abs` and `fact` are
+//! available; other functions fail. `~` and `<<` work on the type's bits, so
+//! `~0` is 255 in `u8`, and bits shifted out are dropped whatever the
+//! overflow handling.
+//!
+//! ```
+//! use rust_calculator::settings::{IntegerOverflow, IntegerType, Settings};
+//!
+//! let mut settings = Settings { integer: Some(IntegerType::U8), ..Settings::default() };
+//! assert_eq!(rust_calculator::evaluate_expression_with_settings("7 / 2", &settings).unwrap(), 3.0);
+//! assert!(rust_calculator::evaluate_expression_with_settings("255 + 1", &settings).is_err());
+//!
+//! settings.integer_overflow = IntegerOverflow::Wrap;
+//! assert_eq!(rust_calculator::evaluate_expression_with_settings("255 + 1", &settings).unwrap(), 0.0);
+//! ```
+
+use crate::ast::{self, Expr};
+use crate::prelude::*;
+use crate::settings::{self, IntegerOverflow, IntegerType};
+use crate::{CalculatorError, Resolver, limits};
+
+/// Evaluates an expression in integer mode with `integer` as its type,
+/// returning the exact result
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::programmer;
+/// use rust_calculator::settings::{IntegerOverflow, IntegerType};
+///
+/// let max = programmer::evaluate("2 ^ 64 - 1", IntegerType::U64, IntegerOverflow::Wrap).unwrap();
+/// assert_eq!(max, u64::MAX as i128);
+/// assert_eq!(programmer::evaluate("-7 / 2", IntegerType::I32, IntegerOverflow::Error).unwrap(), -3);
+/// assert_eq!(programmer::evaluate("127 + 1", IntegerType::I8, IntegerOverflow::Wrap).unwrap(), -128);
+/// ```
+pub fn evaluate(
+  expression: &str,
+  integer: IntegerType,
+  overflow: IntegerOverflow,
+) -> Result<i128, CalculatorError> {
+  let expanded = crate::roman::expand_literals(expression)?;
+  let tree = ast::parse_with_resolver(&expanded, &crate::get_variable)?;
+  Machine { integer, overflow }.evaluate(&tree)
+}
+
+/// Evaluates with the integer type and overflow handling of the current
+/// settings, which must have an integer type
+pub(crate) fn evaluate_with_resolver(
+  expression: &str,
+  resolve: Resolver,
+) -> Result<i128, CalculatorError> {
+  let settings = settings::current();
+  let integer = settings
+    .integer
+    .ok_or_else(|| CalculatorError::ArgumentError("Integer mode is off".to_string()))?;
+  let tree = ast::parse_with_resolver(expression, resolve)?;
+  Machine {
+    integer,
+    overflow: settings.integer_overflow,
+  }
+  .evaluate(&tree)
+}
+
+/// The integer type and overflow handling an evaluation uses
+struct Machine {
+  integer: IntegerType,
+  overflow: IntegerOverflow,
+}
+
+impl Machine {
+  fn evaluate(&self, tree: &Expr) -> Result<i128, CalculatorError> {
+    match tree {
+      Expr::Number(value) => {
+        if value.fract() != 0.0 || !value.is_finite() {
+          return Err(CalculatorError::ArgumentError(format!(
+            "Integer mode needs whole numbers: {}",
+            value
+          )));
+        }
+        // Numbers beyond i128 can't be in any integer type's range
+        let whole = if value.abs() < 1e38 {
+          *value as i128
+        } else {
+          return Err(self.out_of_range(&value.to_string()));
+        };
+        self.fit(whole)
+      }
+      Expr::Variable(name) => Err(crate::unknown_token(name)),
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } => {
+        let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+        self.binary(left, operator, right)
+      }
+      Expr::Function { name, argument } => {
+        let argument = self.evaluate(argument)?;
+        match name.as_str() {
+          "neg" => self.arithmetic(0, argument, i128::checked_sub, i128::wrapping_sub),
+          "abs" if argument < 0 => {
+            self.arithmetic(0, argument, i128::checked_sub, i128::wrapping_sub)
+          }
+          "abs" => Ok(argument),
+          "~" => Ok(self.wrap(!argument)),
+          "not" => Ok((argument == 0) as i128),
+          "ans" => self.evaluate(&Expr::Number(crate::answer()?)),
+          "fact" => {
+            if argument < 0 {
+              return Err(CalculatorError::ArgumentError(
+                "Cannot calculate factorial of negative number".to_string(),
+              ));
+            }
+            limits::check_factorial(argument as f64)?;
+            let mut result = 1;
+            for i in 2..=argument {
+              result = self.arithmetic(result, i, i128::checked_mul, i128::wrapping_mul)?;
+              // Once a wrapped product is zero it stays zero
+              if result == 0 {
+                break;
+              }
+            }
+            Ok(result)
+          }
+          _ => Err(CalculatorError::ArgumentError(format!(
+            "{} isn't available in integer mode",
+            name
+          ))),
+        }
+      }
+    }
+  }
+
+  fn binary(&self, left: i128, operator: &str, right: i128) -> Result<i128, CalculatorError> {
+    match operator {
+      "+" => self.arithmetic(left, right, i128::checked_add, i128::wrapping_add),
+      "-" => self.arithmetic(left, right, i128::checked_sub, i128::wrapping_sub),
+      "*" => self.arithmetic(left, right, i128::checked_mul, i128::wrapping_mul),
+      "/" | "%" if right == 0 => Err(CalculatorError::MathError(
+        if operator == "/" {
+          "Division by zero"
+        } else {
+          "Modulo by zero"
+        }
+        .to_string(),
+      )),
+      // Both truncate toward zero, as integer division in Rust and C does
+      "/" => self.fit(left / right),
+      "%" => self.fit(left % right),
+      "^" => {
+        let Ok(mut exponent) = u64::try_from(right) else {
+          return Err(CalculatorError::ArgumentError(
+            "Integer mode needs a non-negative exponent".to_string(),
+          ));
+        };
+        limits::check_exponent(right as f64)?;
+        // Squaring and multiplying keeps every step in range when the result is
+        let mut base = left;
+        let mut result = self.fit(1)?;
+        while exponent > 0 {
+          if exponent & 1 == 1 {
+            result = self.arithmetic(result, base, i128::checked_mul, i128::wrapping_mul)?;
+          }
+          exponent >>= 1;
+          if exponent > 0 {
+            base = self.arithmetic(base, base, i128::checked_mul, i128::wrapping_mul)?;
+          }
+        }
+        Ok(result)
+      }
+      // Values in range are sign-extended, so these stay in range
+      "&" => Ok(left & right),
+      "|" => Ok(left | right),
+      "xor" => Ok(left ^ right),
+      "<<" => Ok(self.wrap(left << self.shift(right)?)),
+      ">>" => Ok(left >> self.shift(right)?),
+      _ if crate::is_comparison(operator) => Ok(crate::compare(left, operator, right) as i128),
+      "and" | "or" => Ok(crate::both(left != 0, operator, right != 0) as i128),
+      _ => Err(CalculatorError::ArgumentError(format!(
+        "{} isn't available in integer mode",
+        operator
+      ))),
+    }
+  }
+
+  /// Applies an operation, wrapping or checking it as the overflow handling
+  /// says. Wrapping in 128 bits keeps the low bits of every narrower type.
+  fn arithmetic(
+    &self,
+    left: i128,
+    right: i128,
+    checked: fn(i128, i128) -> Option<i128>,
+    wrapping: fn(i128, i128) -> i128,
+  ) -> Result<i128, CalculatorError> {
+    match self.overflow {
+      IntegerOverflow::Wrap => self.fit(wrapping(left, right)),
+      IntegerOverflow::Error => match checked(left, right) {
+        Some(result) => self.fit(result),
+        None => Err(self.out_of_range("the result")),
+      },
+    }
+  }
+
+  /// Brings a value into the type's range, or fails if it's outside and
+  /// overflow isn't wrapped
+  fn fit(&self, value: i128) -> Result<i128, CalculatorError> {
+    let (min, max) = (self.integer.min(), self.integer.max());
+    if (min..=max).contains(&value) {
+      return Ok(value);
+    }
+    match self.overflow {
+      IntegerOverflow::Wrap => Ok(self.wrap(value)),
+      IntegerOverflow::Error => Err(self.out_of_range(&value.to_string())),
+    }
+  }
+
+  /// Keeps the low bits of a value that fit the type
+  fn wrap(&self, value: i128) -> i128 {
+    let min = self.integer.min();
+    value.wrapping_sub(min).rem_euclid(1 << self.integer.bits()) + min
+  }
+
+  /// Reads a shift amount, which must be less than the type's width
+  fn shift(&self, amount: i128) -> Result<u32, CalculatorError> {
+    let bits = self.integer.bits();
+    match u32::try_from(amount) {
+      Ok(amount) if amount < bits => Ok(amount),
+      _ => Err(CalculatorError::ArgumentError(format!(
+        "Shift amount must be from 0 to {} for {}, got {}",
+        bits - 1,
+        self.integer,
+        amount
+      ))),
+    }
+  }
+
+  fn out_of_range(&self, value: &str) -> CalculatorError {
+    CalculatorError::Overflow(format!(
+      "{} is out of range for {} ({} to {})",
+      value,
+      self.integer,
+      self.integer.min(),
+      self.integer.max()
+    ))
+  }
+}
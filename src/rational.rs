@@ -0,0 +1,381 @@
+//! # Rationals
+//!
+//! Exact fraction arithmetic, so `1/3 + 1/6` is exactly `1/2` rather than
+//! `0.49999999999999994` rounded for display. Decimal literals are read as
+//! the decimals they were written as, so `0.1` is `1/10`.
+//!
+//! [`evaluate_fraction`](crate::evaluate_fraction) evaluates an expression
+//! with [`Rational`]s when it only uses `+`, `-`, `*`, `/`, `%`, `^` with an
+//! integer exponent, and `abs`. Numerators and denominators are 128-bit; a
+//! result that doesn't fit fails with [`CalculatorError::Overflow`].
+//!
+//! [`Rational::approximate`] finds the simplest fraction close to a decimal,
+//! and [`continued_fraction`] expands a number as a continued fraction; the
+//! `as_fraction(x[, max_denominator])` and `continued_fraction(x[, terms])`
+//! functions of [`evaluate_to_string`](crate::evaluate_to_string) show them.
+//!
+//! ```
+//! use rust_calculator::evaluate_fraction;
+//!
+//! assert_eq!(evaluate_fraction("1/3 + 1/6").unwrap().to_string(), "1/2");
+//! assert_eq!(evaluate_fraction("0.1 + 0.2").unwrap().to_string(), "3/10");
+//! assert_eq!(evaluate_fraction("(2/3) ^ -2").unwrap().to_string(), "9/4");
+//! ```
+
+use crate::CalculatorError;
+use crate::ast::Expr;
+use crate::prelude::*;
+use core::fmt;
+
+/// A fraction in lowest terms with a positive denominator
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::rational::Rational;
+///
+/// let half = Rational::new(3, 6).unwrap();
+/// assert_eq!((half.numerator(), half.denominator()), (1, 2));
+/// assert_eq!(half.to_f64(), 0.5);
+/// assert_eq!(Rational::new(4, -2).unwrap().to_string(), "-2");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rational {
+  numerator: i128,
+  denominator: i128,
+}
+
+impl Rational {
+  /// Makes the fraction `numerator / denominator` in lowest terms, or `None`
+  /// if the denominator is zero
+  pub fn new(numerator: i128, denominator: i128) -> Option<Rational> {
+    if denominator == 0 {
+      return None;
+    }
+    let divisor = gcd(numerator, denominator);
+    let sign = if denominator < 0 { -1 } else { 1 };
+    Some(Rational {
+      numerator: sign * (numerator / divisor),
+      denominator: sign * (denominator / divisor),
+    })
+  }
+
+  /// A whole number
+  pub fn integer(value: i128) -> Rational {
+    Rational {
+      numerator: value,
+      denominator: 1,
+    }
+  }
+
+  /// Reads a finite `f64` as the shortest decimal that gives it back, which
+  /// is how a literal like `0.1` was written
+  pub fn from_f64(value: f64) -> Option<Rational> {
+    if !value.is_finite() {
+      return None;
+    }
+    let text = format!("{}", value);
+    let (whole, fraction) = text.split_once('.').unwrap_or((&text, ""));
+    let numerator: i128 = format!("{}{}", whole, fraction).parse().ok()?;
+    let denominator = 10i128.checked_pow(fraction.len() as u32)?;
+    Rational::new(numerator, denominator)
+  }
+
+  pub fn numerator(&self) -> i128 {
+    self.numerator
+  }
+
+  /// Always positive
+  pub fn denominator(&self) -> i128 {
+    self.denominator
+  }
+
+  pub fn is_integer(&self) -> bool {
+    self.denominator == 1
+  }
+
+  /// The nearest `f64`
+  pub fn to_f64(&self) -> f64 {
+    self.numerator as f64 / self.denominator as f64
+  }
+
+  /// The fraction with a denominator of at most `max_denominator` that's
+  /// closest to `value` as it's written in decimal, or `None` if `value`
+  /// isn't finite or `max_denominator` isn't positive
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rust_calculator::rational::Rational;
+  ///
+  /// let third = Rational::approximate(0.333333, 1000).unwrap();
+  /// assert_eq!(third.to_string(), "1/3");
+  /// let pi = Rational::approximate(std::f64::consts::PI, 1000).unwrap();
+  /// assert_eq!(pi.to_string(), "355/113");
+  /// ```
+  pub fn approximate(value: f64, max_denominator: i128) -> Option<Rational> {
+    if !value.is_finite() || max_denominator < 1 {
+      return None;
+    }
+    // Too small for a decimal fraction to hold it, and closest to zero anyway
+    let Some(exact) = Rational::from_f64(value.abs()) else {
+      return (value.abs() < 1.0).then(|| Rational::integer(0));
+    };
+    if exact.denominator <= max_denominator {
+      return Some(if value < 0.0 { -exact } else { exact });
+    }
+
+    // Follow the convergents of the continued fraction until the next one's
+    // denominator is too large, then take the better of the last convergent
+    // and the largest semiconvergent that still fits
+    let (mut p0, mut q0, mut p1, mut q1) = (0, 1, 1, 0);
+    let (mut n, mut d) = (exact.numerator, exact.denominator);
+    let next = |a: i128, previous: i128, last: i128| a.checked_mul(last)?.checked_add(previous);
+    while d != 0 {
+      let a = n / d;
+      let (Some(p2), Some(q2)) = (next(a, p0, p1), next(a, q0, q1)) else {
+        break;
+      };
+      if q2 > max_denominator {
+        break;
+      }
+      (p0, q0, p1, q1) = (p1, q1, p2, q2);
+      (n, d) = (d, n % d);
+    }
+    let convergent = Rational::new(p1, q1)?;
+    let k = (max_denominator - q0) / q1;
+    let distance = |approximation: &Rational| {
+      approximation
+        .checked_sub(&exact)
+        .map_or(f64::INFINITY, |difference| difference.to_f64().abs())
+    };
+    let best = match (next(k, p0, p1), next(k, q0, q1)) {
+      (Some(p), Some(q)) => {
+        let semiconvergent = Rational::new(p, q)?;
+        if distance(&semiconvergent) < distance(&convergent) {
+          semiconvergent
+        } else {
+          convergent
+        }
+      }
+      _ => convergent,
+    };
+    Some(if value < 0.0 { -best } else { best })
+  }
+
+  pub fn checked_abs(&self) -> Option<Rational> {
+    Some(Rational {
+      numerator: self.numerator.checked_abs()?,
+      denominator: self.denominator,
+    })
+  }
+
+  pub fn checked_add(&self, other: &Rational) -> Option<Rational> {
+    // Working over the least common denominator keeps the products small
+    let divisor = gcd(self.denominator, other.denominator);
+    let left = self.numerator.checked_mul(other.denominator / divisor)?;
+    let right = other.numerator.checked_mul(self.denominator / divisor)?;
+    let denominator = (self.denominator / divisor).checked_mul(other.denominator)?;
+    Rational::new(left.checked_add(right)?, denominator)
+  }
+
+  pub fn checked_sub(&self, other: &Rational) -> Option<Rational> {
+    self.checked_add(&other.checked_neg()?)
+  }
+
+  pub fn checked_mul(&self, other: &Rational) -> Option<Rational> {
+    // Cancelling across first keeps the products small
+    let first = gcd(self.numerator, other.denominator);
+    let second = gcd(other.numerator, self.denominator);
+    Rational::new(
+      (self.numerator / first).checked_mul(other.numerator / second)?,
+      (self.denominator / second).checked_mul(other.denominator / first)?,
+    )
+  }
+
+  /// `None` when dividing by zero or when the result doesn't fit
+  pub fn checked_div(&self, other: &Rational) -> Option<Rational> {
+    self.checked_mul(&other.recip()?)
+  }
+
+  /// The remainder after dividing by `other` a whole number of times, with
+  /// the sign of `self` like `%` on floats
+  pub fn checked_rem(&self, other: &Rational) -> Option<Rational> {
+    let quotient = self.checked_div(other)?;
+    let whole = Rational::integer(quotient.numerator / quotient.denominator);
+    self.checked_sub(&other.checked_mul(&whole)?)
+  }
+
+  pub fn checked_neg(&self) -> Option<Rational> {
+    Some(Rational {
+      numerator: self.numerator.checked_neg()?,
+      denominator: self.denominator,
+    })
+  }
+
+  /// `None` for zero
+  pub fn recip(&self) -> Option<Rational> {
+    Rational::new(self.denominator, self.numerator)
+  }
+
+  pub fn checked_pow(&self, exponent: i32) -> Option<Rational> {
+    let base = if exponent < 0 { self.recip()? } else { *self };
+    let exponent = exponent.unsigned_abs();
+    Some(Rational {
+      numerator: base.numerator.checked_pow(exponent)?,
+      denominator: base.denominator.checked_pow(exponent)?,
+    })
+  }
+}
+
+impl core::ops::Neg for Rational {
+  type Output = Rational;
+
+  /// Panics for the one numerator that can't be negated, like `-i128::MIN`
+  fn neg(self) -> Rational {
+    Rational {
+      numerator: -self.numerator,
+      denominator: self.denominator,
+    }
+  }
+}
+
+impl From<i64> for Rational {
+  fn from(value: i64) -> Self {
+    Rational::integer(value as i128)
+  }
+}
+
+impl fmt::Display for Rational {
+  /// Shows `numerator/denominator`, or just the numerator for whole numbers
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.is_integer() {
+      write!(f, "{}", self.numerator)
+    } else {
+      write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+  }
+}
+
+/// The terms of the continued fraction of `value` as it's written in
+/// decimal, at most `max_terms` of them. The expansion stops once its
+/// convergent is `value` to the precision of an `f64`, so the terms of an
+/// irrational number don't trail off into rounding noise.
+///
+/// # Examples
+///
+/// ```
+/// use rust_calculator::rational::continued_fraction;
+///
+/// assert_eq!(continued_fraction(0.75, 20).unwrap(), [0, 1, 3]);
+/// assert_eq!(continued_fraction(std::f64::consts::PI, 5).unwrap(), [3, 7, 15, 1, 292]);
+/// assert_eq!(continued_fraction(-1.5, 20).unwrap(), [-2, 2]);
+/// ```
+pub fn continued_fraction(value: f64, max_terms: usize) -> Result<Vec<i128>, CalculatorError> {
+  let exact = Rational::from_f64(value).ok_or_else(|| too_large(&value.to_string()))?;
+  let (mut n, mut d) = (exact.numerator, exact.denominator);
+  // Convergents h/k, starting from the two that come before the first term
+  let (mut h0, mut k0, mut h1, mut k1): (i128, i128, i128, i128) = (0, 1, 1, 0);
+  let mut terms = Vec::new();
+  while d != 0 && terms.len() < max_terms {
+    // Floor division, so every term after the first is positive
+    let a = n.div_euclid(d);
+    terms.push(a);
+    let h2 = a
+      .checked_mul(h1)
+      .and_then(|product| product.checked_add(h0));
+    let k2 = a
+      .checked_mul(k1)
+      .and_then(|product| product.checked_add(k0));
+    let (Some(h2), Some(k2)) = (h2, k2) else {
+      break;
+    };
+    (h0, k0, h1, k1) = (h1, k1, h2, k2);
+    if h1 as f64 / k1 as f64 == value {
+      break;
+    }
+    (n, d) = (d, n.rem_euclid(d));
+  }
+  Ok(terms)
+}
+
+/// Greatest common divisor, positive unless both are zero
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+  while b != 0 {
+    (a, b) = (b, a % b);
+  }
+  if a == 0 { 1 } else { a.abs() }
+}
+
+/// Evaluates a tree exactly, failing for anything that may not give a fraction
+pub(crate) fn evaluate(tree: &Expr) -> Result<Rational, CalculatorError> {
+  match tree {
+    Expr::Number(value) => Rational::from_f64(*value).ok_or_else(|| too_large(&value.to_string())),
+    Expr::Variable(name) => Err(crate::unknown_token(name)),
+    Expr::Binary {
+      operator,
+      left,
+      right,
+    } => {
+      let (left, right) = (evaluate(left)?, evaluate(right)?);
+      let result = match operator.as_str() {
+        "+" => left.checked_add(&right),
+        "-" => left.checked_sub(&right),
+        "*" => left.checked_mul(&right),
+        "/" | "%" if right.numerator == 0 => {
+          return Err(CalculatorError::MathError(
+            if operator == "/" {
+              "Division by zero"
+            } else {
+              "Modulo by zero"
+            }
+            .to_string(),
+          ));
+        }
+        "/" => left.checked_div(&right),
+        "%" => left.checked_rem(&right),
+        _ if crate::is_comparison(operator) => left.checked_sub(&right).map(|difference| {
+          Rational::integer(crate::compare(difference.numerator, operator, 0) as i128)
+        }),
+        "and" | "or" => {
+          Some(Rational::integer(
+            crate::both(left.numerator != 0, operator, right.numerator != 0) as i128,
+          ))
+        }
+        "^" => {
+          if !right.is_integer() {
+            return Err(not_exact("a fractional power"));
+          }
+          if left.numerator == 0 && right.numerator < 0 {
+            return Err(CalculatorError::MathError("Division by zero".to_string()));
+          }
+          crate::limits::check_exponent(right.numerator as f64)?;
+          i32::try_from(right.numerator)
+            .ok()
+            .and_then(|exponent| left.checked_pow(exponent))
+        }
+        _ => return Err(not_exact(operator)),
+      };
+      result.ok_or_else(|| too_large(&format!("{} {} {}", left, operator, right)))
+    }
+    Expr::Function { name, argument } => {
+      let argument = evaluate(argument)?;
+      match name.as_str() {
+        "neg" => argument.checked_neg(),
+        "abs" => argument.checked_abs(),
+        "percent" => argument.checked_div(&Rational::integer(100)),
+        "not" => Some(Rational::integer((argument.numerator == 0) as i128)),
+        _ => return Err(not_exact(name)),
+      }
+      .ok_or_else(|| too_large(&argument.to_string()))
+    }
+  }
+}
+
+fn not_exact(name: &str) -> CalculatorError {
+  CalculatorError::ArgumentError(format!("{} isn't supported in exact fractions", name))
+}
+
+fn too_large(value: &str) -> CalculatorError {
+  CalculatorError::Overflow(format!("{} is too large for an exact fraction", value))
+}
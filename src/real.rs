@@ -0,0 +1,375 @@
+//! # Real Number Backends
+//!
+//! The arithmetic of an expression tree, written once for any type that
+//! implements [`Real`]. `f64` is one such type; with the `double-double`
+//! feature, [`DoubleDouble`](crate::double_double::DoubleDouble) is another,
+//! which [`evaluate_extended`](crate::evaluate_extended) uses for about 31
+//! significant digits.
+//!
+//! The operators `+`, `-`, `*`, `/`, `%` and `^` are supported, with `sqrt`,
+//! `exp`, `ln`, `log`, the trigonometric functions in the current angle
+//! unit, `abs`, `floor`, `ceil`, `round` and `fact`; anything else fails
+//! rather than losing precision in `f64`. Domain errors, overflow and
+//! non-finite values follow the current settings, as they do in the main
+//! evaluator.
+//!
+//! Literals reach the tree as `f64`s, so each is read back from the shortest
+//! decimal that gives the same `f64`: `0.1` is a tenth to every digit of the
+//! type, but digits beyond the 17th of a literal are lost. Literals equal to
+//! the constants `pi`, `e`, `tau` and `phi` are taken as those constants.
+//!
+//! ```
+//! use rust_calculator::{parse, real};
+//!
+//! let value: f64 = real::evaluate(&parse("2 ^ 10 - sqrt(16)").unwrap()).unwrap();
+//! assert_eq!(value, 1020.0);
+//! ```
+
+use crate::CalculatorError;
+use crate::ast::Expr;
+use crate::limits;
+use crate::prelude::*;
+use crate::settings::{self, AngleMode, RoundingMode};
+use core::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+/// A real number type that expressions can be evaluated in
+pub trait Real:
+  Copy
+  + PartialOrd
+  + Add<Output = Self>
+  + Sub<Output = Self>
+  + Mul<Output = Self>
+  + Div<Output = Self>
+  + Rem<Output = Self>
+  + Neg<Output = Self>
+{
+  /// The arithmetic, as named in error messages
+  const NAME: &'static str;
+
+  /// The nearest value to an `f64`, which all types can hold exactly
+  fn from_f64(value: f64) -> Self;
+  /// The nearest `f64`
+  fn to_f64(self) -> f64;
+  /// Reads a decimal such as `0.1` or `1.5e-7`
+  fn parse(text: &str) -> Option<Self>;
+
+  fn pi() -> Self;
+  fn e() -> Self;
+
+  fn floor(self) -> Self;
+  fn ceil(self) -> Self;
+  fn sqrt(self) -> Self;
+  fn exp(self) -> Self;
+  fn ln(self) -> Self;
+  fn sin(self) -> Self;
+  fn cos(self) -> Self;
+  fn tan(self) -> Self {
+    self.sin() / self.cos()
+  }
+  fn asin(self) -> Self;
+  fn acos(self) -> Self;
+  fn atan(self) -> Self;
+}
+
+impl Real for f64 {
+  const NAME: &'static str = "floating-point arithmetic";
+
+  fn from_f64(value: f64) -> f64 {
+    value
+  }
+  fn to_f64(self) -> f64 {
+    self
+  }
+  fn parse(text: &str) -> Option<f64> {
+    text.parse().ok()
+  }
+  fn pi() -> f64 {
+    core::f64::consts::PI
+  }
+  fn e() -> f64 {
+    core::f64::consts::E
+  }
+  fn floor(self) -> f64 {
+    float::floor(self)
+  }
+  fn ceil(self) -> f64 {
+    float::ceil(self)
+  }
+  fn sqrt(self) -> f64 {
+    float::sqrt(self)
+  }
+  fn exp(self) -> f64 {
+    float::exp(self)
+  }
+  fn ln(self) -> f64 {
+    float::ln(self)
+  }
+  fn sin(self) -> f64 {
+    float::sin(self)
+  }
+  fn cos(self) -> f64 {
+    float::cos(self)
+  }
+  fn tan(self) -> f64 {
+    float::tan(self)
+  }
+  fn asin(self) -> f64 {
+    float::asin(self)
+  }
+  fn acos(self) -> f64 {
+    float::acos(self)
+  }
+  fn atan(self) -> f64 {
+    float::atan(self)
+  }
+}
+
+/// The methods of `f64`, or of `Float` without std, where [`Real`]'s methods
+/// of the same names aren't in scope
+mod float {
+  // Unused when std is linked anyway, as for the tests
+  #[cfg(not(feature = "std"))]
+  #[allow(unused_imports)]
+  use num_traits::Float;
+
+  macro_rules! forward {
+    ($($name:ident),*) => {
+      $(pub(super) fn $name(value: f64) -> f64 {
+        value.$name()
+      })*
+    };
+  }
+
+  forward!(floor, ceil, sqrt, exp, ln, sin, cos, tan, asin, acos, atan);
+}
+
+/// Evaluates an expression tree in `T`, looking variables up among the
+/// global variables
+pub fn evaluate<T: Real>(tree: &Expr) -> Result<T, CalculatorError> {
+  match tree {
+    Expr::Number(value) => Ok(literal(*value)),
+    Expr::Variable(name) => match crate::get_variable(name) {
+      Some(value) => crate::check_input(value).map(literal),
+      None => Err(crate::unknown_token(name)),
+    },
+    Expr::Binary {
+      operator,
+      left,
+      right,
+    } => {
+      let (left, right) = (evaluate::<T>(left)?, evaluate::<T>(right)?);
+      if is_nan(left) || is_nan(right) {
+        return Ok(T::from_f64(f64::NAN));
+      }
+      limits::check_time()?;
+      let result = binary(left, operator, right)?;
+      checked(result, is_finite(left) && is_finite(right))
+    }
+    Expr::Function { name, argument } => {
+      let argument = evaluate::<T>(argument)?;
+      if is_nan(argument) {
+        return Ok(argument);
+      }
+      limits::check_time()?;
+      let result = function(name, argument)?;
+      checked(result, is_finite(argument))
+    }
+  }
+}
+
+/// The value of a literal in `T`
+fn literal<T: Real>(value: f64) -> T {
+  let constant = crate::constants::MATH
+    .iter()
+    .find(|constant| constant.value == value);
+  match constant.map(|constant| constant.name) {
+    Some("pi") => T::pi(),
+    Some("e") => T::e(),
+    Some("tau") => T::pi() * T::from_f64(2.0),
+    Some("phi") => (T::from_f64(1.0) + T::from_f64(5.0).sqrt()) / T::from_f64(2.0),
+    _ if value.is_finite() => T::parse(&format!("{:e}", value)).unwrap_or(T::from_f64(value)),
+    _ => T::from_f64(value),
+  }
+}
+
+fn binary<T: Real>(left: T, operator: &str, right: T) -> Result<T, CalculatorError> {
+  let zero = T::from_f64(0.0);
+  match operator {
+    "+" => Ok(left + right),
+    "-" => Ok(left - right),
+    "*" => Ok(left * right),
+    "/" if right == zero => Err(CalculatorError::MathError("Division by zero".to_string())),
+    "/" => Ok(left / right),
+    "%" if right == zero => Err(CalculatorError::MathError("Modulo by zero".to_string())),
+    "%" => Ok(left % right),
+    "^" => power(left, right),
+    _ if crate::is_comparison(operator) => Ok(truth(crate::compare(left, operator, right))),
+    "and" | "or" => Ok(truth(crate::both(left != zero, operator, right != zero))),
+    _ => Err(not_available::<T>(operator)),
+  }
+}
+
+/// 1 for true and 0 for false
+fn truth<T: Real>(value: bool) -> T {
+  T::from_f64(if value { 1.0 } else { 0.0 })
+}
+
+fn power<T: Real>(base: T, exponent: T) -> Result<T, CalculatorError> {
+  limits::check_exponent(exponent.to_f64())?;
+  let (zero, one) = (T::from_f64(0.0), T::from_f64(1.0));
+  if base == zero && exponent == zero {
+    if settings::current().non_finite == settings::NonFinitePolicy::Strict {
+      return Err(CalculatorError::MathError("0 ^ 0 is undefined".to_string()));
+    }
+    return Ok(one);
+  }
+  // Whole exponents multiply, which is exact where the type allows
+  if is_whole(exponent) && exponent.to_f64().abs() <= u32::MAX as f64 {
+    let mut remaining = exponent.to_f64().abs() as u32;
+    let (mut square, mut result) = (base, one);
+    while remaining > 0 {
+      if remaining & 1 == 1 {
+        result = result * square;
+      }
+      remaining >>= 1;
+      if remaining > 0 {
+        square = square * square;
+      }
+    }
+    return Ok(if exponent < zero {
+      one / result
+    } else {
+      result
+    });
+  }
+  if base < zero {
+    return domain_violation("Cannot raise a negative number to a fractional power");
+  }
+  if base == zero {
+    return Ok(if exponent > zero {
+      zero
+    } else {
+      T::from_f64(f64::INFINITY)
+    });
+  }
+  Ok((exponent * base.ln()).exp())
+}
+
+fn function<T: Real>(name: &str, value: T) -> Result<T, CalculatorError> {
+  let (zero, one) = (T::from_f64(0.0), T::from_f64(1.0));
+  let angle_mode = settings::current().angle_mode;
+  match name {
+    "sqrt" if value < zero => domain_violation("Cannot calculate square root of negative number"),
+    "sqrt" => Ok(value.sqrt()),
+    "sin" => Ok(to_radians(value, angle_mode).sin()),
+    "cos" => Ok(to_radians(value, angle_mode).cos()),
+    "tan" => Ok(to_radians(value, angle_mode).tan()),
+    "asin" | "acos" if value < -one || value > one => domain_violation(if name == "asin" {
+      "Inverse sine argument must be between -1 and 1"
+    } else {
+      "Inverse cosine argument must be between -1 and 1"
+    }),
+    "asin" => Ok(from_radians(value.asin(), angle_mode)),
+    "acos" => Ok(from_radians(value.acos(), angle_mode)),
+    "atan" => Ok(from_radians(value.atan(), angle_mode)),
+    "log" | "ln" if value <= zero => domain_violation(if name == "log" {
+      "Cannot calculate logarithm of non-positive number"
+    } else {
+      "Cannot calculate natural logarithm of non-positive number"
+    }),
+    "log" => Ok(value.ln() / T::from_f64(10.0).ln()),
+    "ln" => Ok(value.ln()),
+    "exp" => Ok(value.exp()),
+    "neg" => Ok(-value),
+    "not" => Ok(truth(value == zero)),
+    "percent" => Ok(value / T::from_f64(100.0)),
+    "abs" if value < zero => Ok(-value),
+    "abs" => Ok(value),
+    "floor" => Ok(value.floor()),
+    "ceil" => Ok(value.ceil()),
+    "round" => Ok(round(value, settings::current().rounding)),
+    "fact" => factorial(value),
+    _ => Err(not_available::<T>(name)),
+  }
+}
+
+fn factorial<T: Real>(value: T) -> Result<T, CalculatorError> {
+  if value < T::from_f64(0.0) {
+    return Err(CalculatorError::ArgumentError(
+      "Cannot calculate factorial of negative number".to_string(),
+    ));
+  }
+  limits::check_factorial(value.to_f64())?;
+  if !is_whole(value) {
+    return Err(CalculatorError::ArgumentError(
+      "Factorial requires an integer value".to_string(),
+    ));
+  }
+  let mut result = T::from_f64(1.0);
+  for i in 2..=value.to_f64() as u64 {
+    result = result * T::from_f64(i as f64);
+    if i % 65536 == 0 {
+      limits::check_time()?;
+    }
+  }
+  Ok(result)
+}
+
+/// Rounds to a whole number as `mode` says
+fn round<T: Real>(value: T, mode: RoundingMode) -> T {
+  let floor = value.floor();
+  let fraction = value - floor;
+  let (half, one) = (T::from_f64(0.5), T::from_f64(1.0));
+  let up = match mode {
+    RoundingMode::Floor => false,
+    RoundingMode::Ceiling => fraction > T::from_f64(0.0),
+    // Halves go away from zero, which is down below zero
+    RoundingMode::HalfUp if value < T::from_f64(0.0) => fraction > half,
+    RoundingMode::HalfUp => fraction >= half,
+    RoundingMode::HalfEven if fraction == half => !is_whole(floor / T::from_f64(2.0)),
+    RoundingMode::HalfEven => fraction > half,
+  };
+  if up { floor + one } else { floor }
+}
+
+fn to_radians<T: Real>(angle: T, mode: AngleMode) -> T {
+  match mode {
+    AngleMode::Radians => angle,
+    AngleMode::Degrees => angle * T::pi() / T::from_f64(180.0),
+    AngleMode::Gradians => angle * T::pi() / T::from_f64(200.0),
+  }
+}
+
+fn from_radians<T: Real>(radians: T, mode: AngleMode) -> T {
+  match mode {
+    AngleMode::Radians => radians,
+    AngleMode::Degrees => radians * T::from_f64(180.0) / T::pi(),
+    AngleMode::Gradians => radians * T::from_f64(200.0) / T::pi(),
+  }
+}
+
+fn is_whole<T: Real>(value: T) -> bool {
+  value.floor() == value
+}
+
+fn is_nan<T: Real>(value: T) -> bool {
+  value.to_f64().is_nan()
+}
+
+fn is_finite<T: Real>(value: T) -> bool {
+  value.to_f64().is_finite()
+}
+
+/// Checks a result as the main evaluator does, against the overflow and
+/// non-finite policies
+fn checked<T: Real>(result: T, finite_inputs: bool) -> Result<T, CalculatorError> {
+  crate::check_overflow(result.to_f64(), finite_inputs).map(|_| result)
+}
+
+fn domain_violation<T: Real>(message: &str) -> Result<T, CalculatorError> {
+  crate::domain_violation(message).map(T::from_f64)
+}
+
+fn not_available<T: Real>(name: &str) -> CalculatorError {
+  CalculatorError::ArgumentError(format!("{} isn't available in {}", name, T::NAME))
+}
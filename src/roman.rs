@@ -0,0 +1,107 @@
+//! # Roman Numerals
+//!
+//! Conversions between numbers and Roman numerals. `roman(x)` produces text,
+//! so it is only available through [`evaluate_to_string`](crate::evaluate_to_string),
+//! while `from_roman("XIV")` can appear anywhere in an expression.
+
+use crate::CalculatorError;
+use crate::prelude::*;
+use alloc::borrow::Cow;
+
+/// Numeral symbols, including the subtractive pairs, from largest to smallest
+const NUMERALS: [(u32, &str); 13] = [
+  (1000, "M"),
+  (900, "CM"),
+  (500, "D"),
+  (400, "CD"),
+  (100, "C"),
+  (90, "XC"),
+  (50, "L"),
+  (40, "XL"),
+  (10, "X"),
+  (9, "IX"),
+  (5, "V"),
+  (4, "IV"),
+  (1, "I"),
+];
+
+/// Largest number with a standard Roman numeral
+pub const MAX: u32 = 3999;
+
+/// Converts a whole number between 1 and 3999 to a Roman numeral
+pub fn to_roman(value: f64) -> Result<String, CalculatorError> {
+  if value.fract() != 0.0 || !(1.0..=MAX as f64).contains(&value) {
+    return Err(CalculatorError::ArgumentError(format!(
+      "Roman numerals require a whole number between 1 and {}",
+      MAX
+    )));
+  }
+
+  let mut remaining = value as u32;
+  let mut numeral = String::new();
+  for (amount, symbol) in NUMERALS {
+    while remaining >= amount {
+      numeral.push_str(symbol);
+      remaining -= amount;
+    }
+  }
+  Ok(numeral)
+}
+
+/// Parses a Roman numeral such as `XIV` (case-insensitive).
+///
+/// Only standard numerals are accepted, so `IIII` or `IC` are rejected.
+pub fn from_roman(numeral: &str) -> Result<f64, CalculatorError> {
+  let upper = numeral.trim().to_uppercase();
+  let invalid = || CalculatorError::ArgumentError(format!("Invalid Roman numeral: {}", numeral));
+
+  let mut rest = upper.as_str();
+  let mut value = 0;
+  for (amount, symbol) in NUMERALS {
+    while let Some(stripped) = rest.strip_prefix(symbol) {
+      value += amount;
+      rest = stripped;
+    }
+  }
+
+  // Greedy parsing accepts some non-standard forms, so compare with the canonical spelling
+  if !rest.is_empty() || value == 0 || to_roman(value as f64)? != upper {
+    return Err(invalid());
+  }
+  Ok(value as f64)
+}
+
+/// Replaces every `from_roman("...")` call with its numeric value, since the
+/// tokenizer has no notion of quoted text
+pub(crate) fn expand_literals(expression: &str) -> Result<Cow<'_, str>, CalculatorError> {
+  const CALL: &str = "from_roman(";
+  if !expression.contains(CALL) {
+    return Ok(Cow::Borrowed(expression));
+  }
+
+  let usage = || {
+    CalculatorError::ArgumentError(
+      "from_roman expects a quoted numeral, e.g. from_roman(\"XIV\")".to_string(),
+    )
+  };
+
+  let mut expanded = String::new();
+  let mut rest = expression;
+  while let Some(start) = rest.find(CALL) {
+    expanded.push_str(&rest[..start]);
+    let argument = rest[start + CALL.len()..].trim_start();
+
+    let quote = argument
+      .chars()
+      .next()
+      .filter(|ch| *ch == '"' || *ch == '\'')
+      .ok_or_else(usage)?;
+    let (numeral, after) = argument[1..].split_once(quote).ok_or_else(usage)?;
+    rest = after.trim_start().strip_prefix(')').ok_or_else(usage)?;
+
+    expanded.push_str(&from_roman(numeral)?.to_string());
+  }
+  expanded.push_str(rest);
+
+  Ok(Cow::Owned(expanded))
+}
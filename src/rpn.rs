@@ -0,0 +1,204 @@
+//! # Reverse Polish Notation
+//!
+//! Converts expressions to the postfix (RPN) form the evaluator actually uses
+//! internally, and back to infix, for tooling and teaching material. Postfix
+//! input itself is evaluated by
+//! [`evaluate_rpn_expression`](crate::evaluate_rpn_expression).
+//!
+//! ```
+//! use rust_calculator::rpn::{from_rpn, to_rpn};
+//!
+//! assert_eq!(to_rpn("2 + 3 * 4").unwrap(), "2 3 4 * +");
+//! assert_eq!(from_rpn("2 3 + 4 *").unwrap(), "(2 + 3) * 4");
+//! ```
+
+use crate::prelude::*;
+use crate::{CalculatorError, Resolver, SpannedToken, Token};
+
+/// Precedence of operands and function calls, which never need parentheses
+const ATOM: u8 = u8::MAX;
+
+/// Converts an infix expression to space-separated Reverse Polish Notation.
+///
+/// Constants and variables appear as their values, exactly as the evaluator
+/// sees them, so `2 * pi` becomes `2 3.141592653589793 *`.
+pub fn to_rpn(expression: &str) -> Result<String, CalculatorError> {
+  let tokens = crate::tokenize_spanned(expression, &crate::get_variable)?;
+  crate::check_identifiers(&tokens)?;
+  if tokens.is_empty() {
+    return Err(CalculatorError::SyntaxError("Empty expression".to_string()));
+  }
+
+  let postfix = crate::parser::to_postfix(tokens)?;
+  check_operands(&postfix)?;
+
+  Ok(
+    postfix
+      .iter()
+      .map(|(token, _)| match token {
+        Token::Number(value) => value.to_string(),
+        Token::Identifier(name) | Token::Operator(name) | Token::Function(name) => name.clone(),
+        Token::LeftParen => "(".to_string(),
+        Token::RightParen => ")".to_string(),
+      })
+      .collect::<Vec<_>>()
+      .join(" "),
+  )
+}
+
+/// Converts space-separated Reverse Polish Notation back to an infix
+/// expression, adding only the parentheses needed to keep its structure.
+///
+/// Identifiers that aren't functions are kept as names, so `2 pi *` becomes
+/// `2 * pi`.
+pub fn from_rpn(rpn: &str) -> Result<String, CalculatorError> {
+  // Each entry is an infix fragment and the precedence of its outermost operator
+  let mut stack: Vec<(String, u8)> = Vec::new();
+
+  for token in rpn.split_whitespace() {
+    if token.parse::<f64>().is_ok() {
+      stack.push((token.to_string(), ATOM));
+    } else if let Some(precedence) = operator_precedence(token) {
+      let (Some(right), Some(left)) = (stack.pop(), stack.pop()) else {
+        return Err(not_enough_operands(token));
+      };
+      let right_assoc = token == "^";
+      let left = parenthesize(left, precedence, right_assoc);
+      let infix = match token {
+        // The brackets of an item's position group it already
+        "at" => format!("{}[{}]", left, right.0),
+        "," => format!("{}, {}", left, parenthesize(right, precedence, true)),
+        _ => format!(
+          "{} {} {}",
+          left,
+          token,
+          parenthesize(right, precedence, !right_assoc)
+        ),
+      };
+      stack.push((infix, precedence));
+    } else if crate::parser::is_nullary_function(token) {
+      // Memory recall and clear ignore the placeholder argument they're given
+      if stack.pop().is_none() {
+        return Err(not_enough_operands(token));
+      }
+      stack.push((token.to_string(), ATOM));
+    } else if crate::is_math_function(token) || crate::parser::is_postfix_function(token) {
+      let Some(argument) = stack.pop() else {
+        return Err(not_enough_operands(token));
+      };
+      let infix = match token {
+        "percent" => format!("{}%", parenthesize(argument, ATOM, false)),
+        "list" => format!("[{}]", argument.0),
+        _ => format!("{}({})", token, argument.0),
+      };
+      stack.push((infix, ATOM));
+    } else if token.chars().all(|ch| ch.is_alphanumeric() || ch == '_') {
+      stack.push((token.to_string(), ATOM));
+    } else {
+      return Err(CalculatorError::ParseError(format!(
+        "Unknown token: {}",
+        token
+      )));
+    }
+  }
+
+  match (stack.pop(), stack.is_empty()) {
+    (Some((expression, _)), true) => Ok(expression),
+    (None, _) => Err(CalculatorError::SyntaxError("Empty expression".to_string())),
+    (Some(_), false) => Err(CalculatorError::SyntaxError(
+      "Invalid expression: too many operands".to_string(),
+    )),
+  }
+}
+
+/// Tokenizes space-separated postfix input, which is already in the order
+/// the evaluator consumes
+pub(crate) fn tokenize_postfix(
+  rpn: &str,
+  resolve: Resolver,
+) -> Result<Vec<SpannedToken>, CalculatorError> {
+  let mut tokens: Vec<SpannedToken> = Vec::new();
+
+  for (start, token) in words(rpn) {
+    let span = start..start + token.len();
+    if operator_precedence(token).is_some() {
+      tokens.push((Token::Operator(token.to_string()), span));
+    } else if crate::parser::is_nullary_function(token) {
+      // Memory recall and clear take a placeholder argument, as in to_postfix
+      tokens.push((Token::Number(0.0), span.start..span.start));
+      tokens.push((Token::Function(token.to_string()), span));
+    } else {
+      let mut added = Vec::new();
+      crate::add_token(&mut added, token, resolve).map_err(|err| err.at(span.clone()))?;
+      tokens.extend(added.into_iter().map(|token| (token, span.clone())));
+    }
+  }
+
+  crate::check_identifiers(&tokens)?;
+  Ok(tokens)
+}
+
+/// The whitespace-separated words of `text`, with their byte offsets
+fn words(text: &str) -> Vec<(usize, &str)> {
+  let mut words = Vec::new();
+  let mut start = None;
+  for (index, ch) in text.char_indices() {
+    match (ch.is_whitespace(), start) {
+      (true, Some(word_start)) => {
+        words.push((word_start, &text[word_start..index]));
+        start = None;
+      }
+      (false, None) => start = Some(index),
+      _ => {}
+    }
+  }
+  if let Some(word_start) = start {
+    words.push((word_start, &text[word_start..]));
+  }
+  words
+}
+
+/// Precedence of a binary operator, or `None` if `token` isn't one
+fn operator_precedence(token: &str) -> Option<u8> {
+  crate::is_binary_operator(token).then(|| crate::get_precedence(token))
+}
+
+/// Wraps an operand in parentheses when it binds more loosely than its parent
+/// operator, or equally loosely on the side where order matters
+fn parenthesize((operand, precedence): (String, u8), parent: u8, strict: bool) -> String {
+  if precedence < parent || (strict && precedence == parent) {
+    format!("({})", operand)
+  } else {
+    operand
+  }
+}
+
+/// Checks that every operator and function in a postfix sequence has its operands
+fn check_operands(postfix: &[SpannedToken]) -> Result<(), CalculatorError> {
+  let mut depth = 0usize;
+  for (token, span) in postfix {
+    match token {
+      Token::Number(_) | Token::Identifier(_) => depth += 1,
+      Token::Operator(op) if depth < 2 => return Err(not_enough_operands(op).at(span.clone())),
+      Token::Operator(_) => depth -= 1,
+      Token::Function(name) if depth < 1 => {
+        return Err(not_enough_operands(name).at(span.clone()));
+      }
+      _ => {}
+    }
+  }
+
+  if depth != 1 {
+    return Err(CalculatorError::SyntaxError(
+      "Invalid expression: too many operands".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+fn not_enough_operands(token: &str) -> CalculatorError {
+  CalculatorError::SyntaxError(format!(
+    "Invalid expression: not enough operands for '{}'",
+    token
+  ))
+}
@@ -0,0 +1,91 @@
+//! # Integer Sequences
+//!
+//! Terms of classic integer sequences: `fib(n)` for the Fibonacci numbers,
+//! `lucas(n)` for the Lucas numbers, `triangular(n)` for the triangular
+//! numbers `1 + 2 + ... + n` and `catalan(n)` for the Catalan numbers. Each
+//! takes a whole index from 0.
+//!
+//! Terms beyond 2^53 lose digits as `f64`s, but expressions made of these
+//! functions are worked out again exactly by
+//! [`evaluate_integer`](crate::evaluate_integer), so results show all their
+//! digits. That includes terms too large for an `f64` at all, such as
+//! `fib 2000`, which [`evaluate_to_string`](crate::evaluate_to_string) gives
+//! in full.
+//!
+//! ```
+//! use rust_calculator::sequences;
+//!
+//! assert_eq!(sequences::fibonacci(10), 55.0);
+//! assert_eq!(sequences::lucas(10), 123.0);
+//! assert_eq!(sequences::triangular(10), 55.0);
+//! assert_eq!(sequences::catalan(10), 16796.0);
+//! assert_eq!(rust_calculator::evaluate_integer("fib 100").unwrap().to_string(), "354224848179261915075");
+//! ```
+
+use crate::CalculatorError;
+use crate::prelude::*;
+
+/// The `n`th Fibonacci number, with fib(0) = 0 and fib(1) = 1
+pub fn fibonacci(n: u64) -> f64 {
+  linear(0.0, 1.0, n)
+}
+
+/// The `n`th Lucas number, with lucas(0) = 2 and lucas(1) = 1
+pub fn lucas(n: u64) -> f64 {
+  linear(2.0, 1.0, n)
+}
+
+/// The `n`th triangular number, `n (n + 1) / 2`
+pub fn triangular(n: u64) -> f64 {
+  let n = n as f64;
+  n * (n + 1.0) / 2.0
+}
+
+/// The `n`th Catalan number, `(2n)! / (n! (n + 1)!)`
+pub fn catalan(n: u64) -> f64 {
+  // Each term is the one before times 2 (2i - 1) / (i + 1), which divides
+  // exactly while the product fits in a u128
+  let mut exact: u128 = 1;
+  let mut i = 1;
+  while i <= n {
+    match exact.checked_mul(4 * i as u128 - 2) {
+      Some(product) => exact = product / (i as u128 + 1),
+      None => break,
+    }
+    i += 1;
+  }
+  let mut term = exact as f64;
+  for i in i..=n {
+    term = term * (4 * i - 2) as f64 / (i + 1) as f64;
+    if term.is_infinite() {
+      break;
+    }
+  }
+  term
+}
+
+/// Reads the index of a sequence function, which must be a non-negative
+/// whole number
+pub(crate) fn index(value: f64, function: &str) -> Result<u64, CalculatorError> {
+  if value.fract() != 0.0 || value < 0.0 {
+    return Err(CalculatorError::ArgumentError(format!(
+      "{} needs a non-negative whole number, got {}",
+      function, value
+    )));
+  }
+  Ok(value as u64)
+}
+
+/// The `n`th term of the sequence starting `first, second` in which each
+/// term is the sum of the two before it
+fn linear(first: f64, second: f64, n: u64) -> f64 {
+  let (mut current, mut next) = (first, second);
+  for _ in 0..n {
+    (current, next) = (next, current + next);
+    // Every later term is infinite too
+    if current.is_infinite() {
+      break;
+    }
+  }
+  current
+}
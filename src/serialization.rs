@@ -0,0 +1,172 @@
+//! # Serialization
+//!
+//! `serde` support for expression trees and errors, with the `serde` feature.
+//! Other types derive their implementations where they're defined.
+//!
+//! An [`Expr`] serializes to the same shape as [`Expr::to_json`], and is
+//! validated by the same checks when it's deserialized. A [`CalculatorError`]
+//! serializes to its kind, its message and, for located errors, its span:
+//!
+//! ```json
+//! {"kind": "parse_error", "message": "Unknown token: xyz", "span": {"start": 4, "end": 7}}
+//! ```
+
+use crate::ast::{check_function, check_operator, check_variable};
+use crate::prelude::*;
+use crate::{CalculatorError, Expr};
+use core::ops::Range;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The serialized form of a node of an [`Expr`]
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Node {
+  Number {
+    value: f64,
+  },
+  Variable {
+    name: String,
+  },
+  Binary {
+    operator: String,
+    left: Box<Expr>,
+    right: Box<Expr>,
+  },
+  Function {
+    name: String,
+    argument: Box<Expr>,
+  },
+}
+
+impl Serialize for Expr {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut node;
+    match self {
+      Expr::Number(value) => {
+        node = serializer.serialize_struct("Expr", 2)?;
+        node.serialize_field("type", "number")?;
+        node.serialize_field("value", value)?;
+      }
+      Expr::Variable(name) => {
+        node = serializer.serialize_struct("Expr", 2)?;
+        node.serialize_field("type", "variable")?;
+        node.serialize_field("name", name)?;
+      }
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } => {
+        node = serializer.serialize_struct("Expr", 4)?;
+        node.serialize_field("type", "binary")?;
+        node.serialize_field("operator", operator)?;
+        node.serialize_field("left", left)?;
+        node.serialize_field("right", right)?;
+      }
+      Expr::Function { name, argument } => {
+        node = serializer.serialize_struct("Expr", 3)?;
+        node.serialize_field("type", "function")?;
+        node.serialize_field("name", name)?;
+        node.serialize_field("argument", argument)?;
+      }
+    }
+    node.end()
+  }
+}
+
+impl<'de> Deserialize<'de> for Expr {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    match Node::deserialize(deserializer)? {
+      Node::Number { value } => Ok(Expr::Number(value)),
+      Node::Variable { name } => {
+        check_variable(&name).map_err(serde::de::Error::custom)?;
+        Ok(Expr::Variable(name))
+      }
+      Node::Binary {
+        operator,
+        left,
+        right,
+      } => {
+        check_operator(&operator).map_err(serde::de::Error::custom)?;
+        Ok(Expr::Binary {
+          operator,
+          left,
+          right,
+        })
+      }
+      Node::Function { name, argument } => {
+        check_function(&name).map_err(serde::de::Error::custom)?;
+        Ok(Expr::Function { name, argument })
+      }
+    }
+  }
+}
+
+/// The kinds of [`CalculatorError`], as they're serialized
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ErrorKind {
+  ParseError,
+  MathError,
+  SyntaxError,
+  ArgumentError,
+  Overflow,
+  LimitExceeded,
+  ReservedName,
+  Other,
+}
+
+/// The serialized form of a [`CalculatorError`]
+#[derive(Serialize, Deserialize)]
+struct ErrorRecord {
+  kind: ErrorKind,
+  message: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  span: Option<Range<usize>>,
+}
+
+impl Serialize for CalculatorError {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let (kind, message) = match self.unspanned() {
+      CalculatorError::ParseError(message) => (ErrorKind::ParseError, message),
+      CalculatorError::MathError(message) => (ErrorKind::MathError, message),
+      CalculatorError::SyntaxError(message) => (ErrorKind::SyntaxError, message),
+      CalculatorError::ArgumentError(message) => (ErrorKind::ArgumentError, message),
+      CalculatorError::Overflow(message) => (ErrorKind::Overflow, message),
+      CalculatorError::LimitExceeded(message) => (ErrorKind::LimitExceeded, message),
+      CalculatorError::ReservedName(name) => (ErrorKind::ReservedName, name),
+      CalculatorError::Other(message) => (ErrorKind::Other, message),
+      CalculatorError::Spanned { .. } => unreachable!("unspanned errors aren't located"),
+    };
+    ErrorRecord {
+      kind,
+      message: message.clone(),
+      span: self.span(),
+    }
+    .serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for CalculatorError {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let record = ErrorRecord::deserialize(deserializer)?;
+    let error = match record.kind {
+      ErrorKind::ParseError => CalculatorError::ParseError(record.message),
+      ErrorKind::MathError => CalculatorError::MathError(record.message),
+      ErrorKind::SyntaxError => CalculatorError::SyntaxError(record.message),
+      ErrorKind::ArgumentError => CalculatorError::ArgumentError(record.message),
+      ErrorKind::Overflow => CalculatorError::Overflow(record.message),
+      ErrorKind::LimitExceeded => CalculatorError::LimitExceeded(record.message),
+      ErrorKind::ReservedName => CalculatorError::ReservedName(record.message),
+      ErrorKind::Other => CalculatorError::Other(record.message),
+    };
+    Ok(match record.span {
+      Some(span) => CalculatorError::Spanned {
+        error: Box::new(error),
+        span,
+      },
+      None => error,
+    })
+  }
+}
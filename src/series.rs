@@ -0,0 +1,213 @@
+//! # Sums and Products
+//!
+//! `sum(var, from, to, expr)` adds up `expr` for each whole number `var`
+//! from `from` to `to`, and `prod(var, from, to, expr)` multiplies the terms
+//! instead. The variable is bound only inside `expr`, where it hides any
+//! stored variable of the same name, and calls can be nested. A range whose
+//! end comes before its start is empty: its sum is 0 and its product 1.
+//!
+//! ```
+//! use rust_calculator::evaluate_expression;
+//!
+//! assert_eq!(evaluate_expression("sum(i, 1, 100, i^2)").unwrap(), 338350.0);
+//! assert_eq!(evaluate_expression("prod(k, 1, 5, k)").unwrap(), 120.0);
+//! assert_eq!(evaluate_expression("sum(i, 1, 3, sum(j, 1, i, j))").unwrap(), 10.0);
+//! ```
+
+use crate::ast::{self, Expr};
+use crate::prelude::*;
+use crate::{CalculatorError, Resolver, Token, limits};
+
+/// The most terms a single sum or product may have
+pub const MAX_TERMS: u64 = 1_000_000;
+
+/// Checks whether `name` is a sum or product, which binds a variable
+pub(crate) fn is_series(name: &str) -> bool {
+  matches!(name, "sum" | "prod")
+}
+
+/// Checks whether `name` is a function that binds a variable: a sum or
+/// product, or a function of an expression in the variable named by its
+/// second argument
+pub(crate) fn binds_variable(name: &str) -> bool {
+  is_series(name) || binds_second_argument(name)
+}
+
+fn binds_second_argument(name: &str) -> bool {
+  matches!(name, "solve" | "limit" | "minimize" | "maximize")
+}
+
+/// Parses an expression into a tree, keeping the variables bound by `sum`,
+/// `prod`, `solve`, `limit`, `minimize` and `maximize` as variables so each term can be evaluated
+/// separately
+pub(crate) fn parse(expression: &str, resolve: Resolver) -> Result<Expr, CalculatorError> {
+  let tokens = crate::tokenize_spanned(expression, resolve)?;
+  let bound = bound_names(expression, &tokens);
+  // A bound name stays a variable in the tree, even where a stored variable
+  // of that name exists, and the tree decides which one it means
+  let unbound = |name: &str| {
+    if bound.contains(&name) {
+      None
+    } else {
+      resolve(name)
+    }
+  };
+  let mut tokens = crate::tokenize_spanned(expression, &unbound)?;
+  name_functions(&mut tokens);
+  ast::parse_tokens(tokens, expression)
+}
+
+/// Checks an expression that's evaluated as a tree for errors without
+/// evaluating it: it has to parse, and the only names left in the tree may
+/// be those its calls bind. `tokens` are those of the expression.
+pub(crate) fn check(
+  expression: &str,
+  tokens: &[crate::SpannedToken],
+  resolve: Resolver,
+) -> Result<(), CalculatorError> {
+  let tree = parse(expression, resolve)?;
+  let bound = bound_names(expression, tokens);
+  match tree
+    .variables()
+    .into_iter()
+    .find(|name| !bound.contains(name))
+  {
+    Some(name) => {
+      let span = tokens.iter().find_map(|(token, span)| match token {
+        Token::Identifier(identifier) if identifier == name => Some(span.clone()),
+        _ => None,
+      });
+      let error = crate::unknown_token(name);
+      Err(match span {
+        Some(span) => error.at(span),
+        None => error,
+      })
+    }
+    None => Ok(()),
+  }
+}
+
+/// Turns a function passed by name to `map`, `filter` or `reduce` into an
+/// identifier, so it's an argument rather than a call
+fn name_functions(tokens: &mut [crate::SpannedToken]) {
+  for index in 0..tokens.len() {
+    let passes_function =
+      matches!(&tokens[index].0, Token::Function(name) if crate::lists::takes_function(name));
+    if !passes_function {
+      continue;
+    }
+    if let [
+      (Token::LeftParen, _),
+      (argument, _),
+      (Token::Operator(comma), _),
+      ..,
+    ] = &mut tokens[index + 1..]
+    {
+      if let (Token::Function(name), ",") = (&*argument, comma.as_str()) {
+        *argument = Token::Identifier(name.clone());
+      }
+    }
+  }
+}
+
+/// Evaluates a call of `sum` or `prod` with `arguments` already split at
+/// their commas
+pub(crate) fn evaluate_call(
+  function: &str,
+  arguments: &[&Expr],
+  resolve: Resolver,
+) -> Result<f64, CalculatorError> {
+  crate::check_arity(function, arguments.len())?;
+  let [variable, from, to, term] = arguments else {
+    unreachable!("check_arity allows only 4 arguments");
+  };
+  let Expr::Variable(variable) = variable else {
+    return Err(CalculatorError::ArgumentError(format!(
+      "{} needs a variable name as its first argument",
+      function
+    )));
+  };
+  let from = bound(from.evaluate_with(resolve)?, function)?;
+  let to = bound(to.evaluate_with(resolve)?, function)?;
+  if to >= from && to - from >= MAX_TERMS as f64 {
+    return Err(CalculatorError::ArgumentError(format!(
+      "{} can have at most {} terms",
+      function, MAX_TERMS
+    )));
+  }
+
+  let mut result = match function {
+    "sum" => 0.0,
+    _ => 1.0,
+  };
+  let count = if to < from { 0 } else { (to - from) as u64 + 1 };
+  for step in 0..count {
+    limits::check_time()?;
+    let index = from + step as f64;
+    let binding = |name: &str| {
+      if name == variable {
+        Some(index)
+      } else {
+        resolve(name)
+      }
+    };
+    let value = term.evaluate_with(&binding)?;
+    match function {
+      "sum" => result += value,
+      _ => result *= value,
+    }
+  }
+  crate::check_overflow(result, true)
+}
+
+/// The names bound by the calls among `tokens` that bind variables: the
+/// text of the first token inside each `sum` or `prod` call's parentheses,
+/// and of the first token after the first argument of the others
+fn bound_names<'a>(expression: &'a str, tokens: &[crate::SpannedToken]) -> Vec<&'a str> {
+  let mut names = Vec::new();
+  for (index, (token, _)) in tokens.iter().enumerate() {
+    let Token::Function(name) = token else {
+      continue;
+    };
+    let arguments = &tokens[index + 1..];
+    let span = if is_series(name) || crate::lists::takes_function(name) {
+      arguments.get(1).map(|(_, span)| span)
+    } else if binds_second_argument(name) {
+      second_argument(arguments)
+    } else {
+      None
+    };
+    names.extend(span.map(|span| &expression[span.clone()]));
+  }
+  names.retain(|name| crate::is_identifier(name));
+  names
+}
+
+/// The span of the first token after the first comma of a call, given the
+/// tokens from its left parenthesis
+fn second_argument(tokens: &[crate::SpannedToken]) -> Option<&core::ops::Range<usize>> {
+  let mut depth = 0;
+  for (index, (token, _)) in tokens.iter().enumerate() {
+    match token {
+      Token::LeftParen => depth += 1,
+      Token::RightParen if depth <= 1 => return None,
+      Token::RightParen => depth -= 1,
+      Token::Operator(operator) if operator == "," && depth == 1 => {
+        return tokens.get(index + 1).map(|(_, span)| span);
+      }
+      _ => {}
+    }
+  }
+  None
+}
+
+/// Reads a bound of a sum or product, which must be a whole number
+fn bound(value: f64, function: &str) -> Result<f64, CalculatorError> {
+  if value.fract() != 0.0 || value.is_infinite() {
+    return Err(CalculatorError::ArgumentError(format!(
+      "{} needs whole-number bounds, got {}",
+      function, value
+    )));
+  }
+  Ok(value)
+}
@@ -0,0 +1,631 @@
+//! # Evaluation Settings
+//!
+//! Options that change how expressions are evaluated. A process-wide default
+//! applies to every evaluation, and [`evaluate_expression_with_settings`]
+//! overrides it for a single call without affecting other threads.
+//!
+//! Each option can also be changed by name with [`Settings::set`], as the
+//! CLI's `set` command does, e.g. `set rounding half-even`.
+//!
+//! [`evaluate_expression_with_settings`]: crate::evaluate_expression_with_settings
+
+use crate::CalculatorError;
+use crate::prelude::*;
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::cell::Cell;
+#[cfg(feature = "std")]
+use std::sync::RwLock;
+
+/// Implements `FromStr` and `Display` for an option enum from its variants'
+/// names; the first name of each variant is the one displayed
+macro_rules! option_names {
+  ($option:ident, $label:literal, $($variant:ident => $name:literal $(| $alias:literal)*),+ $(,)?) => {
+    impl FromStr for $option {
+      type Err = CalculatorError;
+
+      fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_lowercase().as_str() {
+          $($name $(| $alias)* => Ok($option::$variant),)+
+          other => Err(CalculatorError::ArgumentError(format!(
+            concat!("Unknown ", $label, ": {} (expected {})"),
+            other,
+            [$($name),+].join(", ")
+          ))),
+        }
+      }
+    }
+
+    impl fmt::Display for $option {
+      fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+          $($option::$variant => $name,)+
+        })
+      }
+    }
+  };
+}
+
+/// What to do when a function is given an argument outside its real domain,
+/// such as `sqrt(-4)` or `log(0)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DomainPolicy {
+  /// Fail with an argument error
+  #[default]
+  Error,
+  /// Return NaN and let it propagate
+  NaN,
+  /// Evaluate in complex numbers; real results are returned as usual, other
+  /// results are available through [`evaluate_complex`](crate::evaluate_complex)
+  Complex,
+}
+
+/// What to do when an operation on finite numbers produces infinity or NaN,
+/// such as `fact 171` or `10 ^ 400`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+  /// Fail with an overflow error
+  #[default]
+  Error,
+  /// Return infinity or NaN as IEEE 754 arithmetic does
+  Ieee,
+}
+
+/// What to do with infinite and NaN numbers written in an expression, such as
+/// `inf`, `nan` or `1e400`, or stored in variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonFinitePolicy {
+  /// Accept them; NaN passes through every operator and function unchanged
+  #[default]
+  Propagate,
+  /// Fail with an argument error
+  Reject,
+  /// Reject them, and also fail with a math error whenever an operation
+  /// gives infinity or NaN, whatever the other policies say, and for the
+  /// indeterminate `0 ^ 0`
+  Strict,
+}
+
+/// Unit of the angles taken by sin, cos and tan and returned by their inverses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AngleMode {
+  #[default]
+  Degrees,
+  Radians,
+  /// 400 to the full turn, so a right angle is 100
+  Gradians,
+}
+
+/// How numbers are rounded by `round` and when formatting results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+  /// Ties go away from zero: 2.5 becomes 3 and -2.5 becomes -3
+  #[default]
+  HalfUp,
+  /// Ties go to the even neighbour (banker's rounding): 2.5 becomes 2
+  HalfEven,
+  /// Always toward negative infinity
+  Floor,
+  /// Always toward positive infinity
+  Ceiling,
+}
+
+/// How results are written: the separator between groups of three digits,
+/// if any, and the decimal separator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+  /// `1234567.89`
+  #[default]
+  Plain,
+  /// `1,234,567.89`, as in English
+  En,
+  /// `1.234.567,89`, as in German, Spanish and Italian
+  De,
+  /// `1 234 567,89`, as in French and most of northern and eastern Europe
+  Fr,
+  /// `1'234'567.89`, as in Switzerland
+  Ch,
+}
+
+impl Locale {
+  /// Separator between groups of three digits of the whole part, if any
+  pub fn group_separator(self) -> Option<char> {
+    match self {
+      Locale::Plain => None,
+      Locale::En => Some(','),
+      Locale::De => Some('.'),
+      Locale::Fr => Some(' '),
+      Locale::Ch => Some('\''),
+    }
+  }
+
+  /// Separator between the whole and fractional parts
+  pub fn decimal_separator(self) -> char {
+    match self {
+      Locale::De | Locale::Fr => ',',
+      Locale::Plain | Locale::En | Locale::Ch => '.',
+    }
+  }
+
+  /// The number style of a POSIX or BCP 47 locale tag such as `de_DE.UTF-8`
+  /// or `en-US`, or `None` for a language it doesn't know
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rust_calculator::settings::Locale;
+  ///
+  /// assert_eq!(Locale::from_tag("de_DE.UTF-8"), Some(Locale::De));
+  /// assert_eq!(Locale::from_tag("fr-CH"), Some(Locale::Ch));
+  /// assert_eq!(Locale::from_tag("C"), Some(Locale::Plain));
+  /// ```
+  pub fn from_tag(tag: &str) -> Option<Locale> {
+    let tag = tag.to_lowercase();
+    let mut parts = tag.split(['_', '-', '.', '@']);
+    let language = parts.next().unwrap_or_default();
+    if parts.next() == Some("ch") {
+      return Some(Locale::Ch);
+    }
+    match language {
+      "c" | "posix" => Some(Locale::Plain),
+      "en" | "ja" | "ko" | "zh" | "th" | "he" | "ga" => Some(Locale::En),
+      "de" | "es" | "it" | "nl" | "pt" | "id" | "tr" | "da" | "el" | "ro" | "hr" | "sl" | "sr"
+      | "vi" => Some(Locale::De),
+      "fr" | "ru" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "nn" | "no" | "uk" | "hu" | "bg"
+      | "lt" | "lv" | "et" => Some(Locale::Fr),
+      _ => None,
+    }
+  }
+}
+
+/// Integer type the integer mode evaluates in, with its width in bits and
+/// whether it's signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegerType {
+  U8,
+  U16,
+  U32,
+  U64,
+  I8,
+  I16,
+  I32,
+  I64,
+}
+
+impl IntegerType {
+  /// Width in bits
+  pub fn bits(self) -> u32 {
+    match self {
+      IntegerType::U8 | IntegerType::I8 => 8,
+      IntegerType::U16 | IntegerType::I16 => 16,
+      IntegerType::U32 | IntegerType::I32 => 32,
+      IntegerType::U64 | IntegerType::I64 => 64,
+    }
+  }
+
+  pub fn is_signed(self) -> bool {
+    matches!(
+      self,
+      IntegerType::I8 | IntegerType::I16 | IntegerType::I32 | IntegerType::I64
+    )
+  }
+
+  /// Smallest value of the type
+  pub fn min(self) -> i128 {
+    if self.is_signed() {
+      -(1 << (self.bits() - 1))
+    } else {
+      0
+    }
+  }
+
+  /// Largest value of the type
+  pub fn max(self) -> i128 {
+    if self.is_signed() {
+      (1 << (self.bits() - 1)) - 1
+    } else {
+      (1 << self.bits()) - 1
+    }
+  }
+}
+
+/// What integer mode does with results outside the range of its type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntegerOverflow {
+  /// Fail with an overflow error
+  #[default]
+  Error,
+  /// Keep the low bits, as fixed-width machine arithmetic does, so 255 + 1
+  /// is 0 in u8
+  Wrap,
+}
+
+impl RoundingMode {
+  /// Rounds to a whole number
+  pub fn round(self, value: f64) -> f64 {
+    match self {
+      RoundingMode::HalfUp => value.round(),
+      RoundingMode::HalfEven => round_ties_even(value),
+      RoundingMode::Floor => value.floor(),
+      RoundingMode::Ceiling => value.ceil(),
+    }
+  }
+
+  /// Rounds to the given number of decimal places. The number is rounded as
+  /// it's written in decimal, so 1.005 rounds half up to 1.01 even though the
+  /// nearest `f64` is a little less than 1.005.
+  pub fn round_to(self, value: f64, decimals: u32) -> f64 {
+    if !value.is_finite() || value == 0.0 {
+      return value;
+    }
+    // The shortest digits that read back as the value, and the power of ten
+    // of the first one
+    let text = format!("{:e}", value.abs());
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let digits = mantissa.replace('.', "");
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let dropped = digits.len() as i32 - 1 - exponent - decimals as i32;
+    if dropped <= 0 {
+      return value;
+    }
+
+    // Leading zeros leave a digit to keep however small the value is
+    let padded = format!("{}{}", "0".repeat(dropped as usize), digits);
+    let (kept, rest) = padded.split_at(padded.len() - dropped as usize);
+    let kept: u64 = kept.parse().unwrap_or(0);
+    let rest = rest.trim_end_matches('0');
+    let negative = value < 0.0;
+    let away_from_zero = match self {
+      RoundingMode::HalfUp => rest >= "5",
+      RoundingMode::HalfEven => rest > "5" || (rest == "5" && kept % 2 == 1),
+      RoundingMode::Floor => negative && !rest.is_empty(),
+      RoundingMode::Ceiling => !negative && !rest.is_empty(),
+    };
+    let magnitude: f64 = format!("{}e-{}", kept + away_from_zero as u64, decimals)
+      .parse()
+      .unwrap_or(value.abs());
+    if negative { -magnitude } else { magnitude }
+  }
+}
+
+option_names!(DomainPolicy, "domain policy", Error => "error", NaN => "nan", Complex => "complex");
+option_names!(OverflowPolicy, "overflow policy", Error => "error", Ieee => "ieee");
+option_names!(
+  NonFinitePolicy,
+  "non-finite policy",
+  Propagate => "propagate",
+  Reject => "reject",
+  Strict => "strict",
+);
+option_names!(
+  AngleMode,
+  "angle mode",
+  Degrees => "degrees" | "deg",
+  Radians => "radians" | "rad",
+  Gradians => "gradians" | "grad" | "gon",
+);
+option_names!(
+  RoundingMode,
+  "rounding mode",
+  HalfUp => "half-up",
+  HalfEven => "half-even" | "bankers",
+  Floor => "floor",
+  Ceiling => "ceiling" | "ceil",
+);
+option_names!(
+  IntegerType,
+  "integer type",
+  U8 => "u8",
+  U16 => "u16",
+  U32 => "u32",
+  U64 => "u64",
+  I8 => "i8",
+  I16 => "i16",
+  I32 => "i32",
+  I64 => "i64",
+);
+option_names!(IntegerOverflow, "integer overflow", Error => "error", Wrap => "wrap");
+option_names!(
+  Locale,
+  "locale",
+  Plain => "plain" | "c" | "posix",
+  En => "en",
+  De => "de",
+  Fr => "fr",
+  Ch => "ch",
+);
+
+impl AngleMode {
+  /// Converts an angle in this unit to radians
+  pub fn to_radians(self, angle: f64) -> f64 {
+    match self {
+      AngleMode::Degrees => angle.to_radians(),
+      AngleMode::Radians => angle,
+      AngleMode::Gradians => angle * core::f64::consts::PI / 200.0,
+    }
+  }
+
+  /// Converts an angle in radians to this unit
+  pub fn from_radians(self, radians: f64) -> f64 {
+    match self {
+      AngleMode::Degrees => radians.to_degrees(),
+      AngleMode::Radians => radians,
+      AngleMode::Gradians => radians * 200.0 / core::f64::consts::PI,
+    }
+  }
+
+  /// Converts an angle in this unit to degrees
+  pub fn to_degrees(self, angle: f64) -> f64 {
+    match self {
+      AngleMode::Degrees => angle,
+      AngleMode::Radians => angle.to_degrees(),
+      AngleMode::Gradians => angle * 0.9,
+    }
+  }
+
+  /// Converts an angle in degrees to this unit
+  pub fn from_degrees(self, degrees: f64) -> f64 {
+    match self {
+      AngleMode::Degrees => degrees,
+      AngleMode::Radians => degrees.to_radians(),
+      AngleMode::Gradians => degrees / 0.9,
+    }
+  }
+}
+
+/// Options controlling evaluation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+  /// Handling of domain violations in sqrt, log, ln, asin, acos and powers
+  /// of negative numbers
+  pub domain: DomainPolicy,
+  /// Angle unit of the trigonometric functions
+  pub angle_mode: AngleMode,
+  /// Handling of results too large to represent
+  pub overflow: OverflowPolicy,
+  /// Handling of infinite and NaN inputs
+  pub non_finite: NonFinitePolicy,
+  /// Rounding used by `round` and when formatting results
+  pub rounding: RoundingMode,
+  /// Number of significant figures results are shown with, if limited
+  pub sigfigs: Option<u32>,
+  /// Number of decimal places every intermediate and final result is
+  /// rounded to with the rounding mode, if limited
+  pub decimals: Option<u32>,
+  /// Integer type of the integer (programmer) mode, or `None` to evaluate
+  /// in floating point
+  pub integer: Option<IntegerType>,
+  /// Handling of results out of the integer type's range in integer mode
+  pub integer_overflow: IntegerOverflow,
+  /// Digit grouping and decimal separator of results
+  pub locale: Locale,
+  /// Most user-defined function calls that may be under way at once, which
+  /// bounds how deep a function can call itself
+  pub max_recursion: u32,
+  /// Whether an expression starting with a binary operator, such as `* 2`,
+  /// continues from the last result as if it started with `ans`
+  pub continuation: bool,
+}
+
+impl Default for Settings {
+  fn default() -> Self {
+    INITIAL
+  }
+}
+
+impl Settings {
+  /// Changes the option called `name` (domain, angle, overflow, nonfinite,
+  /// rounding, sigfigs, decimals, integer, intoverflow, locale, recursion or
+  /// continue) to the
+  /// parsed `value`
+  pub fn set(&mut self, name: &str, value: &str) -> Result<(), CalculatorError> {
+    match name.trim().to_lowercase().as_str() {
+      "domain" => self.domain = value.parse()?,
+      "angle" => self.angle_mode = value.parse()?,
+      "overflow" => self.overflow = value.parse()?,
+      "nonfinite" => self.non_finite = value.parse()?,
+      "rounding" => self.rounding = value.parse()?,
+      "sigfigs" => self.sigfigs = parse_sigfigs(value)?,
+      "decimals" => self.decimals = parse_decimals(value)?,
+      "integer" if value.trim().eq_ignore_ascii_case("off") => self.integer = None,
+      "integer" => self.integer = Some(value.parse()?),
+      "intoverflow" => self.integer_overflow = value.parse()?,
+      "locale" => self.locale = value.parse()?,
+      "recursion" => self.max_recursion = parse_recursion(value)?,
+      "continue" => self.continuation = parse_switch(value)?,
+      other => {
+        return Err(CalculatorError::ArgumentError(format!(
+          "Unknown setting: {}",
+          other
+        )));
+      }
+    }
+    Ok(())
+  }
+
+  /// Lists every option by the name [`Settings::set`] accepts, with its value
+  pub fn entries(&self) -> Vec<(&'static str, String)> {
+    vec![
+      ("domain", self.domain.to_string()),
+      ("angle", self.angle_mode.to_string()),
+      ("overflow", self.overflow.to_string()),
+      ("nonfinite", self.non_finite.to_string()),
+      ("rounding", self.rounding.to_string()),
+      (
+        "sigfigs",
+        self
+          .sigfigs
+          .map_or("off".to_string(), |digits| digits.to_string()),
+      ),
+      (
+        "decimals",
+        self
+          .decimals
+          .map_or("off".to_string(), |decimals| decimals.to_string()),
+      ),
+      (
+        "integer",
+        self
+          .integer
+          .map_or("off".to_string(), |integer| integer.to_string()),
+      ),
+      ("intoverflow", self.integer_overflow.to_string()),
+      ("locale", self.locale.to_string()),
+      ("recursion", self.max_recursion.to_string()),
+      (
+        "continue",
+        if self.continuation { "on" } else { "off" }.to_string(),
+      ),
+    ]
+  }
+}
+
+/// Rounds ties to the even neighbour
+#[cfg(feature = "std")]
+fn round_ties_even(value: f64) -> f64 {
+  value.round_ties_even()
+}
+
+/// Rounds ties to the even neighbour; `core` has no `round_ties_even`
+#[cfg(not(feature = "std"))]
+fn round_ties_even(value: f64) -> f64 {
+  let rounded = value.round();
+  if (value - value.trunc()).abs() == 0.5 {
+    2.0 * (value / 2.0).round()
+  } else {
+    rounded
+  }
+}
+
+/// The deepest recursion the `recursion` setting allows. Each call takes
+/// stack, and this many fit in the 2 MiB of a spawned thread in a release
+/// build; the initial 100 fit in a debug build too.
+pub const MAX_RECURSION: u32 = 1000;
+
+/// The settings every evaluation starts with
+const INITIAL: Settings = Settings {
+  domain: DomainPolicy::Error,
+  angle_mode: AngleMode::Degrees,
+  overflow: OverflowPolicy::Error,
+  non_finite: NonFinitePolicy::Propagate,
+  rounding: RoundingMode::HalfUp,
+  sigfigs: None,
+  decimals: None,
+  integer: None,
+  integer_overflow: IntegerOverflow::Error,
+  locale: Locale::Plain,
+  max_recursion: 100,
+  continuation: false,
+};
+
+#[cfg(feature = "std")]
+static DEFAULT: RwLock<Settings> = RwLock::new(INITIAL);
+
+/// Parses a number of significant figures between 1 and 17, or `off`
+fn parse_sigfigs(value: &str) -> Result<Option<u32>, CalculatorError> {
+  let value = value.trim();
+  if value.eq_ignore_ascii_case("off") {
+    return Ok(None);
+  }
+  match value.parse::<u32>() {
+    // An f64 holds at most 17 significant digits
+    Ok(digits @ 1..=17) => Ok(Some(digits)),
+    _ => Err(CalculatorError::ArgumentError(format!(
+      "Significant figures must be between 1 and 17, or off: {}",
+      value
+    ))),
+  }
+}
+
+/// Parses a number of decimal places between 0 and 15, or `off`
+fn parse_decimals(value: &str) -> Result<Option<u32>, CalculatorError> {
+  let value = value.trim();
+  if value.eq_ignore_ascii_case("off") {
+    return Ok(None);
+  }
+  match value.parse::<u32>() {
+    // Beyond 15 places an f64 can't hold every decimal exactly anyway
+    Ok(decimals @ 0..=15) => Ok(Some(decimals)),
+    _ => Err(CalculatorError::ArgumentError(format!(
+      "Decimal places must be between 0 and 15, or off: {}",
+      value
+    ))),
+  }
+}
+
+/// Parses a recursion depth between 1 and [`MAX_RECURSION`]
+fn parse_recursion(value: &str) -> Result<u32, CalculatorError> {
+  let value = value.trim();
+  match value.parse::<u32>() {
+    Ok(depth @ 1..=MAX_RECURSION) => Ok(depth),
+    _ => Err(CalculatorError::ArgumentError(format!(
+      "Recursion depth must be between 1 and {}: {}",
+      MAX_RECURSION, value
+    ))),
+  }
+}
+
+/// Parses `on` or `off`
+fn parse_switch(value: &str) -> Result<bool, CalculatorError> {
+  match value.trim().to_lowercase().as_str() {
+    "on" => Ok(true),
+    "off" => Ok(false),
+    other => Err(CalculatorError::ArgumentError(format!(
+      "Expected on or off: {}",
+      other
+    ))),
+  }
+}
+
+#[cfg(feature = "std")]
+thread_local! {
+  // Settings passed to the evaluation running on this thread, if any
+  static OVERRIDE: Cell<Option<Settings>> = const { Cell::new(None) };
+}
+
+/// Returns the process-wide default settings
+#[cfg(feature = "std")]
+pub fn get_default() -> Settings {
+  *DEFAULT.read().unwrap()
+}
+
+/// Returns the default settings, which can't be changed without the `std` feature
+#[cfg(not(feature = "std"))]
+pub fn get_default() -> Settings {
+  INITIAL
+}
+
+/// Changes the process-wide default settings
+#[cfg(feature = "std")]
+pub fn set_default(settings: Settings) {
+  *DEFAULT.write().unwrap() = settings;
+}
+
+/// Returns the settings in effect for the current evaluation
+#[cfg(feature = "std")]
+pub fn current() -> Settings {
+  OVERRIDE.with(Cell::get).unwrap_or_else(get_default)
+}
+
+/// Returns the settings in effect for the current evaluation
+#[cfg(not(feature = "std"))]
+pub fn current() -> Settings {
+  INITIAL
+}
+
+/// Runs `f` with `settings` in effect on this thread, restoring the previous
+/// settings afterwards, even if `f` panics
+#[cfg(feature = "std")]
+pub(crate) fn scoped<T>(settings: Settings, f: impl FnOnce() -> T) -> T {
+  struct Restore(Option<Settings>);
+
+  impl Drop for Restore {
+    fn drop(&mut self) {
+      OVERRIDE.with(|cell| cell.set(self.0));
+    }
+  }
+
+  let _restore = Restore(OVERRIDE.with(|cell| cell.replace(Some(settings))));
+  f()
+}
@@ -0,0 +1,226 @@
+//! # Spreadsheet Grid
+//!
+//! A miniature spreadsheet where cells hold formulas that can reference other
+//! cells (`A1 * B2`). Dependencies are tracked automatically, so changing a
+//! cell recalculates every cell that depends on it, and cycles are rejected.
+//!
+//! Cell names are one to three letters followed by a row number, and are
+//! case-insensitive. Empty cells evaluate to `0`, as in most spreadsheets.
+//! Identifiers that aren't cell references fall back to user-defined variables.
+
+use crate::CalculatorError;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A single cell: its formula and the result of the last evaluation
+#[derive(Debug, Clone)]
+pub struct Cell {
+  /// The formula as entered, e.g. `A1 * 2`
+  pub formula: String,
+  /// The computed value, or the error message if evaluation failed
+  pub value: Result<f64, String>,
+  /// Cells referenced by the formula
+  pub dependencies: BTreeSet<String>,
+}
+
+/// A grid of cells with automatic recalculation
+#[derive(Debug, Clone, Default)]
+pub struct Sheet {
+  cells: BTreeMap<String, Cell>,
+}
+
+impl Sheet {
+  /// Creates an empty sheet
+  pub fn new() -> Self {
+    Sheet::default()
+  }
+
+  /// Sets a cell's formula and recalculates it along with its dependents.
+  ///
+  /// Returns the names of all recalculated cells in evaluation order. Fails if
+  /// the cell name is invalid or the formula would create a circular reference.
+  pub fn set(&mut self, name: &str, formula: &str) -> Result<Vec<String>, CalculatorError> {
+    let name = normalize_cell(name)?;
+    let dependencies = cell_references(formula);
+
+    if dependencies.contains(&name) || dependencies.iter().any(|dep| self.reaches(dep, &name)) {
+      return Err(CalculatorError::ArgumentError(format!(
+        "Circular reference: {} depends on itself",
+        name
+      )));
+    }
+
+    self.cells.insert(
+      name.clone(),
+      Cell {
+        formula: formula.trim().to_string(),
+        value: Ok(0.0),
+        dependencies,
+      },
+    );
+
+    Ok(self.recalculate_from(&name))
+  }
+
+  /// Removes a cell, recalculating anything that referenced it
+  pub fn clear(&mut self, name: &str) -> Result<Vec<String>, CalculatorError> {
+    let name = normalize_cell(name)?;
+    if self.cells.remove(&name).is_none() {
+      return Ok(Vec::new());
+    }
+
+    let mut updated = self.recalculate_from(&name);
+    updated.retain(|cell| cell != &name);
+    Ok(updated)
+  }
+
+  /// Gets a cell by name
+  pub fn cell(&self, name: &str) -> Option<&Cell> {
+    normalize_cell(name)
+      .ok()
+      .and_then(|name| self.cells.get(&name))
+  }
+
+  /// Gets a cell's value; empty cells are `0`
+  pub fn value(&self, name: &str) -> Result<f64, CalculatorError> {
+    match self.cell(name) {
+      Some(cell) => cell.value.clone().map_err(CalculatorError::Other),
+      None => normalize_cell(name).map(|_| 0.0),
+    }
+  }
+
+  /// Iterates over the non-empty cells in name order
+  pub fn cells(&self) -> impl Iterator<Item = (&String, &Cell)> {
+    self.cells.iter()
+  }
+
+  /// Recalculates every cell, e.g. after user variables changed
+  pub fn recalculate_all(&mut self) {
+    for name in self.evaluation_order(self.cells.keys().cloned().collect()) {
+      self.evaluate_cell(&name);
+    }
+  }
+
+  /// Recalculates `start` and everything that transitively depends on it
+  fn recalculate_from(&mut self, start: &str) -> Vec<String> {
+    let mut affected = BTreeSet::new();
+    let mut pending = vec![start.to_string()];
+    while let Some(name) = pending.pop() {
+      if affected.insert(name.clone()) {
+        pending.extend(self.dependents(&name));
+      }
+    }
+
+    let order = self.evaluation_order(affected);
+    for name in &order {
+      self.evaluate_cell(name);
+    }
+    order
+  }
+
+  /// Orders cells so that every cell comes after the cells it depends on
+  fn evaluation_order(&self, cells: BTreeSet<String>) -> Vec<String> {
+    fn visit(sheet: &Sheet, name: &str, cells: &BTreeSet<String>, order: &mut Vec<String>) {
+      if order.iter().any(|done| done == name) {
+        return;
+      }
+      if let Some(cell) = sheet.cells.get(name) {
+        for dep in &cell.dependencies {
+          if cells.contains(dep) {
+            visit(sheet, dep, cells, order);
+          }
+        }
+        order.push(name.to_string());
+      }
+    }
+
+    let mut order = Vec::new();
+    for name in &cells {
+      visit(self, name, &cells, &mut order);
+    }
+    order
+  }
+
+  /// Evaluates a single cell using the current values of its dependencies
+  fn evaluate_cell(&mut self, name: &str) {
+    let Some(formula) = self.cells.get(name).map(|cell| cell.formula.clone()) else {
+      return;
+    };
+
+    let value = {
+      let resolve = |identifier: &str| match normalize_cell(identifier) {
+        Ok(cell) => match self.cells.get(&cell) {
+          Some(Cell {
+            value: Ok(value), ..
+          }) => Some(*value),
+          Some(Cell { value: Err(_), .. }) => None,
+          None => Some(0.0),
+        },
+        Err(_) => crate::get_variable(identifier),
+      };
+      crate::evaluate_with_resolver(&formula, &resolve).map_err(|err| err.to_string())
+    };
+
+    if let Some(cell) = self.cells.get_mut(name) {
+      cell.value = value;
+    }
+  }
+
+  /// Cells whose formulas reference `name` directly
+  fn dependents(&self, name: &str) -> Vec<String> {
+    self
+      .cells
+      .iter()
+      .filter(|(_, cell)| cell.dependencies.contains(name))
+      .map(|(dependent, _)| dependent.clone())
+      .collect()
+  }
+
+  /// Whether `from` depends, directly or transitively, on `target`
+  fn reaches(&self, from: &str, target: &str) -> bool {
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![from.to_string()];
+    while let Some(name) = pending.pop() {
+      if name == target {
+        return true;
+      }
+      if visited.insert(name.clone()) {
+        if let Some(cell) = self.cells.get(&name) {
+          pending.extend(cell.dependencies.iter().cloned());
+        }
+      }
+    }
+    false
+  }
+}
+
+/// Checks whether a string is a cell reference like `A1` or `ab12`
+pub fn is_cell_reference(name: &str) -> bool {
+  let letters = name.chars().take_while(char::is_ascii_alphabetic).count();
+  let digits = &name[letters..];
+  (1..=3).contains(&letters)
+    && !digits.is_empty()
+    && digits.chars().all(|ch| ch.is_ascii_digit())
+    && !digits.starts_with('0')
+}
+
+/// Validates a cell name and converts it to its canonical uppercase form
+fn normalize_cell(name: &str) -> Result<String, CalculatorError> {
+  let name = name.trim();
+  if is_cell_reference(name) {
+    Ok(name.to_uppercase())
+  } else {
+    Err(CalculatorError::ArgumentError(format!(
+      "Invalid cell reference: {}",
+      name
+    )))
+  }
+}
+
+/// Extracts the cell references used in a formula
+fn cell_references(formula: &str) -> BTreeSet<String> {
+  formula
+    .split(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_')
+    .filter(|word| is_cell_reference(word))
+    .map(str::to_uppercase)
+    .collect()
+}
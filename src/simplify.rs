@@ -0,0 +1,224 @@
+//! # Simplification
+//!
+//! [`Expr::simplify`] rewrites a tree into a simpler one with the same
+//! value:
+//!
+//! - Operators between numbers are worked out, as in `2 * 3` to `6`, unless
+//!   that fails, as `1 / 0` does, so the error is still raised when the tree
+//!   is evaluated. Functions are left alone, so `sqrt(2)` stays exact.
+//! - Identities drop out: `x + 0`, `x - 0`, `x * 1`, `x / 1`, `x ^ 1` and
+//!   `--x` are all `x`.
+//! - Like terms of a sum are combined, as in `2 * x + 3 * x - 1 + 4` to
+//!   `5 * x + 3`, and numbers in a product are multiplied together, so
+//!   `2 * x * 3` is `6 * x`. Terms that cancel disappear, which assumes
+//!   variables are finite.
+//!
+//! A simplified sum lists its terms in the order they first appear, with
+//! the constant last. [`CompiledExpression`](crate::CompiledExpression)
+//! simplifies a formula before compiling it, and `simplify(expr)` in an
+//! expression shows the simplified form through
+//! [`evaluate_to_string`](crate::evaluate_to_string).
+//!
+//! ```
+//! use rust_calculator::{parse, simplify};
+//!
+//! assert_eq!(simplify("x * 1 + 0").unwrap(), "x");
+//! assert_eq!(simplify("2 * x + 3 * x - 1 + 4").unwrap(), "5 * x + 3");
+//! assert_eq!(parse("y * (2 + 3)").unwrap().simplify().to_string(), "5 * y");
+//! ```
+
+use crate::CalculatorError;
+use crate::ast::{self, Expr};
+use crate::prelude::*;
+
+/// Parses an expression and writes it back simplified, as
+/// [`Expr::simplify`] does
+pub fn simplify(expression: &str) -> Result<String, CalculatorError> {
+  Ok(ast::parse(expression)?.simplify().to_string())
+}
+
+impl Expr {
+  /// Rewrites the tree into a simpler one with the same value, by constant
+  /// folding, dropping identities and combining like terms
+  pub fn simplify(&self) -> Expr {
+    match self {
+      Expr::Number(_) | Expr::Variable(_) => self.clone(),
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } => simplify_binary(operator, left.simplify(), right.simplify()),
+      Expr::Function { name, argument } => simplify_function(name, argument.simplify()),
+    }
+  }
+}
+
+/// Simplifies an operation between two simplified operands
+fn simplify_binary(operator: &str, left: Expr, right: Expr) -> Expr {
+  if let (Expr::Number(left), Expr::Number(right)) = (&left, &right) {
+    if let Some(value) = fold(*left, operator, *right) {
+      return Expr::Number(value);
+    }
+  }
+  let one = Expr::Number(1.0);
+  match operator {
+    "+" | "-" => combine_terms(binary(operator, left, right)),
+    "*" => combine_factors(binary(operator, left, right)),
+    "/" | "^" if right == one => left,
+    _ => binary(operator, left, right),
+  }
+}
+
+/// Simplifies a function applied to a simplified argument
+fn simplify_function(name: &str, argument: Expr) -> Expr {
+  match (name, argument) {
+    // Adding 0 turns -0 into 0
+    ("neg", Expr::Number(value)) => Expr::Number(-value + 0.0),
+    ("neg", Expr::Function { name, argument }) if name == "neg" => *argument,
+    ("neg", product @ Expr::Binary { .. }) if is_product(&product) => {
+      combine_factors(negate(product))
+    }
+    (name, argument) => Expr::Function {
+      name: name.to_string(),
+      argument: Box::new(argument),
+    },
+  }
+}
+
+/// Works out an arithmetic operation between two numbers, or gives `None`
+/// if it fails or overflows
+fn fold(left: f64, operator: &str, right: f64) -> Option<f64> {
+  // Whether 0 ^ 0 is an error depends on the settings in effect
+  let arithmetic = matches!(operator, "+" | "-" | "*" | "/" | "%" | "^");
+  if !arithmetic || (operator == "^" && left == 0.0 && right == 0.0) {
+    return None;
+  }
+  crate::apply_binary_operation(left, operator, right)
+    .ok()
+    .filter(|value| value.is_finite())
+}
+
+/// Rewrites a sum as its distinct terms, each with its coefficient
+fn combine_terms(sum: Expr) -> Expr {
+  let mut terms: Vec<(f64, Vec<Expr>)> = Vec::new();
+  collect_terms(sum.clone(), 1.0, &mut terms);
+  if terms
+    .iter()
+    .any(|(coefficient, _)| !coefficient.is_finite())
+  {
+    return sum;
+  }
+  // The constant term goes last
+  if let Some(constant) = terms.iter().position(|(_, factors)| factors.is_empty()) {
+    let constant = terms.remove(constant);
+    terms.push(constant);
+  }
+
+  let mut result: Option<Expr> = None;
+  for (coefficient, factors) in terms {
+    if coefficient == 0.0 {
+      continue;
+    }
+    result = Some(match result {
+      None => product(coefficient, factors),
+      Some(sum) if coefficient < 0.0 => binary("-", sum, product(-coefficient, factors)),
+      Some(sum) => binary("+", sum, product(coefficient, factors)),
+    });
+  }
+  result.unwrap_or(Expr::Number(0.0))
+}
+
+/// Adds the terms of a chain of `+` and `-` to `terms`, multiplied by
+/// `sign`, merging each into an earlier term with the same factors
+fn collect_terms(expr: Expr, sign: f64, terms: &mut Vec<(f64, Vec<Expr>)>) {
+  match expr {
+    Expr::Binary {
+      operator,
+      left,
+      right,
+    } if operator == "+" || operator == "-" => {
+      collect_terms(*left, sign, terms);
+      let sign = if operator == "-" { -sign } else { sign };
+      collect_terms(*right, sign, terms);
+    }
+    Expr::Function { name, argument } if name == "neg" => collect_terms(*argument, -sign, terms),
+    term => {
+      let (coefficient, factors) = factors(term);
+      match terms.iter_mut().find(|(_, other)| *other == factors) {
+        Some((total, _)) => *total += sign * coefficient,
+        None => terms.push((sign * coefficient, factors)),
+      }
+    }
+  }
+}
+
+/// Rewrites a product with its numbers multiplied into one coefficient
+fn combine_factors(expr: Expr) -> Expr {
+  let (coefficient, factors) = factors(expr.clone());
+  if !coefficient.is_finite() {
+    return expr;
+  }
+  product(coefficient, factors)
+}
+
+/// Splits a product into the product of its numbers and its other factors
+fn factors(expr: Expr) -> (f64, Vec<Expr>) {
+  let mut coefficient = 1.0;
+  let mut factors = Vec::new();
+  let mut pending = vec![expr];
+  while let Some(expr) = pending.pop() {
+    match expr {
+      Expr::Number(value) => coefficient *= value,
+      Expr::Binary {
+        operator,
+        left,
+        right,
+      } if operator == "*" => {
+        // Popped from the end, so the left operand comes first
+        pending.push(*right);
+        pending.push(*left);
+      }
+      Expr::Function { name, argument } if name == "neg" => {
+        coefficient = -coefficient;
+        pending.push(*argument);
+      }
+      other => factors.push(other),
+    }
+  }
+  (coefficient, factors)
+}
+
+/// Builds `coefficient * factors`, leaving out a coefficient of 1
+fn product(coefficient: f64, factors: Vec<Expr>) -> Expr {
+  let mut factors = factors.into_iter();
+  let Some(first) = factors.next() else {
+    return Expr::Number(coefficient);
+  };
+  let start = if coefficient == 1.0 {
+    first
+  } else if coefficient == -1.0 {
+    negate(first)
+  } else {
+    binary("*", Expr::Number(coefficient), first)
+  };
+  factors.fold(start, |product, factor| binary("*", product, factor))
+}
+
+fn is_product(expr: &Expr) -> bool {
+  matches!(expr, Expr::Binary { operator, .. } if operator == "*")
+}
+
+fn negate(expr: Expr) -> Expr {
+  Expr::Function {
+    name: "neg".to_string(),
+    argument: Box::new(expr),
+  }
+}
+
+fn binary(operator: &str, left: Expr, right: Expr) -> Expr {
+  Expr::Binary {
+    operator: operator.to_string(),
+    left: Box::new(left),
+    right: Box::new(right),
+  }
+}
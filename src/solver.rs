@@ -0,0 +1,155 @@
+//! # Equation Solver
+//!
+//! `solve("sin(x) = x/2", x, guess)` finds a value of `x` that makes both
+//! sides of the equation equal, starting from `guess`. Inside the quotes,
+//! `lhs = rhs` reads as `lhs - rhs`, and an expression without `=` is
+//! solved for 0. Any expression the calculator can evaluate may be used,
+//! and the variable hides any stored variable of the same name, as in
+//! [`sum`](crate::series).
+//!
+//! Newton's method runs first, with the slope estimated from nearby values.
+//! If it doesn't settle, the solver looks outwards from the guess for a
+//! change of sign and narrows it down by bisection. Of several roots, the
+//! one found is usually the nearest to the guess, but not always.
+//!
+//! ```
+//! use rust_calculator::evaluate_expression;
+//!
+//! assert!((evaluate_expression("solve(\"x^2 = 2\", x, 1)").unwrap() - 2f64.sqrt()).abs() < 1e-12);
+//! assert_eq!(evaluate_expression("solve(\"2*x + 1\", x, 0)").unwrap(), -0.5);
+//! assert!((evaluate_expression("solve(\"x^3 = x + 1\", x, 1)").unwrap() - 1.3247179572).abs() < 1e-9);
+//! ```
+
+use crate::ast::Expr;
+use crate::prelude::*;
+use crate::{CalculatorError, Resolver, limits};
+
+/// The most steps Newton's method takes before bisection takes over
+pub const MAX_ITERATIONS: usize = 100;
+
+/// How many times the search for a change of sign doubles its distance
+/// from the guess
+const MAX_DOUBLINGS: i32 = 60;
+
+/// Evaluates a call of `solve` with `arguments` already split at their
+/// commas
+pub(crate) fn evaluate_call(
+  arguments: &[&Expr],
+  resolve: Resolver,
+) -> Result<f64, CalculatorError> {
+  crate::check_arity("solve", arguments.len())?;
+  let [equation, variable, guess] = arguments else {
+    unreachable!("check_arity allows only 3 arguments");
+  };
+  let Expr::Variable(variable) = variable else {
+    return Err(CalculatorError::ArgumentError(
+      "solve needs a variable name as its second argument".to_string(),
+    ));
+  };
+  let guess = guess.evaluate_with(resolve)?;
+  let residual = |x: f64| {
+    let binding = |name: &str| {
+      if name == variable {
+        Some(x)
+      } else {
+        resolve(name)
+      }
+    };
+    equation.evaluate_with(&binding)
+  };
+  if let Some(root) = newton(&residual, guess)? {
+    return Ok(root);
+  }
+  bisect(&residual, guess)?
+    .ok_or_else(|| CalculatorError::ArgumentError(format!("solve found no root near {}", guess)))
+}
+
+/// Runs Newton's method from `guess`, or gives `None` if it doesn't settle
+/// on a root or strays where the equation can't be evaluated
+fn newton(
+  residual: &dyn Fn(f64) -> Result<f64, CalculatorError>,
+  guess: f64,
+) -> Result<Option<f64>, CalculatorError> {
+  let mut x = guess;
+  for _ in 0..MAX_ITERATIONS {
+    limits::check_time()?;
+    let step = || -> Option<f64> {
+      let value = residual(x).ok()?;
+      if value == 0.0 {
+        return Some(0.0);
+      }
+      // A central difference, with a step in proportion to x
+      let h = 1e-6 * x.abs().max(1.0);
+      let slope = (residual(x + h).ok()? - residual(x - h).ok()?) / (2.0 * h);
+      Some(value / slope).filter(|step| step.is_finite())
+    };
+    let Some(step) = step() else {
+      return Ok(None);
+    };
+    let next = x - step;
+    if step.abs() <= 1e-12 * next.abs().max(1.0) {
+      // A small step near a pole isn't a root
+      let close = residual(next).is_ok_and(|value| value.abs() <= 1e-9);
+      return Ok(close.then_some(next));
+    }
+    x = next;
+  }
+  Ok(None)
+}
+
+/// Looks for a change of sign ever further either side of `guess`, then
+/// halves it down to a root, or gives `None` if there's none
+fn bisect(
+  residual: &dyn Fn(f64) -> Result<f64, CalculatorError>,
+  guess: f64,
+) -> Result<Option<f64>, CalculatorError> {
+  let at_guess = residual(guess)?;
+  let mut bracket = None;
+  for doubling in 0..MAX_DOUBLINGS {
+    limits::check_time()?;
+    let distance = 2f64.powi(doubling) * 1e-3 * guess.abs().max(1.0);
+    for other in [guess + distance, guess - distance] {
+      // Points where the equation can't be evaluated are passed over
+      let Ok(value) = residual(other) else {
+        continue;
+      };
+      if value.is_finite() && at_guess.is_finite() && (value < 0.0) != (at_guess < 0.0) {
+        bracket = Some((guess.min(other), guess.max(other)));
+        break;
+      }
+    }
+    if bracket.is_some() {
+      break;
+    }
+  }
+  let Some((mut low, mut high)) = bracket else {
+    return Ok(None);
+  };
+  let (at_low, at_high) = (residual(low)?, residual(high)?);
+  let low_negative = at_low < 0.0;
+  // Halve the bracket until it can't get any narrower
+  loop {
+    limits::check_time()?;
+    let middle = low + (high - low) / 2.0;
+    if middle <= low || middle >= high {
+      break;
+    }
+    let Ok(value) = residual(middle) else {
+      return Ok(None);
+    };
+    if (value < 0.0) == low_negative {
+      low = middle;
+    } else {
+      high = middle;
+    }
+  }
+  // A change of sign across a pole or a jump, as in 1/x or floor(x) - 0.5,
+  // isn't a root
+  let root = (low + high) / 2.0;
+  let tolerance = 1e-6 * at_low.abs().max(at_high.abs()).max(1.0);
+  Ok(
+    residual(root)
+      .is_ok_and(|value| value.abs() <= tolerance)
+      .then_some(root),
+  )
+}
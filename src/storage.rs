@@ -0,0 +1,691 @@
+//! # History and Variable Storage
+//!
+//! With the `persistence` feature, the history, the variables, the formulas,
+//! the user-defined functions and the memory are kept in
+//! files in the user's data directory, so they survive between sessions.
+//! Without it they only live in memory, and the library never touches the
+//! disk on its own.
+//!
+//! The variables are kept in a TOML file, a table for each variable with its
+//! type, value, description and creation time:
+//!
+//! ```toml
+//! ["project1.rate"]
+//! type = "number"
+//! value = 0.05
+//! description = "Annual interest rate"
+//! created = 2026-10-15T08:30:00Z
+//! ```
+//!
+//! A variables file in the `name=value` lines of earlier versions is
+//! converted the first time it's read and kept with a `.bak` extension, and
+//! a last line cut off by an interrupted write is left out.
+//!
+//! The variables, formulas, functions and memory are written to a temporary
+//! file that then replaces the old one, so a process killed while saving
+//! leaves the last complete file behind. Damaged entries in the variables
+//! file are skipped when it's read, and the file is rewritten without them
+//! after keeping a copy with a `.bak` extension. Each table is read on its
+//! own, so a syntax error only loses the variable it's in.
+
+use crate::Variable;
+use crate::memory::Memory;
+#[cfg(feature = "persistence")]
+use crate::memory::{MemoryChange, MemoryOperation};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+#[cfg(feature = "persistence")]
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "persistence")]
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+#[cfg(feature = "persistence")]
+use std::path::Path;
+#[cfg(feature = "persistence")]
+use toml::value::{Datetime, Offset};
+
+#[cfg(not(feature = "persistence"))]
+static HISTORY: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+/// Most history entries kept, if limited
+static HISTORY_LIMIT: RwLock<Option<usize>> = RwLock::new(None);
+
+/// Directory of the data files, when it isn't the user's data directory
+#[cfg_attr(not(feature = "persistence"), allow(dead_code))]
+static DATA_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+thread_local! {
+  // Directory of the data files on this thread, set by `with_data_dir`
+  static THREAD_DATA_DIR: std::cell::RefCell<Option<PathBuf>> =
+    const { std::cell::RefCell::new(None) };
+}
+
+/// Runs `f` with the data files of this thread kept in `dir`, restoring the
+/// previous directory afterwards, even if `f` panics
+pub(crate) fn with_data_dir<T>(dir: PathBuf, f: impl FnOnce() -> T) -> T {
+  struct Restore(Option<PathBuf>);
+
+  impl Drop for Restore {
+    fn drop(&mut self) {
+      THREAD_DATA_DIR.with(|cell| cell.replace(self.0.take()));
+    }
+  }
+
+  let _restore = Restore(THREAD_DATA_DIR.with(|cell| cell.replace(Some(dir))));
+  f()
+}
+
+/// Limits the history to its `limit` newest entries from now on
+#[cfg(feature = "config")]
+pub(crate) fn set_history_limit(limit: Option<usize>) {
+  *HISTORY_LIMIT.write().unwrap() = limit;
+}
+
+/// Keeps the data files in `dir` instead of the user's data directory
+#[cfg(feature = "config")]
+pub(crate) fn set_data_dir(dir: Option<PathBuf>) {
+  *DATA_DIR.write().unwrap() = dir;
+}
+
+fn history_limit() -> Option<usize> {
+  *HISTORY_LIMIT.read().unwrap()
+}
+
+/// Appends an entry to the history
+#[cfg(feature = "persistence")]
+pub(crate) fn append_history(entry: &str) -> io::Result<()> {
+  let mut file = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(history_path())?;
+  writeln!(file, "{}", entry)?;
+  drop(file);
+
+  // Drop the oldest entries once the history is over its limit
+  if let Some(limit) = history_limit() {
+    let entries = read_last_history(limit + 1)?;
+    if entries.len() > limit {
+      let kept: String = entries[1..]
+        .iter()
+        .map(|entry| format!("{}\n", entry))
+        .collect();
+      write_atomically(&history_path(), &kept)?;
+    }
+  }
+  Ok(())
+}
+
+/// Appends an entry to the history
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn append_history(entry: &str) -> io::Result<()> {
+  let mut history = HISTORY.lock().unwrap();
+  history.push(entry.to_string());
+  if let Some(limit) = history_limit() {
+    let excess = history.len().saturating_sub(limit);
+    history.drain(..excess);
+  }
+  Ok(())
+}
+
+/// History entries read one at a time, oldest first
+pub(crate) type HistoryEntries = Box<dyn Iterator<Item = io::Result<String>>>;
+
+/// Iterates over the history entries, reading the file a line at a time
+#[cfg(feature = "persistence")]
+pub(crate) fn history_entries() -> io::Result<HistoryEntries> {
+  let history_path = history_path();
+  if !Path::new(&history_path).exists() {
+    return Ok(Box::new(std::iter::empty()));
+  }
+  Ok(Box::new(BufReader::new(File::open(history_path)?).lines()))
+}
+
+/// Iterates over the history entries
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn history_entries() -> io::Result<HistoryEntries> {
+  let entries = HISTORY.lock().unwrap().clone();
+  Ok(Box::new(entries.into_iter().map(Ok)))
+}
+
+/// Reads the last `n` history entries, oldest first, without reading the
+/// rest of the file. Lines that aren't UTF-8 are skipped, and earlier
+/// entries read in their place.
+#[cfg(feature = "persistence")]
+pub(crate) fn read_last_history(n: usize) -> io::Result<Vec<String>> {
+  let history_path = history_path();
+  if n == 0 || !Path::new(&history_path).exists() {
+    return Ok(Vec::new());
+  }
+  let mut file = File::open(history_path)?;
+  let mut lines = n;
+  loop {
+    let start = tail_offset(&mut file, lines)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for line in BufReader::new(&mut file).split(b'\n') {
+      match String::from_utf8(line?) {
+        Ok(mut entry) => {
+          if entry.ends_with('\r') {
+            entry.pop();
+          }
+          entries.push(entry);
+        }
+        Err(_) => skipped += 1,
+      }
+    }
+    if skipped == 0 || start == 0 {
+      return Ok(entries.split_off(entries.len().saturating_sub(n)));
+    }
+    lines += skipped;
+  }
+}
+
+/// Reads the last `n` history entries, oldest first
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn read_last_history(n: usize) -> io::Result<Vec<String>> {
+  let history = HISTORY.lock().unwrap();
+  Ok(history[history.len().saturating_sub(n)..].to_vec())
+}
+
+/// Finds where the last `n` lines of a file start by reading it backwards
+/// in blocks
+#[cfg(feature = "persistence")]
+fn tail_offset(file: &mut File, n: usize) -> io::Result<u64> {
+  const BLOCK: u64 = 8192;
+
+  let file_end = file.seek(SeekFrom::End(0))?;
+  let mut buffer = vec![0; BLOCK as usize];
+  let mut newlines = 0;
+  let mut end = file_end;
+  while end > 0 {
+    let start = end.saturating_sub(BLOCK);
+    let block = &mut buffer[..(end - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(block)?;
+    for (index, _) in block
+      .iter()
+      .enumerate()
+      .rev()
+      .filter(|(_, &byte)| byte == b'\n')
+    {
+      let line_start = start + index as u64 + 1;
+      // The newline ending the last line doesn't start another one
+      if line_start == file_end {
+        continue;
+      }
+      newlines += 1;
+      if newlines == n {
+        return Ok(line_start);
+      }
+    }
+    end = start;
+  }
+  Ok(0)
+}
+
+/// Removes every history entry
+#[cfg(feature = "persistence")]
+pub(crate) fn clear_history() -> io::Result<()> {
+  let history_path = history_path();
+  if Path::new(&history_path).exists() {
+    // Truncate file to zero length
+    OpenOptions::new()
+      .write(true)
+      .truncate(true)
+      .open(history_path)?;
+  }
+  Ok(())
+}
+
+/// Removes every history entry
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn clear_history() -> io::Result<()> {
+  HISTORY.lock().unwrap().clear();
+  Ok(())
+}
+
+/// The type of the variables there are so far
+#[cfg(feature = "persistence")]
+const NUMBER: &str = "number";
+
+/// A variable as the variables file keeps it
+#[cfg(feature = "persistence")]
+#[derive(serde::Serialize)]
+struct StoredVariable {
+  #[serde(rename = "type")]
+  kind: String,
+  value: f64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  description: Option<String>,
+  created: Datetime,
+}
+
+/// Writes all variables to the variables file
+#[cfg(feature = "persistence")]
+pub(crate) fn save_variables(vars: &HashMap<String, Variable>) -> io::Result<()> {
+  write_atomically(&variables_path(), &variables_toml(vars.iter())?)
+}
+
+/// Variables are only kept in memory without the `persistence` feature
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn save_variables(_vars: &HashMap<String, Variable>) -> io::Result<()> {
+  Ok(())
+}
+
+/// The variables file for `vars`, with the variables sorted by name
+#[cfg(feature = "persistence")]
+fn variables_toml<'a>(
+  vars: impl Iterator<Item = (&'a String, &'a Variable)>,
+) -> io::Result<String> {
+  let stored: std::collections::BTreeMap<&String, StoredVariable> = vars
+    .map(|(name, variable)| {
+      let stored = StoredVariable {
+        kind: NUMBER.to_string(),
+        value: variable.value,
+        description: variable.description.clone(),
+        created: datetime(variable.created),
+      };
+      (name, stored)
+    })
+    .collect();
+  toml::to_string(&stored).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// The variables read from the variables file
+#[derive(Default)]
+pub(crate) struct SavedVariables {
+  pub(crate) variables: Vec<(String, Variable)>,
+  /// The damaged entries left out: the name of each variable this version
+  /// can't read, and the line of each table that isn't valid TOML
+  pub(crate) damaged: Vec<String>,
+}
+
+/// Reads the saved variables, converting a variables file of an earlier
+/// version
+#[cfg(feature = "persistence")]
+pub(crate) fn read_variables() -> io::Result<SavedVariables> {
+  let var_path = variables_path();
+  if !Path::new(&var_path).exists() {
+    return Ok(SavedVariables {
+      variables: migrate_variables()?,
+      damaged: Vec::new(),
+    });
+  }
+
+  let text = std::fs::read_to_string(&var_path)?;
+  let mut damaged = Vec::new();
+  let mut vars: Vec<(String, Variable)> = Vec::new();
+  let mut names = std::collections::HashSet::new();
+  for (line, table) in tables(&text) {
+    let Ok(table) = toml::from_str::<toml::Table>(&table) else {
+      damaged.push(format!("line {}", line));
+      continue;
+    };
+    for (name, entry) in table {
+      match variable(&name, &entry) {
+        Some(var) if names.insert(var.0.clone()) => vars.push(var),
+        _ => damaged.push(name),
+      }
+    }
+  }
+
+  if !damaged.is_empty() {
+    std::fs::copy(&var_path, format!("{}.bak", var_path))?;
+    let toml = variables_toml(vars.iter().map(|(name, variable)| (name, variable)))?;
+    write_atomically(&var_path, &toml)?;
+  }
+  Ok(SavedVariables {
+    variables: vars,
+    damaged,
+  })
+}
+
+/// There are no saved variables without the `persistence` feature
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn read_variables() -> io::Result<SavedVariables> {
+  Ok(SavedVariables::default())
+}
+
+/// Splits the variables file into its tables, each with the number of the
+/// line it starts on, so that a syntax error only loses the table it's in
+#[cfg(feature = "persistence")]
+fn tables(text: &str) -> Vec<(usize, String)> {
+  let mut tables: Vec<(usize, String)> = Vec::new();
+  for (index, line) in text.lines().enumerate() {
+    match tables.last_mut() {
+      Some((_, table)) if !line.trim_start().starts_with('[') => {
+        table.push_str(line);
+        table.push('\n');
+      }
+      _ => tables.push((index + 1, format!("{}\n", line))),
+    }
+  }
+  tables
+}
+
+/// The variable stored as `name`, if it's one this version understands. A
+/// missing creation time is taken to be now. The entry is read field by
+/// field, as a date and time in a `toml::Value` doesn't deserialize.
+#[cfg(feature = "persistence")]
+fn variable(name: &str, entry: &toml::Value) -> Option<(String, Variable)> {
+  if !crate::is_identifier(name) || entry.get("type")?.as_str()? != NUMBER {
+    return None;
+  }
+  let value = entry.get("value")?;
+  let description = match entry.get("description") {
+    Some(description) => Some(description.as_str()?.to_string()),
+    None => None,
+  };
+  let mut variable = Variable::new(
+    value
+      .as_float()
+      .or_else(|| value.as_integer().map(|value| value as f64))?,
+  );
+  variable.description = description;
+  if let Some(created) = entry.get("created") {
+    variable.created = seconds(created.as_datetime()?)?;
+  }
+  Some((name.to_lowercase(), variable))
+}
+
+/// Converts the `name=value` lines of the variables file of earlier
+/// versions, if there is one, to the TOML file
+#[cfg(feature = "persistence")]
+fn migrate_variables() -> io::Result<Vec<(String, Variable)>> {
+  let legacy_path = data_file("calculator_variables.txt");
+  if !Path::new(&legacy_path).exists() {
+    return Ok(Vec::new());
+  }
+
+  let content = std::fs::read(&legacy_path)?;
+  // A last line without its newline was cut off while being written
+  let complete = match content.iter().rposition(|&byte| byte == b'\n') {
+    Some(end) => &content[..=end],
+    None => &[],
+  };
+  let vars: Vec<(String, Variable)> = complete
+    .split(|&byte| byte == b'\n')
+    .filter_map(|line| std::str::from_utf8(line).ok().and_then(parse_variable))
+    .map(|(name, value)| (name, Variable::new(value)))
+    .collect();
+
+  let toml = variables_toml(vars.iter().map(|(name, variable)| (name, variable)))?;
+  write_atomically(&variables_path(), &toml)?;
+  std::fs::rename(&legacy_path, format!("{}.bak", legacy_path))?;
+  Ok(vars)
+}
+
+/// Reads a `name=value` line of the variables file of earlier versions
+#[cfg(feature = "persistence")]
+fn parse_variable(line: &str) -> Option<(String, f64)> {
+  let (name, value) = line.split_once('=')?;
+  let value = value.parse::<f64>().ok()?;
+  crate::is_identifier(name).then(|| (name.to_lowercase(), value))
+}
+
+/// The date and time in UTC `seconds` after the Unix epoch
+#[cfg(feature = "persistence")]
+fn datetime(seconds: u64) -> Datetime {
+  let date = crate::dates::Date::from_days((seconds / 86_400) as i64)
+    .expect("a creation time is before year 10000");
+  let time = seconds % 86_400;
+  Datetime {
+    date: Some(toml::value::Date {
+      year: date.year as u16,
+      month: date.month as u8,
+      day: date.day as u8,
+    }),
+    time: Some(toml::value::Time {
+      hour: (time / 3600) as u8,
+      minute: (time / 60 % 60) as u8,
+      second: (time % 60) as u8,
+      nanosecond: 0,
+    }),
+    offset: Some(Offset::Z),
+  }
+}
+
+/// The seconds after the Unix epoch of a date and time, with a missing time
+/// read as midnight and a missing offset as UTC
+#[cfg(feature = "persistence")]
+fn seconds(datetime: &Datetime) -> Option<u64> {
+  let date = datetime.date?;
+  let days =
+    crate::dates::Date::new(date.year.into(), date.month.into(), date.day.into())?.to_days();
+  let time = datetime.time.map_or(0, |time| {
+    i64::from(time.hour) * 3600 + i64::from(time.minute) * 60 + i64::from(time.second)
+  });
+  let offset = match datetime.offset {
+    Some(Offset::Custom { minutes }) => i64::from(minutes) * 60,
+    _ => 0,
+  };
+  u64::try_from(days * 86_400 + time - offset).ok()
+}
+
+/// The memory as the memory file keeps it, with its changes oldest first
+#[cfg(feature = "persistence")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredMemory {
+  memory: f64,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  changes: Vec<StoredChange>,
+}
+
+#[cfg(feature = "persistence")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredChange {
+  operation: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  operand: Option<f64>,
+  previous: f64,
+}
+
+/// Writes the memory and its changes to the memory file
+#[cfg(feature = "persistence")]
+pub(crate) fn save_memory(memory: &Memory) -> io::Result<()> {
+  let stored = StoredMemory {
+    memory: memory.value,
+    changes: memory
+      .changes
+      .iter()
+      .map(|change| StoredChange {
+        operation: change.operation.name().to_string(),
+        operand: change.operand,
+        previous: change.previous,
+      })
+      .collect(),
+  };
+  let toml =
+    toml::to_string(&stored).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+  write_atomically(&memory_path(), &toml)
+}
+
+/// The memory is only kept in memory without the `persistence` feature
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn save_memory(_memory: &Memory) -> io::Result<()> {
+  Ok(())
+}
+
+/// Reads the saved memory, if it was ever saved. Changes with an operation
+/// this version doesn't know are left out.
+#[cfg(feature = "persistence")]
+pub(crate) fn read_memory() -> io::Result<Option<Memory>> {
+  let path = memory_path();
+  if !Path::new(&path).exists() {
+    return Ok(None);
+  }
+  let stored: StoredMemory = toml::from_str(&std::fs::read_to_string(path)?)
+    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+  let changes = stored
+    .changes
+    .into_iter()
+    .filter_map(|change| {
+      Some(MemoryChange {
+        operation: MemoryOperation::from_name(&change.operation)?,
+        operand: change.operand,
+        previous: change.previous,
+      })
+    })
+    .collect();
+  Ok(Some(Memory {
+    value: stored.memory,
+    changes,
+  }))
+}
+
+/// There is no saved memory without the `persistence` feature
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn read_memory() -> io::Result<Option<Memory>> {
+  Ok(None)
+}
+
+/// Writes all user-defined functions to the functions file, one definition
+/// per line
+#[cfg(feature = "persistence")]
+pub(crate) fn save_functions<'a>(
+  functions: impl Iterator<Item = &'a crate::functions::UserFunction>,
+) -> io::Result<()> {
+  let text: String = functions
+    .map(|function| format!("{}\n", function))
+    .collect();
+  write_atomically(&functions_path(), &text)
+}
+
+/// Functions are only kept in memory without the `persistence` feature
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn save_functions<'a>(
+  _functions: impl Iterator<Item = &'a crate::functions::UserFunction>,
+) -> io::Result<()> {
+  Ok(())
+}
+
+/// Reads the definitions of the saved functions
+#[cfg(feature = "persistence")]
+pub(crate) fn read_functions() -> io::Result<Vec<String>> {
+  let path = functions_path();
+  if !Path::new(&path).exists() {
+    return Ok(Vec::new());
+  }
+  BufReader::new(File::open(path)?).lines().collect()
+}
+
+/// There are no saved functions without the `persistence` feature
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn read_functions() -> io::Result<Vec<String>> {
+  Ok(Vec::new())
+}
+
+/// Writes all formulas to the formulas file, one definition per line
+#[cfg(feature = "persistence")]
+pub(crate) fn save_formulas<'a>(
+  formulas: impl Iterator<Item = &'a crate::formulas::Formula>,
+) -> io::Result<()> {
+  let text: String = formulas.map(|formula| format!("{}\n", formula)).collect();
+  write_atomically(&formulas_path(), &text)
+}
+
+/// Formulas are only kept in memory without the `persistence` feature
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn save_formulas<'a>(
+  _formulas: impl Iterator<Item = &'a crate::formulas::Formula>,
+) -> io::Result<()> {
+  Ok(())
+}
+
+/// Reads the definitions of the saved formulas
+#[cfg(feature = "persistence")]
+pub(crate) fn read_formulas() -> io::Result<Vec<String>> {
+  let path = formulas_path();
+  if !Path::new(&path).exists() {
+    return Ok(Vec::new());
+  }
+  BufReader::new(File::open(path)?).lines().collect()
+}
+
+/// There are no saved formulas without the `persistence` feature
+#[cfg(not(feature = "persistence"))]
+pub(crate) fn read_formulas() -> io::Result<Vec<String>> {
+  Ok(Vec::new())
+}
+
+/// Replaces the file at `path` with `contents` by writing them to a
+/// temporary file first and renaming it over the old one, which leaves
+/// either the old file or the new one whole
+#[cfg(feature = "persistence")]
+fn write_atomically(path: &str, contents: &str) -> io::Result<()> {
+  use std::sync::atomic::{AtomicU64, Ordering};
+
+  // Each write has a temporary file of its own, so another process or
+  // thread saving the same file at once can't publish a partial one
+  static WRITES: AtomicU64 = AtomicU64::new(0);
+  let temporary = format!(
+    "{}.{}.{}.tmp",
+    path,
+    std::process::id(),
+    WRITES.fetch_add(1, Ordering::Relaxed)
+  );
+  let written = File::create(&temporary).and_then(|mut file| {
+    file.write_all(contents.as_bytes())?;
+    // The contents have to be on the disk before the rename is
+    file.sync_all()
+  });
+  let result = written.and_then(|()| std::fs::rename(&temporary, path));
+  if result.is_err() {
+    let _ = std::fs::remove_file(&temporary);
+  }
+  result
+}
+
+/// Get the path to the history file
+#[cfg(feature = "persistence")]
+pub(crate) fn history_path() -> String {
+  data_file("calculator_history.txt")
+}
+
+/// Get the path to the variables file
+#[cfg(feature = "persistence")]
+fn variables_path() -> String {
+  data_file("calculator_variables.toml")
+}
+
+/// Get the path to the functions file
+#[cfg(feature = "persistence")]
+fn functions_path() -> String {
+  data_file("calculator_functions.txt")
+}
+
+/// Get the path to the memory file
+#[cfg(feature = "persistence")]
+fn memory_path() -> String {
+  data_file("calculator_memory.toml")
+}
+
+/// Get the path to the formulas file
+#[cfg(feature = "persistence")]
+fn formulas_path() -> String {
+  data_file("calculator_formulas.txt")
+}
+
+/// Path of a file in the data directory, which is created if needed
+#[cfg(feature = "persistence")]
+fn data_file(name: &str) -> String {
+  let dir = THREAD_DATA_DIR
+    .with(|cell| cell.borrow().clone())
+    .or_else(|| DATA_DIR.read().unwrap().clone());
+  if let Some(dir) = dir {
+    std::fs::create_dir_all(&dir).unwrap_or_default();
+    return dir.join(name).display().to_string();
+  }
+  if let Some(proj_dirs) = directories::ProjectDirs::from("com", "thickkoezz", "rust-calculator") {
+    let data_dir = proj_dirs.data_dir();
+    // Create directory if it doesn't exist
+    std::fs::create_dir_all(data_dir).unwrap_or_default();
+    format!("{}/{}", data_dir.display(), name)
+  } else {
+    // Fallback to current directory
+    name.to_string()
+  }
+}
@@ -0,0 +1,230 @@
+//! # Parameter Sweep Tables
+//!
+//! Evaluates an expression over a range of values for one variable, e.g.
+//! `x ^ 2 x from 0 to 10 step 0.5`, and renders the results as an aligned
+//! text table or as CSV. Built on [`evaluate_vectorized`](crate::evaluate_vectorized).
+
+use crate::CalculatorError;
+use std::str::FromStr;
+
+/// Upper bound on the number of rows a single sweep may produce
+pub const MAX_ROWS: usize = 10_000;
+
+/// How a table is rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+  /// Columns padded for reading in a terminal
+  #[default]
+  Aligned,
+  /// Comma-separated values with a header row
+  Csv,
+}
+
+impl FromStr for OutputFormat {
+  type Err = CalculatorError;
+
+  fn from_str(format: &str) -> Result<Self, Self::Err> {
+    match format.trim().to_lowercase().as_str() {
+      "table" | "aligned" | "text" => Ok(OutputFormat::Aligned),
+      "csv" => Ok(OutputFormat::Csv),
+      other => Err(CalculatorError::ArgumentError(format!(
+        "Unknown output format: {}",
+        other
+      ))),
+    }
+  }
+}
+
+/// An expression evaluated over an evenly spaced range of one variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sweep {
+  /// The expression to evaluate
+  pub expression: String,
+  /// The variable that takes each value in the range
+  pub variable: String,
+  /// First value of the range
+  pub start: f64,
+  /// Last value of the range (inclusive)
+  pub end: f64,
+  /// Distance between consecutive values
+  pub step: f64,
+}
+
+impl Sweep {
+  /// Parses a specification of the form `expr var from start to end [step size]`.
+  ///
+  /// The bounds and step may be expressions themselves (`to 2 * pi`). The step
+  /// defaults to `1`, or `-1` when counting down.
+  pub fn parse(spec: &str) -> Result<Sweep, CalculatorError> {
+    let syntax_error = || {
+      CalculatorError::SyntaxError(
+        "Expected: table <expression> <variable> from <start> to <end> [step <size>]".to_string(),
+      )
+    };
+
+    let (head, range) = spec.rsplit_once(" from ").ok_or_else(syntax_error)?;
+    let (expression, variable) = head
+      .trim()
+      .rsplit_once(char::is_whitespace)
+      .ok_or_else(syntax_error)?;
+    let (start, rest) = range.split_once(" to ").ok_or_else(syntax_error)?;
+    let (end, step) = match rest.split_once(" step ") {
+      Some((end, step)) => (end, Some(step)),
+      None => (rest, None),
+    };
+
+    let start = crate::expression_value(start)?;
+    let end = crate::expression_value(end)?;
+    let step = match step {
+      Some(step) => crate::expression_value(step)?,
+      None if end < start => -1.0,
+      None => 1.0,
+    };
+
+    let sweep = Sweep {
+      expression: expression.trim().to_string(),
+      variable: variable.trim().to_lowercase(),
+      start,
+      end,
+      step,
+    };
+    sweep.values()?;
+    Ok(sweep)
+  }
+
+  /// Generates the values of the range, including `end` when it lands on a step
+  pub fn values(&self) -> Result<Vec<f64>, CalculatorError> {
+    if !self.start.is_finite() || !self.end.is_finite() || !self.step.is_finite() {
+      return Err(CalculatorError::ArgumentError(
+        "Range bounds and step must be finite".to_string(),
+      ));
+    }
+    if self.step == 0.0 || (self.end - self.start) * self.step < 0.0 {
+      return Err(CalculatorError::ArgumentError(format!(
+        "Step {} never reaches {} from {}",
+        self.step, self.end, self.start
+      )));
+    }
+
+    // Compute each value from the start to avoid accumulating rounding error
+    let count = ((self.end - self.start) / self.step + 1e-9).floor() + 1.0;
+    if count > MAX_ROWS as f64 {
+      return Err(CalculatorError::ArgumentError(format!(
+        "Range has too many rows (limit is {})",
+        MAX_ROWS
+      )));
+    }
+
+    Ok(
+      (0..count as usize)
+        .map(|index| self.start + index as f64 * self.step)
+        .collect(),
+    )
+  }
+
+  /// Evaluates the expression at every value of the range
+  pub fn evaluate(&self) -> Result<Table, CalculatorError> {
+    let inputs = self.values()?;
+    let outputs = crate::evaluate_vectorized(&self.expression, &self.variable, &inputs);
+
+    Ok(Table {
+      variable: self.variable.clone(),
+      expression: self.expression.clone(),
+      rows: inputs
+        .into_iter()
+        .zip(outputs)
+        .map(|(input, output)| (input, output.map_err(|err| err.to_string())))
+        .collect(),
+    })
+  }
+}
+
+/// The results of a sweep, one row per input value
+#[derive(Debug, Clone)]
+pub struct Table {
+  /// Header of the input column
+  pub variable: String,
+  /// Header of the output column
+  pub expression: String,
+  /// Each input with its result, or the error message if evaluation failed
+  pub rows: Vec<(f64, Result<f64, String>)>,
+}
+
+impl Table {
+  /// Renders the table in the given format
+  pub fn render(&self, format: OutputFormat) -> String {
+    match format {
+      OutputFormat::Aligned => self.to_aligned(),
+      OutputFormat::Csv => self.to_csv(),
+    }
+  }
+
+  /// Renders right-aligned columns with a header and separator line
+  pub fn to_aligned(&self) -> String {
+    let cells: Vec<(String, String)> = self
+      .rows
+      .iter()
+      .map(|(input, output)| {
+        let output = match output {
+          Ok(value) => value.to_string(),
+          Err(err) => format!("Error: {}", err),
+        };
+        (input.to_string(), output)
+      })
+      .collect();
+
+    let input_width = cells
+      .iter()
+      .map(|(input, _)| input.len())
+      .chain([self.variable.len()])
+      .max()
+      .unwrap_or(0);
+    let output_width = cells
+      .iter()
+      .map(|(_, output)| output.len())
+      .chain([self.expression.len()])
+      .max()
+      .unwrap_or(0);
+
+    let mut text = format!(
+      "{:>input_width$} | {:>output_width$}\n{}-+-{}\n",
+      self.variable,
+      self.expression,
+      "-".repeat(input_width),
+      "-".repeat(output_width),
+    );
+    for (input, output) in cells {
+      text.push_str(&format!(
+        "{:>input_width$} | {:>output_width$}\n",
+        input, output
+      ));
+    }
+    text
+  }
+
+  /// Renders comma-separated values; failed rows leave the value empty and add the error
+  pub fn to_csv(&self) -> String {
+    let mut text = format!(
+      "{},{},error\n",
+      csv_field(&self.variable),
+      csv_field(&self.expression)
+    );
+    for (input, output) in &self.rows {
+      let line = match output {
+        Ok(value) => format!("{},{},\n", input, value),
+        Err(err) => format!("{},,{}\n", input, csv_field(err)),
+      };
+      text.push_str(&line);
+    }
+    text
+  }
+}
+
+/// Quotes a CSV field if it contains separators or quotes
+fn csv_field(field: &str) -> String {
+  if field.contains([',', '"', '\n']) {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_string()
+  }
+}
@@ -0,0 +1,225 @@
+//! # Uncertainty Propagation
+//!
+//! Evaluates expressions containing measured values with uncertainties, written
+//! `5.0 ± 0.1`, `5.0 +/- 0.1` or `val(5.0, 0.1)`. Errors are propagated with
+//! the standard first-order rule for independent quantities,
+//! σ² = Σ (∂f/∂xᵢ · σᵢ)², where the partial derivatives are taken numerically,
+//! so every operator and function is supported.
+
+use crate::CalculatorError;
+use crate::prelude::*;
+use core::fmt;
+
+/// A value with its standard uncertainty
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Measurement {
+  pub value: f64,
+  pub uncertainty: f64,
+}
+
+impl fmt::Display for Measurement {
+  /// Shows the uncertainty with two significant figures and the value to the
+  /// same decimal place, e.g. `6.28 ± 0.31`
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.uncertainty == 0.0 || !self.uncertainty.is_finite() {
+      return write!(
+        f,
+        "{} ± {}",
+        crate::format_value(self.value),
+        self.uncertainty
+      );
+    }
+    let exponent = self.uncertainty.log10().floor() as i32;
+    let decimals = (1 - exponent).max(0) as usize;
+    write!(
+      f,
+      "{:.*} ± {:.*}",
+      decimals, self.value, decimals, self.uncertainty
+    )
+  }
+}
+
+/// Prefix of the names that stand in for measured values during evaluation
+const PLACEHOLDER: &str = "_measured";
+
+/// Checks whether an expression contains values with uncertainties
+pub fn contains_uncertainty(expression: &str) -> bool {
+  expression.contains('±') || expression.contains("+/-") || find_call(expression).is_some()
+}
+
+/// Evaluates an expression, propagating the uncertainties of its measured values
+///
+/// # Examples
+///
+/// ```
+/// let result = rust_calculator::uncertainty::evaluate("(5.0 ± 0.1) * 2").unwrap();
+/// assert_eq!(result.value, 10.0);
+/// assert!((result.uncertainty - 0.2).abs() < 1e-9);
+/// ```
+pub fn evaluate(expression: &str) -> Result<Measurement, CalculatorError> {
+  let mut measurements = Vec::new();
+  let expression = replace_calls(expression, &mut measurements)?;
+  let expression = replace_plus_minus(&expression, &mut measurements)?;
+
+  let values: Vec<f64> = measurements.iter().map(|m| m.value).collect();
+  let value = evaluate_at(&expression, &values)?;
+
+  let mut variance = 0.0;
+  for (index, measurement) in measurements.iter().enumerate() {
+    if measurement.uncertainty == 0.0 {
+      continue;
+    }
+    // Central difference with a step small relative to both the value and its uncertainty
+    let step = 1e-6 * measurement.value.abs().max(measurement.uncertainty);
+    let mut shifted = values.clone();
+    shifted[index] = measurement.value + step;
+    let above = evaluate_at(&expression, &shifted)?;
+    shifted[index] = measurement.value - step;
+    let below = evaluate_at(&expression, &shifted)?;
+
+    let derivative = (above - below) / (2.0 * step);
+    variance += (derivative * measurement.uncertainty).powi(2);
+  }
+
+  Ok(Measurement {
+    value,
+    uncertainty: variance.sqrt(),
+  })
+}
+
+/// Evaluates the rewritten expression with the measured values set to `values`
+fn evaluate_at(expression: &str, values: &[f64]) -> Result<f64, CalculatorError> {
+  let resolve = |name: &str| {
+    name
+      .strip_prefix(PLACEHOLDER)
+      .and_then(|index| index.parse::<usize>().ok())
+      .and_then(|index| values.get(index).copied())
+      .or_else(|| crate::get_variable(name))
+  };
+  // The placeholders shift positions, so spans wouldn't match the user's text
+  crate::evaluate_with_resolver(expression, &resolve).map_err(CalculatorError::without_span)
+}
+
+/// Records a measurement and returns the name standing in for it
+fn placeholder(
+  measurements: &mut Vec<Measurement>,
+  value: f64,
+  uncertainty: f64,
+) -> Result<String, CalculatorError> {
+  if uncertainty < 0.0 || !uncertainty.is_finite() {
+    return Err(CalculatorError::ArgumentError(
+      "Uncertainty must be a non-negative number".to_string(),
+    ));
+  }
+  measurements.push(Measurement { value, uncertainty });
+  Ok(format!(" {}{} ", PLACEHOLDER, measurements.len() - 1))
+}
+
+/// Finds the next `val(` call, returning the byte offsets of the call and of
+/// its argument list
+fn find_call(expression: &str) -> Option<(usize, usize)> {
+  let mut offset = 0;
+  while let Some(found) = expression[offset..].find("val(") {
+    let start = offset + found;
+    // Skip names that merely end in "val", such as "interval("
+    let preceded_by_name = expression[..start]
+      .chars()
+      .next_back()
+      .is_some_and(|ch| ch.is_alphanumeric() || ch == '_');
+    if !preceded_by_name {
+      return Some((start, start + "val(".len()));
+    }
+    offset = start + 1;
+  }
+  None
+}
+
+/// Replaces each `val(value, uncertainty)` with a placeholder
+fn replace_calls(
+  expression: &str,
+  measurements: &mut Vec<Measurement>,
+) -> Result<String, CalculatorError> {
+  let usage = || CalculatorError::ArgumentError("Usage: val(value, uncertainty)".to_string());
+
+  let mut replaced = String::new();
+  let mut rest = expression;
+  while let Some((start, arguments)) = find_call(rest) {
+    replaced.push_str(&rest[..start]);
+
+    // Find the closing parenthesis and the comma at the call's own depth
+    let mut depth = 0;
+    let mut comma = None;
+    let mut end = None;
+    for (index, ch) in rest[arguments..].char_indices() {
+      match ch {
+        '(' => depth += 1,
+        ')' if depth == 0 => {
+          end = Some(arguments + index);
+          break;
+        }
+        ')' => depth -= 1,
+        ',' if depth == 0 => comma = Some(arguments + index),
+        _ => {}
+      }
+    }
+    let (Some(comma), Some(end)) = (comma, end) else {
+      return Err(usage());
+    };
+
+    let value = crate::expression_value(&rest[arguments..comma])?;
+    let uncertainty = crate::expression_value(&rest[comma + 1..end])?;
+    replaced.push_str(&placeholder(measurements, value, uncertainty)?);
+    rest = &rest[end + 1..];
+  }
+  replaced.push_str(rest);
+  Ok(replaced)
+}
+
+/// Replaces each `value ± uncertainty` (or `+/-`) between number literals
+/// with a placeholder
+fn replace_plus_minus(
+  expression: &str,
+  measurements: &mut Vec<Measurement>,
+) -> Result<String, CalculatorError> {
+  let expression = expression.replace("+/-", "±");
+  let usage = || {
+    CalculatorError::SyntaxError(
+      "± must be written between two numbers, e.g. 5.0 ± 0.1".to_string(),
+    )
+  };
+
+  let mut replaced = String::new();
+  let mut rest = expression.as_str();
+  while let Some(sign) = rest.find('±') {
+    let before = rest[..sign].trim_end();
+    let after = rest[sign + '±'.len_utf8()..].trim_start();
+
+    // The literal before the sign, including a unary minus
+    let digits = before.len()
+      - before
+        .trim_end_matches(|ch: char| ch.is_ascii_digit() || ch == '.')
+        .len();
+    let mut start = before.len() - digits;
+    let ahead = before[..start].trim_end();
+    if let Some(preceding) = ahead.strip_suffix('-') {
+      let preceding = preceding.trim_end();
+      if preceding.is_empty() || preceding.ends_with(['+', '-', '*', '/', '%', '^', '(', ',']) {
+        start = ahead.len() - 1;
+      }
+    }
+    let value_text: String = before[start..].split_whitespace().collect();
+
+    let length = after
+      .find(|ch: char| !(ch.is_ascii_digit() || ch == '.'))
+      .unwrap_or(after.len());
+    let value = value_text.parse::<f64>().map_err(|_| usage())?;
+    let uncertainty = after[..length].parse::<f64>().map_err(|_| usage())?;
+
+    replaced.push_str(&before[..start]);
+    replaced.push_str(&placeholder(measurements, value, uncertainty)?);
+    rest = &after[length..];
+  }
+  replaced.push_str(rest);
+  Ok(replaced)
+}
@@ -0,0 +1,134 @@
+//! # Values
+//!
+//! The result of evaluating an expression. Most expressions produce a
+//! [`Value::Number`]; the other variants hold booleans, lists, matrices, dates
+//! and quantities with units, for expressions whose results aren't a single
+//! number.
+
+use crate::CalculatorError;
+use crate::prelude::*;
+use core::fmt;
+
+/// A value produced by evaluating an expression
+///
+/// With the `serde` feature, values serialize with their kind, e.g.
+/// `{"type": "number", "value": 8.0}`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(tag = "type", content = "value", rename_all = "lowercase")
+)]
+pub enum Value {
+  Number(f64),
+  Boolean(bool),
+  List(Vec<Value>),
+  /// A matrix stored as rows of equal length
+  Matrix(Vec<Vec<f64>>),
+  /// A calendar date, such as `2024-07-15`
+  Date(crate::dates::Date),
+  /// A number with a unit, such as `5 km`
+  Quantity {
+    value: f64,
+    unit: String,
+  },
+}
+
+impl Value {
+  /// Name of the value's kind, as used in error messages
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Value::Number(_) => "number",
+      Value::Boolean(_) => "boolean",
+      Value::List(_) => "list",
+      Value::Matrix(_) => "matrix",
+      Value::Date(_) => "date",
+      Value::Quantity { .. } => "quantity",
+    }
+  }
+
+  /// Returns the number if the value is one
+  pub fn as_number(&self) -> Option<f64> {
+    match self {
+      Value::Number(value) => Some(*value),
+      _ => None,
+    }
+  }
+
+  /// Returns the boolean if the value is one
+  pub fn as_bool(&self) -> Option<bool> {
+    match self {
+      Value::Boolean(value) => Some(*value),
+      _ => None,
+    }
+  }
+
+  /// Converts the value to a number, failing for any other kind of value
+  pub fn into_number(self) -> Result<f64, CalculatorError> {
+    self.as_number().ok_or_else(|| {
+      CalculatorError::ArgumentError(format!("Expected a number, got a {}", self.type_name()))
+    })
+  }
+}
+
+impl From<f64> for Value {
+  fn from(value: f64) -> Self {
+    Value::Number(value)
+  }
+}
+
+impl From<bool> for Value {
+  fn from(value: bool) -> Self {
+    Value::Boolean(value)
+  }
+}
+
+impl From<Vec<f64>> for Value {
+  fn from(values: Vec<f64>) -> Self {
+    Value::List(values.into_iter().map(Value::Number).collect())
+  }
+}
+
+impl From<Vec<Vec<f64>>> for Value {
+  fn from(rows: Vec<Vec<f64>>) -> Self {
+    Value::Matrix(rows)
+  }
+}
+
+impl TryFrom<Value> for f64 {
+  type Error = CalculatorError;
+
+  fn try_from(value: Value) -> Result<Self, Self::Error> {
+    value.into_number()
+  }
+}
+
+impl fmt::Display for Value {
+  /// Shows numbers as [`format_value`](crate::format_value) does, and lists
+  /// and matrices in brackets, e.g. `[1, 2, 3]` and `[[1, 2], [3, 4]]`
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Value::Number(value) => f.write_str(&crate::format_value(*value)),
+      Value::Boolean(value) => write!(f, "{}", value),
+      Value::List(items) => {
+        let items: Vec<String> = items.iter().map(Value::to_string).collect();
+        write!(f, "[{}]", items.join(", "))
+      }
+      Value::Matrix(rows) => {
+        let rows: Vec<String> = rows
+          .iter()
+          .map(|row| {
+            let row: Vec<String> = row
+              .iter()
+              .map(|value| crate::format_value(*value))
+              .collect();
+            format!("[{}]", row.join(", "))
+          })
+          .collect();
+        write!(f, "[{}]", rows.join(", "))
+      }
+      Value::Date(date) => write!(f, "{}", date),
+      Value::Quantity { value, unit } => write!(f, "{} {}", crate::format_value(*value), unit),
+    }
+  }
+}
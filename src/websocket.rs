@@ -0,0 +1,159 @@
+//! # WebSocket Interface
+//!
+//! Streams evaluation results and history updates to remote front-ends, such
+//! as a browser-based display mirroring the desktop calculator. Enabled with
+//! the `websocket` cargo feature.
+//!
+//! Every connected client receives JSON events:
+//!
+//! - `{"type": "history", "entries": [...]}` once, right after connecting
+//! - `{"type": "result", "expression": "2 + 3", "value": 5.0}` after each evaluation
+//! - `{"type": "error", "expression": "1 / 0", "message": "..."}` when an evaluation fails
+//!
+//! Clients may also send an expression as a text frame; it is evaluated and the
+//! outcome is broadcast to everyone.
+
+use serde_json::{Value, json};
+use std::io::{self, ErrorKind};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+/// How long a connection blocks on reads before flushing queued events
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Number of history entries sent to newly connected clients
+const HISTORY_SNAPSHOT: usize = 50;
+
+/// Handle used to push events to every connected client
+#[derive(Clone, Default)]
+pub struct Broadcaster {
+  clients: Arc<Mutex<Vec<Sender<String>>>>,
+}
+
+impl Broadcaster {
+  /// Creates a broadcaster with no connected clients
+  pub fn new() -> Self {
+    Broadcaster::default()
+  }
+
+  /// Publishes the outcome of an evaluation to all clients
+  pub fn publish(&self, expression: &str, result: &Result<f64, crate::CalculatorError>) {
+    self.send(&evaluation_event(expression, result));
+  }
+
+  /// Sends a raw JSON event to all clients, dropping those that disconnected
+  pub fn send(&self, event: &Value) {
+    let text = event.to_string();
+    let mut clients = self.clients.lock().unwrap();
+    clients.retain(|client| client.send(text.clone()).is_ok());
+  }
+
+  /// Returns the number of currently connected clients
+  pub fn client_count(&self) -> usize {
+    self.clients.lock().unwrap().len()
+  }
+
+  fn subscribe(&self) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    self.clients.lock().unwrap().push(sender);
+    receiver
+  }
+}
+
+/// Starts a WebSocket server on `addr` in a background thread.
+///
+/// Returns the [`Broadcaster`] used to push local evaluations to remote clients.
+pub fn serve<A: ToSocketAddrs>(addr: A) -> io::Result<Broadcaster> {
+  let listener = TcpListener::bind(addr)?;
+  let broadcaster = Broadcaster::new();
+
+  let accept_broadcaster = broadcaster.clone();
+  thread::spawn(move || {
+    for stream in listener.incoming().flatten() {
+      let broadcaster = accept_broadcaster.clone();
+      thread::spawn(move || {
+        if let Err(err) = handle_connection(stream, &broadcaster) {
+          eprintln!("WebSocket connection error: {}", err);
+        }
+      });
+    }
+  });
+
+  Ok(broadcaster)
+}
+
+/// Serves a single client until it disconnects
+fn handle_connection(stream: TcpStream, broadcaster: &Broadcaster) -> io::Result<()> {
+  let mut socket = tungstenite::accept(stream).map_err(|err| match err {
+    tungstenite::HandshakeError::Failure(err) => to_io_error(err),
+    tungstenite::HandshakeError::Interrupted(_) => io::Error::other("Handshake interrupted"),
+  })?;
+  socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+  // Subscribe before the snapshot so no event falls in between
+  let events = broadcaster.subscribe();
+
+  // Bring the new client up to date before streaming live events
+  let history = crate::get_history(HISTORY_SNAPSHOT).unwrap_or_default();
+  socket
+    .send(Message::text(
+      json!({ "type": "history", "entries": history }).to_string(),
+    ))
+    .map_err(to_io_error)?;
+
+  loop {
+    match socket.read() {
+      Ok(Message::Text(expression)) => evaluate(expression.trim(), broadcaster),
+      Ok(Message::Close(_)) => return Ok(()),
+      Ok(_) => {}
+      Err(tungstenite::Error::Io(err))
+        if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+      Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+      Err(err) => return Err(to_io_error(err)),
+    }
+
+    flush_events(&mut socket, &events)?;
+  }
+}
+
+/// Evaluates an expression received from a client and broadcasts the outcome
+fn evaluate(expression: &str, broadcaster: &Broadcaster) {
+  if expression.is_empty() {
+    return;
+  }
+
+  let result = crate::metrics::evaluate(expression);
+  if let Ok(value) = result {
+    // History is best effort; a read-only data directory shouldn't drop the result
+    let _ = crate::add_to_history(expression, value);
+  }
+  broadcaster.publish(expression, &result);
+}
+
+/// Writes every queued event to the client
+fn flush_events(socket: &mut WebSocket<TcpStream>, events: &Receiver<String>) -> io::Result<()> {
+  for event in events.try_iter() {
+    socket.send(Message::text(event)).map_err(to_io_error)?;
+  }
+  Ok(())
+}
+
+/// Unwraps I/O errors and wraps protocol errors so callers deal with one error type
+fn to_io_error(err: tungstenite::Error) -> io::Error {
+  match err {
+    tungstenite::Error::Io(err) => err,
+    err => io::Error::other(err),
+  }
+}
+
+/// Builds the JSON event describing an evaluation
+fn evaluation_event(expression: &str, result: &Result<f64, crate::CalculatorError>) -> Value {
+  match result {
+    Ok(value) => json!({ "type": "result", "expression": expression, "value": value }),
+    Err(err) => json!({ "type": "error", "expression": expression, "message": err.to_string() }),
+  }
+}
@@ -0,0 +1,144 @@
+//! # Numbers in Words
+//!
+//! `spell(x)` writes a number out in words, as on a cheque, so `spell(1234.5)`
+//! is `one thousand two hundred thirty-four point five`. The digits after
+//! the point are read one by one, from the number as
+//! [`format_result`](crate::format::format_result) shows it. Like `roman(x)`
+//! it produces text, so it's only available through
+//! [`evaluate_to_string`](crate::evaluate_to_string).
+//!
+//! The words come from a [`Language`], of which there's only English for
+//! now.
+//!
+//! ```
+//! use rust_calculator::evaluate_to_string;
+//! use rust_calculator::words::{Language, spell};
+//!
+//! assert_eq!(evaluate_to_string("spell(1000 + 234.5)").unwrap(), "one thousand two hundred thirty-four point five");
+//! assert_eq!(spell(-42.0, Language::English).unwrap(), "minus forty-two");
+//! ```
+
+use crate::format::{self, FormatOptions};
+use crate::prelude::*;
+use crate::{CalculatorError, bigint};
+
+/// A language numbers can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+  #[default]
+  English,
+}
+
+const ONES: [&str; 20] = [
+  "zero",
+  "one",
+  "two",
+  "three",
+  "four",
+  "five",
+  "six",
+  "seven",
+  "eight",
+  "nine",
+  "ten",
+  "eleven",
+  "twelve",
+  "thirteen",
+  "fourteen",
+  "fifteen",
+  "sixteen",
+  "seventeen",
+  "eighteen",
+  "nineteen",
+];
+
+const TENS: [&str; 10] = [
+  "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+/// Names of the powers of a thousand, from the smallest
+const SCALES: [&str; 6] = [
+  "",
+  "thousand",
+  "million",
+  "billion",
+  "trillion",
+  "quadrillion",
+];
+
+/// Writes `value` out in words in `language`. The whole part must be at
+/// most 2^53, beyond which not every whole number is exact.
+pub fn spell(value: f64, language: Language) -> Result<String, CalculatorError> {
+  if !value.is_finite() || value.abs() > bigint::MAX_EXACT {
+    return Err(CalculatorError::ArgumentError(format!(
+      "spell needs a number no larger than 2^53, got {}",
+      value
+    )));
+  }
+  // Adding zero turns -0 into 0. Past 15 digits, where format_result would
+  // round off the last ones, there's no fraction to show.
+  let written = if value.abs() < 1e15 {
+    format::format_result(value + 0.0, &FormatOptions::default())
+  } else {
+    format!("{:.0}", value)
+  };
+  let (sign, digits) = match written.strip_prefix('-') {
+    Some(digits) => (true, digits),
+    None => (false, written.as_str()),
+  };
+  let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+  let whole: u64 = whole
+    .parse()
+    .expect("a formatted number below 2^53 has whole digits");
+  let fraction: Vec<u32> = fraction.chars().filter_map(|ch| ch.to_digit(10)).collect();
+  Ok(match language {
+    Language::English => english(sign, whole, &fraction),
+  })
+}
+
+fn english(negative: bool, whole: u64, fraction: &[u32]) -> String {
+  let mut words = Vec::new();
+  if negative {
+    words.push("minus".to_string());
+  }
+  if whole == 0 {
+    words.push(ONES[0].to_string());
+  }
+  // Each group of three digits, from the largest, with its scale
+  let groups = (0..SCALES.len())
+    .rev()
+    .map(|scale| (whole / 1000u64.pow(scale as u32) % 1000, SCALES[scale]));
+  for (group, scale) in groups.filter(|(group, _)| *group != 0) {
+    words.push(english_hundreds(group));
+    if !scale.is_empty() {
+      words.push(scale.to_string());
+    }
+  }
+  if !fraction.is_empty() {
+    words.push("point".to_string());
+    words.extend(
+      fraction
+        .iter()
+        .map(|digit| ONES[*digit as usize].to_string()),
+    );
+  }
+  words.join(" ")
+}
+
+/// Writes a number from 1 to 999 in words
+fn english_hundreds(number: u64) -> String {
+  let (hundreds, rest) = ((number / 100) as usize, (number % 100) as usize);
+  let mut words = Vec::new();
+  if hundreds > 0 {
+    words.push(format!("{} hundred", ONES[hundreds]));
+  }
+  if rest >= 20 {
+    words.push(match rest % 10 {
+      0 => TENS[rest / 10].to_string(),
+      ones => format!("{}-{}", TENS[rest / 10], ONES[ones]),
+    });
+  } else if rest > 0 {
+    words.push(ONES[rest].to_string());
+  }
+  words.join(" ")
+}
@@ -1,9 +1,15 @@
 //! Tests for the rust-calculator library
 
+#![cfg(feature = "std")]
+
 #[cfg(test)]
 mod tests {
   use rust_calculator::evaluate_expression;
 
+  /// Held by the tests that use the global memory, which loading saved
+  /// variables also sets
+  static MEMORY_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
   #[test]
   fn test_basic_operations() {
     // Addition
@@ -140,6 +146,7 @@ mod tests {
 
   #[test]
   fn test_memory_functions() {
+    let _lock = MEMORY_LOCK.lock().unwrap();
     // Reset memory to start with a clean state
     assert_eq!(evaluate_expression("mc").unwrap(), 0.0);
 
@@ -163,6 +170,58 @@ mod tests {
     assert_eq!(evaluate_expression("mr").unwrap(), 0.0);
   }
 
+  #[test]
+  fn test_context_memory() {
+    use rust_calculator::EvalContext;
+    use std::sync::Arc;
+
+    // A context's memory starts empty and isn't shared with other tests
+    let ctx = EvalContext::new();
+    assert_eq!(ctx.evaluate("mr").unwrap(), 0.0);
+    assert_eq!(ctx.evaluate("5 m+").unwrap(), 5.0);
+    assert_eq!(ctx.evaluate("3 m+").unwrap(), 8.0);
+    assert_eq!(ctx.evaluate("2 m-").unwrap(), 6.0);
+    assert_eq!(ctx.memory(), 6.0);
+    assert_eq!(ctx.memory_changes().len(), 3);
+    assert_eq!(ctx.evaluate("mc").unwrap(), 0.0);
+    assert_eq!(ctx.evaluate("mundo").unwrap(), 6.0);
+
+    // Contexts on other threads each have their own memory
+    let handles: Vec<_> = (1..=4)
+      .map(|n| {
+        std::thread::spawn(move || {
+          let ctx = EvalContext::new();
+          for _ in 0..50 {
+            ctx.evaluate(&format!("{} m+", n)).unwrap();
+          }
+          ctx.evaluate("mr").unwrap()
+        })
+      })
+      .collect();
+    let totals: Vec<f64> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert_eq!(totals, vec![50.0, 100.0, 150.0, 200.0]);
+
+    // A shared context keeps every thread's changes
+    let shared = Arc::new(EvalContext::new());
+    let handles: Vec<_> = (0..4)
+      .map(|_| {
+        let shared = Arc::clone(&shared);
+        std::thread::spawn(move || {
+          for _ in 0..25 {
+            shared.evaluate("1 m+").unwrap();
+          }
+        })
+      })
+      .collect();
+    handles.into_iter().for_each(|h| h.join().unwrap());
+    assert_eq!(shared.memory(), 100.0);
+
+    // A clone starts from the memory it was cloned with
+    let mut copy = ctx.clone();
+    copy.clear_memory();
+    assert_eq!((ctx.memory(), copy.memory()), (6.0, 0.0));
+  }
+
   #[test]
   fn test_unit_conversions() {
     // Length conversions
@@ -227,4 +286,3705 @@ mod tests {
     assert_eq!(evaluate_expression("x * y + 2").unwrap(), 52.0);
     assert_eq!(evaluate_expression("(x + y) * 2").unwrap(), 30.0);
   }
+
+  #[test]
+  fn test_jsonrpc_session() {
+    let mut session = rust_calculator::jsonrpc::Session::new();
+
+    // Successful evaluation
+    let responses = session.handle_line(
+      r#"{"jsonrpc":"2.0","id":1,"method":"evaluate","params":{"expression":"2 + 3"}}"#,
+    );
+    assert_eq!(responses.len(), 1);
+    assert!(responses[0].contains(r#""value":5.0"#));
+
+    // Evaluation errors are reported as JSON-RPC errors
+    let responses =
+      session.handle_line(r#"{"jsonrpc":"2.0","id":2,"method":"evaluate","params":["5 / 0"]}"#);
+    assert!(responses[0].contains(r#""code":-32000"#));
+
+    // Unknown methods and malformed JSON
+    let responses = session.handle_line(r#"{"jsonrpc":"2.0","id":3,"method":"nope"}"#);
+    assert!(responses[0].contains(r#""code":-32601"#));
+    let responses = session.handle_line("{not json");
+    assert!(responses[0].contains(r#""code":-32700"#));
+
+    // Subscribed sessions receive a notification after each evaluation
+    session.handle_line(r#"{"jsonrpc":"2.0","id":4,"method":"subscribe"}"#);
+    let responses =
+      session.handle_line(r#"{"jsonrpc":"2.0","id":5,"method":"evaluate","params":["6 * 7"]}"#);
+    assert_eq!(responses.len(), 2);
+    assert!(responses[1].contains(r#""method":"evaluated""#));
+
+    // Variables need names that are identifiers
+    let responses = session.handle_line(
+      r#"{"jsonrpc":"2.0","id":6,"method":"setVariable","params":{"name":"two words","value":1}}"#,
+    );
+    assert!(responses[0].contains(r#""code":-32602"#));
+    assert!(responses[0].contains("Invalid variable name: two words"));
+  }
+
+  #[test]
+  fn test_mcp_session() {
+    let mut session = rust_calculator::mcp::Session::new();
+
+    let responses = session.handle_line(
+      r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2024-11-05"}}"#,
+    );
+    assert!(responses[0].contains(r#""tools":{}"#));
+
+    // The initialized notification gets no reply
+    assert!(
+      session
+        .handle_line(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+        .is_empty()
+    );
+
+    let responses = session.handle_line(r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#);
+    assert!(responses[0].contains(r#""name":"convert_units""#));
+
+    let responses = session.handle_line(
+      r#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"evaluate","arguments":{"expression":"2 ^ 10"}}}"#,
+    );
+    assert!(responses[0].contains(r#""text":"1024""#));
+    assert!(responses[0].contains(r#""isError":false"#));
+
+    let responses = session.handle_line(
+      r#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"convert_units","arguments":{"value":100,"from":"c","to":"f"}}}"#,
+    );
+    assert!(responses[0].contains("100 c = 212 f"));
+
+    // Tool failures are reported in the result, not as protocol errors
+    let responses = session.handle_line(
+      r#"{"jsonrpc":"2.0","id":5,"method":"tools/call","params":{"name":"evaluate","arguments":{"expression":"1 / 0"}}}"#,
+    );
+    assert!(responses[0].contains(r#""isError":true"#));
+
+    let responses = session.handle_line(
+      r#"{"jsonrpc":"2.0","id":6,"method":"tools/call","params":{"name":"define_variable","arguments":{"name":"1st","expression":"2"}}}"#,
+    );
+    assert!(responses[0].contains("Invalid variable name: 1st"));
+    assert!(responses[0].contains(r#""isError":true"#));
+  }
+
+  #[test]
+  fn test_mcp_evaluate_results() {
+    let mut session = rust_calculator::mcp::Session::new();
+    let mut evaluate = |expression: &str| {
+      let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": "evaluate", "arguments": { "expression": expression } },
+      });
+      let response: serde_json::Value =
+        serde_json::from_str(&session.handle_line(&request.to_string())[0]).unwrap();
+      assert_eq!(response["result"]["isError"], false, "{}", expression);
+      response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap()
+        .to_string()
+    };
+
+    // Every kind of value the tool describes comes back as text
+    assert_eq!(evaluate("2 + 3"), "5");
+    assert_eq!(evaluate("fact 25"), "15511210043330985984000000");
+    assert_eq!(evaluate("sort([3, 1, 2])"), "[1, 2, 3]");
+    assert_eq!(evaluate("transpose([[1, 2], [3, 4]])"), "[[1, 3], [2, 4]]");
+    assert_eq!(evaluate("solvequad(1, -3, 2)"), "[1, 2]");
+    assert_eq!(evaluate("\"2024-07-15\" + 90d"), "2024-10-13");
+    assert_eq!(evaluate("weekday(\"2024-07-15\")"), "Monday");
+    assert_eq!(evaluate("spell(42)"), "forty-two");
+    assert_eq!(evaluate("simplify(x * 1 + 0)"), "x");
+  }
+
+  #[cfg(feature = "websocket")]
+  #[test]
+  fn test_websocket_streaming() {
+    // Find a free port for the server
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap()
+      .port();
+    let addr = format!("127.0.0.1:{}", port);
+    let _broadcaster = rust_calculator::websocket::serve(addr.as_str()).unwrap();
+
+    let (mut socket, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+
+    // The first event is the history snapshot
+    let snapshot = socket.read().unwrap().into_text().unwrap();
+    assert!(snapshot.contains(r#""type":"history""#));
+
+    // Expressions sent by a client are evaluated and broadcast back
+    socket.send(tungstenite::Message::text("6 * 7")).unwrap();
+    let event = socket.read().unwrap().into_text().unwrap();
+    assert!(event.contains(r#""type":"result""#));
+    assert!(event.contains(r#""value":42.0"#));
+  }
+
+  #[test]
+  fn test_spreadsheet_cells() {
+    let mut sheet = rust_calculator::sheet::Sheet::new();
+
+    sheet.set("A1", "10").unwrap();
+    sheet.set("B1", "A1 * 2").unwrap();
+    sheet.set("c1", "a1 + b1").unwrap();
+    assert_eq!(sheet.value("C1").unwrap(), 30.0);
+
+    // Changing an input recalculates every dependent cell in order
+    let updated = sheet.set("A1", "5").unwrap();
+    assert_eq!(updated, vec!["A1", "B1", "C1"]);
+    assert_eq!(sheet.value("B1").unwrap(), 10.0);
+    assert_eq!(sheet.value("C1").unwrap(), 15.0);
+
+    // Empty cells count as zero
+    sheet.set("D1", "Z9 + 1").unwrap();
+    assert_eq!(sheet.value("D1").unwrap(), 1.0);
+
+    // Circular references are rejected
+    assert!(sheet.set("A1", "C1 + 1").is_err());
+    assert!(sheet.set("E1", "E1").is_err());
+    assert_eq!(sheet.value("A1").unwrap(), 5.0);
+
+    // Invalid cell names
+    assert!(sheet.set("1A", "3").is_err());
+  }
+
+  #[test]
+  fn test_import_variables() {
+    let dir = std::env::temp_dir();
+
+    let json_path = dir.join("rust_calculator_import_test.json");
+    std::fs::write(
+      &json_path,
+      r#"{"import_rate": 0.25, "import_count": "4", "import_label": "skip me"}"#,
+    )
+    .unwrap();
+    let imported = rust_calculator::import_variables(json_path.to_str().unwrap()).unwrap();
+    assert_eq!(
+      imported,
+      vec![
+        ("import_count".to_string(), 4.0),
+        ("import_rate".to_string(), 0.25)
+      ]
+    );
+    assert_eq!(
+      evaluate_expression("import_rate * import_count").unwrap(),
+      1.0
+    );
+
+    let env_path = dir.join("rust_calculator_import_test.env");
+    std::fs::write(
+      &env_path,
+      "# Parameters\nexport IMPORT_WIDTH=3\nIMPORT_HEIGHT=\"7\"\nIMPORT_NAME=box\n",
+    )
+    .unwrap();
+    let imported = rust_calculator::import_variables(env_path.to_str().unwrap()).unwrap();
+    assert_eq!(imported.len(), 2);
+    assert_eq!(
+      evaluate_expression("import_width * import_height").unwrap(),
+      21.0
+    );
+
+    // Malformed JSON is reported as an error
+    std::fs::write(&json_path, "{ not json").unwrap();
+    assert!(rust_calculator::import_variables(json_path.to_str().unwrap()).is_err());
+  }
+
+  #[test]
+  fn test_parameter_sweep_table() {
+    use rust_calculator::table::{OutputFormat, Sweep};
+
+    let results = rust_calculator::evaluate_vectorized("t * 2 + 1", "t", &[0.0, 1.5, 3.0]);
+    let values: Vec<f64> = results.into_iter().map(Result::unwrap).collect();
+    assert_eq!(values, vec![1.0, 4.0, 7.0]);
+
+    let sweep = Sweep::parse("t^2 t from 0 to 10 step 0.5").unwrap();
+    assert_eq!(sweep.variable, "t");
+    let table = sweep.evaluate().unwrap();
+    assert_eq!(table.rows.len(), 21);
+    assert_eq!(table.rows[3].0, 1.5);
+    assert_eq!(table.rows[20].1, Ok(100.0));
+
+    // Counting down without an explicit step, with a failing row in the middle
+    let table = Sweep::parse("1 / t t from 1 to -1")
+      .unwrap()
+      .evaluate()
+      .unwrap();
+    assert_eq!(table.rows.len(), 3);
+    assert!(table.rows[1].1.is_err());
+
+    let csv = table.render("csv".parse::<OutputFormat>().unwrap());
+    assert!(csv.starts_with("t,1 / t,error\n1,1,\n0,,"));
+    let aligned = table.render(OutputFormat::Aligned);
+    assert!(aligned.starts_with(" t | "));
+
+    assert!(Sweep::parse("t t from 0 to 10 step -1").is_err());
+    assert!(Sweep::parse("t from 0 to 10").is_err());
+  }
+
+  #[test]
+  #[cfg(feature = "dbus")]
+  fn test_dbus_service() {
+    use rust_calculator::dbus::CalculatorService;
+
+    // The methods are exercised directly; a session bus isn't available everywhere
+    let service = CalculatorService;
+    assert_eq!(service.evaluate("6 * 7").unwrap(), 42.0);
+    assert!(service.evaluate("1 / 0").is_err());
+    assert!(service.get_history(5).unwrap().len() <= 5);
+  }
+
+  #[test]
+  #[cfg(unix)]
+  fn test_socket_daemon() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let path = std::env::temp_dir().join(format!("rust-calculator-{}.sock", std::process::id()));
+    let listener = rust_calculator::daemon::bind(&path).unwrap();
+    std::thread::spawn(move || rust_calculator::daemon::serve(listener));
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    stream.write_all(b"2 + 3 * 4\n1 / 0\n").unwrap();
+
+    let mut reply = String::new();
+    reader.read_line(&mut reply).unwrap();
+    assert_eq!(reply, "14\n");
+    reply.clear();
+    reader.read_line(&mut reply).unwrap();
+    assert!(reply.starts_with("error: "));
+
+    // A second daemon can't take over a live socket
+    assert!(rust_calculator::daemon::bind(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_format_value() {
+    assert_eq!(rust_calculator::format_value(42.0), "42");
+    assert_eq!(rust_calculator::format_value(-2.5), "-2.5");
+    assert_eq!(rust_calculator::format_value(1e15), "1000000000000000");
+  }
+
+  #[test]
+  #[cfg(feature = "clipboard")]
+  fn test_clipboard_normalization() {
+    use rust_calculator::clipboard::normalize_expression;
+
+    // Copying and pasting needs a display server, so only the text handling is tested
+    assert_eq!(normalize_expression("  6 × 7\n"), "6 * 7");
+    assert_eq!(normalize_expression("10 ÷ 4 − 1"), "10 / 4 - 1");
+    assert_eq!(
+      evaluate_expression(&normalize_expression("2 ×\n 3")).unwrap(),
+      6.0
+    );
+  }
+
+  #[test]
+  #[cfg(feature = "plot")]
+  fn test_plot_rendering() {
+    let sweep = rust_calculator::plot::parse("1 / p p from -1 to 1").unwrap();
+    assert_eq!(
+      sweep.step,
+      2.0 / rust_calculator::plot::DEFAULT_SAMPLES as f64
+    );
+
+    let dir = std::env::temp_dir();
+    let svg = dir.join("rust_calculator_plot_test.svg");
+    rust_calculator::plot::render(&sweep, &svg).unwrap();
+    assert!(std::fs::read_to_string(&svg).unwrap().contains("<svg"));
+
+    let png = dir.join("rust_calculator_plot_test.png");
+    rust_calculator::plot::render(&sweep, &png).unwrap();
+    assert!(std::fs::read(&png).unwrap().starts_with(b"\x89PNG"));
+
+    assert!(rust_calculator::plot::render(&sweep, dir.join("chart.txt")).is_err());
+  }
+
+  #[test]
+  fn test_rpn_conversion() {
+    use rust_calculator::rpn::{from_rpn, to_rpn};
+
+    assert_eq!(to_rpn("2 + 3 * 4").unwrap(), "2 3 4 * +");
+    assert_eq!(to_rpn("(2 + 3) * 4").unwrap(), "2 3 + 4 *");
+    assert_eq!(to_rpn("2 ^ 3 ^ 2").unwrap(), "2 3 2 ^ ^");
+    assert_eq!(to_rpn("sqrt(16) + 1").unwrap(), "16 sqrt 1 +");
+    assert!(to_rpn("(2 + 3").is_err());
+    assert!(to_rpn("2 +").is_err());
+
+    assert_eq!(from_rpn("2 3 4 * +").unwrap(), "2 + 3 * 4");
+    assert_eq!(from_rpn("2 3 + 4 *").unwrap(), "(2 + 3) * 4");
+    assert_eq!(from_rpn("10 4 2 - -").unwrap(), "10 - (4 - 2)");
+    assert_eq!(from_rpn("2 3 ^ 2 ^").unwrap(), "(2 ^ 3) ^ 2");
+    assert_eq!(from_rpn("16 sqrt pi *").unwrap(), "sqrt(16) * pi");
+    assert!(from_rpn("1 +").is_err());
+    assert!(from_rpn("1 2").is_err());
+
+    // Round trips evaluate to the same value
+    for expression in [
+      "8 - 3 - 2",
+      "2 ^ 3 ^ 2",
+      "100 / (5 * 2) % 3",
+      "abs(2 - 7) * 3",
+    ] {
+      let round_trip = from_rpn(&to_rpn(expression).unwrap()).unwrap();
+      assert_eq!(
+        evaluate_expression(&round_trip).unwrap(),
+        evaluate_expression(expression).unwrap()
+      );
+    }
+  }
+
+  #[test]
+  fn test_ast_json() {
+    use rust_calculator::ast::{Expr, parse};
+
+    let tree = parse("2 * (3 + 4)").unwrap();
+    assert_eq!(
+      tree,
+      Expr::Binary {
+        operator: "*".to_string(),
+        left: Box::new(Expr::Number(2.0)),
+        right: Box::new(Expr::Binary {
+          operator: "+".to_string(),
+          left: Box::new(Expr::Number(3.0)),
+          right: Box::new(Expr::Number(4.0)),
+        }),
+      }
+    );
+    assert_eq!(tree.evaluate().unwrap(), 14.0);
+
+    // JSON round trip
+    let json = parse("sqrt(16) ^ 2 - 1").unwrap().to_json();
+    assert_eq!(json["type"], "binary");
+    assert_eq!(json["left"]["left"]["name"], "sqrt");
+    let restored = Expr::from_json(&json).unwrap();
+    assert_eq!(restored.evaluate().unwrap(), 15.0);
+
+    // Malformed trees are rejected
+    let bad_operator = serde_json::json!({
+      "type": "binary", "operator": "@",
+      "left": { "type": "number", "value": 1 }, "right": { "type": "number", "value": 2 },
+    });
+    assert!(Expr::from_json(&bad_operator).is_err());
+    assert!(Expr::from_json(&serde_json::json!({ "type": "function", "name": "sqrt" })).is_err());
+    assert!(parse("2 +").is_err());
+  }
+
+  #[test]
+  fn test_metrics_endpoint() {
+    use std::io::{Read, Write};
+
+    // Counters are global, so only check that they move
+    let count = |text: &str, metric: &str| -> u64 {
+      text
+        .lines()
+        .find_map(|line| line.strip_prefix(metric))
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap()
+    };
+    let before = rust_calculator::metrics::render();
+
+    assert_eq!(rust_calculator::metrics::evaluate("2 + 2").unwrap(), 4.0);
+    assert!(rust_calculator::metrics::evaluate("1 / 0").is_err());
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    rust_calculator::metrics::serve(addr).unwrap();
+
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("# TYPE calculator_evaluation_duration_seconds histogram"));
+    let evaluations = "calculator_evaluations_total ";
+    assert!(count(&response, evaluations) >= count(&before, evaluations) + 2);
+    let math_errors = "calculator_errors_total{kind=\"math\"} ";
+    assert!(count(&response, math_errors) > count(&before, math_errors));
+  }
+
+  #[test]
+  fn test_roman_numerals() {
+    use rust_calculator::evaluate_to_string;
+    use rust_calculator::roman::{from_roman, to_roman};
+
+    assert_eq!(to_roman(1994.0).unwrap(), "MCMXCIV");
+    assert_eq!(to_roman(3999.0).unwrap(), "MMMCMXCIX");
+    assert!(to_roman(0.0).is_err());
+    assert!(to_roman(4000.0).is_err());
+    assert!(to_roman(2.5).is_err());
+
+    assert_eq!(from_roman("xiv").unwrap(), 14.0);
+    assert!(from_roman("IIII").is_err());
+    assert!(from_roman("IC").is_err());
+    assert!(from_roman("ABC").is_err());
+
+    assert_eq!(evaluate_to_string("roman(2000 + 24)").unwrap(), "MMXXIV");
+    assert_eq!(
+      evaluate_to_string("roman(from_roman('IX') + 1)").unwrap(),
+      "X"
+    );
+    assert_eq!(evaluate_to_string("2 + 3").unwrap(), "5");
+    assert_eq!(
+      evaluate_expression("from_roman(\"XIV\") * 2").unwrap(),
+      28.0
+    );
+    assert!(evaluate_expression("from_roman(XIV)").is_err());
+    // roman() produces text, so it can't feed into arithmetic
+    assert_eq!(
+      evaluate_expression("roman(5) + 1").unwrap_err().to_string(),
+      "Argument error: roman() produces text, which is only available through evaluate_to_string"
+    );
+    assert!(!rust_calculator::returns_text("roman(1) + roman(2)"));
+
+    // The call is read from the parsed expression, not its text
+    assert_eq!(evaluate_to_string(" roman ( 14 ) ").unwrap(), "XIV");
+    assert_eq!(evaluate_to_string("roman((4) + (10))").unwrap(), "XIV");
+    assert!(evaluate_to_string("roman(1) + roman(2)").is_err());
+    assert!(evaluate_to_string("roman(1, 2)").is_err());
+  }
+
+  #[test]
+  fn test_currency_formatting() {
+    use rust_calculator::currency::format_currency;
+    use rust_calculator::evaluate_to_string;
+
+    assert_eq!(format_currency(1234.5, "USD").unwrap(), "$1,234.50");
+    assert_eq!(
+      format_currency(-1234567.891, "eur").unwrap(),
+      "-€1,234,567.89"
+    );
+    assert_eq!(format_currency(1234.5, "JPY").unwrap(), "¥1,235");
+    assert_eq!(format_currency(99.0, "SEK").unwrap(), "99.00 kr");
+    assert_eq!(format_currency(-0.001, "USD").unwrap(), "$0.00");
+    assert!(format_currency(1.0, "XYZ").is_err());
+    assert!(format_currency(f64::INFINITY, "USD").is_err());
+
+    assert_eq!(
+      evaluate_to_string("currency(1000 * 3 + 0.5, \"GBP\")").unwrap(),
+      "£3,000.50"
+    );
+    assert_eq!(evaluate_to_string("currency(12, 'USD')").unwrap(), "$12.00");
+    assert!(rust_calculator::returns_text("currency(1, \"USD\")"));
+    assert!(evaluate_to_string("currency(12)").is_err());
+  }
+
+  #[test]
+  fn test_domain_policy() {
+    use rust_calculator::settings::{DomainPolicy, Settings};
+    use rust_calculator::{CalculatorError, evaluate_complex, evaluate_expression_with_settings};
+
+    let error = Settings::default();
+    let nan = Settings {
+      domain: DomainPolicy::NaN,
+      ..Settings::default()
+    };
+    let complex = Settings {
+      domain: DomainPolicy::Complex,
+      ..Settings::default()
+    };
+
+    // The default policy keeps failing on domain violations
+    assert!(evaluate_expression_with_settings("sqrt(-4)", &error).is_err());
+    assert!(evaluate_expression_with_settings("log(0)", &error).is_err());
+
+    // NaN propagates through the rest of the expression
+    assert!(
+      evaluate_expression_with_settings("sqrt(-4) + 1", &nan)
+        .unwrap()
+        .is_nan()
+    );
+    assert!(
+      evaluate_expression_with_settings("acos(2)", &nan)
+        .unwrap()
+        .is_nan()
+    );
+
+    // Complex promotion gives real results when the imaginary parts cancel
+    let result = evaluate_expression_with_settings("sqrt(-4) * sqrt(-4)", &complex).unwrap();
+    assert!((result + 4.0).abs() < 1e-12);
+    assert!(matches!(
+      evaluate_expression_with_settings("sqrt(-4)", &complex),
+      Err(CalculatorError::MathError(_))
+    ));
+    // Errors that complex numbers can't fix are unchanged
+    assert!(matches!(
+      evaluate_expression_with_settings("1 / 0", &complex),
+      Err(CalculatorError::MathError(_))
+    ));
+
+    assert_eq!(evaluate_complex("sqrt(-4)").unwrap().to_string(), "2i");
+    assert_eq!(
+      evaluate_complex("2 - sqrt(-9)").unwrap().to_string(),
+      "2 - 3i"
+    );
+    let log = evaluate_complex("ln(-1)").unwrap();
+    assert!(log.re.abs() < 1e-12 && (log.im - std::f64::consts::PI).abs() < 1e-12);
+
+    // Settings passed for one call don't change the defaults
+    assert!(evaluate_expression("sqrt(-4)").is_err());
+  }
+
+  #[test]
+  fn test_angle_mode() {
+    use rust_calculator::evaluate_expression_with;
+    use rust_calculator::settings::AngleMode;
+
+    let close = |actual: f64, expected: f64| (actual - expected).abs() < 1e-10;
+
+    assert!(close(
+      evaluate_expression_with("sin(pi / 2)", AngleMode::Radians).unwrap(),
+      1.0
+    ));
+    assert!(close(
+      evaluate_expression_with("cos(pi)", AngleMode::Radians).unwrap(),
+      -1.0
+    ));
+    assert!(close(
+      evaluate_expression_with("atan(1)", AngleMode::Radians).unwrap(),
+      std::f64::consts::FRAC_PI_4
+    ));
+    assert!(close(
+      evaluate_expression_with("sin(90)", AngleMode::Degrees).unwrap(),
+      1.0
+    ));
+    assert!(close(
+      evaluate_expression_with("acos(0)", AngleMode::Degrees).unwrap(),
+      90.0
+    ));
+
+    assert!(close(
+      evaluate_expression_with("cos(200)", AngleMode::Gradians).unwrap(),
+      -1.0
+    ));
+    assert!(close(
+      evaluate_expression_with("asin(1)", AngleMode::Gradians).unwrap(),
+      100.0
+    ));
+    assert_eq!("gon".parse::<AngleMode>().unwrap(), AngleMode::Gradians);
+    assert_eq!(AngleMode::Gradians.to_string(), "gradians");
+
+    // A context keeps its own mode
+    let mut ctx = rust_calculator::EvalContext::new();
+    ctx.set_angle_mode(AngleMode::Radians);
+    assert_eq!(ctx.angle_mode(), AngleMode::Radians);
+    assert!(close(ctx.evaluate("sin(pi / 2)").unwrap(), 1.0));
+
+    // The default stays in degrees
+    assert!(close(evaluate_expression("sin(90)").unwrap(), 1.0));
+  }
+
+  #[test]
+  fn test_overflow_detection() {
+    use rust_calculator::settings::{OverflowPolicy, Settings};
+    use rust_calculator::{CalculatorError, evaluate_expression_with_settings};
+
+    for expression in ["fact 171", "10 ^ 400", "exp(1000)", "1e300 * 1e300"] {
+      assert!(
+        matches!(
+          evaluate_expression(expression),
+          Err(CalculatorError::Overflow(_))
+        ),
+        "{} should overflow",
+        expression
+      );
+    }
+    assert!(evaluate_expression("fact 170").unwrap().is_finite());
+
+    let ieee = Settings {
+      overflow: OverflowPolicy::Ieee,
+      ..Settings::default()
+    };
+    assert_eq!(
+      evaluate_expression_with_settings("10 ^ 400", &ieee).unwrap(),
+      f64::INFINITY
+    );
+    assert_eq!(
+      evaluate_expression_with_settings("0 - 10 ^ 400", &ieee).unwrap(),
+      f64::NEG_INFINITY
+    );
+  }
+
+  #[test]
+  fn test_non_finite_inputs() {
+    use rust_calculator::settings::{NonFinitePolicy, Settings};
+    use rust_calculator::{CalculatorError, evaluate_expression_with_settings};
+
+    // By default infinities are accepted and NaN propagates uniformly
+    assert_eq!(evaluate_expression("inf + 1").unwrap(), f64::INFINITY);
+    assert_eq!(evaluate_expression("fact inf").unwrap(), f64::INFINITY);
+    assert!(evaluate_expression("inf - inf").unwrap().is_nan());
+    assert!(evaluate_expression("nan * 0").unwrap().is_nan());
+    assert!(evaluate_expression("1 ^ nan").unwrap().is_nan());
+    assert!(evaluate_expression("asin(nan)").unwrap().is_nan());
+    assert!(evaluate_expression("fact(nan)").unwrap().is_nan());
+
+    let reject = Settings {
+      non_finite: NonFinitePolicy::Reject,
+      ..Settings::default()
+    };
+    for expression in ["inf", "inf + 1", "nan * 0", "1e400 - 1", "inf km_to_mi"] {
+      assert!(
+        matches!(
+          evaluate_expression_with_settings(expression, &reject),
+          Err(CalculatorError::ArgumentError(_))
+        ),
+        "{} should be rejected",
+        expression
+      );
+    }
+    assert_eq!(
+      evaluate_expression_with_settings("1e300 + 1", &reject).unwrap(),
+      1e300
+    );
+  }
+
+  #[test]
+  fn test_rounding_modes() {
+    use rust_calculator::evaluate_expression_with_settings;
+    use rust_calculator::settings::{RoundingMode, Settings};
+
+    let round = |expression: &str, mode: &str| {
+      let mut settings = Settings::default();
+      settings.set("rounding", mode).unwrap();
+      evaluate_expression_with_settings(expression, &settings).unwrap()
+    };
+
+    assert_eq!(round("round(2.5)", "half-up"), 3.0);
+    assert_eq!(round("round(0 - 2.5)", "half-up"), -3.0);
+    assert_eq!(round("round(2.5)", "half-even"), 2.0);
+    assert_eq!(round("round(3.5)", "bankers"), 4.0);
+    assert_eq!(round("round(2.7)", "floor"), 2.0);
+    assert_eq!(round("round(0 - 2.2)", "floor"), -3.0);
+    assert_eq!(round("round(2.2)", "ceiling"), 3.0);
+    assert_eq!(evaluate_expression("round(2.5)").unwrap(), 3.0);
+
+    assert_eq!(RoundingMode::HalfEven.round_to(0.125, 2), 0.12);
+    assert_eq!(RoundingMode::HalfUp.round_to(0.125, 2), 0.13);
+
+    let mut settings = Settings::default();
+    assert!(settings.set("rounding", "sideways").is_err());
+    assert!(settings.set("colour", "blue").is_err());
+    settings.set("rounding", "CEILING").unwrap();
+    assert_eq!(settings.rounding, RoundingMode::Ceiling);
+    assert!(
+      settings
+        .entries()
+        .contains(&("rounding", "ceiling".to_string()))
+    );
+  }
+
+  #[test]
+  fn test_significant_figures() {
+    use rust_calculator::format_significant;
+    use rust_calculator::settings::Settings;
+
+    assert_eq!(format_significant(12345.0, 3), "1.23e4");
+    assert_eq!(format_significant(12355.0, 3), "1.24e4");
+    assert_eq!(format_significant(123.0, 3), "123");
+    assert_eq!(format_significant(100.0, 2), "1.0e2");
+    assert_eq!(format_significant(2.0 / 3.0, 3), "0.667");
+    assert_eq!(format_significant(-9.996, 3), "-10.0");
+    assert_eq!(format_significant(0.000012345, 2), "1.2e-5");
+    assert_eq!(format_significant(0.0, 3), "0");
+
+    let mut settings = Settings::default();
+    settings.set("sigfigs", "4").unwrap();
+    assert_eq!(settings.sigfigs, Some(4));
+    settings.set("sigfigs", "off").unwrap();
+    assert_eq!(settings.sigfigs, None);
+    assert!(settings.set("sigfigs", "0").is_err());
+    assert!(settings.set("sigfigs", "many").is_err());
+  }
+
+  #[test]
+  fn test_uncertainty_propagation() {
+    use rust_calculator::evaluate_to_string;
+    use rust_calculator::uncertainty::evaluate;
+
+    let close = |actual: f64, expected: f64| (actual - expected).abs() < 1e-6;
+
+    // Sums add absolute uncertainties in quadrature
+    let sum = evaluate("(5.0 ± 0.3) + (2.0 ± 0.4)").unwrap();
+    assert!(close(sum.value, 7.0) && close(sum.uncertainty, 0.5));
+
+    // Products add relative uncertainties in quadrature
+    let product = evaluate("val(10, 0.3) * val(4, 0.16)").unwrap();
+    assert!(close(product.value, 40.0) && close(product.uncertainty, 2.0));
+
+    // Functions use their derivative: d/dx sqrt(x) = 1 / (2 sqrt(x))
+    let root = evaluate("sqrt(16 +/- 0.8)").unwrap();
+    assert!(close(root.value, 4.0) && close(root.uncertainty, 0.1));
+
+    let negative = evaluate("-5 ± 0.1").unwrap();
+    assert!(close(negative.value, -5.0) && close(negative.uncertainty, 0.1));
+
+    assert_eq!(
+      evaluate_to_string("(5.0 ± 0.1) * 2").unwrap(),
+      "10.00 ± 0.20"
+    );
+    assert!(rust_calculator::returns_text("val(1, 0.1)"));
+    assert!(!rust_calculator::returns_text("1 + 2"));
+    assert!(evaluate("5 ± -1").is_err());
+    assert!(evaluate("val(5)").is_err());
+  }
+
+  #[test]
+  fn test_calculator_sessions() {
+    use rust_calculator::Calculator;
+    use rust_calculator::settings::RoundingMode;
+
+    let mut first = Calculator::new();
+    let mut second = Calculator::new();
+
+    first.set_variable("Rate", 0.5).unwrap();
+    second.set_variable("rate", 2.0).unwrap();
+    assert_eq!(first.evaluate("rate * 10").unwrap(), 5.0);
+    assert_eq!(second.evaluate("rate * 10").unwrap(), 20.0);
+
+    // Memory belongs to each session
+    first.evaluate("7 m+").unwrap();
+    first.evaluate("m+(3)").unwrap();
+    second.evaluate("1 m-").unwrap();
+    assert_eq!(first.memory(), 10.0);
+    assert_eq!(first.evaluate("mr").unwrap(), 10.0);
+    assert_eq!(second.memory(), -1.0);
+
+    // Failed evaluations aren't recorded
+    assert!(first.evaluate("1 / 0").is_err());
+    assert_eq!(first.history().len(), 4);
+    assert_eq!(first.history()[0], ("rate * 10".to_string(), 5.0));
+
+    // Settings are per session too
+    second.settings_mut().rounding = RoundingMode::HalfEven;
+    assert_eq!(second.evaluate("round(2.5)").unwrap(), 2.0);
+    assert_eq!(first.evaluate("round(2.5)").unwrap(), 3.0);
+
+    assert_eq!(first.remove_variable("rate"), Some(0.5));
+    assert!(first.evaluate("rate").is_err());
+    first.clear_memory();
+    first.clear_history();
+    assert_eq!(first.memory(), 0.0);
+    assert!(first.history().is_empty());
+
+    // Names are checked as for the global variables
+    assert!(matches!(
+      first.set_variable("pi", 3.0),
+      Err(rust_calculator::CalculatorError::ReservedName(name)) if name == "pi"
+    ));
+    assert!(first.set_variable("sqrt", 1.0).is_err());
+    assert!(first.set_variable("two words", 1.0).is_err());
+    assert!(first.set_variable("1x", 1.0).is_err());
+    assert!(first.variables().is_empty());
+  }
+
+  #[test]
+  fn test_public_parse() {
+    use rust_calculator::{Expr, parse};
+
+    // Variables stay in the tree instead of being looked up
+    let tree = parse("2 * rate + sqrt(base)").unwrap();
+    assert_eq!(tree.variables(), vec!["base", "rate"]);
+    let Expr::Binary { left, .. } = &tree else {
+      panic!("expected a binary node");
+    };
+    assert_eq!(
+      **left,
+      Expr::Binary {
+        operator: "*".to_string(),
+        left: Box::new(Expr::Number(2.0)),
+        right: Box::new(Expr::Variable("rate".to_string())),
+      }
+    );
+
+    let values = |name: &str| match name {
+      "rate" => Some(3.0),
+      "base" => Some(16.0),
+      _ => None,
+    };
+    assert_eq!(tree.evaluate_with(&values).unwrap(), 10.0);
+    assert!(tree.evaluate_with(&|_| None).is_err());
+
+    // Trees print back as infix and survive a JSON round trip
+    assert_eq!(tree.to_string(), "2 * rate + sqrt(base)");
+    assert_eq!(parse("(1 + 2) * 3").unwrap().to_string(), "(1 + 2) * 3");
+    let json = tree.to_json();
+    assert_eq!(json["left"]["right"]["type"], "variable");
+    assert_eq!(Expr::from_json(&json).unwrap(), tree);
+
+    // Evaluation still rejects names it can't resolve
+    assert!(rust_calculator::evaluate_expression("2 * no_such_var").is_err());
+    assert!(parse("2 $ 3").is_err());
+  }
+
+  #[test]
+  fn test_compiled_expression() {
+    use rust_calculator::CompiledExpression;
+
+    let formula = CompiledExpression::new("x^2 + y * sqrt(x)").unwrap();
+    assert_eq!(formula.variables(), ["x", "y"]);
+    assert_eq!(formula.to_string(), "x^2 + y * sqrt(x)");
+    for x in 0..100 {
+      let x = x as f64;
+      let expected = x.powi(2) + 2.0 * x.sqrt();
+      assert!((formula.evaluate(&[x, 2.0]).unwrap() - expected).abs() < 1e-9);
+    }
+
+    // Bindings by name, and errors from evaluation
+    let values = |name: &str| (name == "x").then_some(4.0).or(Some(1.0));
+    assert_eq!(formula.evaluate_with(&values).unwrap(), 18.0);
+    assert!(formula.evaluate(&[1.0]).is_err());
+    assert!(formula.evaluate(&[-4.0, 1.0]).is_err());
+    assert!(
+      CompiledExpression::new("1 / z")
+        .unwrap()
+        .evaluate(&[0.0])
+        .is_err()
+    );
+    assert!(CompiledExpression::new("2 +").is_err());
+  }
+
+  #[test]
+  fn test_eval_context_functions() {
+    use rust_calculator::EvalContext;
+
+    let mut ctx = EvalContext::new();
+    ctx.register_fn("tax", |x| x * 0.21).unwrap();
+    ctx.register_fn("double", |x| x * 2.0).unwrap();
+    ctx.set_variable("price", 100.0);
+    assert_eq!(ctx.functions(), vec!["double", "tax"]);
+
+    assert_eq!(ctx.evaluate("tax(100)").unwrap(), 21.0);
+    assert_eq!(ctx.evaluate("double(tax(price)) + sqrt(16)").unwrap(), 46.0);
+    assert_eq!(ctx.evaluate("double 4").unwrap(), 8.0);
+
+    // Built-in names can't be replaced, and the functions stay in their context
+    assert!(ctx.register_fn("sqrt", |x| x).is_err());
+    assert!(ctx.register_fn("pi", |x| x).is_err());
+    assert!(ctx.register_fn("2x", |x| x).is_err());
+    assert!(evaluate_expression("tax(100)").is_err());
+    assert!(EvalContext::new().evaluate("tax(100)").is_err());
+
+    assert!(ctx.unregister_fn("tax"));
+    assert!(ctx.evaluate("tax(100)").is_err());
+  }
+
+  #[test]
+  fn test_eval_context_panic() {
+    use rust_calculator::EvalContext;
+    use rust_calculator::settings::{self, AngleMode};
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+
+    let mut ctx = EvalContext::new();
+    ctx.set_angle_mode(AngleMode::Radians);
+    ctx.register_fn("boom", |_| panic!("boom")).unwrap();
+    ctx.evaluate("5 m+").unwrap();
+    let angle_mode = settings::current().angle_mode;
+    assert!(catch_unwind(AssertUnwindSafe(|| ctx.evaluate("boom(1)"))).is_err());
+
+    // Nothing the evaluation set up outlives it
+    assert_eq!(settings::current().angle_mode, angle_mode);
+    assert!(evaluate_expression("boom(1)").is_err());
+    assert_eq!(ctx.memory(), 5.0);
+    assert_eq!(ctx.evaluate("1 + 1").unwrap(), 2.0);
+  }
+
+  #[test]
+  fn test_error_spans() {
+    let span = |expression: &str| evaluate_expression(expression).unwrap_err().span();
+
+    assert_eq!(span("2 + xyz"), Some(4..7));
+    assert_eq!(span("(1 + 2"), Some(0..1));
+    assert_eq!(span("1 + 2)"), Some(5..6));
+    assert_eq!(span("2 * (3 +) - 1"), Some(7..8));
+    assert_eq!(span("foo(3) + 1"), Some(0..3));
+    assert_eq!(span("4 / (2 - 2) + 1"), None);
+
+    // Character ranges differ from byte ranges after non-ASCII text
+    let err = evaluate_expression("π + 1 + §").unwrap_err();
+    assert_eq!(err.span(), Some(9..11));
+    assert_eq!(err.char_span("π + 1 + §"), Some(8..9));
+
+    // The message is unchanged, and the kind is still available
+    assert_eq!(err.to_string(), "Parse error: Unknown token: §");
+    assert!(matches!(
+      err.unspanned(),
+      rust_calculator::CalculatorError::ParseError(_)
+    ));
+    assert_eq!(
+      rust_calculator::parse("2 +").unwrap_err().span(),
+      Some(2..3)
+    );
+  }
+
+  #[test]
+  fn test_value_results() {
+    use rust_calculator::{Value, evaluate};
+
+    let value = evaluate("2 * (3 + 4)").unwrap();
+    assert_eq!(value, Value::Number(14.0));
+    assert_eq!(value.as_number(), Some(14.0));
+    assert_eq!(f64::try_from(value).unwrap(), 14.0);
+    assert!(evaluate("2 +").is_err());
+
+    // Other kinds of values display and convert as expected
+    assert_eq!(Value::from(vec![1.0, 2.5]).to_string(), "[1, 2.5]");
+    assert_eq!(
+      Value::Matrix(vec![vec![1.0, 0.0], vec![0.0, 1.0]]).to_string(),
+      "[[1, 0], [0, 1]]"
+    );
+    assert_eq!(Value::from(true).to_string(), "true");
+    let distance = Value::Quantity {
+      value: 5.0,
+      unit: "km".to_string(),
+    };
+    assert_eq!(distance.to_string(), "5 km");
+    assert!(distance.into_number().is_err());
+    assert!(f64::try_from(Value::Boolean(false)).is_err());
+  }
+
+  #[test]
+  fn test_evaluation_limits() {
+    use rust_calculator::CalculatorError;
+    use rust_calculator::evaluate_expression_with_options as evaluate;
+    use rust_calculator::limits::EvalOptions;
+    use std::time::{Duration, Instant};
+
+    let options = EvalOptions {
+      max_length: Some(20),
+      max_depth: Some(3),
+      max_factorial: Some(170),
+      max_exponent: Some(1000.0),
+      time_budget: None,
+    };
+    assert_eq!(evaluate("fact 5 + 2 ^ 10", &options).unwrap(), 1144.0);
+
+    let exceeded = |expression: &str, options: &EvalOptions| {
+      matches!(
+        evaluate(expression, options),
+        Err(CalculatorError::LimitExceeded(_))
+      )
+    };
+    assert!(exceeded("1 + 1 + 1 + 1 + 1 + 1 + 1", &options));
+    assert!(exceeded("((((1))))", &options));
+    // Signs, brackets, bars and functions nest as parentheses do
+    assert!(exceeded("----(1)", &options));
+    assert!(exceeded("[[[[1]]]]", &options));
+    assert!(exceeded("||||1||||", &options));
+    assert!(exceeded("ln ln ln ln 9", &options));
+    assert_eq!(evaluate("|-(1)|", &options).unwrap(), 1.0);
+    assert!(exceeded("fact 1e9", &options));
+    assert!(exceeded("2 ^ 1e6", &options));
+    assert!(exceeded("2 ^ (0 - 1e6)", &options));
+
+    // A time budget stops a long computation early
+    let options = EvalOptions {
+      time_budget: Some(Duration::from_millis(20)),
+      ..EvalOptions::default()
+    };
+    let start = Instant::now();
+    assert!(exceeded("fact 1e12", &options));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // The limits only apply to the call they're passed to
+    assert_eq!(
+      rust_calculator::evaluate_expression("((((1)))) + 2 ^ 0").unwrap(),
+      2.0
+    );
+  }
+
+  #[test]
+  fn test_evaluate_with_variables() {
+    use rust_calculator::evaluate_with_variables;
+    use std::collections::HashMap;
+
+    let variables = HashMap::from([("Width".to_string(), 4.0), ("height".to_string(), 2.5)]);
+    assert_eq!(
+      evaluate_with_variables("width * height + 1", &variables).unwrap(),
+      11.0
+    );
+
+    // The global variables are neither read nor written
+    rust_calculator::set_variable("scoped_only", 7.0).unwrap();
+    assert!(evaluate_with_variables("scoped_only + 1", &variables).is_err());
+    assert!(evaluate_with_variables("scoped_only + 1", &HashMap::new()).is_err());
+    assert_eq!(
+      rust_calculator::evaluate_expression("scoped_only + 1").unwrap(),
+      8.0
+    );
+
+    // Memory starts empty for each call and doesn't leak out
+    assert_eq!(evaluate_with_variables("5 m+", &variables).unwrap(), 5.0);
+    assert_eq!(evaluate_with_variables("mr", &variables).unwrap(), 0.0);
+  }
+
+  #[test]
+  #[cfg(feature = "serde")]
+  fn test_serde_support() {
+    use rust_calculator::{CalculatorError, Expr, Value, evaluate_expression, parse};
+
+    // Trees serialize to the same shape as Expr::to_json
+    for expression in ["2 * sqrt(x)", "-(a ^ 2) % 3 - 1.5", "c_to_f(100) + ln(e)"] {
+      let tree = parse(expression).unwrap();
+      let json = serde_json::to_value(&tree).unwrap();
+      assert_eq!(json, tree.to_json());
+      assert_eq!(serde_json::from_value::<Expr>(json.clone()).unwrap(), tree);
+      assert_eq!(Expr::from_json(&json).unwrap(), tree);
+    }
+
+    // Both paths reject the same nodes with the same messages
+    let one = serde_json::json!({"type": "number", "value": 1.0});
+    for (invalid, message) in [
+      (
+        serde_json::json!({"type": "function", "name": "nope", "argument": one}),
+        "Unknown function: nope",
+      ),
+      (
+        serde_json::json!({"type": "binary", "operator": "@", "left": one, "right": one}),
+        "Unknown operator: @",
+      ),
+      (
+        serde_json::json!({"type": "variable", "name": "2x"}),
+        "Invalid variable name: 2x",
+      ),
+    ] {
+      let serde_error = serde_json::from_value::<Expr>(invalid.clone()).unwrap_err();
+      assert!(serde_error.to_string().contains(message), "{}", serde_error);
+      let json_error = Expr::from_json(&invalid).unwrap_err();
+      assert!(json_error.to_string().contains(message), "{}", json_error);
+    }
+
+    let value = serde_json::to_string(&Value::Number(8.0)).unwrap();
+    assert_eq!(value, r#"{"type":"number","value":8.0}"#);
+    assert_eq!(
+      serde_json::from_str::<Value>(&value).unwrap(),
+      Value::Number(8.0)
+    );
+
+    // Errors keep their kind, message and span
+    let err = evaluate_expression("2 + xyz").unwrap_err();
+    let json = serde_json::to_string(&err).unwrap();
+    assert_eq!(
+      json,
+      r#"{"kind":"parse_error","message":"Unknown token: xyz","span":{"start":4,"end":7}}"#
+    );
+    let decoded: CalculatorError = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.to_string(), err.to_string());
+    assert_eq!(decoded.span(), Some(4..7));
+    let err = evaluate_expression("1 / 0").unwrap_err();
+    assert_eq!(
+      serde_json::to_string(&err).unwrap(),
+      r#"{"kind":"math_error","message":"Division by zero"}"#
+    );
+
+    // Tokens serialize with their kind
+    let tokens = rust_calculator::tokenize("(1").unwrap();
+    assert_eq!(
+      serde_json::to_string(&tokens).unwrap(),
+      r#"[[{"type":"left_paren"},{"start":0,"end":1}],[{"type":"number","value":1.0},{"start":1,"end":2}]]"#
+    );
+  }
+
+  #[test]
+  fn test_pratt_parser() {
+    // Prefix minus negates any operand, binding like a number's sign
+    assert_eq!(evaluate_expression("-(2 + 3) * 2").unwrap(), -10.0);
+    assert_eq!(evaluate_expression("2 * -(1 + 1)").unwrap(), -4.0);
+    assert_eq!(evaluate_expression("-pi").unwrap(), -std::f64::consts::PI);
+    assert_eq!(evaluate_expression("1 - - 2").unwrap(), 3.0);
+    assert_eq!(evaluate_expression("-3 ^ 2").unwrap(), 9.0);
+    assert_eq!(evaluate_expression("2 ^ 3 ^ 2").unwrap(), 512.0);
+    assert_eq!(rust_calculator::parse("-x").unwrap().to_string(), "neg(x)");
+
+    // Postfix conversions and memory operations work inside larger expressions
+    assert!((evaluate_expression("2 + 10 km_to_mi").unwrap() - 8.21371).abs() < 1e-9);
+    assert!((evaluate_expression("sqrt(16) km_to_mi").unwrap() - 2.485484).abs() < 1e-9);
+    assert!((evaluate_expression("10km_to_mi").unwrap() - 6.21371).abs() < 1e-9);
+    assert_eq!(
+      rust_calculator::rpn::to_rpn("5 km_to_mi - 3").unwrap(),
+      "5 km_to_mi 3 -"
+    );
+
+    // Malformed input is reported at the offending token
+    assert_eq!(evaluate_expression("2 3").unwrap_err().span(), Some(2..3));
+    assert_eq!(evaluate_expression("2(3)").unwrap_err().span(), Some(1..2));
+    assert_eq!(evaluate_expression("()").unwrap_err().span(), Some(1..2));
+    assert_eq!(evaluate_expression("sqrt").unwrap_err().span(), Some(0..4));
+    assert!(evaluate_expression("1 + + 2").is_err());
+  }
+
+  #[test]
+  fn test_validate_expression() {
+    use rust_calculator::{EvalContext, validate_expression};
+
+    assert!(validate_expression("2 * (3 + 4) - sqrt 16").is_ok());
+    assert!(validate_expression("10 km_to_mi + mr").is_ok());
+    // Nothing is evaluated, so errors that depend on values aren't found
+    assert!(validate_expression("1 / 0").is_ok());
+    assert!(validate_expression("5 m+").is_ok());
+
+    let span = |expression: &str| validate_expression(expression).unwrap_err().span();
+    assert_eq!(span("2 + undefined_name"), Some(4..18));
+    assert_eq!(span("2 + nope(3)"), Some(4..8));
+    assert_eq!(span("(1 + 2"), Some(0..1));
+    assert_eq!(span("1 + 2)"), Some(5..6));
+    assert_eq!(span("sqrt() + 1"), Some(5..6));
+    assert_eq!(span("2 * * 3"), Some(2..3));
+    assert!(validate_expression("").is_err());
+
+    // A context's functions and variables are known to its own validation
+    let mut ctx = EvalContext::new();
+    ctx.register_fn("double", |x| x * 2.0).unwrap();
+    ctx.set_variable("rate", 0.5);
+    assert!(ctx.validate("double(rate)").is_ok());
+    assert!(validate_expression("double(1)").is_err());
+  }
+
+  #[test]
+  fn test_canonicalize() {
+    use rust_calculator::canonicalize;
+
+    let cases = [
+      ("2+3*4", "2 + (3 * 4)"),
+      ("(2 + 3) * 4", "(2 + 3) * 4"),
+      ("1 - 2 + 3", "1 - 2 + 3"),
+      ("1 - (2 - 3)", "1 - (2 - 3)"),
+      ("8 / 2 * 4", "(8 / 2) * 4"),
+      ("2 * 3 * 4", "2 * 3 * 4"),
+      ("2^3^2", "2 ^ (3 ^ 2)"),
+      ("-3^2", "(-3) ^ 2"),
+      ("-(x+1) * y", "-(x + 1) * y"),
+      ("sqrt 16 + x", "sqrt(16) + x"),
+      ("10 km_to_mi", "km_to_mi(10)"),
+      ("mr + 1", "mr + 1"),
+    ];
+    for (input, canonical) in cases {
+      assert_eq!(canonicalize(input).unwrap(), canonical, "{}", input);
+      // The canonical form means the same, and is its own canonical form
+      let tree = rust_calculator::parse(input).unwrap();
+      assert_eq!(rust_calculator::parse(canonical).unwrap(), tree);
+      assert_eq!(canonicalize(canonical).unwrap(), canonical);
+    }
+    assert!(canonicalize("2 +").is_err());
+  }
+
+  #[test]
+  fn test_error_codes() {
+    use rust_calculator::ErrorCode;
+
+    let code = |expression: &str| evaluate_expression(expression).unwrap_err().code();
+    assert_eq!(code(""), ErrorCode::EmptyExpression);
+    assert_eq!(
+      code("2 + xyz"),
+      ErrorCode::UnknownToken {
+        token: "xyz".to_string()
+      }
+    );
+    assert_eq!(
+      code("foo(3) + 1"),
+      ErrorCode::UnknownFunction {
+        name: "foo".to_string()
+      }
+    );
+    assert_eq!(code("(1 + 2"), ErrorCode::MismatchedParens);
+    assert_eq!(code("1 + 2)"), ErrorCode::MismatchedParens);
+    assert_eq!(code("2 * (3 +) - 1"), ErrorCode::MissingOperand);
+    assert_eq!(code("sqrt"), ErrorCode::MissingOperand);
+    assert_eq!(code("2 3"), ErrorCode::TooManyOperands);
+    assert_eq!(code("4 / (2 - 2)"), ErrorCode::DivisionByZero);
+    assert_eq!(code("4 % 0"), ErrorCode::ModuloByZero);
+    for domain in [
+      "sqrt(-4)",
+      "log 0",
+      "ln(-1)",
+      "asin 2",
+      "acos 2",
+      "(0 - 8) ^ 0.5",
+    ] {
+      assert_eq!(code(domain), ErrorCode::DomainError, "{}", domain);
+    }
+    assert_eq!(code("fact 2.5"), ErrorCode::InvalidArgument);
+    assert_eq!(code("10 ^ 400"), ErrorCode::Overflow);
+
+    // Names are stable identifiers for translation tables
+    assert_eq!(code("1 / 0").name(), "division_by_zero");
+    assert_eq!(code("(1").to_string(), "mismatched_parens");
+  }
+
+  #[test]
+  fn test_rpn_input() {
+    use rust_calculator::evaluate_rpn_expression;
+
+    assert_eq!(evaluate_rpn_expression("3 4 + 2 *").unwrap(), 14.0);
+    assert_eq!(evaluate_rpn_expression("2 3 4 * +").unwrap(), 14.0);
+    assert_eq!(evaluate_rpn_expression("2 3 ^ 2 ^").unwrap(), 64.0);
+    assert_eq!(evaluate_rpn_expression("16 sqrt 1 -").unwrap(), 3.0);
+    assert_eq!(evaluate_rpn_expression("-3 abs 2 *").unwrap(), 6.0);
+    assert_eq!(evaluate_rpn_expression("  2 pi * tau /  ").unwrap(), 1.0);
+    assert_eq!(evaluate_rpn_expression("10 km_to_mi").unwrap(), 6.21371);
+
+    // Errors are located in the postfix input
+    assert_eq!(
+      evaluate_rpn_expression("3 +").unwrap_err().span(),
+      Some(2..3)
+    );
+    assert_eq!(
+      evaluate_rpn_expression("1 2 3 +").unwrap_err().span(),
+      Some(2..5)
+    );
+    assert_eq!(
+      evaluate_rpn_expression("3 xyz *").unwrap_err().span(),
+      Some(2..5)
+    );
+    assert!(evaluate_rpn_expression("4 0 /").is_err());
+    assert!(evaluate_rpn_expression("   ").is_err());
+  }
+
+  #[test]
+  fn test_infix_postfix_conversion() {
+    use rust_calculator::{to_infix, to_postfix};
+
+    assert_eq!(to_postfix("1 - 2 - 3").unwrap(), "1 2 - 3 -");
+    assert_eq!(to_postfix("2 ^ 3 ^ 2").unwrap(), "2 3 2 ^ ^");
+    assert_eq!(to_postfix("-3 ^ 2").unwrap(), "-3 2 ^");
+    assert_eq!(to_infix("1 2 3 - -").unwrap(), "1 - (2 - 3)");
+    for expression in ["1 - (2 - 3)", "2 ^ 3 ^ 2", "(2 + 3) * 4 % 5"] {
+      assert_eq!(
+        to_infix(&to_postfix(expression).unwrap()).unwrap(),
+        expression
+      );
+    }
+    assert!(to_postfix("2 * (3 +").is_err());
+    assert!(to_infix("1 +").is_err());
+  }
+
+  #[test]
+  fn test_public_tokenizer() {
+    use rust_calculator::{Token, tokenize};
+
+    let tokens = tokenize("2 * (pi - y1) m+").unwrap();
+    let kinds: Vec<Token> = tokens.iter().map(|(token, _)| token.clone()).collect();
+    assert_eq!(
+      kinds,
+      vec![
+        Token::Number(2.0),
+        Token::Operator("*".to_string()),
+        Token::LeftParen,
+        Token::Number(std::f64::consts::PI),
+        Token::Operator("-".to_string()),
+        Token::Identifier("y1".to_string()),
+        Token::RightParen,
+        Token::Function("m+".to_string()),
+      ]
+    );
+    let spans: Vec<_> = tokens.into_iter().map(|(_, span)| span).collect();
+    assert_eq!(
+      spans,
+      vec![0..1, 2..3, 4..5, 5..7, 8..9, 10..12, 12..13, 14..16]
+    );
+
+    // Negative literals are a single token, and spans are byte offsets
+    assert_eq!(tokenize("-2.5 × 2").unwrap_err().span(), Some(5..7));
+    assert_eq!(tokenize("-2.5").unwrap(), vec![(Token::Number(-2.5), 0..4)]);
+    assert!(tokenize("").unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_format_result() {
+    use rust_calculator::format::{FormatOptions, Notation, format_result};
+
+    let format = |value: f64, notation: Notation, precision: Option<u32>| {
+      format_result(
+        value,
+        &FormatOptions {
+          notation,
+          precision,
+        },
+      )
+    };
+
+    // The default hides binary fraction noise
+    assert_eq!(format(0.1 + 0.2, Notation::Auto, None), "0.3");
+    assert_eq!(rust_calculator::format_value(0.1 + 0.2), "0.3");
+    assert_eq!(format(1.0 / 3.0, Notation::Auto, None), "0.333333333333333");
+    assert_eq!(format(2.0 / 3.0, Notation::Auto, Some(3)), "0.667");
+
+    assert_eq!(format(1.23456, Notation::Fixed, None), "1.23");
+    assert_eq!(format(2.5, Notation::Fixed, Some(0)), "3");
+    assert_eq!(format(-0.001, Notation::Fixed, Some(2)), "0.00");
+
+    assert_eq!(format(12345.0, Notation::Scientific, Some(3)), "1.23e4");
+    assert_eq!(format(12345.0, Notation::Scientific, None), "1.2345e4");
+    assert_eq!(format(-0.00042, Notation::Scientific, None), "-4.2e-4");
+    assert_eq!(format(999.96, Notation::Scientific, Some(4)), "1.000e3");
+
+    assert_eq!(format(12345.0, Notation::Engineering, Some(3)), "12.3e3");
+    assert_eq!(
+      format(0.000012345, Notation::Engineering, None),
+      "12.345e-6"
+    );
+    assert_eq!(format(1500.0, Notation::Engineering, None), "1.5e3");
+    assert_eq!(format(0.0, Notation::Engineering, None), "0e0");
+
+    assert_eq!(format(f64::INFINITY, Notation::Fixed, Some(2)), "inf");
+    assert_eq!(format(f64::NAN, Notation::Scientific, None), "NaN");
+  }
+
+  #[test]
+  #[cfg(feature = "config")]
+  fn test_config_file() {
+    use rust_calculator::config::{Config, Theme};
+    use rust_calculator::settings::{AngleMode, Locale};
+
+    let text = r#"
+      precision = 10
+      angle = "gradians"
+      locale = "de"
+      theme = "light"
+
+      [history]
+      limit = 500
+
+      [paths]
+      data_dir = "/tmp/calculator"
+    "#;
+    let config = Config::from_toml(text).unwrap();
+    assert_eq!(
+      config,
+      Config {
+        precision: Some(10),
+        angle_mode: Some(AngleMode::Gradians),
+        locale: Some(Locale::De),
+        theme: Theme::Light,
+        history_limit: Some(500),
+        data_dir: Some("/tmp/calculator".into()),
+      }
+    );
+    assert_eq!(Config::from_toml(&config.to_toml()).unwrap(), config);
+
+    // Every entry is optional
+    assert_eq!(Config::from_toml("").unwrap(), Config::default());
+    assert_eq!(Config::default().to_toml(), "");
+
+    assert!(Config::from_toml("angle = \"turns\"").is_err());
+    assert!(Config::from_toml("theme = \"blue\"").is_err());
+    assert!(Config::from_toml("precision = 0").is_err());
+    assert!(Config::from_toml("colour = \"red\"").is_err());
+  }
+
+  #[test]
+  fn test_history_entries() {
+    use rust_calculator::{
+      Calculator, add_to_history, evaluate_expression, get_history, history_entries, with_answer,
+    };
+
+    // A tag no earlier run used, as the history outlives the tests
+    let tag = format!(
+      "{}{}",
+      std::process::id(),
+      std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos()
+    );
+    for step in 1..=3 {
+      add_to_history(&format!("{} + {}", tag, step), step as f64).unwrap();
+    }
+
+    let tagged = |entries: Vec<String>| -> Vec<String> {
+      entries
+        .into_iter()
+        .filter(|entry| entry.starts_with(&tag))
+        .collect()
+    };
+    let expected = vec![
+      format!("{} + 1 = 1", tag),
+      format!("{} + 2 = 2", tag),
+      format!("{} + 3 = 3", tag),
+    ];
+    assert_eq!(tagged(get_history(50).unwrap()), expected);
+    let all: Vec<String> = history_entries().unwrap().map(Result::unwrap).collect();
+    assert_eq!(tagged(all.clone()), expected);
+
+    // Fewer entries only need the end of the history
+    let tail = get_history(2).unwrap();
+    assert_eq!(tail.len(), 2);
+    assert!(all.len() >= 3);
+    assert!(get_history(0).unwrap().is_empty());
+
+    // Every successful evaluation is what ans stands for next, which other
+    // tests change globally, so this is checked on its own
+    let mut calc = Calculator::new();
+    calc.evaluate("1 + 2").unwrap();
+    assert_eq!(calc.evaluate("ans * 2").unwrap(), 6.0);
+    assert!(calc.evaluate("ans / 0").is_err());
+    assert_eq!(calc.answer(), Some(6.0));
+
+    let answers = with_answer(Some(3.0), || {
+      let doubled = evaluate_expression("ans * 2").unwrap();
+      assert!(evaluate_expression("ans / 0").is_err());
+      (doubled, evaluate_expression("ans + 1").unwrap())
+    });
+    assert_eq!(answers, (6.0, 7.0));
+    assert!(with_answer(None, || evaluate_expression("ans")).is_err());
+  }
+
+  #[test]
+  fn test_calculator_snapshots() {
+    use rust_calculator::Calculator;
+    use rust_calculator::settings::AngleMode;
+
+    let mut calc = Calculator::new();
+    calc.set_variable("x", 2.0).unwrap();
+    calc.evaluate("5 m+").unwrap();
+    let saved = calc.snapshot();
+
+    calc.set_variable("x", 3.0).unwrap();
+    calc.set_variable("y", 1.0).unwrap();
+    calc.evaluate("mc").unwrap();
+    calc.settings_mut().angle_mode = AngleMode::Radians;
+    assert_eq!(saved.get_variable("x"), Some(2.0));
+    assert_eq!(saved.memory(), 5.0);
+
+    // Restoring rolls back the state but keeps the history
+    calc.restore(&saved);
+    assert_eq!(calc.variables(), vec![("x".to_string(), 2.0)]);
+    assert_eq!(calc.memory(), 5.0);
+    assert_eq!(calc.settings().angle_mode, AngleMode::Degrees);
+    assert_eq!(calc.history().len(), 2);
+    assert_eq!(calc.snapshot(), saved);
+
+    // Branches run in parallel without affecting each other
+    let branches: Vec<_> = (1..=4)
+      .map(|factor| {
+        let snapshot = saved.clone();
+        std::thread::spawn(move || {
+          let mut branch = Calculator::from_snapshot(&snapshot);
+          branch.evaluate(&format!("(x * {}) m+", factor)).unwrap();
+          branch.memory()
+        })
+      })
+      .collect();
+    let memories: Vec<f64> = branches
+      .into_iter()
+      .map(|branch| branch.join().unwrap())
+      .collect();
+    assert_eq!(memories, vec![7.0, 9.0, 11.0, 13.0]);
+    assert_eq!(calc.memory(), 5.0);
+  }
+
+  #[test]
+  fn test_cached_evaluation() {
+    use rust_calculator::{CompiledExpression, evaluate_cached, set_variable};
+    use std::sync::Arc;
+
+    for expression in [
+      "2 + 3 * 4",
+      "2 ^ 3 ^ 2",
+      "sqrt(16) + fact 4",
+      "10 % 4 - 1 / 4",
+    ] {
+      assert_eq!(
+        evaluate_cached(expression).unwrap(),
+        evaluate_expression(expression).unwrap()
+      );
+    }
+    let first = CompiledExpression::cached("2 + 3 * 4").unwrap();
+    assert!(Arc::ptr_eq(
+      &first,
+      &CompiledExpression::cached("2 + 3 * 4").unwrap()
+    ));
+
+    // Variables are read each time the cached program runs
+    set_variable("cached_rate", 2.0).unwrap();
+    assert_eq!(evaluate_cached("cached_rate * 10").unwrap(), 20.0);
+    set_variable("cached_rate", 3.0).unwrap();
+    assert_eq!(evaluate_cached("cached_rate * 10").unwrap(), 30.0);
+
+    // Syntax errors are located as evaluate_expression locates them
+    assert_eq!(evaluate_cached("2 * (3 +").unwrap_err().span(), Some(7..8));
+    assert!(evaluate_cached("1 / 0").is_err());
+  }
+
+  #[test]
+  fn test_constant_registry() {
+    use rust_calculator::{constants, evaluate_cached};
+
+    assert_eq!(evaluate_expression("PI").unwrap(), std::f64::consts::PI);
+    assert!(constants::list().iter().any(|(name, _)| name == "tau"));
+
+    constants::register("registry_k", 2.5).unwrap();
+    assert_eq!(evaluate_expression("registry_k * 4").unwrap(), 10.0);
+    assert_eq!(evaluate_cached("registry_k * 4").unwrap(), 10.0);
+    // Registered names are case-sensitive, and changing one is seen by cached programs
+    assert!(evaluate_expression("REGISTRY_K").is_err());
+    constants::register("registry_k", 3.0).unwrap();
+    assert_eq!(evaluate_cached("registry_k * 4").unwrap(), 12.0);
+    assert!(constants::unregister("registry_k"));
+    assert!(evaluate_expression("registry_k").is_err());
+
+    // Built-in names and bad values are refused
+    assert!(constants::register("E", 1.0).is_err());
+    assert!(constants::register("sqrt", 1.0).is_err());
+    assert!(constants::register("mr", 1.0).is_err());
+    assert!(constants::register("two words", 1.0).is_err());
+    assert!(constants::register("registry_nan", f64::NAN).is_err());
+  }
+
+  #[cfg(feature = "physics")]
+  #[test]
+  fn test_physics_constants() {
+    assert_eq!(evaluate_expression("c").unwrap(), 299_792_458.0);
+    assert_eq!(
+      evaluate_expression("N_A * k_B").unwrap(),
+      6.022_140_76e23 * 1.380_649e-23
+    );
+    assert_eq!(evaluate_expression("G").unwrap(), 6.674_30e-11);
+    assert_eq!(evaluate_expression("q_e").unwrap(), 1.602_176_634e-19);
+    assert!(evaluate_expression("n_a").is_err());
+  }
+
+  #[cfg(feature = "double-double")]
+  #[test]
+  fn test_double_double_backend() {
+    use rust_calculator::{DoubleDouble, evaluate_extended, parse, real};
+
+    let extended = |expression: &str| evaluate_extended(expression).unwrap().to_string();
+    assert_eq!(extended("1/3"), "0.3333333333333333333333333333333");
+    assert_eq!(extended("0.1 + 0.2"), "0.3");
+    assert_eq!(extended("pi"), "3.14159265358979323846264338328");
+    assert_eq!(extended("exp(1)"), "2.718281828459045235360287471353");
+    assert_eq!(extended("ln 2"), "0.6931471805599453094172321214582");
+    assert_eq!(extended("sin(1)"), "0.01745240643728351281941897851632");
+    assert_eq!(extended("atan(10)"), "84.28940686250035748730411865177");
+    assert_eq!(extended("2 ^ 0.5"), extended("sqrt 2"));
+    assert_eq!(extended("2 ^ 100"), "1267650600228229401496703205376");
+    assert_eq!(extended("-7.5 % 2"), "-1.5");
+    assert_eq!(extended("1e300 * 10"), "1e301");
+
+    assert!(evaluate_extended("1 / 0").is_err());
+    assert!(evaluate_extended("sqrt(-1)").is_err());
+    assert!(evaluate_extended("5 & 3").is_err());
+    assert!(evaluate_extended("gamma(2)").is_err());
+
+    let hundredth: DoubleDouble = "1e-2".parse().unwrap();
+    assert_eq!(f64::from(hundredth), 0.01);
+    assert!("1.2.3".parse::<DoubleDouble>().is_err());
+
+    // The same evaluator works in f64
+    let tree = parse("sqrt(2) * 3 ^ 2 - fact 4").unwrap();
+    assert_eq!(
+      real::evaluate::<f64>(&tree).unwrap(),
+      evaluate_expression("sqrt(2) * 3 ^ 2 - fact 4").unwrap()
+    );
+  }
+
+  #[cfg(feature = "tracing")]
+  #[test]
+  fn test_tracing_spans() {
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Collects span names and the fields recorded on them, and tracks the
+    /// entered spans so the library can record on the current one
+    #[derive(Default)]
+    struct Recorder {
+      seen: Arc<Mutex<Vec<String>>>,
+      spans: Mutex<Vec<&'static Metadata<'static>>>,
+      entered: Mutex<Vec<Id>>,
+    }
+
+    struct Fields<'a>(&'a mut Vec<String>);
+
+    impl Visit for Fields<'_> {
+      fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.push(format!("{}={:?}", field.name(), value));
+      }
+    }
+
+    impl Subscriber for Recorder {
+      fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+      }
+      fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut seen = self.seen.lock().unwrap();
+        seen.push(span.metadata().name().to_string());
+        span.record(&mut Fields(&mut seen));
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(span.metadata());
+        Id::from_u64(spans.len() as u64)
+      }
+      fn record(&self, _: &Id, values: &Record<'_>) {
+        values.record(&mut Fields(&mut self.seen.lock().unwrap()));
+      }
+      fn record_follows_from(&self, _: &Id, _: &Id) {}
+      fn event(&self, event: &Event<'_>) {
+        event.record(&mut Fields(&mut self.seen.lock().unwrap()));
+      }
+      fn enter(&self, span: &Id) {
+        self.entered.lock().unwrap().push(span.clone());
+      }
+      fn exit(&self, _: &Id) {
+        self.entered.lock().unwrap().pop();
+      }
+      fn current_span(&self) -> tracing_core::span::Current {
+        match self.entered.lock().unwrap().last() {
+          Some(id) => {
+            let metadata = self.spans.lock().unwrap()[id.into_u64() as usize - 1];
+            tracing_core::span::Current::new(id.clone(), metadata)
+          }
+          None => tracing_core::span::Current::none(),
+        }
+      }
+    }
+
+    let recorder = Recorder::default();
+    let seen = recorder.seen.clone();
+    tracing::subscriber::with_default(recorder, || {
+      evaluate_expression("2 + 3").unwrap();
+      evaluate_expression("2 +").unwrap_err();
+    });
+
+    let seen = seen.lock().unwrap();
+    for expected in [
+      "evaluate",
+      "expression=\"2 + 3\"",
+      "tokenize",
+      "parse",
+      "return=5.0",
+      "expression=\"2 +\"",
+    ] {
+      assert!(
+        seen.iter().any(|entry| entry == expected),
+        "{} not in {:?}",
+        expected,
+        seen
+      );
+    }
+    assert!(seen.iter().any(|entry| entry.starts_with("elapsed_us=")));
+    assert!(seen.iter().any(|entry| entry.starts_with("error=")));
+  }
+
+  #[test]
+  fn test_combinatorics_and_big_integers() {
+    use rust_calculator::{BigInt, CalculatorError, evaluate_integer, format_exact, to_postfix};
+
+    assert_eq!(evaluate_expression("5 nCr 2").unwrap(), 10.0);
+    assert_eq!(evaluate_expression("52 NCR 5").unwrap(), 2598960.0);
+    assert_eq!(evaluate_expression("10 nPr 3").unwrap(), 720.0);
+    assert_eq!(evaluate_expression("3 nCr 5").unwrap(), 0.0);
+    // nCr binds like *, so this is (2 * 5) nCr 2
+    assert_eq!(evaluate_expression("2 * 5 nCr 2 + 1").unwrap(), 46.0);
+    assert_eq!(to_postfix("10 nCr 3").unwrap(), "10 3 ncr");
+    assert!(evaluate_expression("5.5 nCr 2").is_err());
+    assert!(evaluate_expression("-5 nPr 2").is_err());
+
+    // Exact integers past the 2^53 where f64 drops digits
+    assert_eq!(
+      evaluate_integer("fact 30").unwrap().to_string(),
+      "265252859812191058636308480000000"
+    );
+    assert_eq!(
+      evaluate_integer("-(3 ^ 41) + 1").unwrap().to_string(),
+      "-36472996377170786402"
+    );
+    assert_eq!(
+      evaluate_integer("2 ^ 64 - 2 ^ 64").unwrap(),
+      BigInt::from(0)
+    );
+    assert_eq!(
+      evaluate_integer("200 nCr 100").unwrap().to_string(),
+      "90548514656103281165404177077484163874504589675413336841320"
+    );
+    assert!(evaluate_integer("10 / 2").is_err());
+    assert!(evaluate_integer("2 ^ -1").is_err());
+
+    let result = evaluate_expression("fact 22").unwrap();
+    assert_eq!(format_exact("fact 22", result), "1124000727777607680000");
+    assert_eq!(format_exact("sqrt 16", 4.0), "4");
+    assert_eq!(
+      rust_calculator::evaluate_to_string("2 ^ 70").unwrap(),
+      "1180591620717411303424"
+    );
+
+    // Past the range of f64, integer results are still worked out exactly
+    let err = evaluate_expression("fact 200").unwrap_err();
+    assert!(matches!(err, CalculatorError::Overflow(_)));
+    let exact = rust_calculator::format_overflow("fact 200", err).unwrap();
+    assert_eq!(exact.len(), 375);
+    assert!(exact.starts_with("788657867364790503552363213932185062295135977687"));
+    assert!(exact.ends_with(&"0".repeat(49)));
+    let exact = rust_calculator::evaluate_to_string("2 ^ 2000").unwrap();
+    assert_eq!(exact, evaluate_integer("2 ^ 2000").unwrap().to_string());
+    assert_eq!(exact.len(), 603);
+    assert!(exact.ends_with("029376"));
+    assert!(rust_calculator::evaluate_to_string("10 ^ 400 / 3").is_err());
+    assert!(matches!(
+      rust_calculator::evaluate_to_string("fact 1e6"),
+      Err(CalculatorError::Overflow(_))
+    ));
+    assert!(evaluate_integer("2 ^ 100000").is_err());
+  }
+
+  #[test]
+  fn test_exact_fractions() {
+    use rust_calculator::{Rational, evaluate_fraction};
+
+    let fraction = |expression: &str| evaluate_fraction(expression).unwrap().to_string();
+    assert_eq!(fraction("1/3 + 1/6"), "1/2");
+    assert_eq!(fraction("0.1 + 0.2"), "3/10");
+    assert_eq!(fraction("1/3 * 3"), "1");
+    assert_eq!(fraction("-(1/4) - 1/4"), "-1/2");
+    assert_eq!(fraction("(2/3) ^ -2"), "9/4");
+    assert_eq!(fraction("7/2 % 1"), "1/2");
+    assert_eq!(fraction("abs(-3/9)"), "1/3");
+
+    let half = Rational::new(-2, -4).unwrap();
+    assert_eq!((half.numerator(), half.denominator()), (1, 2));
+    assert_eq!(half.to_f64(), 0.5);
+    assert_eq!(Rational::from_f64(0.125), Rational::new(1, 8));
+    assert_eq!(Rational::new(1, 0), None);
+
+    // Anything that may not be a fraction is refused
+    assert!(evaluate_fraction("sqrt 2").is_err());
+    assert!(evaluate_fraction("2 ^ 0.5").is_err());
+    assert!(evaluate_fraction("1 / (1 - 1)").is_err());
+    assert!(matches!(
+      evaluate_fraction("10 ^ 50"),
+      Err(rust_calculator::CalculatorError::Overflow(_))
+    ));
+  }
+
+  #[test]
+  fn test_integer_mode() {
+    use rust_calculator::evaluate_expression_with_settings as evaluate_with;
+    use rust_calculator::programmer;
+    use rust_calculator::settings::{IntegerOverflow, IntegerType, Settings};
+
+    let mut settings = Settings::default();
+    settings.set("integer", "u8").unwrap();
+    assert_eq!(settings.integer, Some(IntegerType::U8));
+    assert_eq!(evaluate_with("7 / 2", &settings).unwrap(), 3.0);
+    assert_eq!(evaluate_with("17 % 5 + 2 ^ 7", &settings).unwrap(), 130.0);
+    assert!(evaluate_with("255 + 1", &settings).is_err());
+    assert!(evaluate_with("0 - 1", &settings).is_err());
+    assert!(evaluate_with("2.5 * 2", &settings).is_err());
+    assert!(evaluate_with("sqrt 16", &settings).is_err());
+    assert!(evaluate_with("1 / 0", &settings).is_err());
+
+    settings.set("intoverflow", "wrap").unwrap();
+    assert_eq!(evaluate_with("255 + 1", &settings).unwrap(), 0.0);
+    assert_eq!(evaluate_with("0 - 1", &settings).unwrap(), 255.0);
+    assert_eq!(evaluate_with("16 * 17", &settings).unwrap(), 16.0);
+
+    settings.integer = Some(IntegerType::I8);
+    assert_eq!(evaluate_with("127 + 1", &settings).unwrap(), -128.0);
+    assert_eq!(evaluate_with("-7 / 2", &settings).unwrap(), -3.0);
+
+    // Results past 2^53 stay exact
+    let wrap = IntegerOverflow::Wrap;
+    assert_eq!(
+      programmer::evaluate("0 - 1", IntegerType::U64, wrap).unwrap(),
+      u64::MAX as i128
+    );
+    assert_eq!(
+      programmer::evaluate("2 ^ 63", IntegerType::I64, wrap).unwrap(),
+      i64::MIN as i128
+    );
+    assert_eq!(
+      programmer::evaluate("fact 20", IntegerType::U64, IntegerOverflow::Error).unwrap(),
+      2432902008176640000
+    );
+    assert!(programmer::evaluate("fact 21", IntegerType::U64, IntegerOverflow::Error).is_err());
+
+    settings.set("integer", "off").unwrap();
+    assert_eq!(evaluate_with("7 / 2", &settings).unwrap(), 3.5);
+    assert!(settings.set("integer", "u128").is_err());
+  }
+
+  #[test]
+  fn test_bitwise_operators() {
+    use rust_calculator::programmer;
+    use rust_calculator::rpn::to_rpn;
+    use rust_calculator::settings::{IntegerOverflow, IntegerType};
+
+    assert_eq!(evaluate_expression("12 & 10").unwrap(), 8.0);
+    assert_eq!(evaluate_expression("12 | 10").unwrap(), 14.0);
+    assert_eq!(evaluate_expression("12 xor 10").unwrap(), 6.0);
+    assert_eq!(evaluate_expression("12 XOR 10").unwrap(), 6.0);
+    assert_eq!(evaluate_expression("~5").unwrap(), -6.0);
+    assert_eq!(evaluate_expression("~(2 + 3)").unwrap(), -6.0);
+    assert_eq!(evaluate_expression("1<<4").unwrap(), 16.0);
+    assert_eq!(evaluate_expression("-16 >> 2").unwrap(), -4.0);
+    assert_eq!(evaluate_expression("1 << 62").unwrap(), 2f64.powi(62));
+
+    // | below xor below & below shifts below + and -
+    assert_eq!(evaluate_expression("1 << 2 + 1").unwrap(), 8.0);
+    assert_eq!(evaluate_expression("1 | 6 & 3").unwrap(), 3.0);
+    assert_eq!(evaluate_expression("1 | 3 xor 2").unwrap(), 1.0);
+    assert_eq!(to_rpn("1 | 2 & 3").unwrap(), "1 2 3 & |");
+    assert_eq!(
+      rust_calculator::canonicalize("a << 1 | b").unwrap(),
+      "(a << 1) | b"
+    );
+
+    assert!(evaluate_expression("2.5 & 1").is_err());
+    assert!(evaluate_expression("1 << 64").is_err());
+    assert!(evaluate_expression("1 >> -1").is_err());
+    assert_eq!(evaluate_expression("1 < 2").unwrap(), 1.0);
+
+    // Integer mode keeps the type's width
+    let wrap = IntegerOverflow::Wrap;
+    let error = IntegerOverflow::Error;
+    assert_eq!(
+      programmer::evaluate("~0", IntegerType::U8, error).unwrap(),
+      255
+    );
+    assert_eq!(
+      programmer::evaluate("~0", IntegerType::I8, error).unwrap(),
+      -1
+    );
+    assert_eq!(
+      programmer::evaluate("200 << 1", IntegerType::U8, error).unwrap(),
+      144
+    );
+    assert_eq!(
+      programmer::evaluate("64 << 1", IntegerType::I8, wrap).unwrap(),
+      -128
+    );
+    assert_eq!(
+      programmer::evaluate("-128 >> 7", IntegerType::I8, error).unwrap(),
+      -1
+    );
+    assert_eq!(
+      programmer::evaluate("~0 xor 1 << 63", IntegerType::U64, error).unwrap(),
+      i64::MAX as i128
+    );
+    assert!(programmer::evaluate("1 << 8", IntegerType::U8, wrap).is_err());
+  }
+
+  #[test]
+  fn test_scientific_notation() {
+    assert_eq!(evaluate_expression("1.5e3").unwrap(), 1500.0);
+    assert_eq!(evaluate_expression("2E-6").unwrap(), 2e-6);
+    assert_eq!(evaluate_expression("1e+3 + 1").unwrap(), 1001.0);
+    assert_eq!(evaluate_expression("-2e-3*2").unwrap(), -4e-3);
+    assert_eq!(evaluate_expression(".5e1").unwrap(), 5.0);
+    assert_eq!(evaluate_expression("2e3-1").unwrap(), 1999.0);
+    // The constant e is still e
+    assert_eq!(
+      evaluate_expression("e - 1").unwrap(),
+      std::f64::consts::E - 1.0
+    );
+    assert_eq!(
+      evaluate_expression("2 * e-1").unwrap(),
+      2.0 * std::f64::consts::E - 1.0
+    );
+    assert!(evaluate_expression("2e").is_err());
+    assert!(evaluate_expression("2e-").is_err());
+
+    let tokens = rust_calculator::tokenize("1.5E-3 + 1").unwrap();
+    assert_eq!(tokens[0], (rust_calculator::Token::Number(1.5e-3), 0..6));
+  }
+
+  #[test]
+  fn test_digit_separators_and_radix_prefixes() {
+    assert_eq!(evaluate_expression("1_000_000").unwrap(), 1e6);
+    assert_eq!(evaluate_expression("1_000.000_5 * 2").unwrap(), 2000.001);
+    assert_eq!(evaluate_expression("1_000e-3").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("0xFFFF_FFFF").unwrap(), 4294967295.0);
+    assert_eq!(evaluate_expression("0xff + 0XDC").unwrap(), 475.0);
+    assert_eq!(evaluate_expression("-0b1010_1010").unwrap(), -170.0);
+    assert_eq!(evaluate_expression("0o17").unwrap(), 15.0);
+    assert_eq!(evaluate_expression("0x1e-3").unwrap(), 27.0);
+    assert_eq!(evaluate_expression("1_000 km_to_mi").unwrap(), 621.371);
+
+    // Underscores only go between digits
+    for bad in ["1__0", "1_", "1_.5", "0x_FF", "0x", "0b102"] {
+      assert!(evaluate_expression(bad).is_err(), "{} should fail", bad);
+    }
+    assert!(evaluate_expression("_1").is_err());
+  }
+
+  #[test]
+  fn test_working_precision() {
+    use rust_calculator::evaluate_expression_with_settings as evaluate_with;
+    use rust_calculator::settings::{RoundingMode, Settings};
+
+    // Rounding follows the decimal digits rather than the binary value
+    assert_eq!(RoundingMode::HalfUp.round_to(1.005, 2), 1.01);
+    assert_eq!(RoundingMode::HalfUp.round_to(-2.675, 2), -2.68);
+    assert_eq!(RoundingMode::HalfEven.round_to(0.125, 2), 0.12);
+    assert_eq!(RoundingMode::HalfEven.round_to(0.135, 2), 0.14);
+    assert_eq!(RoundingMode::Floor.round_to(-0.001, 2), -0.01);
+    assert_eq!(RoundingMode::Ceiling.round_to(1e-20, 3), 0.001);
+    assert_eq!(RoundingMode::HalfUp.round_to(123.0, 2), 123.0);
+
+    let mut settings = Settings::default();
+    settings.set("decimals", "2").unwrap();
+    assert_eq!(evaluate_with("0.1 + 0.2", &settings).unwrap(), 0.3);
+    assert_eq!(evaluate_with("1.005", &settings).unwrap(), 1.01);
+    // Intermediate results are rounded as well
+    assert_eq!(evaluate_with("10 / 3 * 3", &settings).unwrap(), 9.99);
+    settings.set("rounding", "bankers").unwrap();
+    assert_eq!(evaluate_with("0.125 * 1", &settings).unwrap(), 0.12);
+
+    assert!(settings.entries().contains(&("decimals", "2".to_string())));
+    assert!(settings.set("decimals", "16").is_err());
+    settings.set("decimals", "off").unwrap();
+    assert_eq!(settings.decimals, None);
+  }
+
+  #[test]
+  fn test_locale_formatting() {
+    use rust_calculator::format::localize;
+    use rust_calculator::settings::{Locale, Settings};
+
+    assert_eq!(localize("1234567.89", Locale::Plain), "1234567.89");
+    assert_eq!(localize("1234567.89", Locale::En), "1,234,567.89");
+    assert_eq!(localize("1234567.89", Locale::De), "1.234.567,89");
+    assert_eq!(localize("-1234567.89", Locale::Fr), "-1 234 567,89");
+    assert_eq!(localize("1234567.89", Locale::Ch), "1'234'567.89");
+    assert_eq!(localize("123", Locale::En), "123");
+    assert_eq!(localize("0.5", Locale::De), "0,5");
+    assert_eq!(localize("1.23e4", Locale::De), "1,23e4");
+    assert_eq!(localize("-inf", Locale::De), "-inf");
+
+    assert_eq!(Locale::from_tag("en_US.UTF-8"), Some(Locale::En));
+    assert_eq!(Locale::from_tag("pt-BR"), Some(Locale::De));
+    assert_eq!(Locale::from_tag("de_CH"), Some(Locale::Ch));
+    assert_eq!(Locale::from_tag("sv_SE"), Some(Locale::Fr));
+    assert_eq!(Locale::from_tag("xx"), None);
+
+    let mut settings = Settings::default();
+    settings.set("locale", "de").unwrap();
+    assert_eq!(settings.locale, Locale::De);
+    assert!(settings.set("locale", "mars").is_err());
+  }
+
+  #[test]
+  fn test_rational_approximation() {
+    use rust_calculator::evaluate_to_string;
+    use rust_calculator::rational::{Rational, continued_fraction};
+
+    assert_eq!(evaluate_to_string("as_fraction(0.333333)").unwrap(), "1/3");
+    assert_eq!(evaluate_to_string("as_fraction(pi)").unwrap(), "355/113");
+    assert_eq!(evaluate_to_string("as_fraction(pi, 10)").unwrap(), "22/7");
+    assert_eq!(
+      evaluate_to_string("as_fraction(pi, 100000)").unwrap(),
+      "312689/99532"
+    );
+    assert_eq!(evaluate_to_string("as_fraction(-0.75)").unwrap(), "-3/4");
+    assert_eq!(evaluate_to_string("as_fraction(1.5 + 1)").unwrap(), "5/2");
+    assert!(evaluate_to_string("as_fraction(0.5, 0)").is_err());
+    assert!(evaluate_to_string("as_fraction(0.5, 2.5)").is_err());
+    assert_eq!(
+      evaluate_to_string("as_fraction(round(0.49, 1), round(9.6, 0))").unwrap(),
+      "1/2"
+    );
+    assert!(evaluate_to_string("as_fraction(0.5, 2, 3)").is_err());
+    assert!(rust_calculator::returns_text("as_fraction(0.1)"));
+
+    assert_eq!(
+      evaluate_to_string("continued_fraction(sqrt(2), 6)").unwrap(),
+      "[1; 2, 2, 2, 2, 2]"
+    );
+    assert_eq!(
+      evaluate_to_string("continued_fraction(0.75)").unwrap(),
+      "[0; 1, 3]"
+    );
+    assert_eq!(evaluate_to_string("continued_fraction(4)").unwrap(), "[4]");
+
+    assert_eq!(Rational::approximate(0.1, 1000), Rational::new(1, 10));
+    assert_eq!(
+      Rational::approximate(1e-300, 1000),
+      Some(Rational::integer(0))
+    );
+    assert_eq!(Rational::approximate(f64::NAN, 1000), None);
+    // Stops once the convergent is the number
+    assert_eq!(continued_fraction(0.1, 100).unwrap(), [0, 10]);
+    assert_eq!(continued_fraction(-0.5, 100).unwrap(), [-1, 2]);
+  }
+
+  #[test]
+  fn test_mixed_numbers() {
+    assert_eq!(evaluate_expression("3 1/2 + 1/4").unwrap(), 3.75);
+    assert_eq!(evaluate_expression("-3 1/2").unwrap(), -3.5);
+    assert_eq!(evaluate_expression("5 - 3 1/2").unwrap(), 1.5);
+    assert_eq!(evaluate_expression("2 * 1 3/4").unwrap(), 3.5);
+    assert_eq!(evaluate_expression("(1 1/2) ^ 2").unwrap(), 2.25);
+    assert_eq!(
+      rust_calculator::evaluate_fraction("3 1/2 + 1/4")
+        .unwrap()
+        .to_string(),
+      "15/4"
+    );
+    assert_eq!(rust_calculator::rpn::to_rpn("3 1/2").unwrap(), "3 1 2 / +");
+
+    // Only digits make a mixed number
+    assert!(evaluate_expression("3 1.5/2").is_err());
+    assert!(evaluate_expression("pi 1/2").is_err());
+    assert!(evaluate_expression("3 1/2^2").is_err());
+    assert!(evaluate_expression("3 1").is_err());
+  }
+
+  #[test]
+  fn test_strict_non_finite_policy() {
+    use rust_calculator::CalculatorError;
+    use rust_calculator::evaluate_expression_with_settings as evaluate_with;
+    use rust_calculator::settings::{NonFinitePolicy, Settings};
+
+    let mut strict = Settings::default();
+    strict.set("nonfinite", "strict").unwrap();
+    assert_eq!(strict.non_finite, NonFinitePolicy::Strict);
+
+    assert!(matches!(
+      evaluate_with("0 ^ 0", &strict),
+      Err(CalculatorError::MathError(_))
+    ));
+    assert!(evaluate_with("inf - inf", &strict).is_err());
+    assert!(evaluate_with("nan", &strict).is_err());
+    // Results the other policies would let through are math errors
+    strict.set("overflow", "ieee").unwrap();
+    assert!(matches!(
+      evaluate_with("10 ^ 400", &strict),
+      Err(CalculatorError::MathError(_))
+    ));
+    strict.set("domain", "nan").unwrap();
+    assert!(matches!(
+      evaluate_with("sqrt(-1)", &strict),
+      Err(CalculatorError::MathError(_))
+    ));
+    assert_eq!(evaluate_with("0 ^ 1 + 2 ^ 0", &strict).unwrap(), 1.0);
+
+    // The library default still propagates
+    assert_eq!(evaluate_expression("0 ^ 0").unwrap(), 1.0);
+    assert!(evaluate_expression("inf - inf").unwrap().is_nan());
+  }
+
+  #[test]
+  fn test_degrees_minutes_seconds() {
+    use rust_calculator::dms;
+    use rust_calculator::evaluate_expression_with_settings as evaluate_with;
+    use rust_calculator::evaluate_to_string;
+    use rust_calculator::settings::{AngleMode, Settings};
+
+    assert_eq!(
+      dms::parse("45°30'15\""),
+      Some(45.0 + 30.0 / 60.0 + 15.0 / 3600.0)
+    );
+    assert_eq!(dms::parse("45d30m15s"), dms::parse("45°30′15″"));
+    assert_eq!(dms::parse("-12d30m"), Some(-12.5));
+    assert_eq!(dms::parse("10°15\""), Some(10.0 + 15.0 / 3600.0));
+    assert_eq!(dms::parse("45°60'"), None);
+    assert_eq!(dms::parse("30'"), None);
+    assert_eq!(dms::parse("45°30'15\"x"), None);
+
+    assert_eq!(evaluate_expression("2 * 45d30m").unwrap(), 91.0);
+    // A literal is the same angle whatever the mode
+    let radians = Settings {
+      angle_mode: AngleMode::Radians,
+      ..Settings::default()
+    };
+    assert!((evaluate_with("sin(30°)", &radians).unwrap() - 0.5).abs() < 1e-12);
+    assert!((evaluate_with("90°", &radians).unwrap() - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+
+    assert_eq!(dms::to_dms(45.50416666666667).unwrap(), "45°30'15\"");
+    assert_eq!(dms::to_dms(-0.5).unwrap(), "-0°30'0\"");
+    assert_eq!(dms::to_dms(10.999999).unwrap(), "11°0'0\"");
+    assert_eq!(dms::to_dms(1.0 + 1.5 / 3600.0).unwrap(), "1°0'1.5\"");
+    assert!(dms::to_dms(f64::INFINITY).is_err());
+    assert_eq!(
+      evaluate_to_string("to_dms(12d30m + 0.25)").unwrap(),
+      "12°45'0\""
+    );
+  }
+
+  #[test]
+  fn test_error_function_and_normal_distribution() {
+    let close = |expression: &str, expected: f64| {
+      let value = evaluate_expression(expression).unwrap();
+      assert!(
+        (value - expected).abs() < 1e-15,
+        "{} = {}",
+        expression,
+        value
+      );
+    };
+    close("erf 0", 0.0);
+    close("erf 0.5", 0.5204998778130465);
+    close("erf(-0.5)", -0.5204998778130465);
+    close("erf 1 + erfc 1", 1.0);
+    close("normpdf 0", 0.3989422804014327);
+    close("normcdf 0", 0.5);
+    close("normcdf 1.96", 0.9750021048517795);
+    close("normcdf 1 - normcdf(-1)", 0.6826894921370859);
+    // The complement keeps the digits of far tails
+    let tail = evaluate_expression("erfc 10").unwrap();
+    assert!((tail / 2.088487583762545e-45 - 1.0).abs() < 1e-12);
+    assert!(evaluate_expression("normcdf(-10)").unwrap() > 0.0);
+  }
+
+  #[test]
+  fn test_rounding_functions_and_multiple_arguments() {
+    use rust_calculator::{CompiledExpression, canonicalize, parse, rpn};
+
+    assert_eq!(evaluate_expression("round(2.71828, 2)").unwrap(), 2.72);
+    assert_eq!(evaluate_expression("round(2.675, 2)").unwrap(), 2.68);
+    assert_eq!(evaluate_expression("round(1234.5678, 0)").unwrap(), 1235.0);
+    assert_eq!(evaluate_expression("trunc(-2.7)").unwrap(), -2.0);
+    assert_eq!(evaluate_expression("trunc 2.7").unwrap(), 2.0);
+    assert_eq!(evaluate_expression("sign(-4)").unwrap(), -1.0);
+    assert_eq!(evaluate_expression("sign 3").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("sign 0").unwrap(), 0.0);
+    assert_eq!(evaluate_expression("frac 2.75").unwrap(), 0.75);
+    assert_eq!(evaluate_expression("frac(-2.75)").unwrap(), -0.75);
+    assert_eq!(evaluate_expression("clamp(15, 0, 10)").unwrap(), 10.0);
+    assert_eq!(evaluate_expression("clamp(-3, 0, 10)").unwrap(), 0.0);
+    assert_eq!(
+      evaluate_expression("clamp(-5, -1 - 1, 2) + 1").unwrap(),
+      -1.0
+    );
+    assert_eq!(
+      evaluate_expression("clamp(round(2.5), 1 + 1, 2 ^ 3)").unwrap(),
+      3.0
+    );
+
+    for invalid in [
+      "1, 2",
+      "(1, 2) + 3",
+      "clamp(1, (2, 3))",
+      "clamp(1, 2)",
+      "clamp(5, 10, 0)",
+      "round(1, -1)",
+      "round(1, 0.5)",
+      "sqrt(16, 2)",
+      "clamp(1, 2,)",
+    ] {
+      assert!(evaluate_expression(invalid).is_err(), "{}", invalid);
+    }
+
+    // Trees, compiled programs and RPN keep the arguments apart
+    let tree = parse("clamp(x + 1, 0, 1)").unwrap();
+    assert_eq!(tree.to_string(), "clamp(x + 1, 0, 1)");
+    assert_eq!(tree.evaluate_with(&|_| Some(-5.0)).unwrap(), 0.0);
+    assert_eq!(
+      canonicalize("clamp(x+1,0,1)").unwrap(),
+      "clamp(x + 1, 0, 1)"
+    );
+    let compiled = CompiledExpression::new("clamp(x, 0, 1) + round(y, 1)").unwrap();
+    assert_eq!(compiled.evaluate(&[2.0, 0.25]).unwrap(), 1.3);
+    assert_eq!(rpn::to_rpn("clamp(1, 2, 3)").unwrap(), "1 2 , 3 , clamp");
+    assert_eq!(rpn::from_rpn("1 2 , 3 , clamp").unwrap(), "clamp(1, 2, 3)");
+  }
+
+  #[test]
+  fn test_cube_and_nth_roots() {
+    use rust_calculator::ErrorCode;
+    use rust_calculator::evaluate_expression;
+
+    assert_eq!(evaluate_expression("cbrt(-8)").unwrap(), -2.0);
+    assert_eq!(evaluate_expression("cbrt 27").unwrap(), 3.0);
+    assert_eq!(evaluate_expression("root(3, -8)").unwrap(), -2.0);
+    assert_eq!(evaluate_expression("root(5, -32)").unwrap(), -2.0);
+    assert_eq!(evaluate_expression("root(4, 16)").unwrap(), 2.0);
+    assert_eq!(evaluate_expression("root(3, 1000)").unwrap(), 10.0);
+    assert_eq!(evaluate_expression("root(-2, 4)").unwrap(), 0.5);
+
+    for invalid in [
+      "root(4, -16)",
+      "root(0, 4)",
+      "root(2.5, -1)",
+      "root(1, 2, 3)",
+      "(-8)^(1/3)",
+    ] {
+      assert!(evaluate_expression(invalid).is_err(), "{}", invalid);
+    }
+    assert_eq!(
+      evaluate_expression("root(4, -16)").unwrap_err().code(),
+      ErrorCode::DomainError
+    );
+  }
+
+  #[test]
+  fn test_primes() {
+    use rust_calculator::{evaluate_expression, evaluate_to_string, primes, returns_text};
+
+    assert_eq!(evaluate_expression("isprime 97").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("isprime 91").unwrap(), 0.0);
+    assert_eq!(evaluate_expression("isprime(2^53 - 111)").unwrap(), 1.0);
+    assert_eq!(
+      evaluate_expression("isprime(0) + isprime(1) + isprime(-7)").unwrap(),
+      0.0
+    );
+    assert_eq!(evaluate_expression("nextprime 100").unwrap(), 101.0);
+    assert_eq!(evaluate_expression("nextprime(-5)").unwrap(), 2.0);
+    assert!(evaluate_expression("nextprime(2^53 - 111)").is_err());
+    assert!(evaluate_expression("isprime 2.5").is_err());
+
+    assert_eq!(
+      evaluate_to_string("factorize(360)").unwrap(),
+      "2^3 * 3^2 * 5"
+    );
+    assert_eq!(
+      evaluate_to_string("factorize(2^53 - 1)").unwrap(),
+      "6361 * 69431 * 20394401"
+    );
+    assert_eq!(evaluate_to_string("factorize(97)").unwrap(), "97");
+    assert_eq!(evaluate_to_string("factorize(1)").unwrap(), "1");
+    assert!(evaluate_to_string("factorize(0)").is_err());
+    assert!(evaluate_expression("factorize(360) + 1").is_err());
+    assert!(returns_text("factorize(12)"));
+
+    // Strong pseudoprimes to small bases are still found composite
+    assert!(!primes::is_prime(3_215_031_751));
+    assert_eq!(primes::factorize(1), vec![]);
+  }
+
+  #[test]
+  fn test_integer_sequences() {
+    use rust_calculator::{evaluate_expression, evaluate_integer, evaluate_to_string};
+
+    let fibonacci: Vec<f64> = (0..10)
+      .map(|n| evaluate_expression(&format!("fib {}", n)).unwrap())
+      .collect();
+    assert_eq!(
+      fibonacci,
+      [0.0, 1.0, 1.0, 2.0, 3.0, 5.0, 8.0, 13.0, 21.0, 34.0]
+    );
+    assert_eq!(
+      evaluate_expression("lucas 0 + lucas 1 + lucas 5").unwrap(),
+      14.0
+    );
+    assert_eq!(evaluate_expression("triangular 100").unwrap(), 5050.0);
+    assert_eq!(evaluate_expression("catalan 10").unwrap(), 16796.0);
+    assert_eq!(evaluate_expression("catalan 0").unwrap(), 1.0);
+    assert!(evaluate_expression("fib(-1)").is_err());
+    assert!(evaluate_expression("lucas 2.5").is_err());
+    assert!(evaluate_expression("fib(10^15)").is_err());
+
+    // Terms beyond 2^53 are exact
+    assert_eq!(
+      evaluate_to_string("fib 100").unwrap(),
+      "354224848179261915075"
+    );
+    assert_eq!(
+      evaluate_integer("catalan 40").unwrap().to_string(),
+      "2622127042276492108820"
+    );
+    assert_eq!(
+      evaluate_integer("lucas 80 - fib 79 - fib 81")
+        .unwrap()
+        .to_string(),
+      "0"
+    );
+    assert_eq!(
+      evaluate_integer("triangular(10^10)").unwrap().to_string(),
+      "50000000005000000000"
+    );
+
+    // And so are terms past the range of f64
+    assert!(evaluate_expression("fib 2000").is_err());
+    let term = evaluate_to_string("fib 2000").unwrap();
+    assert_eq!(term.len(), 418);
+    assert!(term.starts_with("42246963333923048787067256023414827825798528402506"));
+    assert_eq!(term, evaluate_integer("fib 2000").unwrap().to_string());
+    assert_eq!(
+      evaluate_integer("lucas 1500 - fib 1499 - fib 1501")
+        .unwrap()
+        .to_string(),
+      "0"
+    );
+    assert!(evaluate_to_string("lucas 1500").unwrap().len() > 308);
+    assert!(evaluate_to_string("catalan 600").unwrap().len() > 308);
+    assert!(evaluate_to_string("fib(10^15)").is_err());
+  }
+
+  #[test]
+  fn test_percent() {
+    use rust_calculator::{canonicalize, evaluate_expression, evaluate_fraction, rpn};
+
+    assert_eq!(evaluate_expression("50%").unwrap(), 0.5);
+    assert_eq!(evaluate_expression("200 + 10%").unwrap(), 220.0);
+    assert_eq!(evaluate_expression("200 - 10%").unwrap(), 180.0);
+    assert_eq!(evaluate_expression("200 * 10%").unwrap(), 20.0);
+    assert_eq!(evaluate_expression("(50 + 50)%").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("100 + 10% + 10%").unwrap(), 121.0);
+    assert_eq!(evaluate_expression("clamp(150%, 0, 1)").unwrap(), 1.0);
+
+    // % between two operands is still modulo
+    assert_eq!(evaluate_expression("10 % 3").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("10%3").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("7 % -3").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("50% - 1").unwrap(), -0.5);
+
+    assert_eq!(canonicalize("200+10%").unwrap(), "(200 * (100 + 10)) / 100");
+    assert_eq!(canonicalize("(1 + 2)%").unwrap(), "(1 + 2)%");
+    assert_eq!(rpn::to_rpn("50%").unwrap(), "50 percent");
+    let third = evaluate_fraction("1/3 + 10%").unwrap();
+    assert_eq!((third.numerator(), third.denominator()), (11, 30));
+  }
+
+  #[test]
+  fn test_absolute_value_bars() {
+    use rust_calculator::{canonicalize, evaluate_expression};
+
+    assert_eq!(evaluate_expression("|-5|").unwrap(), 5.0);
+    assert_eq!(evaluate_expression("|3 - |5 - 9||").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("||-3||").unwrap(), 3.0);
+    assert_eq!(evaluate_expression("|3 - 5| + |2 - 8|").unwrap(), 8.0);
+    assert_eq!(evaluate_expression("-|-4| * 2").unwrap(), -8.0);
+    assert_eq!(evaluate_expression("sqrt |-16|").unwrap(), 4.0);
+    assert_eq!(evaluate_expression("clamp(|-5|, 0, 3)").unwrap(), 3.0);
+
+    // A bar after an operand with none open is still bitwise or
+    assert_eq!(evaluate_expression("12 | 10").unwrap(), 14.0);
+    assert_eq!(evaluate_expression("|2 - 5| | 8").unwrap(), 11.0);
+    assert_eq!(evaluate_expression("|(1 | 2)| + 1").unwrap(), 4.0);
+
+    assert!(evaluate_expression("|3").is_err());
+    assert!(evaluate_expression("|3| |").is_err());
+    assert_eq!(canonicalize("|x - 1|").unwrap(), "abs(x - 1)");
+  }
+
+  #[test]
+  fn test_comparisons() {
+    use rust_calculator::{
+      CompiledExpression, Value, canonicalize, evaluate, evaluate_expression, evaluate_fraction,
+    };
+
+    assert_eq!(evaluate_expression("2^10 == 1024").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("3 != 3").unwrap(), 0.0);
+    assert_eq!(evaluate_expression("1 + 1 < 3").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("2 <= 2").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("-1 > -2").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("2 >= 3").unwrap(), 0.0);
+    assert_eq!(evaluate_expression("0.1 + 0.2 == 0.3").unwrap(), 0.0);
+    assert_eq!(evaluate_expression("(1 < 2) + (2 < 3)").unwrap(), 2.0);
+    // Looser than shifts and arithmetic, tighter than the bitwise operators
+    assert_eq!(evaluate_expression("1 << 2 < 5").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("5 & 4 == 4").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("1 < 2 == 1").unwrap(), 1.0);
+
+    assert_eq!(evaluate("2^10 == 1024").unwrap(), Value::Boolean(true));
+    assert_eq!(evaluate("1 > 2").unwrap().to_string(), "false");
+    assert_eq!(evaluate("(1 < 2) + 1").unwrap(), Value::Number(2.0));
+
+    assert_eq!(canonicalize("1<2+3").unwrap(), "1 < (2 + 3)");
+    assert_eq!(canonicalize("(1<2)==1").unwrap(), "(1 < 2) == 1");
+    let compiled = CompiledExpression::new("x >= 0").unwrap();
+    assert_eq!(compiled.evaluate(&[-1.0]).unwrap(), 0.0);
+    let fraction = evaluate_fraction("1/3 < 0.34").unwrap();
+    assert_eq!(fraction.numerator(), 1);
+  }
+
+  #[test]
+  fn test_logical_operators() {
+    use rust_calculator::settings::{IntegerOverflow, IntegerType};
+    use rust_calculator::{Value, canonicalize, evaluate, evaluate_expression, programmer};
+
+    assert_eq!(evaluate_expression("1 < 2 and 2 < 3").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("1 < 2 && 3 < 2").unwrap(), 0.0);
+    assert_eq!(evaluate_expression("1 > 2 or 2 < 3").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("0 || 0").unwrap(), 0.0);
+    assert_eq!(evaluate_expression("5 AND -2").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("not 0").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("!(1 > 2)").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("!0 + 1").unwrap(), 2.0);
+    assert_eq!(evaluate_expression("(1 < 2) xor (2 < 3)").unwrap(), 0.0);
+    // and binds more tightly than or
+    assert_eq!(evaluate_expression("1 or 1 and 0").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("5 & 6 and 1").unwrap(), 1.0);
+
+    // Bars still open absolute values, and close them before a bitwise or
+    assert_eq!(evaluate_expression("||-3||").unwrap(), 3.0);
+    assert_eq!(evaluate_expression("|1 - 3|| 4").unwrap(), 6.0);
+
+    assert_eq!(evaluate("1 < 2 or 0").unwrap(), Value::Boolean(true));
+    assert_eq!(evaluate("!1").unwrap(), Value::Boolean(false));
+    assert_eq!(canonicalize("a && b || x").unwrap(), "(a and b) or x");
+    assert_eq!(
+      programmer::evaluate("3 > 2 and 1", IntegerType::U8, IntegerOverflow::Error).unwrap(),
+      1
+    );
+  }
+
+  #[test]
+  fn test_euclidean_modulo() {
+    use rust_calculator::evaluate_expression;
+
+    // % truncates, so the remainder has the sign of the dividend
+    assert_eq!(evaluate_expression("-7 % 3").unwrap(), -1.0);
+    assert_eq!(evaluate_expression("7 % -3").unwrap(), 1.0);
+
+    assert_eq!(evaluate_expression("mod(-7, 3)").unwrap(), 2.0);
+    assert_eq!(evaluate_expression("mod(7, 3)").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("mod(7, -3)").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("mod(-7, -3)").unwrap(), 2.0);
+    assert_eq!(evaluate_expression("mod(-7.5, 2)").unwrap(), 0.5);
+    assert_eq!(evaluate_expression("mod(-9, 3)").unwrap(), 0.0);
+    assert!(evaluate_expression("mod(1, 0)").is_err());
+    assert!(evaluate_expression("mod(5)").is_err());
+  }
+
+  #[test]
+  fn test_sum_and_product() {
+    use rust_calculator::{evaluate_expression, evaluate_with_variables};
+    use std::collections::HashMap;
+
+    assert_eq!(
+      evaluate_expression("sum(i, 1, 100, i^2)").unwrap(),
+      338350.0
+    );
+    assert_eq!(evaluate_expression("prod(k, 1, 5, k)").unwrap(), 120.0);
+    assert_eq!(
+      evaluate_expression("2 * sum(i, 1, 3, i) + 1").unwrap(),
+      13.0
+    );
+    assert_eq!(
+      evaluate_expression("sum(i, 1, 4, sum(j, 1, i, j))").unwrap(),
+      20.0
+    );
+    assert_eq!(evaluate_expression("sum(i, -2, 2, i^3)").unwrap(), 0.0);
+
+    // An empty range
+    assert_eq!(evaluate_expression("sum(i, 5, 1, i)").unwrap(), 0.0);
+    assert_eq!(evaluate_expression("prod(i, 5, 1, i)").unwrap(), 1.0);
+
+    // The bound variable hides a stored one only inside the term
+    let variables = HashMap::from([("x".to_string(), 10.0)]);
+    assert_eq!(
+      evaluate_with_variables("x + sum(x, 1, 3, x)", &variables).unwrap(),
+      16.0
+    );
+    assert_eq!(
+      evaluate_with_variables("sum(i, 1, 3, x * i)", &variables).unwrap(),
+      60.0
+    );
+
+    assert!(evaluate_expression("sum(2, 1, 3, 1)").is_err());
+    assert!(evaluate_expression("sum(i, 1, 3)").is_err());
+    assert!(evaluate_expression("sum(i, 0.5, 3, i)").is_err());
+    assert!(evaluate_expression("sum(i, 1, 1e9, i)").is_err());
+    assert!(evaluate_expression("sum(i, 1, 3, y)").is_err());
+  }
+
+  #[test]
+  fn test_lists_and_statistics() {
+    use rust_calculator::{Value, canonicalize, evaluate, evaluate_expression, evaluate_to_string};
+
+    assert_eq!(
+      evaluate("[3, 5, 2 + 6]").unwrap(),
+      Value::from(vec![3.0, 5.0, 8.0])
+    );
+    assert_eq!(evaluate_to_string("[1, 2.5]").unwrap(), "[1, 2.5]");
+    assert_eq!(canonicalize("[1, 2 * 3]").unwrap(), "[1, 2 * 3]");
+
+    assert_eq!(evaluate_expression("mean([3, 5, 8, 13])").unwrap(), 7.25);
+    assert_eq!(evaluate_expression("median([13, 3, 8])").unwrap(), 8.0);
+    assert_eq!(evaluate_expression("median([3, 5, 8, 13])").unwrap(), 6.5);
+    assert_eq!(evaluate_expression("mode([3, 1, 3, 1, 2])").unwrap(), 1.0);
+    assert_eq!(
+      evaluate_expression("var([2, 4, 4, 4, 5, 5, 7, 9])").unwrap(),
+      32.0 / 7.0
+    );
+    assert_eq!(evaluate_expression("stdev([1, 3])").unwrap(), 2f64.sqrt());
+    assert_eq!(evaluate_expression("sum([3, 5, 8, 13])").unwrap(), 29.0);
+    assert_eq!(
+      evaluate_expression("count([3, 5, 8, 13]) * 2").unwrap(),
+      8.0
+    );
+
+    // With four arguments, sum is still a sum over a range
+    assert_eq!(evaluate_expression("sum(i, 1, 4, i)").unwrap(), 10.0);
+
+    assert!(evaluate_expression("[1, 2]").is_err());
+    assert!(evaluate_expression("mean(5)").is_err());
+    assert!(evaluate_expression("var([5])").is_err());
+    assert!(evaluate_expression("mean([1, [2, 3]])").is_err());
+  }
+
+  #[test]
+  fn test_list_utilities() {
+    use rust_calculator::{Value, canonicalize, evaluate, evaluate_expression, parse};
+
+    assert_eq!(
+      evaluate("sort([12, 7, 30, 18])").unwrap(),
+      Value::from(vec![7.0, 12.0, 18.0, 30.0])
+    );
+    assert_eq!(evaluate_expression("min([12, 7, 30, 18])").unwrap(), 7.0);
+    assert_eq!(evaluate_expression("max([12, 7, 30, 18])").unwrap(), 30.0);
+    assert_eq!(evaluate_expression("range([12, 7, 30, 18])").unwrap(), 23.0);
+
+    // Positions count from 1, and indexing binds tighter than any operator
+    assert_eq!(evaluate_expression("[12, 7, 30][1]").unwrap(), 12.0);
+    assert_eq!(evaluate_expression("sort([12, 7, 30])[3]").unwrap(), 30.0);
+    assert_eq!(evaluate_expression("2 ^ [1, 3][1 + 1]").unwrap(), 8.0);
+    assert_eq!(
+      evaluate("[[1, 2], [3, 4]][2]").unwrap(),
+      Value::from(vec![3.0, 4.0])
+    );
+    assert_eq!(evaluate_expression("[[1, 2], [3, 4]][2][1]").unwrap(), 3.0);
+    assert_eq!(
+      canonicalize("sort([3, 1])[1 + 1]").unwrap(),
+      "sort([3, 1])[1 + 1]"
+    );
+    assert_eq!(parse("[3, 1][2] * 2").unwrap().to_string(), "[3, 1][2] * 2");
+
+    assert!(evaluate_expression("[1, 2][3]").is_err());
+    assert!(evaluate_expression("[1, 2][0]").is_err());
+    assert!(evaluate_expression("[1, 2][1.5]").is_err());
+    assert!(evaluate_expression("5[1]").is_err());
+    assert!(evaluate_expression("min(5)").is_err());
+    assert!(evaluate_expression("sort([2, 1])").is_err());
+  }
+
+  #[test]
+  fn test_linear_regression() {
+    use rust_calculator::lists::{Regression, linear_regression};
+    use rust_calculator::{Value, evaluate, evaluate_expression};
+
+    assert_eq!(
+      linear_regression(&[1.0, 2.0, 3.0], &[3.0, 5.0, 7.0]),
+      Some(Regression {
+        slope: 2.0,
+        intercept: 1.0,
+        r2: 1.0
+      })
+    );
+    let fit = linear_regression(&[1.0, 2.0, 3.0, 4.0], &[2.1, 3.9, 6.2, 7.8]).unwrap();
+    assert!((fit.slope - 1.94).abs() < 1e-12);
+    assert!((fit.intercept - 0.15).abs() < 1e-12);
+    assert!((fit.r2 - 0.995661375661375).abs() < 1e-12);
+
+    assert_eq!(
+      evaluate("linreg([0, 2, 4], [1, 0, -1])").unwrap(),
+      Value::from(vec![-0.5, 1.0, 1.0])
+    );
+    assert_eq!(
+      evaluate_expression("linreg([0, 2], [1, 5])[1]").unwrap(),
+      2.0
+    );
+    // A flat line fits points at one height exactly
+    assert_eq!(
+      evaluate("linreg([1, 2, 3], [4, 4, 4])").unwrap(),
+      Value::from(vec![0.0, 4.0, 1.0])
+    );
+
+    assert_eq!(linear_regression(&[1.0, 1.0], &[2.0, 3.0]), None);
+    assert!(evaluate("linreg([1, 2], [1])").is_err());
+    assert!(evaluate("linreg([1, 2])").is_err());
+  }
+
+  #[test]
+  fn test_financial_functions() {
+    use rust_calculator::{evaluate_expression, finance};
+
+    assert_eq!(evaluate_expression("fv(0.5, 2, 100)").unwrap(), 225.0);
+    assert_eq!(
+      evaluate_expression("round(fv(0.05, 10, 1000), 2)").unwrap(),
+      1628.89
+    );
+    assert_eq!(
+      evaluate_expression("round(pmt(0.05 / 12, 360, 200000), 2)").unwrap(),
+      1073.64
+    );
+    assert_eq!(evaluate_expression("pmt(0, 4, 1000)").unwrap(), 250.0);
+
+    assert_eq!(evaluate_expression("npv(0, [-100, 30, 80])").unwrap(), 10.0);
+    assert_eq!(evaluate_expression("npv(0.5, [-100, 150])").unwrap(), 0.0);
+    assert_eq!(
+      evaluate_expression("round(npv(0.1, [-1000, 300, 400, 500]), 4)").unwrap(),
+      -21.0368
+    );
+
+    let rate = finance::irr(&[-1000.0, 300.0, 400.0, 500.0]).unwrap();
+    assert!(finance::npv(rate, &[-1000.0, 300.0, 400.0, 500.0]).abs() < 1e-9);
+    assert_eq!(
+      evaluate_expression("round(irr([-100, 60, 60]), 6)").unwrap(),
+      0.130662
+    );
+    assert_eq!(finance::irr(&[100.0, 200.0]), None);
+
+    assert!(evaluate_expression("pmt(-1, 10, 100)").is_err());
+    assert!(evaluate_expression("pmt(0.1, 0, 100)").is_err());
+    assert!(evaluate_expression("npv(0.1, 100)").is_err());
+    assert!(evaluate_expression("irr([1, 2])").is_err());
+    assert!(evaluate_expression("fv(0.1, 2)").is_err());
+  }
+
+  #[test]
+  fn test_dates() {
+    use rust_calculator::dates::Date;
+    use rust_calculator::{Value, evaluate, evaluate_expression, evaluate_to_string};
+
+    let date = |year, month, day| Value::Date(Date::new(year, month, day).unwrap());
+    assert_eq!(evaluate("2024-07-15").unwrap(), date(2024, 7, 15));
+    assert_eq!(evaluate("date(2024, 7, 15)").unwrap(), date(2024, 7, 15));
+    assert_eq!(evaluate("2024-01-01 + 90d").unwrap(), date(2024, 3, 31));
+    assert_eq!(evaluate("90 days + 2024-01-01").unwrap(), date(2024, 3, 31));
+    assert_eq!(evaluate("2024-03-01 - 1 days").unwrap(), date(2024, 2, 29));
+    assert_eq!(evaluate("2023-03-01 - 1 days").unwrap(), date(2023, 2, 28));
+    assert_eq!(evaluate("1999-12-25 + 2 weeks").unwrap(), date(2000, 1, 8));
+    assert_eq!(
+      evaluate_to_string("2024-01-01 + 90d").unwrap(),
+      "2024-03-31"
+    );
+
+    assert_eq!(
+      evaluate_expression(r#"days_between("2024-01-01", "2024-07-15")"#).unwrap(),
+      196.0
+    );
+    assert_eq!(
+      evaluate_expression(r#""2024-07-15" - "2024-01-01""#).unwrap(),
+      196.0
+    );
+    assert_eq!(evaluate_expression(r#""1970-01-01""#).unwrap(), 0.0);
+    assert_eq!(
+      evaluate(r#""2024-01-01" + 90d"#).unwrap(),
+      date(2024, 3, 31)
+    );
+    assert_eq!(
+      evaluate_to_string("days_between(2024-01-01, 2024-07-15)").unwrap(),
+      "196"
+    );
+    assert_eq!(evaluate_to_string("weekday(2024-07-15)").unwrap(), "Monday");
+    assert_eq!(
+      evaluate_to_string("weekday(2000-01-01)").unwrap(),
+      "Saturday"
+    );
+    assert!(evaluate("today").is_ok());
+
+    for days in [-719_162, -1, 0, 59, 11_016, 2_932_896] {
+      assert_eq!(Date::from_days(days).unwrap().to_days(), days);
+    }
+    assert_eq!(Date::from_days(-719_163), None);
+    assert_eq!(Date::new(1900, 2, 29), None);
+
+    // Without a date, 90d is an angle, and a short date is a subtraction.
+    // Functions giving numbers only read dates in quotes, so a date without
+    // them is still a subtraction there.
+    assert_eq!(evaluate_expression("sin(90d)").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("2024-1-1").unwrap(), 2022.0);
+    assert_eq!(evaluate("2024-1-1").unwrap(), Value::Number(2022.0));
+    assert_eq!(evaluate_expression("2024-01-01 + 90").unwrap(), 2112.0);
+    assert_eq!(evaluate_expression("12024-01-01").unwrap(), 12022.0);
+
+    assert!(evaluate("2024-02-30").is_err());
+    assert!(evaluate("date(2024, 13, 1)").is_err());
+    assert!(evaluate("2024-01-01 + 0.5").is_err());
+  }
+
+  #[test]
+  fn test_polynomial_roots() {
+    use rust_calculator::{Value, evaluate, evaluate_to_string, polynomials};
+
+    assert_eq!(
+      evaluate("solvequad(1, -3, 2)").unwrap(),
+      Value::from(vec![1.0, 2.0])
+    );
+    assert_eq!(
+      evaluate("solvequad(1, 2, 1)").unwrap(),
+      Value::from(vec![-1.0])
+    );
+    assert_eq!(
+      evaluate("solvequad(1, 0, 1)").unwrap(),
+      Value::from(Vec::<f64>::new())
+    );
+    assert_eq!(
+      evaluate("solvecubic(1, -6, 11, -6)").unwrap(),
+      Value::from(vec![1.0, 2.0, 3.0])
+    );
+    assert_eq!(
+      evaluate("solvecubic(1, -4, 5, -2)").unwrap(),
+      Value::from(vec![1.0, 2.0])
+    );
+    assert_eq!(
+      evaluate("solvecubic(2, 0, 0, -16)").unwrap(),
+      Value::from(vec![2.0])
+    );
+    assert_eq!(evaluate_to_string("solvequad(1, -3, 2)").unwrap(), "[1, 2]");
+    assert!(evaluate("solvequad(0, 1, 1)").is_err());
+    assert!(evaluate("solvecubic(1, 2, 3)").is_err());
+
+    let roots = polynomials::roots(&[1.0, 0.0, 0.0, -1.0]).unwrap();
+    assert_eq!(roots.len(), 3);
+    assert_eq!(roots[0].to_string(), "1");
+    assert!(roots[1].im > 0.0 && roots[2].im < 0.0);
+    let roots = polynomials::roots(&[1.0, 0.0, 4.0]).unwrap();
+    assert_eq!(roots[0].to_string(), "2i");
+    assert_eq!(roots[1].to_string(), "-2i");
+  }
+
+  #[test]
+  fn test_solve() {
+    use rust_calculator::evaluate_expression;
+
+    let root = evaluate_expression("solve(\"x^2 = 2\", x, 1)").unwrap();
+    assert!((root - 2f64.sqrt()).abs() < 1e-12);
+    let root = evaluate_expression("solve(\"x^2 = 2\", x, -1)").unwrap();
+    assert!((root + 2f64.sqrt()).abs() < 1e-12);
+    assert_eq!(
+      evaluate_expression("solve(\"2*x + 1\", x, 0)").unwrap(),
+      -0.5
+    );
+    let root = evaluate_expression("solve(\"exp(x) = 10\", x, 0)").unwrap();
+    assert!((root - 10f64.ln()).abs() < 1e-12);
+    // The variable hides a stored one, and can be bound by sum
+    assert_eq!(
+      evaluate_expression("sum(i, 1, 3, solve(\"y = i\", y, 0))").unwrap(),
+      6.0
+    );
+    // Bisection takes over where Newton's method can't start
+    let root = evaluate_expression("solve(\"sqrt(x) = 3\", x, 1)").unwrap();
+    assert!((root - 9.0).abs() < 1e-9);
+
+    assert!(evaluate_expression("solve(\"x^2 + 1\", x, 1)").is_err());
+    assert!(evaluate_expression("solve(\"1/x\", x, 1)").is_err());
+    assert!(evaluate_expression("solve(\"floor(x) = 2.5\", x, 0)").is_err());
+    assert!(evaluate_expression("solve(\"x = 1 = 2\", x, 1)").is_err());
+    assert!(evaluate_expression("solve(\"x = 1, x, 1)").is_err());
+    assert!(evaluate_expression("solve(\"x = 1\", 2, 1)").is_err());
+  }
+
+  #[test]
+  fn test_simplify() {
+    use rust_calculator::{CompiledExpression, evaluate_to_string, parse, simplify};
+
+    assert_eq!(simplify("x * 1 + 0").unwrap(), "x");
+    assert_eq!(simplify("x / 1 - 0").unwrap(), "x");
+    assert_eq!(simplify("x ^ 1").unwrap(), "x");
+    assert_eq!(simplify("2 * 3 + 4").unwrap(), "10");
+    assert_eq!(simplify("2 * x + 3 * x - 1 + 4").unwrap(), "5 * x + 3");
+    assert_eq!(simplify("2 * x * 3").unwrap(), "6 * x");
+    assert_eq!(simplify("2 * x * y + y * 0 + x * y").unwrap(), "3 * x * y");
+    assert_eq!(simplify("x - 2 * y + y").unwrap(), "x - y");
+    assert_eq!(simplify("x - x").unwrap(), "0");
+    assert_eq!(simplify("-(2 * x)").unwrap(), "-2 * x");
+    assert_eq!(simplify("sqrt(2) * 1").unwrap(), "sqrt(2)");
+    // A failing operation is kept for evaluation to report
+    assert_eq!(simplify("1 / 0 + x").unwrap(), "1 / 0 + x");
+    assert_eq!(evaluate_to_string("simplify(x + x)").unwrap(), "2 * x");
+
+    let tree = parse("y * (2 + 3)").unwrap().simplify();
+    assert_eq!(tree, parse("5 * y").unwrap());
+
+    // Compiled formulas are simplified, but keep every variable
+    let formula = CompiledExpression::new("x - x + y * 1").unwrap();
+    assert_eq!(formula.variables(), ["x", "y"]);
+    assert_eq!(formula.evaluate(&[3.0, 4.0]).unwrap(), 4.0);
+    assert!(
+      CompiledExpression::new("1 / 0 * x")
+        .unwrap()
+        .evaluate(&[1.0])
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn test_matrices() {
+    use rust_calculator::{Value, evaluate, evaluate_expression, evaluate_to_string, matrices};
+
+    let m = evaluate("[[1, 2], [3, 4]]").unwrap();
+    assert_eq!(m, Value::from(vec![vec![1.0, 2.0], vec![3.0, 4.0]]));
+    // Rows of different lengths stay a list of lists
+    assert!(matches!(evaluate("[[1, 2], [3]]").unwrap(), Value::List(_)));
+
+    assert_eq!(
+      evaluate_to_string("[[1, 2], [3, 4]] * [[5, 6], [7, 8]]").unwrap(),
+      "[[19, 22], [43, 50]]"
+    );
+    assert_eq!(
+      evaluate_to_string("2 * [[1, 2], [3, 4]] - [[1, 1], [1, 1]]").unwrap(),
+      "[[1, 3], [5, 7]]"
+    );
+    assert_eq!(
+      evaluate_to_string("transpose([[1, 2, 3], [4, 5, 6]])").unwrap(),
+      "[[1, 4], [2, 5], [3, 6]]"
+    );
+    assert_eq!(evaluate_expression("det([[1, 2], [3, 4]])").unwrap(), -2.0);
+    assert_eq!(
+      evaluate_expression("det([[0, 2, 1], [3, 1, 4], [2, 5, 3]])").unwrap(),
+      11.0
+    );
+    assert_eq!(
+      evaluate_to_string("inv([[1, 2], [3, 4]])").unwrap(),
+      "[[-2, 1], [1.5, -0.5]]"
+    );
+    assert_eq!(
+      evaluate_to_string("linsolve([[2, 1], [1, 3]], [3, 5])").unwrap(),
+      "[0.8, 1.4]"
+    );
+    assert_eq!(
+      evaluate_expression("[[1, 2], [3, 4]][2][1] + 1").unwrap(),
+      4.0
+    );
+    assert_eq!(
+      matrices::determinant(&[vec![2.0, 1.0], vec![4.0, 2.0]]),
+      0.0
+    );
+
+    assert!(evaluate("inv([[1, 2], [2, 4]])").is_err());
+    assert!(evaluate("det([[1, 2, 3]])").is_err());
+    assert!(evaluate("[[1, 2]] * [[1, 2]]").is_err());
+    assert!(evaluate("[[1, 2]] + [[1, 2], [3, 4]]").is_err());
+    assert!(evaluate("linsolve([[1, 0], [0, 1]], [1, 2, 3])").is_err());
+    assert!(evaluate_expression("[[1, 2], [3, 4]] + 1").is_err());
+  }
+
+  #[test]
+  fn test_limit() {
+    use rust_calculator::{evaluate, evaluate_expression};
+
+    assert_eq!(
+      evaluate_expression("limit((x^2 - 1) / (x - 1), x, 1)").unwrap(),
+      2.0
+    );
+    assert_eq!(
+      evaluate_expression("limit((exp(x) - 1) / x, x, 0)").unwrap(),
+      1.0
+    );
+    assert_eq!(evaluate_expression("limit(sqrt(x), x, 0)").unwrap(), 0.0);
+    assert_eq!(evaluate_expression("limit(x^2, x, 3)").unwrap(), 9.0);
+    assert_eq!(
+      evaluate_expression("limit(abs(x) / x, x, 0, 1)").unwrap(),
+      1.0
+    );
+    assert_eq!(
+      evaluate_expression("limit(abs(x) / x, x, 0, -1)").unwrap(),
+      -1.0
+    );
+    assert_eq!(evaluate_expression("limit(1 / n, n, inf)").unwrap(), 0.0);
+    assert!(
+      (evaluate_expression("limit((1 + 1/n)^n, n, inf)").unwrap() - core::f64::consts::E).abs()
+        < 1e-9
+    );
+
+    let error = evaluate("limit(abs(x) / x, x, 0)").unwrap_err().to_string();
+    assert!(
+      error.contains("-1 from below and 1 from above"),
+      "{}",
+      error
+    );
+    let error = evaluate("limit(1 / x^2, x, 0)").unwrap_err().to_string();
+    assert!(error.contains("grow towards ∞"), "{}", error);
+    assert!(evaluate("limit(floor(x), x, 2)").is_err());
+    assert!(evaluate("limit(x, 2, 0)").is_err());
+    assert!(evaluate("limit(x, x, 0, 2)").is_err());
+  }
+
+  #[test]
+  fn test_minimize_maximize() {
+    use rust_calculator::{evaluate, evaluate_expression};
+
+    assert_eq!(
+      evaluate_expression("minimize(x^2 - 4*x, x, 0, 5)").unwrap(),
+      2.0
+    );
+    assert_eq!(
+      evaluate_expression("maximize(x * (10 - x), x, 0, 10)").unwrap(),
+      5.0
+    );
+    assert_eq!(
+      evaluate_expression("minimize((x - 0.3)^2, x, -10, 10)").unwrap(),
+      0.3
+    );
+    assert_eq!(evaluate_expression("minimize(x, x, -1, 1)").unwrap(), -1.0);
+    assert_eq!(evaluate_expression("maximize(x, x, 1, -1)").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("minimize(1/x, x, 0, 2)").unwrap(), 2.0);
+    let x = evaluate_expression("minimize(exp(x) - 2*x, x, 0, 3)").unwrap();
+    assert!((x - 2f64.ln()).abs() < 1e-7, "{}", x);
+    let x = evaluate_expression("minimize(x^4 - 3*x, x, -5, 5)").unwrap();
+    assert!((x - 0.75f64.cbrt()).abs() < 1e-6, "{}", x);
+
+    assert!(evaluate("minimize(x, 2, 0, 1)").is_err());
+    assert!(evaluate("maximize(x, x, 0)").is_err());
+  }
+
+  #[test]
+  fn test_spell() {
+    use rust_calculator::evaluate_to_string;
+    use rust_calculator::words::{Language, spell};
+
+    assert_eq!(
+      evaluate_to_string("spell(1234.5)").unwrap(),
+      "one thousand two hundred thirty-four point five"
+    );
+    assert_eq!(spell(0.0, Language::English).unwrap(), "zero");
+    assert_eq!(spell(-0.0, Language::English).unwrap(), "zero");
+    assert_eq!(spell(-17.0, Language::English).unwrap(), "minus seventeen");
+    assert_eq!(
+      spell(0.25, Language::English).unwrap(),
+      "zero point two five"
+    );
+    assert_eq!(
+      spell(2_000_000_019.0, Language::English).unwrap(),
+      "two billion nineteen"
+    );
+    assert_eq!(
+      spell(100_010.0, Language::English).unwrap(),
+      "one hundred thousand ten"
+    );
+    assert_eq!(
+      evaluate_to_string("spell(0.1 + 0.2)").unwrap(),
+      "zero point three"
+    );
+
+    assert!(
+      spell(2f64.powi(53), Language::English)
+        .unwrap()
+        .ends_with("seven hundred forty thousand nine hundred ninety-two")
+    );
+    assert!(evaluate_to_string("spell(2^60)").is_err());
+    assert!(evaluate_to_string("spell(1) + 1").is_err());
+  }
+
+  #[test]
+  fn test_user_functions() {
+    use rust_calculator::{evaluate_expression, functions, set_variable};
+
+    let square = functions::define("uf_square(x) = x^2 + 3*x").unwrap();
+    assert_eq!(square.to_string(), "uf_square(x) = x^2 + 3*x");
+    assert_eq!(evaluate_expression("uf_square(2) + 1").unwrap(), 11.0);
+    assert_eq!(evaluate_expression("uf_square 3").unwrap(), 18.0);
+    assert_eq!(
+      evaluate_expression("sum(i, 1, 3, uf_square(i))").unwrap(),
+      32.0
+    );
+
+    functions::define("uf_hyp(a, b) = sqrt(a^2 + b^2)").unwrap();
+    assert_eq!(evaluate_expression("uf_hyp(3, 4)").unwrap(), 5.0);
+    assert!(evaluate_expression("uf_hyp(3)").is_err());
+
+    // Variables are read when the function is called
+    set_variable("uf_rate", 2.0).unwrap();
+    functions::define("uf_scaled(x) = uf_square(x) * uf_rate").unwrap();
+    assert_eq!(evaluate_expression("uf_scaled(1)").unwrap(), 8.0);
+    set_variable("uf_rate", 3.0).unwrap();
+    assert_eq!(evaluate_expression("uf_scaled(1)").unwrap(), 12.0);
+    assert!(functions::list().iter().any(|f| f.name == "uf_scaled"));
+
+    assert!(functions::define("sin(x) = x").is_err());
+    assert!(functions::define("uf_bad(pi) = pi").is_err());
+    assert!(functions::define("uf_bad(x, x) = x").is_err());
+    assert!(functions::define("uf_bad(x) = x +").is_err());
+    assert!(functions::define("uf_bad = 2").is_err());
+    assert!(functions::get("uf_bad").is_none());
+  }
+
+  #[test]
+  fn test_user_functions_in_isolated_evaluations() {
+    use rust_calculator::{
+      Calculator, CompiledExpression, EvalContext, evaluate_with_variables, functions, set_variable,
+    };
+    use std::collections::HashMap;
+
+    // Function bodies read the caller's variables, not the stored ones
+    set_variable("iso_k", 100.0).unwrap();
+    functions::define("iso_addk(x) = x + iso_k").unwrap();
+
+    let variables = HashMap::from([("iso_k".to_string(), 1.0)]);
+    assert_eq!(
+      evaluate_with_variables("iso_addk(1)", &variables).unwrap(),
+      2.0
+    );
+    assert_eq!(
+      evaluate_with_variables("if(1, iso_addk(1), 0)", &variables).unwrap(),
+      2.0
+    );
+    assert!(evaluate_with_variables("iso_addk(1)", &HashMap::new()).is_err());
+
+    let mut calculator = Calculator::new();
+    calculator.set_variable("iso_k", 1.0).unwrap();
+    assert_eq!(calculator.evaluate("iso_addk(1)").unwrap(), 2.0);
+
+    let mut ctx = EvalContext::new();
+    ctx.set_variable("iso_k", 1.0);
+    assert_eq!(ctx.evaluate("iso_addk(1)").unwrap(), 2.0);
+    assert!(EvalContext::new().evaluate("iso_addk(1)").is_err());
+
+    let compiled = CompiledExpression::new("iso_addk(x) + 0 * iso_k").unwrap();
+    assert_eq!(compiled.variables(), ["iso_k", "x"]);
+    assert_eq!(compiled.evaluate(&[1.0, 1.0]).unwrap(), 2.0);
+    let compiled = CompiledExpression::new("iso_addk(1)").unwrap();
+    assert_eq!(
+      compiled
+        .evaluate_with(&|name| (name == "iso_k").then_some(1.0))
+        .unwrap(),
+      2.0
+    );
+    assert!(compiled.evaluate(&[]).is_err());
+  }
+
+  #[test]
+  fn test_map_filter_reduce() {
+    use rust_calculator::{Value, evaluate, evaluate_expression, functions, validate_expression};
+
+    functions::define("hof_square(x) = x^2").unwrap();
+    functions::define("hof_even(x) = x % 2 == 0").unwrap();
+    functions::define("hof_add(a, b) = a + b").unwrap();
+
+    assert_eq!(
+      evaluate("map(sqrt, [1, 4, 9])").unwrap(),
+      Value::from(vec![1.0, 2.0, 3.0])
+    );
+    assert_eq!(
+      evaluate("map(hof_square, [1, 2, 3])").unwrap(),
+      Value::from(vec![1.0, 4.0, 9.0])
+    );
+    assert_eq!(
+      evaluate("filter(hof_even, [1, 2, 3, 4])").unwrap(),
+      Value::from(vec![2.0, 4.0])
+    );
+    assert_eq!(
+      evaluate_expression("reduce(hof_add, [1, 2, 3, 4], 10)").unwrap(),
+      20.0
+    );
+    assert_eq!(
+      evaluate_expression("sum(map(hof_square, filter(hof_even, [1, 2, 3, 4])))").unwrap(),
+      20.0
+    );
+    assert_eq!(
+      evaluate("filter(hof_even, [1, 3])").unwrap(),
+      Value::List(vec![])
+    );
+
+    // Function bodies may use them, and sums, too
+    functions::define("hof_total(n) = reduce(hof_add, map(hof_square, [1, n]), 0)").unwrap();
+    assert_eq!(evaluate_expression("hof_total(3)").unwrap(), 10.0);
+    functions::define("hof_squares(n) = sum(i, 1, n, i^2)").unwrap();
+    assert_eq!(evaluate_expression("hof_squares(3)").unwrap(), 14.0);
+    assert!(validate_expression("map(sqrt, [1, 4])").is_ok());
+    assert!(validate_expression("sum(i, 1, 3, j)").is_err());
+
+    assert!(evaluate("map(3, [1, 2])").is_err());
+    assert!(evaluate("map(hof_add, [1, 2])").is_err());
+    assert!(evaluate("reduce(hof_square, [1, 2], 0)").is_err());
+    assert!(evaluate("map(hof_square, 2)").is_err());
+    assert!(evaluate("filter(hof_even)").is_err());
+  }
+
+  #[test]
+  fn test_recursive_functions() {
+    use rust_calculator::settings::Settings;
+    use rust_calculator::{
+      CalculatorError, evaluate_expression, evaluate_expression_with_settings, functions,
+    };
+
+    functions::define("rec_fact(n) = if(n <= 1, 1, n * rec_fact(n - 1))").unwrap();
+    assert_eq!(evaluate_expression("rec_fact(5)").unwrap(), 120.0);
+    functions::define("rec_gcd(a, b) = if(b == 0, a, rec_gcd(b, a % b))").unwrap();
+    assert_eq!(evaluate_expression("rec_gcd(48, 18)").unwrap(), 6.0);
+
+    // Functions can call each other
+    functions::define("rec_even(n) = if(n == 0, 1, rec_odd(n - 1))").unwrap_err();
+    functions::define("rec_odd(n) = n").unwrap();
+    functions::define("rec_even(n) = if(n == 0, 1, rec_odd(n - 1))").unwrap();
+    functions::define("rec_odd(n) = if(n == 0, 0, rec_even(n - 1))").unwrap();
+    assert_eq!(evaluate_expression("rec_even(10)").unwrap(), 1.0);
+
+    assert_eq!(evaluate_expression("if(2 > 1, 10, 20)").unwrap(), 10.0);
+    assert_eq!(evaluate_expression("if(0, 1 / 0, 20)").unwrap(), 20.0);
+    assert!(evaluate_expression("if(1, 2)").is_err());
+
+    functions::define("rec_forever(x) = rec_forever(x + 1)").unwrap();
+    let shallow = Settings {
+      max_recursion: 20,
+      ..Settings::default()
+    };
+    let err = evaluate_expression_with_settings("rec_forever(0)", &shallow).unwrap_err();
+    assert!(matches!(err, CalculatorError::LimitExceeded(_)));
+    assert_eq!(
+      err.to_string(),
+      "Limit exceeded: user functions are nested more than 20 calls deep"
+    );
+    assert!(evaluate_expression_with_settings("rec_fact(25)", &shallow).is_err());
+    assert!(evaluate_expression("rec_fact(25)").is_ok());
+
+    let mut settings = Settings::default();
+    assert_eq!(settings.max_recursion, 100);
+    settings.set("recursion", "500").unwrap();
+    assert_eq!(settings.max_recursion, 500);
+    assert!(settings.set("recursion", "0").is_err());
+    assert!(settings.set("recursion", "1000000").is_err());
+
+    // A body that doesn't parse leaves no function behind
+    assert!(functions::define("rec_bad(x) = rec_bad(x) +").is_err());
+    assert!(functions::get("rec_bad").is_none());
+  }
+
+  #[test]
+  fn test_user_function_panic() {
+    use rust_calculator::settings::Settings;
+    use rust_calculator::{
+      CompiledExpression, evaluate_expression_with_settings, functions, set_variable,
+    };
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+
+    set_variable("ufp_k", 1.0).unwrap();
+    functions::define("ufp_addk(x) = x + ufp_k").unwrap();
+    functions::define("ufp_count(n) = if(n <= 0, 0, 1 + ufp_count(n - 1))").unwrap();
+
+    // The caller's resolver panics while the body is being evaluated
+    let compiled = CompiledExpression::new("ufp_addk(1)").unwrap();
+    let panicked = catch_unwind(AssertUnwindSafe(|| {
+      compiled.evaluate_with(&|_| panic!("resolver failed"))
+    }));
+    assert!(panicked.is_err());
+
+    // The call no longer counts toward the recursion limit
+    let settings = Settings {
+      max_recursion: 20,
+      ..Settings::default()
+    };
+    assert_eq!(
+      evaluate_expression_with_settings("ufp_count(19)", &settings).unwrap(),
+      19.0
+    );
+    assert!(evaluate_expression_with_settings("ufp_count(20)", &settings).is_err());
+  }
+
+  #[test]
+  fn test_session_answer() {
+    use rust_calculator::settings::{IntegerType, Settings};
+    use rust_calculator::{Calculator, evaluate_with_variables};
+    use std::collections::HashMap;
+
+    let mut calc = Calculator::new();
+    assert_eq!(calc.answer(), None);
+    assert!(calc.evaluate("ans + 1").is_err());
+    calc.evaluate("6 * 7").unwrap();
+    assert_eq!(calc.answer(), Some(42.0));
+    assert_eq!(calc.evaluate("ans / 2").unwrap(), 21.0);
+    assert_eq!(calc.evaluate("ans + 1").unwrap(), 22.0);
+    assert_eq!(calc.evaluate("sqrt(ans + 3) * ans").unwrap(), 110.0);
+
+    // Failed evaluations leave it as it was
+    assert!(calc.evaluate("ans / 0").is_err());
+    assert_eq!(calc.answer(), Some(110.0));
+    calc.reset_answer();
+    assert!(calc.evaluate("ans").is_err());
+
+    let mut other = Calculator::new();
+    assert!(other.evaluate("ans").is_err());
+
+    let mut programmer = Calculator::with_settings(Settings {
+      integer: Some(IntegerType::U8),
+      ..Settings::default()
+    });
+    programmer.evaluate("200").unwrap();
+    assert_eq!(programmer.evaluate("ans + 50").unwrap(), 250.0);
+
+    // A single evaluation with its own variables has no last result
+    assert!(evaluate_with_variables("ans", &HashMap::new()).is_err());
+  }
+
+  #[test]
+  fn test_continuation() {
+    use rust_calculator::Calculator;
+    use rust_calculator::settings::Settings;
+
+    let mut strict = Calculator::new();
+    strict.evaluate("100").unwrap();
+    assert!(strict.evaluate("* 1.21").is_err());
+    assert_eq!(strict.evaluate("-5").unwrap(), -5.0);
+
+    let mut calc = Calculator::with_settings(Settings {
+      continuation: true,
+      ..Settings::default()
+    });
+    assert!(calc.evaluate("* 2").is_err());
+    calc.evaluate("100").unwrap();
+    assert_eq!(calc.evaluate("* 1.21").unwrap(), 121.0);
+    assert_eq!(calc.evaluate("  - 21").unwrap(), 100.0);
+    assert_eq!(calc.evaluate("+ 5").unwrap(), 105.0);
+    assert_eq!(calc.evaluate("+5").unwrap(), 110.0);
+    assert_eq!(calc.evaluate("/ 11").unwrap(), 10.0);
+    assert_eq!(calc.evaluate("^ 0").unwrap(), 1.0);
+    assert_eq!(calc.evaluate("== 1").unwrap(), 1.0);
+    // A sign right before its operand starts a new calculation
+    assert_eq!(calc.evaluate("-5").unwrap(), -5.0);
+    assert_eq!(calc.evaluate("-(2 * 3)").unwrap(), -6.0);
+
+    let mut settings = Settings::default();
+    settings.set("continue", "on").unwrap();
+    assert!(settings.continuation);
+    assert!(settings.set("continue", "maybe").is_err());
+  }
+
+  #[test]
+  fn test_assignment() {
+    use rust_calculator::{
+      Calculator, evaluate_cached, evaluate_expression, get_variable, validate_expression,
+    };
+
+    assert_eq!(evaluate_expression("asg_x = 3 * 4").unwrap(), 12.0);
+    assert_eq!(get_variable("asg_x"), Some(12.0));
+    assert_eq!(evaluate_expression("asg_x + 1").unwrap(), 13.0);
+    assert_eq!(evaluate_expression("asg_x = asg_x / 2").unwrap(), 6.0);
+    assert_eq!(evaluate_cached("asg_y = asg_x * 2").unwrap(), 12.0);
+    assert_eq!(evaluate_expression("asg_a = asg_b = 7").unwrap(), 7.0);
+    assert_eq!(get_variable("asg_a"), Some(7.0));
+    assert_eq!(get_variable("asg_b"), Some(7.0));
+
+    // Comparisons aren't assignments
+    assert_eq!(evaluate_expression("asg_x == 6").unwrap(), 1.0);
+    assert_eq!(evaluate_expression("asg_x <= 5").unwrap(), 0.0);
+    assert_eq!(evaluate_expression("asg_x != 6").unwrap(), 0.0);
+
+    assert!(evaluate_expression("pi = 3").is_err());
+    assert!(evaluate_expression("sin = 3").is_err());
+    let err = evaluate_expression("asg_z = 2 + xyz").unwrap_err();
+    assert_eq!(err.span(), Some(12..15));
+    assert_eq!(get_variable("asg_z"), None);
+    assert!(validate_expression("asg_z = 2 + 2").is_ok());
+    assert_eq!(
+      validate_expression("asg_z = 2 +").unwrap_err().span(),
+      Some(10..11)
+    );
+    assert_eq!(get_variable("asg_z"), None);
+
+    // A session assigns its own variables
+    let mut calc = Calculator::new();
+    assert_eq!(calc.evaluate("asg_rate = 0.2").unwrap(), 0.2);
+    assert_eq!(calc.get_variable("asg_rate"), Some(0.2));
+    assert_eq!(calc.evaluate("150 * asg_rate").unwrap(), 30.0);
+    assert_eq!(get_variable("asg_rate"), None);
+    assert_eq!(calc.history()[0].0, "asg_rate = 0.2");
+  }
+
+  #[test]
+  fn test_delete_variable() {
+    use rust_calculator::{delete_variable, evaluate_expression, get_all_variables, set_variable};
+
+    set_variable("del_x", 4.0).unwrap();
+    assert_eq!(evaluate_expression("del_x * 2").unwrap(), 8.0);
+    assert_eq!(delete_variable("DEL_X").unwrap(), Some(4.0));
+    assert!(evaluate_expression("del_x * 2").is_err());
+    assert!(
+      !get_all_variables()
+        .unwrap()
+        .iter()
+        .any(|(name, _)| name == "del_x")
+    );
+    assert_eq!(delete_variable("del_x").unwrap(), None);
+  }
+
+  #[test]
+  fn test_reserved_names() {
+    use rust_calculator::{
+      CalculatorError, ErrorCode, constants, evaluate_expression, functions, get_variable,
+      set_variable,
+    };
+
+    for name in ["pi", "e", "sin", "mr", "ans", "km_to_mi"] {
+      let err = set_variable(name, 5.0).unwrap_err();
+      assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+      assert_eq!(
+        err.to_string(),
+        format!("Reserved name: {} is already taken", name)
+      );
+      assert_eq!(get_variable(name), None);
+    }
+    assert!(evaluate_expression("e").unwrap() > 2.7);
+
+    let err = evaluate_expression("pi = 3").unwrap_err();
+    assert!(matches!(&err, CalculatorError::ReservedName(name) if name == "pi"));
+    assert_eq!(
+      err.code(),
+      ErrorCode::ReservedName {
+        name: "pi".to_string()
+      }
+    );
+    assert!(matches!(
+      functions::define("sqrt(x) = x"),
+      Err(CalculatorError::ReservedName(_))
+    ));
+    assert!(matches!(
+      constants::register("cos", 1.0),
+      Err(CalculatorError::ReservedName(_))
+    ));
+
+    set_variable("rsv_free", 1.0).unwrap();
+    assert_eq!(get_variable("rsv_free"), Some(1.0));
+  }
+
+  #[test]
+  fn test_formula_variables() {
+    use rust_calculator::{
+      CalculatorError, delete_variable, evaluate_expression, formulas, get_variable, set_variable,
+    };
+
+    let formula = formulas::define("fml_Area", "pi * fml_r^2").unwrap();
+    assert_eq!(formula.to_string(), "fml_area := pi * fml_r^2");
+    assert!(evaluate_expression("fml_area").is_err());
+
+    set_variable("fml_r", 2.0).unwrap();
+    assert_eq!(
+      evaluate_expression("fml_area").unwrap(),
+      4.0 * std::f64::consts::PI
+    );
+    set_variable("fml_r", 1.0).unwrap();
+    assert_eq!(get_variable("fml_area"), Some(std::f64::consts::PI));
+
+    // Formulas can build on each other, but not on themselves
+    formulas::define("fml_double", "2 * fml_area").unwrap();
+    assert_eq!(
+      evaluate_expression("fml_double").unwrap(),
+      2.0 * std::f64::consts::PI
+    );
+    assert!(formulas::define("fml_r", "fml_double + 1").is_err());
+    assert!(formulas::define("fml_loop", "fml_loop + 1").is_err());
+    assert!(matches!(
+      formulas::define("pi", "3"),
+      Err(CalculatorError::ReservedName(_))
+    ));
+    assert!(formulas::define("fml_bad", "1 +").is_err());
+    assert_eq!(formulas::get("fml_bad"), None);
+
+    // A plain value and a formula replace each other
+    formulas::define("fml_r", "3").unwrap();
+    assert_eq!(delete_variable("fml_r").unwrap(), None);
+    assert_eq!(
+      evaluate_expression("fml_area").unwrap(),
+      9.0 * std::f64::consts::PI
+    );
+    set_variable("fml_double", 1.0).unwrap();
+    assert_eq!(formulas::get("fml_double"), None);
+
+    assert!(formulas::remove("fml_area").unwrap().is_some());
+    assert!(formulas::remove("fml_area").unwrap().is_none());
+    assert!(evaluate_expression("fml_area").is_err());
+  }
+
+  #[test]
+  fn test_formula_dependencies() {
+    use rust_calculator::{
+      delete_variable, evaluate_expression, formulas, functions, set_variable,
+    };
+
+    set_variable("dep_w", 2.0).unwrap();
+    formulas::define("dep_area", "dep_w * dep_h").unwrap();
+    formulas::define("dep_cost", "dep_area * 3").unwrap();
+    assert!(formulas::get("dep_cost").unwrap().value.is_err());
+
+    set_variable("dep_h", 5.0).unwrap();
+    let names: Vec<String> = formulas::dependents("dep_h")
+      .into_iter()
+      .map(|formula| formula.name)
+      .collect();
+    assert_eq!(names, ["dep_area", "dep_cost"]);
+    assert_eq!(formulas::get("dep_cost").unwrap().value, Ok(30.0));
+    assert!(
+      formulas::get("dep_area")
+        .unwrap()
+        .dependencies
+        .contains("dep_h")
+    );
+
+    // Dependencies through a function's body are followed
+    functions::define("dep_f(x) = x + dep_cost").unwrap();
+    formulas::define("dep_total", "dep_f(1)").unwrap();
+    set_variable("dep_w", 1.0).unwrap();
+    assert_eq!(evaluate_expression("dep_total").unwrap(), 16.0);
+
+    let err = formulas::define("dep_w", "dep_total").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Argument error: Circular reference: dep_w depends on itself"
+    );
+    functions::define("dep_f(x) = x + dep_total").unwrap();
+    assert_eq!(
+      formulas::get("dep_total").unwrap().value,
+      Err("Circular reference: dep_total depends on itself".to_string())
+    );
+    assert!(evaluate_expression("dep_total").is_err());
+    functions::define("dep_f(x) = x").unwrap();
+    assert_eq!(evaluate_expression("dep_total").unwrap(), 1.0);
+
+    delete_variable("dep_h").unwrap();
+    assert!(evaluate_expression("dep_cost").is_err());
+  }
+
+  #[test]
+  fn test_variable_namespaces() {
+    use rust_calculator::{
+      clear_namespace, evaluate_expression, formulas, get_namespace, get_variable, in_namespace,
+      set_variable,
+    };
+
+    set_variable("ns_project1.rate", 0.05).unwrap();
+    set_variable("ns_project1.loan.Years", 10.0).unwrap();
+    set_variable("ns_project2.rate", 0.07).unwrap();
+    assert_eq!(evaluate_expression("ns_project1.rate * 100").unwrap(), 5.0);
+    assert_eq!(
+      evaluate_expression("ns_other.rate = ns_project2.rate * 2").unwrap(),
+      0.14
+    );
+    assert!(evaluate_expression("ns_project1.missing").is_err());
+    assert!(evaluate_expression("ns_project1..rate").is_err());
+
+    assert!(in_namespace("ns_project1.loan.years", "ns_project1"));
+    assert!(in_namespace("ns_project1.loan.years", "NS_project1.loan"));
+    assert!(!in_namespace("ns_project10.rate", "ns_project1"));
+    assert_eq!(
+      get_namespace("ns_project1").unwrap(),
+      [
+        ("ns_project1.loan.years".to_string(), 10.0),
+        ("ns_project1.rate".to_string(), 0.05),
+      ]
+    );
+
+    formulas::define(
+      "ns_project1.total",
+      "ns_project1.rate * ns_project1.loan.years",
+    )
+    .unwrap();
+    assert_eq!(clear_namespace("ns_project1").unwrap(), 3);
+    assert_eq!(get_variable("ns_project1.rate"), None);
+    assert_eq!(formulas::get("ns_project1.total"), None);
+    assert_eq!(clear_namespace("ns_project1").unwrap(), 0);
+    assert_eq!(get_variable("ns_project2.rate"), Some(0.07));
+  }
+
+  #[test]
+  #[cfg(all(feature = "config", feature = "persistence"))]
+  fn test_variables_file() {
+    use rust_calculator::{
+      describe_variable, get_variable, get_variable_info, load_variables, set_variable,
+      with_data_dir,
+    };
+
+    // The files of this test are kept apart from those of the others
+    let dir = std::env::temp_dir().join(format!("calculator_store_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let _lock = MEMORY_LOCK.lock().unwrap();
+    let temporary_files = || {
+      std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("tmp".as_ref()))
+        .count()
+    };
+    with_data_dir(&dir, || {
+      let legacy = dir.join("calculator_variables.txt");
+      let path = dir.join("calculator_variables.toml");
+      // A damaged line, a line that isn't UTF-8 and a last line cut off
+      let mut damaged = b"sto_a=1\nsto_junk\n\xff=2\nsto_b=2\n".to_vec();
+      damaged.extend_from_slice(b"sto_c=3.1");
+      std::fs::write(&legacy, &damaged).unwrap();
+
+      // The lines of earlier versions become the TOML file
+      load_variables().unwrap();
+      assert_eq!(get_variable("sto_a"), Some(1.0));
+      assert_eq!(get_variable("sto_b"), Some(2.0));
+      assert_eq!(get_variable("sto_c"), None);
+      assert!(!legacy.exists());
+      assert_eq!(
+        std::fs::read(dir.join("calculator_variables.txt.bak")).unwrap(),
+        damaged
+      );
+      let migrated = std::fs::read_to_string(&path).unwrap();
+      assert!(migrated.contains("[sto_a]\ntype = \"number\"\nvalue = 1.0\ncreated = "));
+      assert!(!migrated.contains("sto_c"));
+
+      set_variable("sto.d", 4.0).unwrap();
+      describe_variable("sto.d", "Four").unwrap();
+      let saved = std::fs::read_to_string(&path).unwrap();
+      assert!(
+        saved.contains("[\"sto.d\"]\ntype = \"number\"\nvalue = 4.0\ndescription = \"Four\"")
+      );
+      assert_eq!(temporary_files(), 0);
+
+      // Entries this version can't read are left out and the file repaired
+      let toml = r#"
+      [sto_e]
+      type = "number"
+      value = 5.0
+      description = "Five"
+      created = 2024-07-15T10:30:00+02:00
+
+      [sto_f]
+      type = "list"
+      value = [1.0, 2.0]
+      created = 2024-07-15T10:30:00Z
+
+      [sto_g]
+      value = "seven"
+    "#;
+      std::fs::write(&path, toml).unwrap();
+      let err = load_variables().unwrap_err();
+      assert_eq!(err.to_string(), "Left out damaged variables: sto_f, sto_g");
+      let sto_e = get_variable_info("sto_e").unwrap();
+      assert_eq!(sto_e.value, 5.0);
+      assert_eq!(sto_e.description.as_deref(), Some("Five"));
+      assert_eq!(sto_e.created, 1_721_032_200);
+      assert_eq!(get_variable("sto_f"), None);
+      assert_eq!(get_variable("sto_g"), None);
+      assert_eq!(
+        std::fs::read_to_string(dir.join("calculator_variables.toml.bak")).unwrap(),
+        toml
+      );
+      let repaired = std::fs::read_to_string(&path).unwrap();
+      assert!(repaired.contains("created = 2024-07-15T08:30:00Z") && !repaired.contains("sto_f"));
+
+      // The memory is saved when it changes and comes back with the variables
+      evaluate_expression("mc").unwrap();
+      evaluate_expression("7.5 m+").unwrap();
+      let memory = dir.join("calculator_memory.toml");
+      let saved = std::fs::read_to_string(&memory).unwrap();
+      assert!(saved.starts_with("memory = 7.5\n") && saved.contains("operation = \"m+\""));
+      std::fs::write(&memory, "memory = 2").unwrap();
+      load_variables().unwrap();
+      assert_eq!(evaluate_expression("mr").unwrap(), 2.0);
+      evaluate_expression("mc").unwrap();
+
+      // A syntax error only loses the table it's in, a variable without a
+      // creation time is kept, and the functions still load
+      let functions = dir.join("calculator_functions.txt");
+      std::fs::write(&functions, "sto_fn(x) = x + 1\n").unwrap();
+      let toml = "[sto_h\nvalue = 1\n\n[sto_i]\ntype = \"number\"\nvalue = 9\n";
+      std::fs::write(&path, toml).unwrap();
+      let err = load_variables().unwrap_err();
+      assert_eq!(err.to_string(), "Left out damaged variables: line 1");
+      assert_eq!(get_variable("sto_i"), Some(9.0));
+      assert!(get_variable_info("sto_i").unwrap().created > 1_721_032_200);
+      assert_eq!(evaluate_expression("sto_fn(1)").unwrap(), 2.0);
+
+      // A save that fails leaves no temporary file behind
+      std::fs::remove_file(&functions).unwrap();
+      std::fs::create_dir(&functions).unwrap();
+      assert!(rust_calculator::functions::define("sto_fn2(x) = x").is_err());
+      assert_eq!(temporary_files(), 0);
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_memory_undo() {
+    use rust_calculator::Calculator;
+    use rust_calculator::memory::{MAX_CHANGES, MemoryChange, MemoryOperation};
+
+    let mut calc = Calculator::new();
+    assert!(calc.evaluate("mundo").is_err());
+    calc.evaluate("3 m+").unwrap();
+    calc.evaluate("5 m+").unwrap();
+    calc.evaluate("2 m-").unwrap();
+    calc.evaluate("mc").unwrap();
+    assert_eq!(calc.memory_changes().len(), 4);
+    assert_eq!(
+      calc.memory_changes()[2],
+      MemoryChange {
+        operation: MemoryOperation::Subtract,
+        operand: Some(2.0),
+        previous: 8.0,
+      }
+    );
+    assert_eq!(calc.memory_changes()[3].operand, None);
+
+    // mundo reverts one change at a time, and mr isn't recorded
+    assert_eq!(calc.evaluate("mundo").unwrap(), 6.0);
+    assert_eq!(calc.evaluate("mr").unwrap(), 6.0);
+    assert_eq!(calc.evaluate("mundo").unwrap(), 8.0);
+    assert_eq!(calc.evaluate("mundo + 1").unwrap(), 4.0);
+    assert_eq!(calc.memory_changes().len(), 1);
+    assert_eq!(calc.evaluate("mundo").unwrap(), 0.0);
+    assert!(calc.evaluate("mundo").is_err());
+    assert_eq!(calc.memory(), 0.0);
+
+    // Only the latest changes are kept
+    for _ in 0..MAX_CHANGES + 5 {
+      calc.evaluate("1 m+").unwrap();
+    }
+    assert_eq!(calc.memory_changes().len(), MAX_CHANGES);
+    assert_eq!(calc.memory_changes()[0].previous, 5.0);
+
+    // A snapshot keeps the changes along with the memory
+    let snapshot = calc.snapshot();
+    calc.evaluate("mc").unwrap();
+    calc.restore(&snapshot);
+    assert_eq!(calc.evaluate("mundo").unwrap(), 104.0);
+  }
+
+  #[test]
+  fn test_deep_nesting() {
+    use rust_calculator::{CalculatorError, evaluate_to_string, parse};
+
+    let limited = |result: Result<(), CalculatorError>| {
+      matches!(result, Err(CalculatorError::LimitExceeded(_)))
+    };
+    let deep = [
+      format!("{}1{}", "(".repeat(5000), ")".repeat(5000)),
+      format!("{}1", "-".repeat(100_000)),
+      format!("{}1", "sqrt ".repeat(5000)),
+      format!("{}1{}", "|".repeat(5000), "|".repeat(5000)),
+      format!("{}1{}", "[".repeat(5000), "]".repeat(5000)),
+      format!("1{}", "^1".repeat(5000)),
+    ];
+    for expression in &deep {
+      assert!(limited(evaluate_expression(expression).map(drop)));
+      assert!(limited(evaluate_to_string(expression).map(drop)));
+      assert!(limited(parse(expression).map(drop)));
+    }
+
+    // Long but flat sums still evaluate, though their trees are too deep
+    let sum = format!("1{}", " + 1".repeat(100_000));
+    assert_eq!(evaluate_expression(&sum).unwrap(), 100_001.0);
+    assert!(limited(parse(&sum).map(drop)));
+
+    // Nesting well short of the limit is fine
+    let nested = format!("{}1{}", "(".repeat(150), ")".repeat(150));
+    assert_eq!(evaluate_expression(&nested).unwrap(), 1.0);
+    assert_eq!(evaluate_to_string(&nested).unwrap(), "1");
+  }
+
+  #[test]
+  #[cfg(feature = "persistence")]
+  fn test_history_damaged_lines() {
+    use rust_calculator::{get_history, with_data_dir};
+
+    let dir = std::env::temp_dir().join(format!("calculator_history_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    with_data_dir(&dir, || {
+      // Lines that aren't UTF-8 are skipped, not the end of the history
+      let mut history = b"1 + 1 = 2\r\n2 + 2 = 4\n".to_vec();
+      history.extend_from_slice(b"\xff\xfe = 3\n3 + 3 = 6\n\xc3 = 1\n4 + 4 = 8\n");
+      std::fs::write(dir.join("calculator_history.txt"), history).unwrap();
+
+      assert_eq!(get_history(1).unwrap(), vec!["4 + 4 = 8"]);
+      assert_eq!(
+        get_history(3).unwrap(),
+        vec!["2 + 2 = 4", "3 + 3 = 6", "4 + 4 = 8"]
+      );
+      assert_eq!(get_history(10).unwrap().len(), 4);
+      assert_eq!(get_history(10).unwrap()[0], "1 + 1 = 2");
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  #[cfg(feature = "persistence")]
+  fn test_define_function_save_failure() {
+    use rust_calculator::{evaluate_expression, functions, with_data_dir};
+
+    // A file where the data directory should be can't be saved into
+    let file = std::env::temp_dir().join(format!("calculator_not_a_dir_{}", std::process::id()));
+    std::fs::write(&file, "").unwrap();
+    with_data_dir(&file, || {
+      let err = functions::define("sf_double(x) = 2 * x").unwrap_err();
+      assert!(err.to_string().contains("Cannot save functions"));
+    });
+    std::fs::remove_file(&file).unwrap();
+
+    // The function isn't defined when it couldn't be saved
+    assert!(functions::get("sf_double").is_none());
+    assert!(evaluate_expression("sf_double(2)").is_err());
+  }
 }